@@ -0,0 +1,150 @@
+//! Benchmarks of the ways a backup can copy its files, over generated trees of many small files
+//! and of few large files, so that regressions of `backup.rs` are noticed and its defaults
+//! (buffered copy, number of files opened at the same time) are chosen on measurements.
+//!
+//! Run with `cargo bench --bench copy_strategies`; on Linux, add `--features io_uring` to compare
+//! the copy made with io_uring as well.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use group_39::backup::{self, BackupOptions, BackupReport, ConflictPolicy, PlaceholderPolicy};
+use group_39::filesystem::DiskFilesystem;
+use tokio::runtime::Runtime;
+
+/// A tree of files generated for the benchmarks.
+struct Tree {
+    name: &'static str,
+    files: usize,
+    file_size: usize,
+}
+
+const TREES: &[Tree] = &[
+    Tree { name: "many_small_files", files: 2000, file_size: 4 * 1024 },
+    Tree { name: "few_large_files", files: 8, file_size: 32 * 1024 * 1024 },
+];
+
+/// Numbers of files opened at the same time by the whole backup.
+const CONCURRENCY_LEVELS: &[usize] = &[1, 8, 64, 550];
+
+/// Returns the folder where the trees and the copies of the benchmarks are written.
+fn bench_dir() -> PathBuf {
+    std::env::temp_dir().join("group_39_bench")
+}
+
+/// Creates the source tree (if not created by a previous run), spread in folders of 100 files.
+fn generate_tree(tree: &Tree) -> Vec<PathBuf> {
+    let root = bench_dir().join("source").join(tree.name);
+    let content: Vec<u8> = (0..tree.file_size).map(|i| (i % 251) as u8).collect();
+    (0..tree.files)
+        .map(|i| {
+            let path = root.join(format!("dir_{}", i / 100)).join(format!("file_{}.bin", i));
+            if fs::metadata(&path).map_or(true, |metadata| metadata.len() != tree.file_size as u64) {
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(&path, &content).unwrap();
+            }
+            path
+        })
+        .collect()
+}
+
+/// Returns an empty destination folder for a benchmark.
+fn clean_destination(name: &str) -> PathBuf {
+    let destination = bench_dir().join("destination").join(name);
+    let _ = fs::remove_dir_all(&destination);
+    fs::create_dir_all(&destination).unwrap();
+    destination
+}
+
+/// Returns, for each source file, the path of its copy in the destination.
+fn destination_paths(files: &[PathBuf], source: &Path, destination: &Path) -> Vec<PathBuf> {
+    let paths: Vec<PathBuf> = files.iter().map(|file| destination.join(file.strip_prefix(source).unwrap())).collect();
+    for path in &paths {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+    }
+    paths
+}
+
+/// Compares, one file at a time, the buffered copy of the backup, `tokio::fs::copy`
+/// and the copy of the standard library, which uses the zero-copy call of the platform
+/// (`copy_file_range` on Linux, `CopyFileEx` on Windows, `fcopyfile` on macOS).
+fn copy_strategies(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    for tree in TREES {
+        let files = generate_tree(tree);
+        let source = bench_dir().join("source").join(tree.name);
+        let destination = clean_destination(tree.name);
+        let targets = destination_paths(&files, &source, &destination);
+
+        let mut group = c.benchmark_group(format!("copy/{}", tree.name));
+        group.sample_size(10);
+        group.throughput(Throughput::Bytes((tree.files * tree.file_size) as u64));
+
+        group.bench_function("buffered", |b| {
+            b.to_async(&rt).iter(|| async {
+                for (src, dest) in files.iter().zip(&targets) {
+                    backup::copy_file(&DiskFilesystem, src, dest).await.unwrap();
+                }
+            })
+        });
+        #[cfg(all(target_os = "linux", feature = "io_uring"))]
+        group.bench_function("io_uring", |b| {
+            group_39::uring::set_enabled(true);
+            b.to_async(&rt).iter(|| async {
+                for (src, dest) in files.iter().zip(&targets) {
+                    backup::copy_file(&DiskFilesystem, src, dest).await.unwrap();
+                }
+            });
+            group_39::uring::set_enabled(false);
+        });
+        group.bench_function("tokio_fs_copy", |b| {
+            b.to_async(&rt).iter(|| async {
+                for (src, dest) in files.iter().zip(&targets) {
+                    tokio::fs::copy(src, dest).await.unwrap();
+                }
+            })
+        });
+        group.bench_function("platform_zero_copy", |b| {
+            b.iter(|| {
+                for (src, dest) in files.iter().zip(&targets) {
+                    fs::copy(src, dest).unwrap();
+                }
+            })
+        });
+        group.finish();
+    }
+}
+
+/// Runs the whole backup engine with different limits on the files opened at the same time.
+fn concurrency_levels(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    for tree in TREES {
+        let files = generate_tree(tree);
+        let source = bench_dir().join("source").join(tree.name);
+        let destination = clean_destination(&format!("{}_backup", tree.name));
+
+        let mut group = c.benchmark_group(format!("backup/{}", tree.name));
+        group.sample_size(10);
+        group.throughput(Throughput::Bytes((tree.files * tree.file_size) as u64));
+
+        for &max_file_opened in CONCURRENCY_LEVELS {
+            group.bench_with_input(BenchmarkId::new("max_file_opened", max_file_opened), &max_file_opened, |b, &max_file_opened| {
+                b.to_async(&rt).iter(|| async {
+                    let options = BackupOptions {
+                        total_files: files.len(),
+                        max_file_opened,
+                        on_conflict: ConflictPolicy::Overwrite,
+                        placeholders: PlaceholderPolicy::Hydrate,
+                        ..Default::default()
+                    };
+                    backup::backup(Arc::new(DiskFilesystem), &source, &destination, &options, Arc::new(Mutex::new(BackupReport::default())), None).await.unwrap();
+                })
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, copy_strategies, concurrency_levels);
+criterion_main!(benches);
@@ -0,0 +1,117 @@
+use eframe::egui::{self, Color32, Stroke};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// How much larger the text of the notifications and of the windows is with `large_notifications`.
+pub const LARGE_TEXT_SCALE: f32 = 1.5;
+
+/// Whether the notifications are shown with extra-large text (`large_notifications`).
+static LARGE_TEXT: AtomicBool = AtomicBool::new(false);
+
+/// Sets whether the notifications and the windows use extra-large text.
+pub fn set_large_text(large: bool) {
+    LARGE_TEXT.store(large, Ordering::SeqCst);
+}
+
+/// Returns whether the notifications and the windows use extra-large text.
+pub fn large_text() -> bool {
+    LARGE_TEXT.load(Ordering::SeqCst)
+}
+
+/// Returns whether the high-contrast mode of the system is on.
+#[cfg(target_os = "windows")]
+pub fn high_contrast() -> bool {
+    use winapi::um::winuser::{SystemParametersInfoW, HCF_HIGHCONTRASTON, HIGHCONTRASTW, SPI_GETHIGHCONTRAST};
+
+    let mut high_contrast: HIGHCONTRASTW = unsafe { std::mem::zeroed() };
+    high_contrast.cbSize = std::mem::size_of::<HIGHCONTRASTW>() as u32;
+    let found = unsafe {
+        SystemParametersInfoW(SPI_GETHIGHCONTRAST, high_contrast.cbSize, &mut high_contrast as *mut _ as *mut _, 0)
+    };
+    found != 0 && high_contrast.dwFlags & HCF_HIGHCONTRASTON != 0
+}
+
+#[cfg(target_os = "macos")]
+pub fn high_contrast() -> bool {
+    command_output("defaults", &["read", "com.apple.universalaccess", "increaseContrast"]).map_or(false, |value| value == "1")
+}
+
+#[cfg(target_os = "linux")]
+pub fn high_contrast() -> bool {
+    let theme = std::env::var("GTK_THEME").unwrap_or_default();
+    theme.contains("HighContrast")
+        || command_output("gsettings", &["get", "org.gnome.desktop.a11y.interface", "high-contrast"]).is_some_and(|value| value == "true")
+}
+
+/// Returns the text scale set in the accessibility settings of the system (1.0 if not set),
+/// which is not included in the scale of the screen.
+#[cfg(target_os = "windows")]
+pub fn text_scale() -> f32 {
+    // "Make text bigger", from 100 to 225 percent
+    command_output("reg", &["query", r"HKCU\Software\Microsoft\Accessibility", "/v", "TextScaleFactor"])
+        .and_then(|output| output.split_whitespace().last().map(str::to_string))
+        .and_then(|value| u32::from_str_radix(value.trim_start_matches("0x"), 16).ok())
+        .map_or(1.0, |percent| percent as f32 / 100.0)
+}
+
+#[cfg(target_os = "macos")]
+pub fn text_scale() -> f32 {
+    1.0
+}
+
+#[cfg(target_os = "linux")]
+pub fn text_scale() -> f32 {
+    command_output("gsettings", &["get", "org.gnome.desktop.interface", "text-scaling-factor"])
+        .and_then(|value| value.parse::<f32>().ok())
+        .filter(|scale| *scale > 0.0)
+        .unwrap_or(1.0)
+}
+
+/// Runs a command and returns its trimmed output, if it succeeds.
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Adapts a window of the application to the accessibility settings: the text scale of the system
+/// (and `large_notifications`) and its high-contrast mode.
+///
+/// # Arguments
+///
+/// * `ctx` - The context of the window, when it is created.
+pub fn apply_to_egui(ctx: &egui::Context) {
+    let large = if large_text() { LARGE_TEXT_SCALE } else { 1.0 };
+    ctx.set_zoom_factor(text_scale() * large);
+    if high_contrast() {
+        ctx.set_visuals(high_contrast_visuals());
+    }
+}
+
+/// Returns white on black visuals with thick outlines and yellow highlights, as the high-contrast themes of the systems.
+fn high_contrast_visuals() -> egui::Visuals {
+    let mut visuals = egui::Visuals::dark();
+    visuals.override_text_color = Some(Color32::WHITE);
+    visuals.panel_fill = Color32::BLACK;
+    visuals.window_fill = Color32::BLACK;
+    visuals.extreme_bg_color = Color32::BLACK;
+    visuals.faint_bg_color = Color32::BLACK;
+    visuals.hyperlink_color = Color32::YELLOW;
+    visuals.error_fg_color = Color32::from_rgb(255, 80, 80);
+    visuals.selection.bg_fill = Color32::YELLOW;
+    visuals.selection.stroke = Stroke::new(2.0, Color32::BLACK);
+    for (widget, fill) in [
+        (&mut visuals.widgets.noninteractive, Color32::BLACK),
+        (&mut visuals.widgets.inactive, Color32::BLACK),
+        (&mut visuals.widgets.hovered, Color32::from_rgb(0, 0, 160)),
+        (&mut visuals.widgets.active, Color32::from_rgb(0, 0, 160)),
+        (&mut visuals.widgets.open, Color32::BLACK),
+    ] {
+        widget.bg_fill = fill;
+        widget.weak_bg_fill = fill;
+        widget.bg_stroke = Stroke::new(2.0, Color32::WHITE);
+        widget.fg_stroke = Stroke::new(2.0, Color32::WHITE);
+    }
+    visuals.widgets.hovered.bg_stroke = Stroke::new(3.0, Color32::YELLOW);
+    visuals.widgets.active.bg_stroke = Stroke::new(3.0, Color32::YELLOW);
+    visuals
+}
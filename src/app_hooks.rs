@@ -0,0 +1,151 @@
+use crate::events::{self, AppEvent};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use sysinfo::System;
+use tokio::process::Command;
+
+/// Extensions of the SQLite databases, which many applications use to store their data (browsers, mail clients, ...).
+const SQLITE_EXTENSIONS: [&str; 3] = ["sqlite", "sqlite3", "db"];
+
+/// Longest wait for an application hook, so that the backup is never blocked by it.
+const HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest wait for the end of a transaction on a SQLite database without WAL (while its `-journal` file exists).
+const TRANSACTION_WAIT: Duration = Duration::from_secs(5);
+
+/// Whether the hooks run before copying the files of the known applications (`app_flush_hooks` in the configuration).
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// The applications whose warning has already been shown, once for each backup.
+static WARNED: Mutex<Option<HashSet<&'static str>>> = Mutex::new(None);
+
+/// A mail client whose stores cannot be flushed from outside: while it runs they may be copied half written
+/// (or not at all, if it locks them).
+struct MailClient {
+    name: &'static str,
+    /// Names of its process, without the extension.
+    processes: &'static [&'static str],
+    /// Whether a file belongs to its stores.
+    owns: fn(&Path) -> bool,
+}
+
+const MAIL_CLIENTS: [MailClient; 2] = [
+    MailClient {
+        name: "Thunderbird",
+        processes: &["thunderbird"],
+        // The mail folders (mbox files and their .msf indexes) inside the Thunderbird profiles (`.thunderbird` on Linux)
+        owns: |path| path.components().any(|component| component.as_os_str().to_string_lossy().to_lowercase().contains("thunderbird")),
+    },
+    MailClient {
+        name: "Outlook",
+        processes: &["outlook"],
+        owns: |path| has_extension(path, &["pst", "ost"]),
+    },
+];
+
+/// Sets whether the hooks of the known applications run before their files are copied.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+/// Forgets the warnings shown, at the beginning of a backup.
+pub fn reset() {
+    *WARNED.lock().unwrap() = None;
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| extensions.iter().any(|known| ext.eq_ignore_ascii_case(known)))
+}
+
+/// Returns the path of a file next to a database, e.g. its `-wal` or `-journal` file.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut sibling = path.as_os_str().to_owned();
+    sibling.push(suffix);
+    PathBuf::from(sibling)
+}
+
+/// Prepares a file of a known application for its copy, to reduce the chance of copying it half written:
+/// the SQLite databases in WAL mode are checkpointed (the committed transactions are written in the database
+/// file) and the ones without WAL are copied after the transaction in progress, if any; if a mail client
+/// is running while its stores are copied, the user is warned once.
+/// Any other file is returned immediately.
+///
+/// # Arguments
+///
+/// * `path` - The source file about to be copied.
+///
+/// # Returns
+///
+/// `true` if the application has flushed the file.
+pub async fn before_copy(path: &Path) -> bool {
+    if !ENABLED.load(Ordering::SeqCst) {
+        return false;
+    }
+    if has_extension(path, &SQLITE_EXTENSIONS) {
+        return prepare_sqlite(path).await;
+    }
+    if let Some(client) = MAIL_CLIENTS.iter().find(|client| (client.owns)(path)) {
+        warn_if_running(client);
+    }
+    false
+}
+
+/// Checkpoints a SQLite database in WAL mode, or waits for the end of the transaction in progress.
+async fn prepare_sqlite(path: &Path) -> bool {
+    if sibling(path, "-wal").exists() {
+        // PASSIVE: the application using the database is never blocked
+        let checkpoint = Command::new("sqlite3")
+            .arg("-cmd").arg(".timeout 2000")
+            .arg(path)
+            .arg("PRAGMA wal_checkpoint(PASSIVE);")
+            .kill_on_drop(true)
+            .output();
+        return match tokio::time::timeout(HOOK_TIMEOUT, checkpoint).await {
+            Ok(Ok(output)) if output.status.success() => true,
+            Ok(Ok(output)) => {
+                eprintln!("Unable to checkpoint {:?}: {}", path, String::from_utf8_lossy(&output.stderr).trim());
+                false
+            }
+            // sqlite3 is not installed: the database is copied as it is
+            Ok(Err(_)) => false,
+            Err(_) => {
+                eprintln!("Unable to checkpoint {:?}: no answer in {:?}", path, HOOK_TIMEOUT);
+                false
+            }
+        };
+    }
+    let journal = sibling(path, "-journal");
+    let start = Instant::now();
+    while journal.exists() && start.elapsed() < TRANSACTION_WAIT {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    false
+}
+
+/// Warns the user, once for each backup, that a mail client is running while its stores are copied.
+fn warn_if_running(client: &MailClient) {
+    {
+        let mut warned = WARNED.lock().unwrap();
+        if !warned.get_or_insert_with(HashSet::new).insert(client.name) {
+            return;
+        }
+    }
+    let mut system = System::new();
+    system.refresh_processes();
+    let running = system.processes().values().any(|process| {
+        let name = process.name().to_lowercase();
+        let name = name.strip_suffix(".exe").unwrap_or(&name);
+        client.processes.contains(&name)
+    });
+    if running {
+        events::publish(AppEvent::Error(format!(
+            "{} is running: its mail may be copied while it is being written. Close {} and make the backup again to be sure.",
+            client.name, client.name
+        )));
+    }
+}
@@ -0,0 +1,130 @@
+use std::fmt;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{OnceLock, RwLock};
+use std::thread;
+
+/// The state of the whole application, owned by the coordinator: the recognizers and the backup drive it
+/// with `send`, the other modules read it with `current` or follow it with `subscribe`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum AppState {
+    /// Waiting for the activation pattern.
+    #[default]
+    Idle,
+    /// The first step of the pattern has been done: waiting for the confirmation or the cancellation.
+    Armed,
+    /// The backup has been confirmed (or triggered) and is running, with its percentage of progress.
+    BackingUp { progress: usize },
+    /// The last backup has ended.
+    Done,
+    /// The last backup has failed.
+    Error(String),
+}
+
+/// What happened, sent to the coordinator to change the state.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateEvent {
+    /// The first step of the activation pattern.
+    Armed,
+    /// The backup has been confirmed with the pattern, or started without it (a trigger or `backup now`).
+    Started,
+    /// The backup has been canceled before it started copying (with the pattern, or to confirm it again).
+    Canceled,
+    /// A new percentage of progress of the backup.
+    Progress(usize),
+    Finished,
+    Failed(String),
+}
+
+impl AppState {
+    /// Returns the state after an event, or `None` if the event is not expected in this state
+    /// (e.g. a progress when no backup is running), so that the modules cannot drift apart.
+    fn next(&self, event: &StateEvent) -> Option<AppState> {
+        match (self, event) {
+            (AppState::BackingUp { .. }, StateEvent::Armed) => None,
+            (_, StateEvent::Armed) => Some(AppState::Armed),
+            (AppState::BackingUp { .. }, StateEvent::Started) => None,
+            (_, StateEvent::Started) => Some(AppState::BackingUp { progress: 0 }),
+            (AppState::Armed | AppState::BackingUp { progress: 0 }, StateEvent::Canceled) => Some(AppState::Idle),
+            (AppState::BackingUp { .. }, StateEvent::Progress(progress)) => Some(AppState::BackingUp { progress: *progress }),
+            (AppState::BackingUp { .. }, StateEvent::Finished) => Some(AppState::Done),
+            (_, StateEvent::Failed(msg)) => Some(AppState::Error(msg.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AppState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppState::Idle => write!(f, "idle"),
+            AppState::Armed => write!(f, "armed"),
+            AppState::BackingUp { progress } => write!(f, "backing up ({}%)", progress),
+            AppState::Done => write!(f, "done"),
+            AppState::Error(msg) => write!(f, "error: {}", msg),
+        }
+    }
+}
+
+/// A request to the coordinator.
+enum Command {
+    Event(StateEvent),
+    Subscribe(Sender<AppState>),
+}
+
+/// The current state, written only by the coordinator.
+static CURRENT: RwLock<AppState> = RwLock::new(AppState::Idle);
+
+/// The channel of the coordinator, started by the first request.
+static COORDINATOR: OnceLock<Sender<Command>> = OnceLock::new();
+
+/// Returns the channel of the coordinator, starting its thread if needed.
+fn coordinator() -> &'static Sender<Command> {
+    COORDINATOR.get_or_init(|| {
+        let (tx, rx) = channel();
+        thread::spawn(move || run_coordinator(rx));
+        tx
+    })
+}
+
+/// Applies the events in the order they are received and sends every new state to the subscribers.
+fn run_coordinator(commands: Receiver<Command>) {
+    let mut subscribers: Vec<Sender<AppState>> = Vec::new();
+    for command in commands {
+        match command {
+            Command::Subscribe(subscriber) => {
+                if subscriber.send(current()).is_ok() {
+                    subscribers.push(subscriber);
+                }
+            }
+            Command::Event(event) => {
+                let next = current().next(&event);
+                match next {
+                    Some(state) if state != current() => {
+                        *CURRENT.write().unwrap() = state.clone();
+                        // The subscribers that are gone are forgotten
+                        subscribers.retain(|subscriber| subscriber.send(state.clone()).is_ok());
+                    }
+                    Some(_) => {}
+                    None => eprintln!("Event {:?} ignored in the state {}", event, current()),
+                }
+            }
+        }
+    }
+}
+
+/// Sends an event to the coordinator, which changes the state if the event is expected.
+pub fn send(event: StateEvent) {
+    let _ = coordinator().send(Command::Event(event));
+}
+
+/// Returns the current state.
+pub fn current() -> AppState {
+    CURRENT.read().unwrap().clone()
+}
+
+/// Returns a channel receiving the current state and then every change of state.
+pub fn subscribe() -> Receiver<AppState> {
+    let (tx, rx) = channel();
+    let _ = coordinator().send(Command::Subscribe(tx));
+    rx
+}
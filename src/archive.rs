@@ -0,0 +1,280 @@
+use crate::backup::{is_excluded, is_file_type_accepted, BackupOptions, BackupReport};
+use crate::filesystem::Filesystem;
+use async_recursion::async_recursion;
+use chrono::{DateTime, Datelike, Local, Timelike};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc;
+
+/// Bytes read from a source file at a time.
+const CHUNK: usize = 1024 * 1024;
+
+/// Chunks read ahead of the writer of the archive, which compresses them one at a time.
+const QUEUED_CHUNKS: usize = 16;
+
+/// Extension of the archive while it is being written: an interrupted backup never looks like a complete one.
+const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Extension of the file where each file is written before being added to the archive.
+const SPOOL_SUFFIX: &str = ".spool";
+
+/// The format of the archive written instead of the copies of the files (`archive` in the configuration).
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveFormat {
+    /// A zip file, opened by the file manager of every system
+    Zip,
+    /// A tar file compressed with gzip, which keeps the names as they are on every system
+    TarGz,
+}
+
+impl ArchiveFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+}
+
+/// What the copy sends to the writer of the archive.
+enum Message {
+    /// A new file: its path in the archive and when it was modified.
+    Start { name: String, modified: Option<SystemTime> },
+    Data(Vec<u8>),
+    /// The file has been read whole, or not (`false`) if reading it failed.
+    End(bool),
+}
+
+/// Adds the files to back up in a folder and in its subfolders to `files`.
+#[async_recursion]
+async fn list_files(filesystem: &dyn Filesystem, dir: &Path, type_files: &[String], exclude: &[String], files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut paths = filesystem.read_dir(dir).await?;
+    paths.retain(|path| !is_excluded(path, exclude));
+    paths.sort();
+    for path in paths {
+        if filesystem.is_dir(&path).await {
+            list_files(filesystem, &path, type_files, exclude, files).await?;
+        } else if type_files.is_empty() || is_file_type_accepted(&path, type_files) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the name of a file in the archive: its path relative to the source, with `/` between the folders.
+fn entry_name(source: &Path, path: &Path) -> String {
+    path.strip_prefix(source)
+        .unwrap_or(path)
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Writes the backup as a single compressed archive in the destination folder, instead of copying the files one
+/// by one: it takes much less space on a small drive. The files are read in order and streamed to the writer
+/// of the archive, which compresses them in a thread of its own while the next ones are read.
+///
+/// # Arguments
+///
+/// * `filesystem` - The file system of the source.
+/// * `source` - The folder to back up.
+/// * `destination` - The folder where the archive is written, named after the time of the backup.
+/// * `format` - The format of the archive.
+/// * `options` - The settings of the backup: the files are chosen by `type_files` and `exclude`, and with a
+///   `deadline` (`max_run_minutes`) the archive holds the files read until then.
+/// * `report` - Where the files archived, failed or left out are counted.
+/// * `progress` - An optional channel where every new percentage is sent.
+///
+/// # Returns
+///
+/// An `io::Result` containing the path of the archive, or an error if it cannot be written.
+pub async fn archive_backup(filesystem: &dyn Filesystem, source: &Path, destination: &Path, format: ArchiveFormat, options: &BackupOptions, report: &mut BackupReport, progress: Option<Sender<usize>>) -> io::Result<PathBuf> {
+    let started = Instant::now();
+    let mut files = Vec::new();
+    list_files(filesystem, source, &options.type_files, &options.exclude, &mut files).await?;
+
+    let name = format!("emergency_backup_{}.{}", Local::now().format("%Y-%m-%d_%H-%M-%S"), format.extension());
+    let archive_path = destination.join(&name);
+    let partial_path = destination.join(format!("{}{}", name, PARTIAL_SUFFIX));
+    let spool_path = destination.join(format!("{}{}", name, SPOOL_SUFFIX));
+    let file = File::create(&partial_path)?;
+    let (tx, rx) = mpsc::channel(QUEUED_CHUNKS);
+    let writer_spool = spool_path.clone();
+    let writer = tokio::task::spawn_blocking(move || match format {
+        ArchiveFormat::Zip => write_archive(zip::ZipWriter::new(file), &writer_spool, rx),
+        ArchiveFormat::TarGz => write_archive(tar::Builder::new(flate2::write::GzEncoder::new(file, flate2::Compression::default())), &writer_spool, rx),
+    });
+
+    let mut last_percent = 0;
+    for (index, path) in files.iter().enumerate() {
+        if options.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            report.unfinished = files.len() - index;
+            break;
+        }
+        let info = match filesystem.metadata(path).await {
+            Ok(info) => info,
+            Err(e) => {
+                report.add_failure(&e);
+                continue;
+            }
+        };
+        // Reading a cloud placeholder would download it
+        if info.placeholder {
+            report.placeholders += 1;
+            continue;
+        }
+        let start = Message::Start { name: entry_name(source, path), modified: info.modified };
+        if tx.send(start).await.is_err() {
+            break;
+        }
+        let read = stream_file(filesystem, path, &tx).await;
+        let ended = tx.send(Message::End(read.is_ok())).await;
+        match read {
+            Ok(len) => {
+                report.copied += 1;
+                report.copied_bytes += len;
+            }
+            Err(e) => {
+                eprintln!("Failed to archive {:?}: {}", path, e);
+                report.add_failure(&e);
+            }
+        }
+        // The writer has stopped: its error is returned below
+        if ended.is_err() {
+            break;
+        }
+        let percent = (index + 1) * 100 / files.len();
+        if percent > last_percent {
+            last_percent = percent;
+            if let Some(progress) = &progress {
+                let _ = progress.send(percent);
+            }
+        }
+    }
+    drop(tx);
+    let written = writer.await
+        .map_err(io::Error::other)
+        .and_then(|result| result)
+        .and_then(|_| fs::rename(&partial_path, &archive_path));
+    let _ = fs::remove_file(&spool_path);
+    if let Err(e) = written {
+        // An archive that could not be completed is useless: it would only take the space of the next one
+        let _ = fs::remove_file(&partial_path);
+        return Err(e);
+    }
+    report.copy_time = started.elapsed();
+    Ok(archive_path)
+}
+
+/// Reads a source file and sends its content to the writer of the archive.
+///
+/// # Returns
+///
+/// The bytes read, or the error that stopped the reading.
+async fn stream_file(filesystem: &dyn Filesystem, path: &Path, tx: &mpsc::Sender<Message>) -> io::Result<u64> {
+    let mut reader = filesystem.open_read(path).await?;
+    let mut total = 0;
+    loop {
+        let mut buffer = vec![0u8; CHUNK];
+        let read = reader.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(total);
+        }
+        buffer.truncate(read);
+        total += read as u64;
+        if tx.send(Message::Data(buffer)).await.is_err() {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "the archive is no longer written"));
+        }
+    }
+}
+
+fn zip_error(e: zip::result::ZipError) -> io::Error {
+    io::Error::other(e)
+}
+
+/// The writer of an archive, which adds the files read whole.
+trait ArchiveWriter {
+    /// Adds a file to the archive.
+    fn append(&mut self, name: &str, size: u64, modified: Option<SystemTime>, content: &mut dyn Read) -> io::Result<()>;
+
+    /// Writes the end of the archive.
+    fn finish(self) -> io::Result<()>;
+}
+
+impl ArchiveWriter for zip::ZipWriter<File> {
+    fn append(&mut self, name: &str, size: u64, modified: Option<SystemTime>, content: &mut dyn Read) -> io::Result<()> {
+        let mut options = zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .large_file(size >= u32::MAX as u64);
+        if let Some(time) = modified.and_then(zip_time) {
+            options = options.last_modified_time(time);
+        }
+        self.start_file(name, options).map_err(zip_error)?;
+        io::copy(content, self)?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        zip::ZipWriter::finish(&mut self).map_err(zip_error)?;
+        Ok(())
+    }
+}
+
+impl ArchiveWriter for tar::Builder<flate2::write::GzEncoder<File>> {
+    fn append(&mut self, name: &str, size: u64, modified: Option<SystemTime>, content: &mut dyn Read) -> io::Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(size);
+        header.set_mode(0o644);
+        header.set_mtime(modified.and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map_or(0, |since| since.as_secs()));
+        self.append_data(&mut header, name, content)
+    }
+
+    fn finish(self) -> io::Result<()> {
+        self.into_inner()?.finish()?;
+        Ok(())
+    }
+}
+
+/// Converts a modification time to the format of zip (local time, from 1980).
+fn zip_time(time: SystemTime) -> Option<zip::DateTime> {
+    let time: DateTime<Local> = time.into();
+    zip::DateTime::from_date_and_time(
+        time.year().try_into().ok()?, time.month() as u8, time.day() as u8,
+        time.hour() as u8, time.minute() as u8, time.second() as u8,
+    ).ok()
+}
+
+/// Writes the files received in the archive. Each file is first written in a spool file and added to the archive
+/// only once it has been read whole, with its real size: a file that could not be read whole (or that has
+/// changed size while it was read) is left out, instead of being stored cut or filled with zeros.
+fn write_archive(mut archive: impl ArchiveWriter, spool_path: &Path, mut rx: mpsc::Receiver<Message>) -> io::Result<()> {
+    let mut spool = File::options().read(true).write(true).create(true).truncate(true).open(spool_path)?;
+    let mut current: Option<(String, Option<SystemTime>)> = None;
+    while let Some(message) = rx.blocking_recv() {
+        match message {
+            Message::Start { name, modified } => {
+                spool.set_len(0)?;
+                spool.seek(SeekFrom::Start(0))?;
+                current = Some((name, modified));
+            }
+            Message::Data(data) => spool.write_all(&data)?,
+            Message::End(read_whole) => {
+                let Some((name, modified)) = current.take() else { continue };
+                if !read_whole {
+                    continue;
+                }
+                let size = spool.stream_position()?;
+                spool.seek(SeekFrom::Start(0))?;
+                archive.append(&name, size, modified, &mut (&mut spool).take(size))?;
+            }
+        }
+    }
+    archive.finish()
+}
@@ -1,13 +1,361 @@
 extern crate libc;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
 use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use async_recursion::async_recursion;
+use serde::{Deserialize, Serialize};
+use crate::app_hooks;
+use crate::archive;
 use crate::config::Config;
-use crate::notification_popup::{show_popup, NotificationType};
+use crate::elevation::{self, Elevation};
+use crate::encryption::{self, FileEncryptor};
+use crate::events::{self, ActivationPattern, AppEvent};
+use crate::file_log::{FileLog, FILE_LOG_NAME};
+use crate::filesystem::{self, DiskFilesystem, Filesystem, ProgressFilesystem};
+use crate::hash_cache::HashCache;
+use crate::input_hook;
+use crate::locale;
+use crate::manifest::{self, Manifest, RunTotals};
+use crate::memory::MemoryBudget;
+use crate::network;
+use crate::power;
+use crate::ransomware::{self, Suspicion};
+use crate::read_ahead::{ReadAhead, ReadAheadFilesystem};
+use crate::resume::ProgressJournal;
+use crate::snapshot::SnapshotInfo;
+use crate::tuning::{self, AutoTuner, TuningResult};
+use crate::uring;
+use crate::volume;
 use tokio::fs::{self, File};
-use tokio::io::{self, AsyncWriteExt, BufReader, BufWriter};
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::runtime;
 use tokio::sync::Semaphore;
 use tokio::task::JoinHandle;
+use tracing::Instrument;
+
+/// Summary of a backup operation: how many files were found, copied, skipped or failed
+/// and the categories of the errors that occurred.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct BackupReport {
+	pub total_files: usize,
+	pub total_size: u64,
+	pub copied: usize,
+	/// Bytes written in the main destination, and how long the copies took, to measure its speed.
+	pub copied_bytes: u64,
+	pub copy_time: Duration,
+	pub skipped: usize,
+	pub failed: usize,
+	/// Number of failures for each error category (the `io::ErrorKind` of the failure).
+	pub errors: HashMap<String, usize>,
+	/// The copies made in the secondary destination, if configured.
+	pub secondary: Option<ReplicaReport>,
+	/// The result of reading back the copied files, if enabled.
+	pub verification: Option<VerificationReport>,
+	/// How many files already existed in the destination, and what was done with them.
+	pub conflicts: ConflictCounts,
+	/// Source files removed after their copy was verified (evacuation mode).
+	pub evacuated: usize,
+	/// Source files kept because their copy could not be verified or they could not be removed (evacuation mode).
+	pub evacuation_failed: usize,
+	/// Files stored with another name because the destination file system cannot represent the original one.
+	pub sanitized: usize,
+	/// Files not copied again because an interrupted run of the same backup had already copied them.
+	pub resumed: usize,
+	/// Cloud placeholders (files whose content is only online) skipped or stored as empty stubs.
+	pub placeholders: usize,
+	/// Files whose copy stopped making progress and was canceled, even after a second attempt.
+	pub stalled: Vec<PathBuf>,
+	/// Source files that could not be read for missing privileges, to copy them again elevated.
+	#[serde(skip)]
+	pub denied: Vec<PathBuf>,
+	/// The source files copied (or found already copied) by this backup, with the path written in the main destination,
+	/// to read them back: the skipped and the kept files are not among them.
+	#[serde(skip)]
+	pub copies: Vec<(PathBuf, PathBuf)>,
+	/// Files modified while they were copied, whose copy may be internally inconsistent ("fuzzy").
+	pub fuzzy: Vec<PathBuf>,
+	/// Files flushed by their application before the copy (e.g. the SQLite databases checkpointed).
+	pub app_flushed: usize,
+	/// Files not copied because the maximum duration of the backup was reached, left to the next backup.
+	pub unfinished: usize,
+	/// Groups of source files with the same content, if they were looked for.
+	pub duplicates: Vec<DuplicateGroup>,
+	/// The settings found by the tuning of the copies, if enabled.
+	pub tuning: Option<TuningResult>,
+	/// Far fewer files (or bytes) found in the source than by the previous backup, if so.
+	pub drift: Option<Drift>,
+	/// The signs of a ransomware found among the changed files, if any: nothing has been overwritten or removed.
+	pub suspicion: Option<Suspicion>,
+	/// How long each phase of the backup took, in the order they ran.
+	pub phases: Vec<(Phase, Duration)>,
+}
+
+/// The phases of a backup, timed in the report and traced as `backup_phase` spans.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+	/// Counting the files of the source and their size.
+	Counting,
+	/// Listing the copies to make, and checking the changes of the source for a ransomware.
+	Scheduling,
+	Copying,
+	/// Hashing the files: the duplicates, and the copies read back by the verification.
+	Hashing,
+	/// Writing the manifest and the progress of the backup in the destinations.
+	Finalization,
+}
+
+impl Phase {
+	pub fn name(&self) -> &'static str {
+		match self {
+			Phase::Counting => "counting",
+			Phase::Scheduling => "scheduling",
+			Phase::Copying => "copying",
+			Phase::Hashing => "hashing",
+			Phase::Finalization => "finalization",
+		}
+	}
+}
+
+/// A phase of the backup in progress: its tracing span, closed with the duration of the phase when it ends.
+/// The work of the phase runs inside the span: the futures through `in_span`, the code that does not wait
+/// through `enter` (a span entered across an `.await` would also cover the other tasks run meanwhile).
+struct PhaseTimer {
+	phase: Phase,
+	started: Instant,
+	span: tracing::Span,
+}
+
+impl PhaseTimer {
+	fn start(phase: Phase) -> PhaseTimer {
+		let span = tracing::info_span!("backup_phase", phase = phase.name(), elapsed_ms = tracing::field::Empty);
+		tracing::debug!(parent: &span, "phase started");
+		PhaseTimer { phase, started: Instant::now(), span }
+	}
+
+	/// Makes the span of the phase the current one, until the guard returned is dropped.
+	fn enter(&self) -> tracing::span::Entered<'_> {
+		self.span.enter()
+	}
+
+	/// Runs a future of the phase (e.g. a copy spawned during it) inside the span of the phase.
+	fn in_span<F: std::future::Future>(&self, future: F) -> tracing::instrument::Instrumented<F> {
+		future.instrument(self.span.clone())
+	}
+
+	/// Ends the phase, adding its duration to the report.
+	fn end(self, report: &mut BackupReport) {
+		let elapsed = self.started.elapsed();
+		self.span.record("elapsed_ms", elapsed.as_millis() as u64);
+		tracing::info!(parent: &self.span, elapsed_ms = elapsed.as_millis() as u64, "phase ended");
+		report.add_phase(self.phase, elapsed);
+	}
+}
+
+/// The source has shrunk since the previous backup beyond `drift_alarm_percent`: maybe its files have been
+/// encrypted or deleted, or a filter excludes far more than intended.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Drift {
+	pub previous: RunTotals,
+	pub current: RunTotals,
+}
+
+impl Drift {
+	/// Compares the files found by a backup with the ones found by the previous one.
+	///
+	/// # Arguments
+	///
+	/// * `previous` - The files found by the previous backup.
+	/// * `current` - The files found by this backup.
+	/// * `alarm_percent` - The largest decrease allowed, in percent, of the number of files or of their size.
+	///
+	/// # Returns
+	///
+	/// The drift, if the number of files or their size has decreased by more than `alarm_percent`.
+	pub fn check(previous: RunTotals, current: RunTotals, alarm_percent: u8) -> Option<Drift> {
+		let shrunk = |before: u64, now: u64| now.saturating_mul(100) < before.saturating_mul(100 - alarm_percent.min(100) as u64);
+		(alarm_percent > 0 && (shrunk(previous.files as u64, current.files as u64) || shrunk(previous.size, current.size)))
+			.then_some(Drift { previous, current })
+	}
+
+	/// Returns the warning shown to the user.
+	pub fn message(&self) -> String {
+		format!(
+			"WARNING: the source has shrunk since the previous backup: {} files ({}) instead of {} ({}). Check that its files have not been encrypted or deleted, and that the filters are right.",
+			self.current.files, bytes_to_human_readable(self.current.size), self.previous.files, bytes_to_human_readable(self.previous.size)
+		)
+	}
+}
+
+/// Source files with the same content.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct DuplicateGroup {
+	/// Size of each file.
+	pub size: u64,
+	/// BLAKE3 hash of the content, in hexadecimal.
+	pub hash: String,
+	pub paths: Vec<PathBuf>,
+}
+
+impl DuplicateGroup {
+	/// Returns the space taken by the copies beyond the first one.
+	pub fn wasted(&self) -> u64 {
+		self.size * (self.paths.len() as u64 - 1)
+	}
+}
+
+/// What to do with the cloud placeholders (e.g. OneDrive "online-only" files), whose content is downloaded when read.
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaceholderPolicy {
+	/// The placeholder is not copied (the default), so the backup never starts a download
+	#[default]
+	Skip,
+	/// The content is downloaded and copied like any other file
+	Hydrate,
+	/// An empty file with the same name is created in the destination and recorded in the manifest
+	Stub,
+}
+
+/// What to do when a file already exists in the destination.
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+	/// The existing file is replaced (the default)
+	#[default]
+	Overwrite,
+	/// The existing file is kept and the source file is not copied
+	Skip,
+	/// The existing file is kept and the source file is copied with a new name, e.g. `report (1).pdf`
+	Rename,
+	/// The existing file is replaced only if the source file has been modified after it
+	NewerWins,
+}
+
+/// Number of files that already existed in the destination, by what was done with them.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ConflictCounts {
+	pub overwritten: usize,
+	pub renamed: usize,
+	pub skipped: usize,
+}
+
+impl ConflictCounts {
+	/// Total number of files that already existed in the destination.
+	pub fn total(&self) -> usize {
+		self.overwritten + self.renamed + self.skipped
+	}
+
+	/// Records how a conflict has been resolved.
+	fn record(&mut self, resolution: &Resolution) {
+		match resolution {
+			Resolution::NoConflict => {}
+			Resolution::Overwritten => self.overwritten += 1,
+			Resolution::Renamed(_) => self.renamed += 1,
+			Resolution::Skipped => self.skipped += 1,
+		}
+	}
+}
+
+/// How the copy of a file has been adapted to an existing file in the destination.
+enum Resolution {
+	/// The destination file does not exist
+	NoConflict,
+	/// The destination file is replaced
+	Overwritten,
+	/// The file is copied with this new name
+	Renamed(PathBuf),
+	/// The file is not copied
+	Skipped,
+}
+
+/// Which copied files are read back after the backup.
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerifyMode {
+	/// No file is read back (the default)
+	#[default]
+	Off,
+	/// A sample of files, spread over the whole backup
+	Sample,
+	/// Every file
+	All,
+}
+
+/// Result of reading back the copied files.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct VerificationReport {
+	/// Files read back.
+	pub checked: usize,
+	/// Files whose size or content differs from the source.
+	pub mismatched: Vec<PathBuf>,
+	/// Files that cannot be read back.
+	pub unreadable: Vec<PathBuf>,
+}
+
+impl VerificationReport {
+	/// Returns whether every file read back matches its source.
+	pub fn is_ok(&self) -> bool {
+		self.mismatched.is_empty() && self.unreadable.is_empty()
+	}
+}
+
+/// Summary of the copies made in the secondary destination, tracked independently of the main one.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ReplicaReport {
+	pub path: PathBuf,
+	pub copied: usize,
+	pub failed: usize,
+	/// Number of failures for each error category (the `io::ErrorKind` of the failure).
+	pub errors: HashMap<String, usize>,
+}
+
+impl ReplicaReport {
+	/// Records a failed copy under the category of its error.
+	pub fn add_failure(&mut self, error: &io::Error) {
+		self.failed += 1;
+		*self.errors.entry(format!("{:?}", error.kind())).or_insert(0) += 1;
+	}
+
+	/// Returns whether every file has been copied in this destination.
+	pub fn is_complete(&self) -> bool {
+		self.failed == 0
+	}
+}
+
+impl BackupReport {
+	/// Records a failed copy under the category of its error.
+	pub fn add_failure(&mut self, error: &io::Error) {
+		self.failed += 1;
+		*self.errors.entry(format!("{:?}", error.kind())).or_insert(0) += 1;
+	}
+
+	/// Adds the duration of a phase, to the previous one if the phase has already run (e.g. the hashing of
+	/// the duplicates and of the verification).
+	pub fn add_phase(&mut self, phase: Phase, duration: Duration) {
+		match self.phases.iter_mut().find(|(existing, _)| *existing == phase) {
+			Some((_, total)) => *total += duration,
+			None => self.phases.push((phase, duration)),
+		}
+	}
+
+	/// Returns the space that removing the duplicate files would save.
+	pub fn duplicate_savings(&self) -> u64 {
+		self.duplicates.iter().map(DuplicateGroup::wasted).sum()
+	}
+
+	/// Returns the `n` most frequent error categories, sorted by number of occurrences.
+	pub fn top_errors(&self, n: usize) -> Vec<(String, usize)> {
+		let mut errors: Vec<(String, usize)> = self.errors.iter().map(|(k, v)| (k.clone(), *v)).collect();
+		errors.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+		errors.truncate(n);
+		errors
+	}
+}
 
 #[cfg(target_os = "windows")]
 pub fn get_max_open_files() -> usize {
@@ -30,36 +378,143 @@ pub fn get_max_open_files() -> usize {
 	550
 }
 
+/// Throughput assumed to estimate the duration of a backup (bytes per second),
+/// a conservative value for a USB drive.
+const ESTIMATED_THROUGHPUT: u64 = 30 * 1024 * 1024;
+
+/// Estimates, in a separate thread, the number of files, the size and the duration of the backup,
+/// and shows them in the notification of the first step as soon as they are known
+/// (if the user has not confirmed or canceled the backup in the meantime).
+///
+/// # Arguments
+///
+/// * `config` - The configuration of the backup to estimate.
+/// * `pattern` - The activation pattern whose first step has been done.
+pub fn estimate_backup_in_background(config: Config, pattern: ActivationPattern) {
+	thread::spawn(move || {
+		let rt = match runtime::Runtime::new() {
+			Ok(rt) => rt,
+			Err(e) => {
+				println!("Unable to estimate the backup: {}", e);
+				return;
+			}
+		};
+		match rt.block_on(calculate_total_files(&DiskFilesystem, config.path_orig_backup.as_path(), &config.type_files, &config.exclude)) {
+			Ok((total_files, total_size)) => {
+				let mut text = estimate_text(total_files, total_size);
+				if let Some(excess) = size_excess(&config, total_files, total_size) {
+					text.push_str(&format!("\n{}: the pattern will have to be repeated to start the backup", excess));
+				}
+				events::publish(AppEvent::Estimated(pattern, text));
+			}
+			Err(e) => println!("Unable to estimate the backup: {}", e),
+		}
+	});
+}
+
+/// Describes the estimate of a backup, e.g. "≈ 12,400 files, 8.30 GB, about 6 minutes".
+fn estimate_text(total_files: usize, total_size: u64) -> String {
+	let minutes = (total_size / ESTIMATED_THROUGHPUT).div_ceil(60);
+	let duration = match minutes {
+		0 | 1 => "less than a minute".to_string(),
+		_ => format!("about {} minutes", minutes),
+	};
+	format!("≈ {} files, {}, {}", locale::format_count(total_files as u64), bytes_to_human_readable(total_size), duration)
+}
+
+/// Describes how much the source exceeds the size expected in the configuration (`max_expected_files`
+/// and `max_expected_size_gb`), e.g. because a recursive link multiplied its files.
+///
+/// # Arguments
+///
+/// * `config` - The configuration with the expected size.
+/// * `total_files` - The number of files found in the source.
+/// * `total_size` - The size of the files found in the source.
+///
+/// # Returns
+///
+/// A message for the user, or `None` if the source is within the expectations.
+pub fn size_excess(config: &Config, total_files: usize, total_size: u64) -> Option<String> {
+	let mut excesses = Vec::new();
+	if let Some(max_files) = config.max_expected_files {
+		if total_files > max_files {
+			excesses.push(format!("{} files (expected at most {})", locale::format_count(total_files as u64), locale::format_count(max_files as u64)));
+		}
+	}
+	if let Some(max_size_gb) = config.max_expected_size_gb {
+		let max_size = (max_size_gb * (1024 * 1024 * 1024) as f64) as u64;
+		if total_size > max_size {
+			excesses.push(format!("{} (expected at most {})", bytes_to_human_readable(total_size), bytes_to_human_readable(max_size)));
+		}
+	}
+	if excesses.is_empty() {
+		None
+	} else {
+		Some(format!("The source is larger than expected: {}", excesses.join(", ")))
+	}
+}
+
+/// Scans the source and checks it against the size expected in the configuration (see `size_excess`).
+/// If the source cannot be scanned, the backup reports the error itself.
+pub async fn check_expected_size(config: &Config) -> Option<String> {
+	if config.max_expected_files.is_none() && config.max_expected_size_gb.is_none() {
+		return None;
+	}
+	match calculate_total_files(&DiskFilesystem, config.path_orig_backup.as_path(), &config.type_files, &config.exclude).await {
+		Ok((total_files, total_size)) => size_excess(config, total_files, total_size),
+		Err(_) => None,
+	}
+}
+
+/// Converts a size in bytes to a human-readable string format.
+///
+/// This function takes a size in bytes and converts it to a more readable format,
+/// such as KB, MB, or GB, depending on the size, with the separators of the locale (see `locale`).
+///
+/// # Arguments
+///
+/// * `bytes` - The size in bytes to be converted.
+///
+/// # Returns
+///
+/// A `String` representing the size in a human-readable format.
+pub fn bytes_to_human_readable(bytes: u64) -> String {
+	locale::format_size(bytes)
+}
+
 
 /// Calculates the total number of files and their cumulative size within a given path,
 /// recursively considering only the files of specified types as defined in config.yaml, if set; otherwise, all files are considered.
 ///
 /// # Arguments
 ///
+/// * `filesystem` - The file system where the files are.
 /// * `source` - A reference to the path to scan.
 /// * `type_files` - A vector of strings representing the file types to include in the count.
+/// * `exclude` - A vector of name patterns of files and directories to leave out.
 ///
 /// # Returns
 ///
 /// * An `io::Result` containing a tuple of the total file count and cumulative file size, or an error if the operation fails.
 #[async_recursion]
-pub async fn calculate_total_files(source: &Path, type_files: &Vec<String>) -> io::Result<(usize, u64)> {
+pub async fn calculate_total_files(filesystem: &dyn Filesystem, source: &Path, type_files: &Vec<String>, exclude: &Vec<String>) -> io::Result<(usize, u64)> {
 	let mut count = 0;
 	let mut total_size = 0u64;
 
-	if source.is_dir() {
-		let mut entries = fs::read_dir(source).await?;
-		while let Some(entry) = entries.next_entry().await? {
-			let path = entry.path();
-			if path.is_dir() {
+	if filesystem.is_dir(source).await {
+		for path in filesystem.read_dir(source).await? {
+			if is_excluded(&path, exclude) {
+				continue;
+			}
+			if filesystem.is_dir(&path).await {
 				//Box::pin is used to prevent asynchronous functions from moving in the heap during recursive operations.
-				let (inner_count, inner_size) = Box::pin(calculate_total_files(&path, type_files)).await?;
+				let (inner_count, inner_size) = Box::pin(calculate_total_files(filesystem, &path, type_files, exclude)).await?;
 				count += inner_count;
 				total_size += inner_size;
 			} else {
 				if type_files.is_empty() || is_file_type_accepted(&path, type_files) {
 					count += 1;
-					total_size += fs::metadata(&path).await?.len();
+					total_size += filesystem.metadata(&path).await?.len;
 				}
 			}
 		}
@@ -68,31 +523,211 @@ pub async fn calculate_total_files(source: &Path, type_files: &Vec<String>) -> i
 	Ok((count, total_size))
 }
 
+/// Files and size of a folder or of an extension, in the breakdown of the source.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SizeEntry {
+	/// The path of the folder relative to the source, or the extension (e.g. `.mp4`, empty for the files without one).
+	pub name: String,
+	pub files: usize,
+	pub size: u64,
+}
+
+/// What a backup would copy with the current filters, to find what makes it large before running it.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SourceBreakdown {
+	pub total_files: usize,
+	pub total_size: u64,
+	/// Every folder containing files to back up, with the files of its subfolders, the largest first.
+	pub directories: Vec<SizeEntry>,
+	/// Every extension of the files to back up, the largest first.
+	pub extensions: Vec<SizeEntry>,
+}
+
+/// Walks the source as the backup would (with the same types and exclusions) and sums the files and sizes
+/// by folder and by extension, like `du`.
+///
+/// # Arguments
+///
+/// * `filesystem` - The file system where the files are.
+/// * `source` - The folder to back up.
+/// * `type_files` - The file types to back up, all if empty.
+/// * `exclude` - The name patterns of the files and directories to leave out.
+///
+/// # Returns
+///
+/// * An `io::Result` containing the breakdown of the source, or an error if a folder cannot be read.
+pub async fn analyze_source(filesystem: &dyn Filesystem, source: &Path, type_files: &Vec<String>, exclude: &Vec<String>) -> io::Result<SourceBreakdown> {
+	let mut directories = HashMap::new();
+	let mut extensions = HashMap::new();
+	let (total_files, total_size) = sum_sizes(filesystem, source, source, type_files, exclude, &mut directories, &mut extensions).await?;
+	let sorted = |entries: HashMap<String, (usize, u64)>| {
+		let mut entries: Vec<SizeEntry> = entries
+			.into_iter()
+			.map(|(name, (files, size))| SizeEntry { name, files, size })
+			.collect();
+		entries.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)));
+		entries
+	};
+	Ok(SourceBreakdown { total_files, total_size, directories: sorted(directories), extensions: sorted(extensions) })
+}
+
+/// Sums the files to back up in a folder and in its subfolders, recording the totals of each subfolder and extension.
+#[async_recursion]
+async fn sum_sizes(filesystem: &dyn Filesystem, source: &Path, dir: &Path, type_files: &Vec<String>, exclude: &Vec<String>, directories: &mut HashMap<String, (usize, u64)>, extensions: &mut HashMap<String, (usize, u64)>) -> io::Result<(usize, u64)> {
+	let (mut count, mut total_size) = (0, 0u64);
+	for path in filesystem.read_dir(dir).await? {
+		if is_excluded(&path, exclude) {
+			continue;
+		}
+		if filesystem.is_dir(&path).await {
+			let (inner_count, inner_size) = sum_sizes(filesystem, source, &path, type_files, exclude, directories, extensions).await?;
+			count += inner_count;
+			total_size += inner_size;
+		} else if type_files.is_empty() || is_file_type_accepted(&path, type_files) {
+			let size = filesystem.metadata(&path).await?.len;
+			let extension = path.extension().map(|ext| format!(".{}", ext.to_string_lossy().to_lowercase())).unwrap_or_default();
+			let entry = extensions.entry(extension).or_default();
+			entry.0 += 1;
+			entry.1 += size;
+			count += 1;
+			total_size += size;
+		}
+	}
+	// The source itself is the total; the folders without files to back up are not listed
+	if dir != source && count > 0 {
+		let name = dir.strip_prefix(source).unwrap_or(dir).to_string_lossy().into_owned();
+		directories.insert(name, (count, total_size));
+	}
+	Ok((count, total_size))
+}
+
+/// The files written by a backup in the main and in the secondary destination (relative paths).
+#[derive(Default)]
+struct StoredFiles {
+	main: BTreeSet<String>,
+	secondary: BTreeSet<String>,
+}
+
+/// The settings of a backup, taken from the configuration (see `wrapper_backup`).
+#[derive(Clone)]
+pub struct BackupOptions {
+	/// The file types to back up (e.g. ".txt"), all if empty.
+	pub type_files: Vec<String>,
+	/// The name patterns of the files and folders to leave out.
+	pub exclude: Vec<String>,
+	/// Whether the progress is printed on the console.
+	pub verbose: bool,
+	/// The number of files to copy, to compute the progress.
+	pub total_files: usize,
+	/// The maximum number of files copied at the same time.
+	pub max_file_opened: usize,
+	/// A second destination where every file is copied as well.
+	pub secondary_destination: Option<PathBuf>,
+	/// Whether the folders without files to copy are created in the destination as well.
+	pub preserve_empty_dirs: bool,
+	/// What to do with the files that already exist in the destination.
+	pub on_conflict: ConflictPolicy,
+	/// Whether each source file is removed once its copy has been verified.
+	pub evacuate: bool,
+	/// Whether the names that the destination file system cannot store are replaced, recording the original
+	/// ones in the manifest of the destination.
+	pub sanitize_names: bool,
+	/// What to do with the cloud placeholders, whose content would be downloaded by the copy.
+	pub placeholders: PlaceholderPolicy,
+	/// How long a copy can go without reading any data before it is canceled and tried again.
+	pub stall_timeout: Duration,
+	/// Whether the files modified while they were copied are copied once more.
+	pub recopy_changed: bool,
+	/// The moment after which no more files are copied: the copies in progress are finished and the progress
+	/// journal is kept, so that the next backup resumes from there.
+	pub deadline: Option<Instant>,
+	/// A log where every copied file is recorded with the duration of its copy.
+	pub file_log: Option<Arc<FileLog>>,
+	/// Whether the source files with the same content are looked for and added to the report.
+	pub find_duplicates: bool,
+	/// Whether the parallel copies (up to `max_file_opened`) and their buffers are adjusted to the fastest settings.
+	pub auto_tune: bool,
+	/// The maximum of the memory used by the copies in progress, in bytes.
+	pub memory_cap: Option<u64>,
+	/// The memory used to read the source files ahead in order (for a rotational source), in bytes.
+	pub read_ahead: Option<u64>,
+	/// The only source files to copy (e.g. the ones denied to a previous backup), all if `None`.
+	pub only_files: Option<Arc<HashSet<PathBuf>>>,
+}
+
+impl Default for BackupOptions {
+	fn default() -> Self {
+		BackupOptions {
+			type_files: Vec::new(),
+			exclude: Vec::new(),
+			verbose: false,
+			total_files: 0,
+			max_file_opened: get_max_open_files(),
+			secondary_destination: None,
+			preserve_empty_dirs: false,
+			on_conflict: ConflictPolicy::default(),
+			evacuate: false,
+			sanitize_names: false,
+			placeholders: PlaceholderPolicy::default(),
+			stall_timeout: Duration::from_secs(120),
+			recopy_changed: false,
+			deadline: None,
+			file_log: None,
+			find_duplicates: false,
+			auto_tune: false,
+			memory_cap: None,
+			read_ahead: None,
+			only_files: None,
+		}
+	}
+}
+
+impl BackupOptions {
+	/// Returns whether a file is of a type to back up.
+	pub fn accepts(&self, path: &Path) -> bool {
+		(self.type_files.is_empty() || is_file_type_accepted(path, &self.type_files))
+			&& self.only_files.as_ref().is_none_or(|only| only.contains(path))
+	}
+}
+
 /// Schedules backup tasks for each file and directory within a given source directory.
 /// It recursively identifies all files and directories to be backed up and adds them to a task list.
+/// The destination folders are created only for the files to back up, unless `preserve_empty_dirs` is set.
+/// With `sanitize_names`, the names that a FAT or exFAT destination cannot store are replaced (see `sanitize_name`).
 ///
 /// # Arguments
 ///
+/// * `filesystem` - The file system where the files are.
 /// * `source` - A reference to the path of the directory where files are sourced.
 /// * `destination` - A reference to the path where files will be backed up.
-/// * `type_files` - A vector of strings representing the file types to include in the backup.
+/// * `options` - The settings of the backup: `type_files`, `exclude`, `preserve_empty_dirs` and `sanitize_names` are used.
 /// * `tasks` - A mutable reference to a vector that will store the paths of source files and their corresponding backup destinations.
 ///
 /// # Returns
 ///
 /// * An `io::Result<()>` indicating success or failure of the task scheduling.
 #[async_recursion]
-async fn schedule_backup_tasks(source: &Path, destination: &Path, type_files: &Vec<String>, tasks: &mut Vec<(PathBuf, PathBuf)>) -> io::Result<()> {
-	if source.is_dir() {
-		fs::create_dir_all(destination).await?;
-
-		let mut entries = fs::read_dir(source).await?;
-		while let Some(entry) = entries.next_entry().await? {
-			let path = entry.path();
-			let new_destination = destination.join(path.file_name().unwrap());
-			if path.is_dir() {
-				Box::pin(schedule_backup_tasks(&path, &new_destination, type_files, tasks)).await?;
+async fn schedule_backup_tasks(filesystem: &dyn Filesystem, source: &Path, destination: &Path, options: &BackupOptions, tasks: &mut Vec<(PathBuf, PathBuf)>) -> io::Result<()> {
+	if filesystem.is_dir(source).await {
+		let mut destination_created = false;
+		if options.preserve_empty_dirs {
+			filesystem.create_dir_all(destination).await?;
+			destination_created = true;
+		}
+
+		let mut paths = filesystem.read_dir(source).await?;
+		paths.retain(|path| !is_excluded(path, &options.exclude));
+
+		let names = destination_names(&paths, options.sanitize_names);
+		for (path, name) in paths.into_iter().zip(names) {
+			let new_destination = destination.join(name);
+			if filesystem.is_dir(&path).await {
+				Box::pin(schedule_backup_tasks(filesystem, &path, &new_destination, options, tasks)).await?;
 			} else {
+				if !destination_created && options.accepts(&path) {
+					filesystem.create_dir_all(destination).await?;
+					destination_created = true;
+				}
 				tasks.push((path, new_destination));
 			}
 		}
@@ -105,32 +740,172 @@ async fn schedule_backup_tasks(source: &Path, destination: &Path, type_files: &V
 ///
 /// # Arguments
 ///
+/// * `filesystem` - The file system where the files are read and written (the disk, or a `MemoryFilesystem`).
 /// * `source` - A reference to the source directory path.
 /// * `destination` - A reference to the destination directory path.
-/// * `type_files` - A vector of strings detailing which file types should be backed up.
-/// * `verbose` - A boolean flag to enable verbose progress output.
-/// * `total_files` - The total number of files expected to be processed for backup.
-/// * `copied_files` - An atomic reference to the count of files successfully copied.
-/// * `last_printed_percent` - An atomic reference to the last printed percentage of progress.
-/// * `max_file_opened` - The maximum number of file handles that can be opened concurrently during the backup.
+/// * `options` - The settings of the backup (see `BackupOptions`).
+/// * `report` - A shared reference to the report where copied, skipped and failed files are counted.
+/// * `progress` - An optional channel where every new percentage of progress is sent.
 ///
 /// # Returns
 ///
 /// * An `io::Result<()>` indicating the success or failure of the backup operation.
-pub async fn backup(source: &Path, destination: &Path, type_files: &Vec<String>, verbose: bool, total_files: usize, copied_files: Arc<Mutex<usize>>, last_printed_percent: Arc<Mutex<usize>>, max_file_opened: usize) -> io::Result<()> {
+pub async fn backup(filesystem: Arc<dyn Filesystem>, source: &Path, destination: &Path, options: &BackupOptions, report: Arc<Mutex<BackupReport>>, progress: Option<Sender<usize>>) -> io::Result<()> {
+	let BackupOptions { verbose, total_files, max_file_opened, on_conflict, evacuate, placeholders, stall_timeout, recopy_changed, deadline, find_duplicates, auto_tune, memory_cap, read_ahead, .. } = *options;
+	let type_files = &options.type_files;
+	let secondary_destination = options.secondary_destination.as_deref();
+	let file_log = options.file_log.clone();
+	let copied_files = Arc::new(Mutex::new(0));
+	let last_printed_percent = Arc::new(Mutex::new(0));
+	let scheduling = PhaseTimer::start(Phase::Scheduling);
 	let mut tasks: Vec<(PathBuf, PathBuf)> = Vec::new();
 
 	// Fill the task list by calling a recursive function to identify files and directories for backup. Each with original path and destination path.
-	schedule_backup_tasks(source, destination, type_files, &mut tasks).await?;
+	scheduling.in_span(schedule_backup_tasks(&*filesystem, source, destination, options, &mut tasks)).await?;
+	if let Some(only) = &options.only_files {
+		tasks.retain(|(path, _)| only.contains(path));
+	}
+
+	// The files stored with another name, to record their original one in the manifest
+	let renamed: BTreeMap<String, String> = tasks
+		.iter()
+		.filter(|(path, _)| type_files.is_empty() || is_file_type_accepted(path, type_files))
+		.filter_map(|(path, dest_path)| {
+			let original = path.strip_prefix(source).ok()?;
+			let stored = dest_path.strip_prefix(destination).ok()?;
+			(original != stored).then(|| (stored.to_string_lossy().into_owned(), original.to_string_lossy().into_owned()))
+		})
+		.collect();
+	report.lock().unwrap().sanitized = renamed.len();
+
+	// The source may have been encrypted by a ransomware: the copies in the destinations are not overwritten
+	// and the source files are not removed, so that the last clean copy survives
+	let suspicion = scheduling.in_span(ransomware::inspect(&*filesystem, tasks.iter().filter(|(path, _)| type_files.is_empty() || is_file_type_accepted(path, type_files)))).await;
+	let keep_existing = suspicion.is_some();
+	let (on_conflict, evacuate) = match (&suspicion, on_conflict) {
+		(Some(_), ConflictPolicy::Overwrite | ConflictPolicy::NewerWins) => (ConflictPolicy::Rename, false),
+		(Some(_), on_conflict) => (on_conflict, false),
+		(None, on_conflict) => (on_conflict, evacuate),
+	};
+	if let Some(suspicion) = &suspicion {
+		events::publish(AppEvent::Error(suspicion.message()));
+	}
+	report.lock().unwrap().suspicion = suspicion;
+
+	// Files already copied by an interrupted run of the same backup (e.g. the process was killed)
+	let already_copied = ProgressJournal::load(destination)
+		.filter(|journal| journal.source == source)
+		.map(|journal| journal.completed_files())
+		.unwrap_or_default();
+	let copy_tasks: Vec<PathBuf> = tasks
+		.iter()
+		.filter(|(path, _)| type_files.is_empty() || is_file_type_accepted(path, type_files))
+		.map(|(path, _)| path.clone())
+		.collect();
+	// The files are read ahead in the order of the copies, except the ones already copied
+	let read_ahead = read_ahead.map(|memory| {
+		let order: Vec<PathBuf> = copy_tasks.iter().filter(|path| !already_copied.contains(*path)).cloned().collect();
+		let read_ahead = Arc::new(ReadAhead::new(memory, &order));
+		(read_ahead.start(filesystem.clone(), order), read_ahead)
+	});
+	let filesystem: Arc<dyn Filesystem> = match &read_ahead {
+		Some((_, read_ahead)) => Arc::new(ReadAheadFilesystem::new(filesystem, read_ahead.clone())),
+		None => filesystem,
+	};
+	let journal = Arc::new(Mutex::new(ProgressJournal::new(destination, source, copy_tasks)));
+	let mut task_index = 0;
+	// The placeholders stored as stubs, recorded in the manifest
+	let mut stubs: BTreeSet<String> = BTreeSet::new();
 
 	// Create a semaphore to limit concurrent file operations to the maximum allowed.
-	let semaphore = Arc::new(Semaphore::new(max_file_opened));
+	// When tuned, the backup starts with fewer of them and the tuner adds or withholds permits.
+	let permits = if auto_tune { max_file_opened.min(tuning::INITIAL_CONCURRENCY) } else { max_file_opened };
+	let semaphore = Arc::new(Semaphore::new(permits));
+	let tuner = auto_tune.then(|| Arc::new(AutoTuner::new(semaphore.clone(), permits, max_file_opened)));
+	let tuning_task = tuner.as_ref().map(AutoTuner::start);
+	// The copies wait for their buffers to fit in the cap, if any
+	let memory_budget = memory_cap.map(MemoryBudget::start);
+	let destination_count = 1 + secondary_destination.is_some() as usize;
 
 	// Initialize a vector to store asynchronous file copy threads.
 	let mut handles: Vec<JoinHandle<()>> = vec![];
+	scheduling.end(&mut report.lock().unwrap());
+	let copying = PhaseTimer::start(Phase::Copying);
+
+	// To know whether the machine slept during the backup
+	let resumes_at_start = power::resume_count();
+	// The copies rewritten by this backup are the ones modified after this moment
+	let backup_started = SystemTime::now();
+	// The files modified while they were copied, recorded in the manifest
+	let fuzzy_names: Arc<Mutex<BTreeSet<String>>> = Arc::new(Mutex::new(BTreeSet::new()));
+	// The files written in each destination, recorded in the manifest: `prune` deletes only these
+	let written: Arc<Mutex<StoredFiles>> = Arc::new(Mutex::new(StoredFiles::default()));
+	// The source files by size, the candidates to be duplicates
+	let sizes: Arc<Mutex<HashMap<u64, Vec<PathBuf>>>> = Arc::new(Mutex::new(HashMap::new()));
 
 	for (path, dest_path) in tasks {
 		if type_files.is_empty() || is_file_type_accepted(&path, type_files) {
+			let index = task_index;
+			task_index += 1;
+			let read_ahead = read_ahead.as_ref().map(|(_, read_ahead)| read_ahead.clone());
+			if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+				report.lock().unwrap().unfinished += 1;
+				if let Some(read_ahead) = &read_ahead {
+					read_ahead.discard(&path);
+				}
+				continue;
+			}
+			if already_copied.contains(&path) && copy_still_valid(&*filesystem, &path, &dest_path).await {
+				journal.lock().unwrap().mark_completed(index);
+				let name = dest_path.strip_prefix(destination).unwrap_or(&dest_path).to_string_lossy().into_owned();
+				let mut written = written.lock().unwrap();
+				if secondary_destination.is_some() {
+					written.secondary.insert(name.clone());
+				}
+				written.main.insert(name);
+				{
+					let mut report = report.lock().unwrap();
+					report.resumed += 1;
+					report.copies.push((path.clone(), dest_path.clone()));
+				}
+				let mut copied = copied_files.lock().unwrap();
+				*copied += 1;
+				print_progress(*copied, total_files, &last_printed_percent, verbose, &progress);
+				continue;
+			}
+			// Reading a cloud placeholder would download it: it is handled as configured
+			if placeholders != PlaceholderPolicy::Hydrate && filesystem.metadata(&path).await.is_ok_and(|info| info.placeholder) {
+				if let Some(read_ahead) = &read_ahead {
+					read_ahead.discard(&path);
+				}
+				let stored = match placeholders {
+					PlaceholderPolicy::Stub => create_stub(&*filesystem, &dest_path).await.map(|()| true),
+					_ => Ok(false),
+				};
+				match stored {
+					Ok(stub) => {
+						if stub {
+							let name = dest_path.strip_prefix(destination).unwrap_or(&dest_path).to_string_lossy().into_owned();
+							written.lock().unwrap().main.insert(name.clone());
+							stubs.insert(name);
+						}
+						report.lock().unwrap().placeholders += 1;
+						journal.lock().unwrap().mark_completed(index);
+					}
+					Err(e) => {
+						eprintln!("Failed to create the stub of {:?}: {}", path, e);
+						report.lock().unwrap().add_failure(&e);
+					}
+				}
+				let mut copied = copied_files.lock().unwrap();
+				*copied += 1;
+				print_progress(*copied, total_files, &last_printed_percent, verbose, &progress);
+				continue;
+			}
+			let reservation = match &memory_budget {
+				Some(budget) => Some(budget.reserve(copy_memory(destination_count)).await),
+				None => None,
+			};
 			// Clone semaphore to control the number of concurrent operations.
 			let semaphore = semaphore.clone();
 			// Acquire a permit to proceed with a file copy operation.
@@ -138,21 +913,179 @@ pub async fn backup(source: &Path, destination: &Path, type_files: &Vec<String>,
 			// Clone the atomic counters to update progress in each task.
 			let copied_files_clone = copied_files.clone();
 			let last_printed_percent_clone = last_printed_percent.clone();
+			let report_clone = report.clone();
+			let progress_clone = progress.clone();
+			let filesystem = filesystem.clone();
+			let journal_clone = journal.clone();
+			let fuzzy_names_clone = fuzzy_names.clone();
+			let written_clone = written.clone();
+			let secondary_root = secondary_destination.map(Path::to_path_buf);
+			let destination_root = destination.to_path_buf();
+			let file_log = file_log.clone();
+			let sizes_clone = sizes.clone();
+			let tuner = tuner.clone();
+			// Same relative path in the secondary destination
+			let secondary_path = secondary_destination
+				.map(|secondary| secondary.join(dest_path.strip_prefix(destination).unwrap_or(&dest_path)));
+			let secondary_path = match secondary_path {
+				Some(secondary_path) if keep_existing && filesystem.metadata(&secondary_path).await.is_ok() => Some(free_name(&*filesystem, &secondary_path).await),
+				secondary_path => secondary_path,
+			};
 
 			// Spawn an asynchronous task to copy each file.
-			let handle = tokio::spawn(async move {
-				if let Err(e) = copy_file(&path, &dest_path).await {
-					println!("Failed to copy {:?}: {}", path, e);
+			let handle = tokio::spawn(copying.in_span(async move {
+				let resolution = resolve_conflict(&*filesystem, &path, &dest_path, on_conflict).await;
+				let target = match &resolution {
+					Resolution::Skipped => None,
+					Resolution::Renamed(renamed) => Some(renamed.clone()),
+					Resolution::NoConflict | Resolution::Overwritten => Some(dest_path.clone()),
+				};
+				report_clone.lock().unwrap().conflicts.record(&resolution);
+
+				// The secondary copy is independent: it is made even if the main one is skipped or failed
+				let secondary = match &secondary_path {
+					Some(secondary_path) => Some(prepare_replica(&*filesystem, secondary_path).await.map(|()| secondary_path.as_path())),
+					None => None,
+				};
+				// The source file is read once and written to all the destinations at the same time
+				let destinations: Vec<&Path> = target
+					.as_deref()
+					.into_iter()
+					.chain(secondary.as_ref().and_then(|prepared| prepared.as_ref().ok().copied()))
+					.collect();
+				// Databases and mail stores in use are flushed by their application first, when it can be asked to
+				if !destinations.is_empty() && app_hooks::before_copy(&path).await {
+					report_clone.lock().unwrap().app_flushed += 1;
+				}
+				let version = source_version(&*filesystem, &path).await;
+				if let (true, Some((len, _))) = (find_duplicates, version) {
+					sizes_clone.lock().unwrap().entry(len).or_default().push(path.clone());
+				}
+				let copy_started = Instant::now();
+				let (mut results, stalled) = copy_with_watchdog(filesystem.clone(), &path, &destinations, stall_timeout).await;
+				if stalled {
+					report_clone.lock().unwrap().stalled.push(path.clone());
+				}
+				// A file modified during its copy (e.g. a database being written) may be copied half old and half new
+				let mut changed = results.iter().any(Result::is_ok) && source_version(&*filesystem, &path).await != version;
+				if changed && recopy_changed {
+					let version = source_version(&*filesystem, &path).await;
+					(results, _) = copy_with_watchdog(filesystem.clone(), &path, &destinations, stall_timeout).await;
+					changed = results.iter().any(Result::is_ok) && source_version(&*filesystem, &path).await != version;
+				}
+				if changed {
+					eprintln!("{:?} was modified while it was copied: its copy may be inconsistent", path);
+					report_clone.lock().unwrap().fuzzy.push(path.clone());
+					let stored = target.as_deref().unwrap_or(&dest_path);
+					let stored_name = stored.strip_prefix(&destination_root).unwrap_or(stored).to_string_lossy().into_owned();
+					fuzzy_names_clone.lock().unwrap().insert(stored_name);
+				}
+				if let (Some(tuner), Some((len, _))) = (&tuner, version) {
+					if !destinations.is_empty() && results.iter().all(Result::is_ok) {
+						tuner.record(len, copy_started.elapsed());
+					}
+				}
+				if let Some(file_log) = &file_log {
+					let outcome = match results.iter().find_map(|result| result.as_ref().err()) {
+						_ if destinations.is_empty() => "skipped".to_string(),
+						Some(e) => format!("failed: {}", e),
+						None if changed => "copied (modified during the copy)".to_string(),
+						None => "copied".to_string(),
+					};
+					file_log.record(&path, version.map(|(len, _)| len), copy_started.elapsed(), &outcome);
+				}
+				let mut results = results.into_iter();
+				let main_result = target.as_ref().map(|_| results.next().unwrap());
+				let secondary_result = secondary.map(|prepared| prepared.and_then(|_| results.next().unwrap()));
+				// The file is copied again by a resumed backup unless every destination has it
+				let mut completed = true;
+
+				if let (Some(target), Some(mut result)) = (target, main_result) {
+					if result.is_err() && power::resume_count() != resumes_at_start {
+						// The machine slept during the backup: the devices may still be reconnecting, so wait for them and retry
+						wait_after_resume(&*filesystem, &target).await;
+						result = copy_file(&*filesystem, &path, &target).await;
+					}
+					match result {
+						Ok(()) => {
+							let name = target.strip_prefix(&destination_root).unwrap_or(&target).to_string_lossy().into_owned();
+							written_clone.lock().unwrap().main.insert(name);
+							{
+								let mut report = report_clone.lock().unwrap();
+								report.copied += 1;
+								report.copied_bytes += version.map_or(0, |(len, _)| len);
+							}
+							// An evacuated source cannot be read back: only the copies whose source is still there are verified
+							if evacuate {
+								let evacuated = evacuate_file(&*filesystem, &path, &target).await;
+								let mut report = report_clone.lock().unwrap();
+								match evacuated {
+									Ok(()) => report.evacuated += 1,
+									Err(e) => {
+										eprintln!("Source file {:?} kept: {}", path, e);
+										report.evacuation_failed += 1;
+										report.copies.push((path.clone(), target.clone()));
+									}
+								}
+							} else {
+								report_clone.lock().unwrap().copies.push((path.clone(), target.clone()));
+							}
+						}
+						Err(e) => {
+							eprintln!("Failed to copy {:?}: {}", path, e);
+							let mut report = report_clone.lock().unwrap();
+							report.add_failure(&e);
+							if e.kind() == io::ErrorKind::PermissionDenied {
+								report.denied.push(path.clone());
+							}
+							completed = false;
+						}
+					}
+					take_encrypted_copy(&target);
+				}
+				if let Some(secondary_path) = &secondary_path {
+					take_encrypted_copy(secondary_path);
+				}
+				if let Some(result) = secondary_result {
+					completed &= result.is_ok();
+					let mut report = report_clone.lock().unwrap();
+					if let Some(replica) = report.secondary.as_mut() {
+						match result {
+							Ok(()) => {
+								replica.copied += 1;
+								if let (Some(secondary_path), Some(secondary_root)) = (&secondary_path, &secondary_root) {
+									let name = secondary_path.strip_prefix(secondary_root).unwrap_or(secondary_path).to_string_lossy().into_owned();
+									written_clone.lock().unwrap().secondary.insert(name);
+								}
+							}
+							Err(e) => {
+								eprintln!("Failed to copy {:?} to the secondary destination: {}", path, e);
+								replica.add_failure(&e);
+							}
+						}
+					}
+				}
+				if completed {
+					journal_clone.lock().unwrap().mark_completed(index);
+				}
+				// A file not read by its copy (e.g. kept in the destination) would keep its memory in the read-ahead
+				if let Some(read_ahead) = &read_ahead {
+					read_ahead.discard(&path);
 				}
 				drop(permit);
+				drop(reservation);
 				// Lock the mutex to safely update the number of copied files.
 				let mut copied = copied_files_clone.lock().unwrap();
 				*copied += 1;
-				if verbose {
-					print_progress(*copied, total_files, &last_printed_percent_clone);
-				}
-			});
+				print_progress(*copied, total_files, &last_printed_percent_clone, verbose, &progress_clone);
+			}));
 			handles.push(handle);
+			// The handles of the copies already ended are dropped, so that millions of files do not keep millions of them
+			if handles.len() >= 2 * max_file_opened.max(1) {
+				handles.retain(|handle| !handle.is_finished());
+			}
+		} else {
+			report.lock().unwrap().skipped += 1;
 		}
 	}
 
@@ -160,44 +1093,653 @@ pub async fn backup(source: &Path, destination: &Path, type_files: &Vec<String>,
 	for handle in handles {
 		let _ = handle.await;
 	}
+	report.lock().unwrap().copy_time = backup_started.elapsed().unwrap_or_default();
+	if let Some(budget) = &memory_budget {
+		budget.finish();
+	}
+	if let Some((read_ahead_task, _)) = read_ahead {
+		read_ahead_task.abort();
+	}
+	if let (Some(tuner), Some(tuning_task)) = (tuner, tuning_task) {
+		tuning_task.abort();
+		report.lock().unwrap().tuning = Some(tuner.finish());
+	}
+	copying.end(&mut report.lock().unwrap());
+	if find_duplicates {
+		let hashing = PhaseTimer::start(Phase::Hashing);
+		let sizes = std::mem::take(&mut *sizes.lock().unwrap());
+		let duplicates = hashing.in_span(group_duplicates(&*filesystem, sizes)).await;
+		let mut report = report.lock().unwrap();
+		report.duplicates = duplicates;
+		hashing.end(&mut report);
+	}
+	let finalization = PhaseTimer::start(Phase::Finalization);
+	let entered = finalization.enter();
+	let unfinished = report.lock().unwrap().unfinished;
+	if unfinished > 0 {
+		// Out of time: the next backup resumes from the files not copied yet
+		eprintln!("Maximum duration of the backup reached: {} files left to the next backup", unfinished);
+		if let Err(e) = journal.lock().unwrap().save() {
+			eprintln!("Unable to save the progress of the backup: {}", e);
+		}
+	} else {
+		// The backup has ended: the next one starts from scratch
+		journal.lock().unwrap().remove();
+	}
+
+	for root in std::iter::once(destination).chain(secondary_destination) {
+		if let Err(e) = manifest::record_renamed(root, renamed.clone()) {
+			eprintln!("Failed to record the renamed files in the manifest of {:?}: {}", root, e);
+		}
+	}
+	let written = std::mem::take(&mut *written.lock().unwrap());
+	if let Err(e) = manifest::record_stored(destination, written.main) {
+		eprintln!("Failed to record the copied files in the manifest of {:?}: {}", destination, e);
+	}
+	if let Some(secondary_destination) = secondary_destination {
+		if let Err(e) = manifest::record_stored(secondary_destination, written.secondary) {
+			eprintln!("Failed to record the copied files in the manifest of {:?}: {}", secondary_destination, e);
+		}
+	}
+	let fuzzy_names = fuzzy_names.lock().unwrap().clone();
+	for root in std::iter::once(destination).chain(secondary_destination) {
+		if let Err(e) = manifest::record_fuzzy(root, fuzzy_names.clone(), backup_started) {
+			eprintln!("Failed to record the files modified during the copy in the manifest of {:?}: {}", root, e);
+		}
+	}
+	// The stubs are only created in the main destination
+	if let Err(e) = manifest::record_placeholders(destination, stubs) {
+		eprintln!("Failed to record the cloud placeholders in the manifest of {:?}: {}", destination, e);
+	}
+	// The deletions of `prune` are paused until a backup finds nothing suspicious
+	let suspicion = report.lock().unwrap().suspicion.as_ref().map(Suspicion::message);
+	for root in std::iter::once(destination).chain(secondary_destination) {
+		if let Err(e) = manifest::record_suspicion(root, suspicion.clone()) {
+			eprintln!("Failed to record the ransomware check in the manifest of {:?}: {}", root, e);
+		}
+	}
+	drop(entered);
+	finalization.end(&mut report.lock().unwrap());
 
 	Ok(())
 }
 
+/// Finds the files with the same content among the ones with the same size, reading only those.
+///
+/// # Arguments
+///
+/// * `filesystem` - The file system of the source.
+/// * `sizes` - The source files, grouped by size.
+///
+/// # Returns
+///
+/// The groups of files with the same content, the ones wasting more space first.
+async fn group_duplicates(filesystem: &dyn Filesystem, sizes: HashMap<u64, Vec<PathBuf>>) -> Vec<DuplicateGroup> {
+	let mut groups = Vec::new();
+	// The empty files are all equal, but they waste no space
+	for (size, paths) in sizes.into_iter().filter(|(size, paths)| *size > 0 && paths.len() > 1) {
+		let mut by_hash: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+		for path in paths {
+			match hash_source(filesystem, &path).await {
+				Ok(hash) => by_hash.entry(hash).or_default().push(path),
+				Err(e) => eprintln!("Unable to compare {:?} with the files of the same size: {}", path, e),
+			}
+		}
+		for (hash, mut paths) in by_hash.into_iter().filter(|(_, paths)| paths.len() > 1) {
+			paths.sort();
+			groups.push(DuplicateGroup { size, hash, paths });
+		}
+	}
+	groups.sort_by(|a, b| b.wasted().cmp(&a.wasted()).then_with(|| a.paths.cmp(&b.paths)));
+	groups
+}
+
+/// Returns the BLAKE3 hash of a source file, in hexadecimal.
+async fn hash_source(filesystem: &dyn Filesystem, path: &Path) -> io::Result<String> {
+	let mut reader = filesystem.open_read(path).await?;
+	let mut hasher = blake3::Hasher::new();
+	let mut buffer = vec![0u8; 1024 * 1024];
+	loop {
+		let read = reader.read(&mut buffer).await?;
+		if read == 0 {
+			break;
+		}
+		hasher.update(&buffer[..read]);
+	}
+	Ok(hasher.finalize().to_hex().to_string())
+}
 
 /// Copies a file from a source path to a destination path using asynchronous I/O operations.
 /// This function employs buffered reading and writing for efficient data transfer.
 ///
 /// # Arguments
 ///
+/// * `filesystem` - The file system where the file is read and written.
 /// * `src` - A reference to the source file path.
 /// * `dest` - A reference to the destination file path.
 ///
 /// # Returns
 ///
 /// * An `io::Result<()>` indicating the success or failure of the file copy operation.
-pub async fn copy_file(src: &Path, dest: &Path) -> io::Result<()> {
-	let mut reader = BufReader::new(File::open(src).await?);
-	let mut writer = BufWriter::new(File::create(dest).await?);
+pub async fn copy_file(filesystem: &dyn Filesystem, src: &Path, dest: &Path) -> io::Result<()> {
+	if encryption::is_enabled() {
+		return copy_encrypted(filesystem, src, dest).await;
+	}
+	// The throughput cap needs to account every chunk, so the throttled copies are never made with io_uring
+	if uring::is_enabled() && !network::is_throttled() {
+		if let Some(result) = filesystem.copy_with_uring(src, dest, Arc::new(|| {})).await {
+			return result;
+		}
+	}
+	let mut reader = BufReader::with_capacity(tuning::buffer_size(), filesystem.open_read(src).await?);
+	let mut writer = BufWriter::with_capacity(tuning::buffer_size(), filesystem.create(dest).await?);
 
-	io::copy(&mut reader, &mut writer).await?;
+	if network::is_throttled() {
+		// Copy in chunks, waiting after each one to respect the throughput cap
+		let mut buffer = vec![0u8; 64 * 1024];
+		loop {
+			let read = reader.read(&mut buffer).await?;
+			if read == 0 {
+				break;
+			}
+			writer.write_all(&buffer[..read]).await?;
+			network::throttle(read).await;
+		}
+	} else {
+		io::copy(&mut reader, &mut writer).await?;
+	}
 	writer.flush().await?;
 	Ok(())
 }
 
+/// Copies a file encrypting it with the key of the destination (`encrypt` in the configuration).
+async fn copy_encrypted(filesystem: &dyn Filesystem, src: &Path, dest: &Path) -> io::Result<()> {
+	let mut encryptor = FileEncryptor::new()?;
+	let mut reader = BufReader::with_capacity(tuning::buffer_size(), filesystem.open_read(src).await?);
+	let mut writer = BufWriter::with_capacity(tuning::buffer_size(), filesystem.create(dest).await?);
+	let mut buffer = vec![0u8; 64 * 1024];
+	let (mut plain, mut encrypted) = (blake3::Hasher::new(), blake3::Hasher::new());
+	loop {
+		let read = reader.read(&mut buffer).await?;
+		if read == 0 {
+			break;
+		}
+		plain.update(&buffer[..read]);
+		let chunk = encryptor.update(&buffer[..read])?;
+		encrypted.update(&chunk);
+		writer.write_all(&chunk).await?;
+		if network::is_throttled() {
+			network::throttle(read).await;
+		}
+	}
+	let chunk = encryptor.finish()?;
+	encrypted.update(&chunk);
+	writer.write_all(&chunk).await?;
+	writer.flush().await?;
+	let hashes = EncryptedCopy { plain: plain.finalize().to_hex().to_string(), encrypted: encrypted.finalize().to_hex().to_string() };
+	ENCRYPTED_COPIES.lock().unwrap().insert(dest.to_path_buf(), hashes);
+	Ok(())
+}
+
+/// The hashes of an encrypted copy, taken while it was written: the copy cannot be compared with its source
+/// byte by byte, since its content is encrypted.
+struct EncryptedCopy {
+	/// BLAKE3 hash of the content in clear, as read from the source.
+	plain: String,
+	/// BLAKE3 hash of the encrypted content, as written in the destination.
+	encrypted: String,
+}
+
+/// The hashes of the encrypted copies in progress, until their source is evacuated (or their copy ends).
+static ENCRYPTED_COPIES: Mutex<BTreeMap<PathBuf, EncryptedCopy>> = Mutex::new(BTreeMap::new());
+
+/// Returns the hashes taken while an encrypted copy was written, forgetting them.
+fn take_encrypted_copy(dest: &Path) -> Option<EncryptedCopy> {
+	ENCRYPTED_COPIES.lock().unwrap().remove(dest)
+}
+
+/// Bytes read from the source at a time when a file is copied to several destinations.
+const FAN_OUT_CHUNK: usize = 1024 * 1024;
+
+/// Chunks read ahead of the slowest destination when a file is copied to several destinations.
+const FAN_OUT_QUEUE: usize = 4;
+
+/// Returns the memory used by the copy of a file to some destinations: the buffers of the reader and of the writers
+/// and, with several destinations, the chunks queued for each writer.
+fn copy_memory(destinations: usize) -> u64 {
+	let buffers = (tuning::buffer_size() * (1 + destinations)) as u64;
+	if destinations > 1 {
+		buffers + (FAN_OUT_CHUNK * (1 + FAN_OUT_QUEUE * destinations)) as u64
+	} else {
+		buffers
+	}
+}
+
+/// Copies a file to several destinations reading it only once: every chunk read from the source is sent
+/// to one writer task per destination, so the destinations are written at the same time.
+/// A destination that fails does not stop the others.
+///
+/// # Arguments
+///
+/// * `filesystem` - The file system where the files are read and written.
+/// * `src` - A reference to the source file path.
+/// * `dests` - The destination file paths.
+///
+/// # Returns
+///
+/// * The result of the copy for each destination, in the same order.
+pub async fn copy_file_to_all(filesystem: &dyn Filesystem, src: &Path, dests: &[&Path]) -> Vec<io::Result<()>> {
+	// Each encrypted copy has its own file key, so it is read and encrypted once for each destination
+	if dests.len() <= 1 || encryption::is_enabled() {
+		let mut results = Vec::new();
+		for dest in dests {
+			results.push(copy_file(filesystem, src, dest).await);
+		}
+		return results;
+	}
+
+	let same_error = |e: &io::Error| -> Vec<io::Result<()>> { dests.iter().map(|_| Err(io::Error::new(e.kind(), e.to_string()))).collect() };
+	let mut reader = match filesystem.open_read(src).await {
+		Ok(file) => BufReader::new(file),
+		Err(e) => return same_error(&e),
+	};
+
+	let mut senders = Vec::new();
+	let mut writers = Vec::new();
+	for dest in dests {
+		// The writer tasks outlive this borrow of the file system, so each one opens its file before being spawned
+		let file = match filesystem.create(dest).await {
+			Ok(file) => file,
+			Err(e) => {
+				writers.push(tokio::spawn(async move { Err(e) }));
+				senders.push(None);
+				continue;
+			}
+		};
+		let (tx, mut rx) = tokio::sync::mpsc::channel::<Arc<Vec<u8>>>(FAN_OUT_QUEUE);
+		writers.push(tokio::spawn(async move {
+			let mut writer = BufWriter::new(file);
+			while let Some(chunk) = rx.recv().await {
+				writer.write_all(&chunk).await?;
+			}
+			writer.flush().await
+		}));
+		senders.push(Some(tx));
+	}
+
+	let mut buffer = vec![0u8; FAN_OUT_CHUNK];
+	let read_result = loop {
+		let read = match reader.read(&mut buffer).await {
+			Ok(0) => break Ok(()),
+			Ok(read) => read,
+			Err(e) => break Err(e),
+		};
+		let chunk = Arc::new(buffer[..read].to_vec());
+		for sender in senders.iter_mut() {
+			// A writer that failed has dropped its receiver: the chunk is only sent to the others
+			if let Some(tx) = sender {
+				if tx.send(chunk.clone()).await.is_err() {
+					*sender = None;
+				}
+			}
+		}
+		if senders.iter().all(Option::is_none) {
+			break Ok(());
+		}
+		if network::is_throttled() {
+			network::throttle(read).await;
+		}
+	};
+
+	if let Err(e) = read_result {
+		// The writers must not complete files that were read only in part
+		for writer in &writers {
+			writer.abort();
+		}
+		return same_error(&e);
+	}
+
+	// Closing the channels lets the writers flush and finish
+	drop(senders);
+	let mut results = Vec::new();
+	for writer in writers {
+		results.push(writer.await.unwrap_or_else(|e| Err(io::Error::other(e))));
+	}
+	results
+}
+
+
+/// Interval between two checks of the progress of a copy.
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Copies a file to all the destinations like `copy_file_to_all`, but cancels the copy if no data is read for
+/// `stall_timeout` (e.g. a stuck USB controller or a dead network share) and tries it once more,
+/// so that a single hung file cannot stall the whole backup.
+///
+/// # Arguments
+///
+/// * `filesystem` - The file system where the files are read and written.
+/// * `src` - A reference to the source file path.
+/// * `dests` - The destination file paths.
+/// * `stall_timeout` - How long the copy can go without reading any data.
+///
+/// # Returns
+///
+/// * The result of the copy for each destination, in the same order, and whether both attempts stalled.
+async fn copy_with_watchdog(filesystem: Arc<dyn Filesystem>, src: &Path, dests: &[&Path], stall_timeout: Duration) -> (Vec<io::Result<()>>, bool) {
+	for attempt in 1..=2 {
+		let watched = ProgressFilesystem::new(filesystem.clone());
+		let copy = copy_file_to_all(&watched, src, dests);
+		tokio::pin!(copy);
+		loop {
+			tokio::select! {
+				results = &mut copy => return (results, false),
+				_ = tokio::time::sleep(WATCHDOG_INTERVAL) => {
+					if watched.idle_time() >= stall_timeout {
+						break;
+					}
+				}
+			}
+		}
+		eprintln!("Copy of {:?} canceled: no progress for {} seconds (attempt {} of 2)", src, stall_timeout.as_secs(), attempt);
+	}
+	let results = dests
+		.iter()
+		.map(|_| Err(io::Error::new(io::ErrorKind::TimedOut, format!("no progress for {} seconds", stall_timeout.as_secs()))))
+		.collect();
+	(results, true)
+}
+
+/// Maximum time to wait for the destination to be available again after the machine resumed from sleep.
+const RESUME_SETTLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Waits (up to `RESUME_SETTLE_TIMEOUT`) until the folder of a destination file is reachable again,
+/// e.g. until the USB drive has been mounted again after the machine resumed from sleep.
+///
+/// # Arguments
+///
+/// * `filesystem` - The file system of the destination.
+/// * `dest` - The destination file.
+async fn wait_after_resume(filesystem: &dyn Filesystem, dest: &Path) {
+	let dir = match dest.parent() {
+		Some(dir) => dir,
+		None => return,
+	};
+	let start = Instant::now();
+	while !filesystem.is_dir(dir).await && start.elapsed() < RESUME_SETTLE_TIMEOUT {
+		tokio::time::sleep(Duration::from_secs(1)).await;
+	}
+}
+
+/// Bytes compared at the beginning and at the end of every file read back.
+const VERIFY_CHUNK: usize = 64 * 1024;
+
+/// Reads back the copied files (all or a sample spread over the backup) and compares their size,
+/// their first and their last bytes with the source, to detect drives that report successful writes
+/// but return garbage. Files that were not copied are not checked.
+///
+/// # Arguments
+///
+/// * `copies` - The source files copied by the backup and the paths where they were written (`BackupReport::copies`).
+/// * `destination` - The destination folder of the backup, where the hashes are cached.
+/// * `mode` - Whether all the files or a sample are read back.
+/// * `sample_size` - The number of files read back with `VerifyMode::Sample`.
+/// * `by_hash` - A boolean flag to compare the whole content through hashes instead of the first and last bytes.
+///   The hashes are cached in the destination, so unchanged files are not read again by the next verifications.
+///
+/// # Returns
+///
+/// * The `VerificationReport`.
+pub async fn verify_backup(copies: &[(PathBuf, PathBuf)], destination: &Path, mode: VerifyMode, sample_size: usize, by_hash: bool) -> VerificationReport {
+	// The copies end in any order: sorted, the sample follows the folders of the source
+	let mut tasks: Vec<&(PathBuf, PathBuf)> = copies.iter().collect();
+	tasks.sort();
+
+	let selected: Vec<&(PathBuf, PathBuf)> = match mode {
+		VerifyMode::Off => Vec::new(),
+		VerifyMode::All => tasks,
+		// Evenly spaced files, so that the whole drive is sampled and not only the first folders
+		VerifyMode::Sample => {
+			let n = sample_size.min(tasks.len());
+			(0..n).map(|i| tasks[i * tasks.len() / n]).collect()
+		}
+	};
+
+	// The encrypted copies are read back whole and decrypted, without the cache (which holds the hashes of the files as stored)
+	let encrypted = encryption::is_enabled();
+	let mut hash_cache = (by_hash && !encrypted).then(|| HashCache::load(destination));
+	let mut report = VerificationReport::default();
+	for (path, dest_path) in selected {
+		report.checked += 1;
+		let result = if encrypted {
+			encrypted_file_matches(path, dest_path).await
+		} else {
+			match hash_cache.as_mut() {
+				Some(cache) => hashes_match(cache, path, dest_path).await,
+				None => files_match(path, dest_path).await,
+			}
+		};
+		match result {
+			Ok(true) => {}
+			Ok(false) => report.mismatched.push(dest_path.clone()),
+			Err(_) => report.unreadable.push(dest_path.clone()),
+		}
+	}
+	if let Some(mut cache) = hash_cache {
+		if let Err(e) = cache.save() {
+			eprintln!("Failed to save the hash cache: {}", e);
+		}
+	}
+	report
+}
+
+/// Waits until the user has not used the keyboard and the mouse for some time, before a heavy phase of the backup.
+/// If the idle time cannot be known (e.g. no input listener on this system), the phase is not delayed.
+///
+/// # Arguments
+///
+/// * `min_idle` - The time without input to wait for.
+/// * `deadline` - The end of the backup (`max_run_minutes`), if any.
+///
+/// # Returns
+///
+/// `false` if the deadline has been reached before.
+async fn wait_for_idle(min_idle: Duration, deadline: Option<Instant>) -> bool {
+	let mut announced = false;
+	loop {
+		match input_hook::idle_time() {
+			Some(idle) if idle < min_idle => {}
+			_ => return true,
+		}
+		if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+			eprintln!("Maximum duration reached before the user was idle: the verification is skipped");
+			return false;
+		}
+		if !announced {
+			eprintln!("Waiting for {} minutes without input before the verification", min_idle.as_secs() / 60);
+			announced = true;
+		}
+		tokio::time::sleep(Duration::from_secs(5)).await;
+	}
+}
+
+/// Compares the hash of a copied file with the one of its source, using the cached hashes of the unchanged files.
+async fn hashes_match(cache: &mut HashCache, src: &Path, dest: &Path) -> io::Result<bool> {
+	// A file just copied must be read from the drive, not from the memory where it was written
+	filesystem::drop_cached_pages(&File::open(dest).await?);
+	Ok(cache.hash(src).await? == cache.hash(dest).await?)
+}
+
+/// Compares the size, the first and the last bytes of a copied file with its source.
+async fn files_match(src: &Path, dest: &Path) -> io::Result<bool> {
+	use tokio::io::AsyncSeekExt;
+
+	let src_len = fs::metadata(src).await?.len();
+	let mut dest_file = File::open(dest).await?;
+	if dest_file.metadata().await?.len() != src_len {
+		return Ok(false);
+	}
+	filesystem::drop_cached_pages(&dest_file);
+	let mut src_file = filesystem::open_source(src).await?;
+
+	let mut offsets = vec![0u64];
+	if src_len > VERIFY_CHUNK as u64 {
+		offsets.push(src_len - VERIFY_CHUNK as u64);
+	}
+	for offset in offsets {
+		let len = (src_len - offset).min(VERIFY_CHUNK as u64) as usize;
+		let mut src_bytes = vec![0u8; len];
+		let mut dest_bytes = vec![0u8; len];
+		src_file.seek(io::SeekFrom::Start(offset)).await?;
+		src_file.read_exact(&mut src_bytes).await?;
+		dest_file.seek(io::SeekFrom::Start(offset)).await?;
+		dest_file.read_exact(&mut dest_bytes).await?;
+		if src_bytes != dest_bytes {
+			return Ok(false);
+		}
+	}
+	Ok(true)
+}
+
+/// Compares the hash of the content in clear of an encrypted copy with the one of its source.
+async fn encrypted_file_matches(src: &Path, dest: &Path) -> io::Result<bool> {
+	filesystem::drop_cached_pages(&File::open(dest).await?);
+	let dest = dest.to_path_buf();
+	let dest_hash = tokio::task::spawn_blocking(move || encryption::hash_decrypted(&dest))
+		.await
+		.map_err(io::Error::other)??;
+	Ok(hash_source(&DiskFilesystem, src).await? == dest_hash)
+}
+
+/// Removes a source file after checking that its copy reads back from the drive as it was written: byte by byte
+/// against the source, or, for an encrypted copy, through the hashes taken while it was written (its content
+/// differs from the source).
+async fn evacuate_file(filesystem: &dyn Filesystem, src: &Path, dest: &Path) -> io::Result<()> {
+	let identical = match take_encrypted_copy(dest) {
+		Some(hashes) => encrypted_copy_intact(filesystem, src, dest, &hashes).await?,
+		None if encryption::is_enabled() => {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "the hashes of the encrypted copy have not been recorded"));
+		}
+		None => files_identical(filesystem, src, dest).await?,
+	};
+	if !identical {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, "the copy differs from the source"));
+	}
+	filesystem.remove_file(src).await
+}
+
+/// Checks that the source has not changed since it was encrypted and that the encrypted copy reads back as it was written.
+async fn encrypted_copy_intact(filesystem: &dyn Filesystem, src: &Path, dest: &Path, hashes: &EncryptedCopy) -> io::Result<bool> {
+	filesystem.forget_cached(dest).await?;
+	Ok(hash_source(filesystem, dest).await? == hashes.encrypted && hash_source(filesystem, src).await? == hashes.plain)
+}
+
+/// Compares the whole content of a copied file with its source.
+async fn files_identical(filesystem: &dyn Filesystem, src: &Path, dest: &Path) -> io::Result<bool> {
+	if filesystem.metadata(src).await?.len != filesystem.metadata(dest).await?.len {
+		return Ok(false);
+	}
+	filesystem.forget_cached(dest).await?;
+	let mut dest_file = filesystem.open_read(dest).await?;
+	let mut src_file = filesystem.open_read(src).await?;
+
+	let mut src_bytes = vec![0u8; VERIFY_CHUNK];
+	let mut dest_bytes = vec![0u8; VERIFY_CHUNK];
+	loop {
+		let read = src_file.read(&mut src_bytes).await?;
+		if read == 0 {
+			return Ok(true);
+		}
+		dest_file.read_exact(&mut dest_bytes[..read]).await?;
+		if src_bytes[..read] != dest_bytes[..read] {
+			return Ok(false);
+		}
+	}
+}
+
+/// Decides, according to the policy, what to do if the destination file already exists.
+/// If the files cannot be compared, the copy goes on and reports its own error.
+async fn resolve_conflict(filesystem: &dyn Filesystem, src: &Path, dest: &Path, policy: ConflictPolicy) -> Resolution {
+	let dest_modified = match filesystem.metadata(dest).await {
+		Ok(info) => info.modified,
+		Err(_) => return Resolution::NoConflict,
+	};
+
+	match policy {
+		ConflictPolicy::Overwrite => Resolution::Overwritten,
+		ConflictPolicy::Skip => Resolution::Skipped,
+		ConflictPolicy::Rename => Resolution::Renamed(free_name(filesystem, dest).await),
+		ConflictPolicy::NewerWins => {
+			let src_modified = filesystem.metadata(src).await.ok().and_then(|info| info.modified);
+			match (src_modified, dest_modified) {
+				(Some(src_time), Some(dest_time)) if src_time <= dest_time => Resolution::Skipped,
+				_ => Resolution::Overwritten,
+			}
+		}
+	}
+}
+
+/// Returns the first name not used yet in the folder of a file, adding a number to it: `name (1).ext`, `name (2).ext`...
+async fn free_name(filesystem: &dyn Filesystem, path: &Path) -> PathBuf {
+	let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+	let extension = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+	let mut n = 1;
+	loop {
+		let candidate = path.with_file_name(format!("{} ({}){}", stem, n, extension));
+		if filesystem.metadata(&candidate).await.is_err() {
+			return candidate;
+		}
+		n += 1;
+	}
+}
 
-/// Prints the current progress of a file copying operation as a percentage of total files copied.
+/// Returns the size and the modification time of a source file, to detect whether it changes while it is copied.
+async fn source_version(filesystem: &dyn Filesystem, src: &Path) -> Option<(u64, Option<SystemTime>)> {
+	filesystem.metadata(src).await.ok().map(|info| (info.len, info.modified))
+}
+
+/// Creates an empty file in place of a cloud placeholder, so that the destination shows that the file existed.
+async fn create_stub(filesystem: &dyn Filesystem, dest: &Path) -> io::Result<()> {
+	filesystem.create(dest).await?.shutdown().await
+}
+
+/// Returns whether a file copied by an interrupted backup is still in the destination, with the size of its source.
+async fn copy_still_valid(filesystem: &dyn Filesystem, src: &Path, dest: &Path) -> bool {
+	match (filesystem.metadata(src).await, filesystem.metadata(dest).await) {
+		(Ok(src_info), Ok(dest_info)) => src_info.len == dest_info.len,
+		_ => false,
+	}
+}
+
+/// Creates the folder of a file in the secondary destination, if needed
+/// (the folders of the secondary destination are not created in advance like the main ones).
+async fn prepare_replica(filesystem: &dyn Filesystem, dest: &Path) -> io::Result<()> {
+	match dest.parent() {
+		Some(dir) => filesystem.create_dir_all(dir).await,
+		None => Ok(()),
+	}
+}
+
+/// Prints the current progress of a file copying operation as a percentage of total files copied,
+/// and sends it to the progress channel (if any).
 ///
 /// # Arguments
 ///
 /// * `copied_files` - The number of files that have been successfully copied so far.
 /// * `total_files` - The total number of files that need to be copied.
 /// * `last_printed_percent` - A reference to an atomic integer wrapped in a mutex that stores the last printed percentage, to avoid redundant messages.
-fn print_progress(copied_files: usize, total_files: usize, last_printed_percent: &Arc<Mutex<usize>>) {
+/// * `verbose` - A boolean flag to print the progress on the console.
+/// * `progress` - An optional channel where every new percentage is sent.
+fn print_progress(copied_files: usize, total_files: usize, last_printed_percent: &Arc<Mutex<usize>>, verbose: bool, progress: &Option<Sender<usize>>) {
 	let percent = copied_files * 100 / total_files;
 	let mut last_percent = last_printed_percent.lock().unwrap();
 	if percent > *last_percent {
-		println!("Progress: {}% ({} of {} files)", percent, copied_files, total_files);
+		if verbose {
+			eprintln!("Progress: {}% ({} of {} files)", percent, copied_files, total_files);
+		}
+		if let Some(progress) = progress {
+			let _ = progress.send(percent);
+		}
 		*last_percent = percent;
 	}
 }
@@ -214,7 +1756,7 @@ fn print_progress(copied_files: usize, total_files: usize, last_printed_percent:
 /// # Returns
 ///
 /// * A boolean value indicating whether the file type is accepted based on its extension.
-fn is_file_type_accepted(path: &Path, type_files: &Vec<String>) -> bool {
+pub(crate) fn is_file_type_accepted(path: &Path, type_files: &[String]) -> bool {
 	path.extension()
 		.and_then(|ext| ext.to_str())
 		.map(|ext| format!(".{}", ext))
@@ -223,43 +1765,509 @@ fn is_file_type_accepted(path: &Path, type_files: &Vec<String>) -> bool {
 }
 
 
+/// Determines if the file or directory at the specified path matches one of the exclusion patterns.
+/// Patterns are compared with the name of the file or directory and can contain the `*` and `?` wildcards.
+///
+/// # Arguments
+///
+/// * `path` - A reference to the path to check.
+/// * `exclude` - A vector of name patterns (e.g., "*.tmp", "node_modules").
+///
+/// # Returns
+///
+/// * A boolean value indicating whether the path must be left out of the backup.
+pub(crate) fn is_excluded(path: &Path, exclude: &[String]) -> bool {
+	match path.file_name().and_then(|name| name.to_str()) {
+		Some(name) => exclude.iter().any(|pattern| matches_pattern(name, pattern)),
+		None => false,
+	}
+}
+
+/// Characters that FAT and exFAT cannot store in a name (besides the control characters).
+const INVALID_NAME_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Names reserved by Windows for devices, whatever their extension (e.g. `con.txt`).
+const RESERVED_NAMES: &[&str] = &[
+	"CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+	"LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Maximum length of a name on FAT and exFAT, in UTF-16 units.
+const MAX_NAME_LEN: usize = 255;
+
+/// Returns the names of a folder's entries in the destination. With `sanitize_names`, the names that cannot be stored
+/// are replaced, making sure that they do not collide with each other or with the other names of the folder
+/// (FAT and exFAT ignore the case of the names).
+fn destination_names(paths: &[PathBuf], sanitize_names: bool) -> Vec<OsString> {
+	let names: Vec<&OsStr> = paths.iter().map(|path| path.file_name().unwrap()).collect();
+	if !sanitize_names {
+		return names.into_iter().map(OsStr::to_os_string).collect();
+	}
+
+	let sanitized: Vec<Option<String>> = names.iter().map(|name| sanitize_name(name)).collect();
+	// The names kept as they are have priority, so they never change between two backups
+	let mut used: HashSet<String> = names
+		.iter()
+		.zip(&sanitized)
+		.filter(|(_, sanitized)| sanitized.is_none())
+		.map(|(name, _)| name.to_string_lossy().to_lowercase())
+		.collect();
+
+	names
+		.into_iter()
+		.zip(sanitized)
+		.map(|(name, sanitized)| match sanitized {
+			None => name.to_os_string(),
+			Some(sanitized) => {
+				let unique = unique_name(&sanitized, &used);
+				used.insert(unique.to_lowercase());
+				OsString::from(unique)
+			}
+		})
+		.collect()
+}
+
+/// Returns the name to use on a FAT or exFAT destination, or `None` if the name can be stored as it is:
+/// the invalid and control characters are replaced by `_`, trailing dots and spaces (dropped by Windows) are
+/// replaced by `_`, reserved names get a `_` prefix, names that are not valid Unicode are converted and too long
+/// names are shortened.
+fn sanitize_name(name: &OsStr) -> Option<String> {
+	let mut sanitized: String = name
+		.to_string_lossy()
+		.chars()
+		.map(|c| if c.is_control() || INVALID_NAME_CHARS.contains(&c) { '_' } else { c })
+		.collect();
+
+	let kept = sanitized.trim_end_matches(['.', ' ']).len();
+	if kept < sanitized.len() {
+		sanitized.truncate(kept);
+		sanitized.push('_');
+	}
+
+	let stem = sanitized.split('.').next().unwrap_or("").trim_end().to_uppercase();
+	if RESERVED_NAMES.contains(&stem.as_str()) {
+		sanitized.insert(0, '_');
+	}
+
+	while sanitized.encode_utf16().count() > MAX_NAME_LEN {
+		sanitized.pop();
+	}
+
+	if name.to_str() == Some(sanitized.as_str()) {
+		None
+	} else {
+		Some(sanitized)
+	}
+}
+
+/// Returns `name`, or `name~2`, `name~3`... (before the extension) if it is already used in the folder.
+fn unique_name(name: &str, used: &HashSet<String>) -> String {
+	if !used.contains(&name.to_lowercase()) {
+		return name.to_string();
+	}
+	let (stem, extension) = match name.rfind('.') {
+		Some(dot) if dot > 0 => name.split_at(dot),
+		_ => (name, ""),
+	};
+	(2..)
+		.map(|n| format!("{}~{}{}", stem, n, extension))
+		.find(|candidate| !used.contains(&candidate.to_lowercase()))
+		.unwrap()
+}
+
+/// Matches a name against a pattern with the `*` (any sequence) and `?` (any character) wildcards.
+pub(crate) fn matches_pattern(name: &str, pattern: &str) -> bool {
+	let name: Vec<char> = name.chars().collect();
+	let pattern: Vec<char> = pattern.chars().collect();
+	let (mut n, mut p) = (0, 0);
+	// Position of the last `*` in the pattern and of the name when it was found, to backtrack
+	let mut star: Option<(usize, usize)> = None;
+
+	while n < name.len() {
+		if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+			n += 1;
+			p += 1;
+		} else if p < pattern.len() && pattern[p] == '*' {
+			star = Some((p, n));
+			p += 1;
+		} else if let Some((star_p, star_n)) = star {
+			p = star_p + 1;
+			n = star_n + 1;
+			star = Some((star_p, star_n + 1));
+		} else {
+			return false;
+		}
+	}
+	pattern[p..].iter().all(|c| *c == '*')
+}
+
+
+/// The only source files copied by the next backups, if set (see `set_retry_files`).
+static RETRY_FILES: Mutex<Option<Arc<HashSet<PathBuf>>>> = Mutex::new(None);
+
+/// Makes the next backups copy only the given source files (e.g. the ones denied to the backup run without
+/// privileges), or all the files again with `None`.
+pub fn set_retry_files(files: Option<HashSet<PathBuf>>) {
+	*RETRY_FILES.lock().unwrap() = files.map(Arc::new);
+}
+
 /// Orchestrates the backup process by invoking necessary functions before to calculate file totals (calculate_total_files),
 /// then execute the backup, and handle any errors or special conditions such as non-existent paths.
 ///
 /// # Arguments
 ///
 /// * `config` - A `Config` set by the configuration in the config.yaml file
-/// * `final_total_files` - A mutable reference to the main counter for the total number of files.
-/// * `final_total_size` - A mutable reference to the main counter for the total size of the files.
+/// * `final_report` - A mutable reference to the main report, filled with totals and copied/skipped/failed counts.
+/// * `progress` - An optional channel where every new percentage of progress is sent.
+/// * `run_dir` - The log directory of the run, where `files.log` is written with `verbose_file_log`.
 ///
 /// # Returns
 ///
 /// * A `Result<(), Box<dyn std::error::Error>>` indicating the success or failure of the backup operation.
-pub async fn wrapper_backup(config: Config, final_total_files: &mut usize, final_total_size: &mut u64) -> Result<(), Box<dyn std::error::Error>> {
+pub async fn wrapper_backup(config: Config, final_report: &mut BackupReport, progress: Option<Sender<usize>>, run_dir: Option<&Path>) -> Result<(), Box<dyn std::error::Error>> {
 	if config.path_orig_backup.exists() && config.path_dest_backup.exists() {
-		let (total_files, total_size) = calculate_total_files(config.path_orig_backup.as_path(), &config.type_files).await?;
-		*final_total_size = total_size;
-		*final_total_files = total_files;
-		let copied_files = Arc::new(Mutex::new(0));
-		let last_printed_percent = Arc::new(Mutex::new(0));
-		let max_file_opened = get_max_open_files();
+		if config.elevation != Elevation::Never {
+			if let Err(e) = elevation::enable_backup_privilege() {
+				eprintln!("Protected files may not be copied: {}", e);
+			}
+		}
+		// A rotational source is read in order, instead of by many copies at the same time
+		let read_ahead = (config.read_ahead_mb > 0 && volume::is_rotational(&config.path_orig_backup)).then(|| config.read_ahead_mb * 1024 * 1024);
+		// The maximum duration includes counting the files
+		let deadline = config.max_run_minutes.map(|minutes| Instant::now() + Duration::from_secs(minutes * 60));
+		let only_files = RETRY_FILES.lock().unwrap().clone();
+		let counting = PhaseTimer::start(Phase::Counting);
+		let (total_files, total_size) = match &only_files {
+			Some(only) => (only.len(), only.iter().filter_map(|path| std::fs::metadata(path).ok()).map(|info| info.len()).sum()),
+			None => counting.in_span(calculate_total_files(&DiskFilesystem, config.path_orig_backup.as_path(), &config.type_files, &config.exclude)).await?,
+		};
+		counting.end(final_report);
+		final_report.total_size = total_size;
+		final_report.total_files = total_files;
+		// Compared with the previous backup before anything is copied, so that even an empty source raises the alarm;
+		// a retry of a few files is not a whole backup, so it is not compared and its totals are not recorded
+		let totals = RunTotals { files: total_files, size: total_size };
+		let previous = Manifest::load(&config.path_dest_backup).ok().and_then(|manifest| manifest.last_totals).filter(|_| only_files.is_none());
+		final_report.drift = previous.and_then(|previous| Drift::check(previous, totals, config.drift_alarm_percent));
+		if let Some(drift) = &final_report.drift {
+			events::publish(AppEvent::Error(drift.message()));
+		}
+		final_report.secondary = config.secondary_dest.as_ref().map(|path| ReplicaReport { path: path.clone(), ..Default::default() });
+		// FAT and exFAT drives cannot store some names: they are replaced, and recorded in the manifest
+		let sanitize_names = volume::has_restricted_names(&config.path_dest_backup);
+		let mut options = BackupOptions {
+			type_files: config.type_files.clone(),
+			exclude: config.exclude.clone(),
+			verbose: true,
+			total_files,
+			max_file_opened: get_max_open_files(),
+			secondary_destination: config.secondary_dest.clone(),
+			preserve_empty_dirs: config.preserve_empty_dirs,
+			on_conflict: config.on_conflict,
+			evacuate: config.evacuate,
+			sanitize_names,
+			placeholders: config.cloud_placeholders,
+			stall_timeout: Duration::from_secs(config.stall_timeout_secs),
+			recopy_changed: config.recopy_changed,
+			deadline,
+			file_log: None,
+			find_duplicates: config.find_duplicates,
+			auto_tune: config.auto_tune,
+			memory_cap: config.max_memory_mb.map(|mb| mb * 1024 * 1024),
+			read_ahead,
+			only_files: only_files.clone(),
+		};
 		if total_files > 0 {
-			backup(config.path_orig_backup.as_path(), config.path_dest_backup.as_path(), &config.type_files, true, total_files, copied_files.clone(), last_printed_percent.clone(), max_file_opened).await?;
+			// Towards a network share, follow the policy of the current connection
+			if !config.network_policies.is_empty() && volume::is_remote(&config.path_dest_backup) {
+				network::apply_network_policy(&config.network_policies).await;
+			}
+			// Written first, so that even an interrupted backup tells where and how it was made
+			let snapshot_info = SnapshotInfo::collect(&config);
+			for root in std::iter::once(&config.path_dest_backup).chain(&config.secondary_dest) {
+				if let Err(e) = snapshot_info.save(root) {
+					eprintln!("Failed to write the snapshot information in {:?}: {}", root, e);
+				}
+			}
+			// A single compressed archive instead of the copies of the files
+			if let Some(format) = config.archive {
+				let copying = PhaseTimer::start(Phase::Copying);
+				let archive = copying.in_span(archive::archive_backup(&DiskFilesystem, config.path_orig_backup.as_path(), config.path_dest_backup.as_path(), format, &options, final_report, progress)).await?;
+				copying.end(final_report);
+				eprintln!("Backup archived in {:?}", archive);
+				let finalization = PhaseTimer::start(Phase::Finalization);
+				let entered = finalization.enter();
+				let name = archive.file_name().map(|name| name.to_string_lossy().into_owned()).into_iter().collect();
+				if let Err(e) = manifest::record_stored(&config.path_dest_backup, name) {
+					eprintln!("Failed to record the archive in the manifest of {:?}: {}", config.path_dest_backup, e);
+				}
+				if let Err(e) = manifest::record_totals(&config.path_dest_backup, totals) {
+					eprintln!("Failed to record the totals of the backup in the manifest of {:?}: {}", config.path_dest_backup, e);
+				}
+				drop(entered);
+				finalization.end(final_report);
+				return Ok(());
+			}
+			options.file_log = match run_dir.filter(|_| config.verbose_file_log) {
+				Some(run_dir) => match FileLog::create(&run_dir.join(FILE_LOG_NAME)) {
+					Ok(file_log) => Some(Arc::new(file_log)),
+					Err(e) => {
+						eprintln!("Unable to create the per-file log in {:?}: {}", run_dir, e);
+						None
+					}
+				},
+				None => None,
+			};
+			// A wrong passphrase stops the backup before anything is written in clear
+			let roots: Vec<&Path> = std::iter::once(&config.path_dest_backup).chain(&config.secondary_dest).map(PathBuf::as_path).collect();
+			encryption::unlock(&roots)?;
+			let report = Arc::new(Mutex::new(final_report.clone()));
+			app_hooks::reset();
+			backup(Arc::new(DiskFilesystem), config.path_orig_backup.as_path(), config.path_dest_backup.as_path(), &options, report.clone(), progress).await?;
+			if let Some(file_log) = &options.file_log {
+				file_log.flush();
+			}
+			*final_report = report.lock().unwrap().clone();
+			// The next backup is compared with this one
+			let finalization = PhaseTimer::start(Phase::Finalization);
+			let entered = finalization.enter();
+			if only_files.is_none() {
+				if let Err(e) = manifest::record_totals(&config.path_dest_backup, totals) {
+					eprintln!("Failed to record the totals of the backup in the manifest of {:?}: {}", config.path_dest_backup, e);
+				}
+			}
+			drop(entered);
+			finalization.end(final_report);
+			// The files left to the next backup would all be reported as missing
+			let idle = match config.verify_when_idle_minutes {
+				Some(minutes) if config.verify != VerifyMode::Off => wait_for_idle(Duration::from_secs(minutes * 60), deadline).await,
+				_ => true,
+			};
+			if config.verify != VerifyMode::Off && final_report.unfinished == 0 && idle {
+				let hashing = PhaseTimer::start(Phase::Hashing);
+				final_report.verification = Some(hashing.in_span(verify_backup(&final_report.copies, config.path_dest_backup.as_path(), config.verify, config.verify_sample_size, config.verify_hash)).await);
+				hashing.end(final_report);
+			}
 			Ok(())
 		} else {
-			show_popup(NotificationType::GenericError, Some("No files to copy.".to_string()));
+			events::publish(AppEvent::Error("No files to copy.".to_string()));
 			Ok(())
 		}
 	} else {
 		if !config.path_orig_backup.exists() {
-			show_popup(NotificationType::GenericError, Some(format!("Error: Source path does not exist: {:?}", config.path_orig_backup)));
+			events::publish(AppEvent::Error(format!("Error: Source path does not exist: {:?}", config.path_orig_backup)));
 			return Ok(())
 		}
 		if !config.path_dest_backup.exists() {
-			show_popup(NotificationType::GenericError, Some(format!("Error: Destination path does not exist: {:?}", config.path_dest_backup)));
+			events::publish(AppEvent::Error(format!("Error: Destination path does not exist: {:?}", config.path_dest_backup)));
 			return Ok(())
 		}
 		Ok(())
 	}
 
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::filesystem::MemoryFilesystem;
+
+	/// A folder on the disk for what the backup keeps there (the progress journal and the manifest),
+	/// while the copies are written in memory.
+	fn destination(name: &str) -> PathBuf {
+		let path = std::env::temp_dir().join(format!("group_39_test_{}_{}", name, std::process::id()));
+		let _ = std::fs::remove_dir_all(&path);
+		std::fs::create_dir_all(&path).unwrap();
+		path
+	}
+
+	async fn run(filesystem: &MemoryFilesystem, source: &Path, destination: &Path, options: BackupOptions) -> (io::Result<()>, BackupReport) {
+		let report = Arc::new(Mutex::new(BackupReport::default()));
+		let result = backup(Arc::new(filesystem.clone()), source, destination, &options, report.clone(), None).await;
+		let report = report.lock().unwrap().clone();
+		(result, report)
+	}
+
+	#[tokio::test]
+	async fn copies_the_files_of_the_source() {
+		let filesystem = MemoryFilesystem::new();
+		filesystem.add_file("/src/a.txt", b"first");
+		filesystem.add_file("/src/b.txt", b"second");
+		let dest = destination("copy");
+
+		let (result, report) = run(&filesystem, Path::new("/src"), &dest, BackupOptions { total_files: 2, ..Default::default() }).await;
+
+		result.unwrap();
+		assert_eq!(report.copied, 2);
+		assert_eq!(report.failed, 0);
+		assert_eq!(filesystem.file(dest.join("a.txt")).as_deref(), Some(&b"first"[..]));
+		assert_eq!(filesystem.file(dest.join("b.txt")).as_deref(), Some(&b"second"[..]));
+		std::fs::remove_dir_all(&dest).unwrap();
+	}
+
+	#[tokio::test]
+	async fn leaves_out_the_excluded_files_and_folders() {
+		let filesystem = MemoryFilesystem::new();
+		filesystem.add_file("/src/keep.txt", b"kept");
+		filesystem.add_file("/src/draft.tmp", b"left out");
+		filesystem.add_file("/src/node_modules/lib.js", b"left out");
+		let dest = destination("exclude");
+		let options = BackupOptions { total_files: 1, exclude: vec!["*.tmp".to_string(), "node_modules".to_string()], ..Default::default() };
+
+		let (result, report) = run(&filesystem, Path::new("/src"), &dest, options).await;
+
+		result.unwrap();
+		assert_eq!(report.copied, 1);
+		assert!(filesystem.file(dest.join("keep.txt")).is_some());
+		assert!(filesystem.file(dest.join("draft.tmp")).is_none());
+		assert!(filesystem.file(dest.join("node_modules/lib.js")).is_none());
+		std::fs::remove_dir_all(&dest).unwrap();
+	}
+
+	#[tokio::test]
+	async fn keeps_the_nested_folders() {
+		let filesystem = MemoryFilesystem::new();
+		filesystem.add_file("/src/top.txt", b"top");
+		filesystem.add_file("/src/a/b/c/deep.txt", b"deep");
+		filesystem.add_file("/src/a/side.txt", b"side");
+		let dest = destination("nested");
+
+		let (result, report) = run(&filesystem, Path::new("/src"), &dest, BackupOptions { total_files: 3, ..Default::default() }).await;
+
+		result.unwrap();
+		assert_eq!(report.copied, 3);
+		assert_eq!(filesystem.file(dest.join("a/b/c/deep.txt")).as_deref(), Some(&b"deep"[..]));
+		assert_eq!(filesystem.file(dest.join("a/side.txt")).as_deref(), Some(&b"side"[..]));
+		assert_eq!(filesystem.file(dest.join("top.txt")).as_deref(), Some(&b"top"[..]));
+		std::fs::remove_dir_all(&dest).unwrap();
+	}
+
+	#[tokio::test]
+	async fn copies_nothing_from_a_missing_source() {
+		let filesystem = MemoryFilesystem::new();
+		filesystem.add_file("/other/a.txt", b"not in the source");
+		let dest = destination("missing");
+
+		let (result, report) = run(&filesystem, Path::new("/src"), &dest, BackupOptions { total_files: 1, ..Default::default() }).await;
+
+		result.unwrap();
+		assert_eq!(report.copied, 0);
+		assert_eq!(report.failed, 0);
+		assert!(filesystem.file(dest.join("a.txt")).is_none());
+		std::fs::remove_dir_all(&dest).unwrap();
+	}
+
+	#[tokio::test]
+	async fn records_the_path_written_for_each_copy() {
+		let filesystem = MemoryFilesystem::new();
+		filesystem.add_file("/src/new.txt", b"new");
+		filesystem.add_file("/src/old.txt", b"changed");
+		let dest = destination("copies");
+		filesystem.add_file(dest.join("old.txt"), b"previous");
+		let options = BackupOptions { total_files: 2, on_conflict: ConflictPolicy::Rename, ..Default::default() };
+
+		let (result, report) = run(&filesystem, Path::new("/src"), &dest, options).await;
+
+		result.unwrap();
+		let mut copies = report.copies.clone();
+		copies.sort();
+		assert_eq!(copies, vec![
+			(PathBuf::from("/src/new.txt"), dest.join("new.txt")),
+			(PathBuf::from("/src/old.txt"), dest.join("old (1).txt")),
+		]);
+		assert_eq!(filesystem.file(dest.join("old.txt")).as_deref(), Some(&b"previous"[..]));
+		std::fs::remove_dir_all(&dest).unwrap();
+	}
+
+	#[tokio::test]
+	async fn records_no_copy_for_the_skipped_files() {
+		let filesystem = MemoryFilesystem::new();
+		filesystem.add_file("/src/new.txt", b"new");
+		filesystem.add_file("/src/old.txt", b"changed");
+		let dest = destination("skipped");
+		filesystem.add_file(dest.join("old.txt"), b"previous");
+		let options = BackupOptions { total_files: 2, on_conflict: ConflictPolicy::Skip, ..Default::default() };
+
+		let (result, report) = run(&filesystem, Path::new("/src"), &dest, options).await;
+
+		result.unwrap();
+		assert_eq!(report.copies, vec![(PathBuf::from("/src/new.txt"), dest.join("new.txt"))]);
+		std::fs::remove_dir_all(&dest).unwrap();
+	}
+
+	#[test]
+	fn the_wildcards_match_the_names() {
+		assert!(matches_pattern("draft.tmp", "*.tmp"));
+		assert!(matches_pattern("node_modules", "node_modules"));
+		assert!(matches_pattern("report-2024.pdf", "report-????.pdf"));
+		assert!(matches_pattern("a.b.c", "*.*"));
+		assert!(matches_pattern("", "*"));
+		assert!(matches_pattern("Thumbs.db", "*humbs*"));
+		assert!(!matches_pattern("draft.tmp.txt", "*.tmp"));
+		assert!(!matches_pattern("report-24.pdf", "report-????.pdf"));
+		assert!(!matches_pattern("Draft.TMP", "*.tmp"));
+	}
+
+	#[test]
+	fn a_star_backtracks_to_the_last_match() {
+		assert!(matches_pattern("aaab", "*ab"));
+		assert!(matches_pattern("mississippi", "m*iss*pi"));
+		assert!(!matches_pattern("mississippi", "m*iss*x"));
+	}
+
+	#[test]
+	fn sanitizes_the_names_that_fat_cannot_store() {
+		let sanitize = |name: &str| sanitize_name(OsStr::new(name));
+		assert_eq!(sanitize("report.pdf"), None);
+		assert_eq!(sanitize("meeting 10:30?.txt"), Some("meeting 10_30_.txt".to_string()));
+		assert_eq!(sanitize("tab\there"), Some("tab_here".to_string()));
+		assert_eq!(sanitize("notes. ."), Some("notes_".to_string()));
+		assert_eq!(sanitize("CON"), Some("_CON".to_string()));
+		assert_eq!(sanitize("con.txt"), Some("_con.txt".to_string()));
+		assert_eq!(sanitize("console.txt"), None);
+		assert_eq!(sanitize(&"é".repeat(300)), Some("é".repeat(MAX_NAME_LEN)));
+	}
+
+	#[test]
+	fn the_sanitized_names_do_not_collide() {
+		let paths = ["/src/a:b.txt", "/src/a_b.txt", "/src/a?b.txt", "/src/A_B~2.txt"].map(PathBuf::from);
+
+		let names = destination_names(&paths, true);
+
+		assert_eq!(names, ["a_b~3.txt", "a_b.txt", "a_b~4.txt", "A_B~2.txt"].map(OsString::from));
+		assert_eq!(destination_names(&paths, false), paths.iter().map(|path| path.file_name().unwrap().to_os_string()).collect::<Vec<_>>());
+	}
+
+	fn totals(files: usize, size: u64) -> RunTotals {
+		RunTotals { files, size }
+	}
+
+	#[test]
+	fn a_source_shrunk_beyond_the_alarm_is_a_drift() {
+		let previous = totals(1000, 10_000);
+		assert!(Drift::check(previous, totals(400, 10_000), 50).is_some());
+		assert!(Drift::check(previous, totals(1000, 4_000), 50).is_some());
+		assert!(Drift::check(previous, totals(500, 5_000), 50).is_none());
+		assert!(Drift::check(previous, totals(2000, 20_000), 50).is_none());
+		assert!(Drift::check(totals(0, 0), totals(0, 0), 50).is_none());
+	}
+
+	#[test]
+	fn no_drift_is_reported_with_the_alarm_disabled() {
+		assert!(Drift::check(totals(1000, 10_000), totals(0, 0), 0).is_none());
+		assert!(Drift::check(totals(1000, 10_000), totals(0, 0), 99).is_some());
+	}
+
+	#[test]
+	fn the_totals_of_the_previous_backup_are_read_from_the_manifest() {
+		let dest = destination("drift");
+
+		assert_eq!(manifest::record_totals(&dest, totals(1000, 10_000)).unwrap(), None);
+		let previous = manifest::record_totals(&dest, totals(10, 100)).unwrap();
+
+		assert_eq!(previous, Some(totals(1000, 10_000)));
+		let drift = Drift::check(previous.unwrap(), totals(10, 100), 50).unwrap();
+		assert!(drift.message().contains("10 files"));
+		std::fs::remove_dir_all(&dest).unwrap();
+	}
+}
+
@@ -1,73 +1,422 @@
-use rodio::{Decoder, OutputStream, Source};
+use rodio::source::{SineWave, Zero};
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufReader, Error};
-use std::path::PathBuf;
+use std::io::{self, BufReader, Cursor, Error, ErrorKind, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Mutex, OnceLock, PoisonError, RwLock};
 use std::thread;
-use std::thread::JoinHandle;
+use std::time::Duration;
 
-/// Emit a beep sound in a dedicated thread.
+use crate::{speech, supervisor};
+
+/// Events signaled with a sound.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SoundEvent {
+    /// The first step of the pattern has been recognized
+    Armed,
+    /// The backup has been confirmed and started
+    Started,
+    /// The backup has been completed
+    Done,
+    /// The backup has been canceled
+    Canceled,
+    /// Something went wrong
+    Error,
+    /// The backup reached 25, 50 or 75 percent
+    Milestone,
+}
+
+/// Default sounds, embedded in the binary so that it works even without the `resources` folder.
+const POSITIVE_BEEP: &[u8] = include_bytes!("../resources/positive-beep.wav");
+const NEGATIVE_BEEP: &[u8] = include_bytes!("../resources/negative-beep.wav");
+
+/// A decoded sound, ready to be played.
+type Sound = Box<dyn Source<Item = f32> + Send>;
+
+impl SoundEvent {
+    /// Name of the bundled sound file used when the user does not choose one
+    /// (`None` for the events whose default sound is synthesized).
+    fn default_file_name(&self) -> Option<&'static str> {
+        match self {
+            SoundEvent::Armed | SoundEvent::Started => Some("positive-beep.wav"),
+            SoundEvent::Canceled => Some("negative-beep.wav"),
+            SoundEvent::Done | SoundEvent::Error | SoundEvent::Milestone => None,
+        }
+    }
+
+    /// Content of the embedded sound used when the user does not choose one.
+    fn default_sound(&self) -> &'static [u8] {
+        match self {
+            SoundEvent::Armed | SoundEvent::Started | SoundEvent::Done | SoundEvent::Milestone => POSITIVE_BEEP,
+            SoundEvent::Canceled | SoundEvent::Error => NEGATIVE_BEEP,
+        }
+    }
+
+    /// Frequency (Hz) of the synthesized tone used when no sound file can be played.
+    fn tone_frequency(&self) -> f32 {
+        match self {
+            SoundEvent::Armed | SoundEvent::Started | SoundEvent::Done => 880.0,
+            SoundEvent::Milestone => 1320.0,
+            SoundEvent::Canceled => 440.0,
+            SoundEvent::Error => 220.0,
+        }
+    }
+
+    /// Sentence spoken for the event when text-to-speech is enabled
+    /// (`None` for the events announced with more details by the caller, or too frequent to be spoken).
+    fn announcement(&self) -> Option<&'static str> {
+        match self {
+            SoundEvent::Armed => Some("Backup armed"),
+            SoundEvent::Started => Some("Backup started"),
+            SoundEvent::Canceled => Some("Backup canceled"),
+            SoundEvent::Error => Some("Backup error"),
+            SoundEvent::Done | SoundEvent::Milestone => None,
+        }
+    }
+}
+
+/// Folder of the sound files chosen at runtime (e.g. in portable mode); if not set, the default one is used.
+static RESOURCES_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Whether the beeps are enabled and their volume (from 0.0 to 1.0).
+static AUDIO_SETTINGS: RwLock<(bool, f32)> = RwLock::new((true, 1.0));
+
+/// Sound files chosen by the user for some events.
+static CUSTOM_SOUNDS: RwLock<Option<HashMap<SoundEvent, PathBuf>>> = RwLock::new(None);
+
+/// Sets whether the beeps are played and at which volume.
 ///
 /// # Arguments
 ///
-/// * `is_positive` - A boolean indicating whether the beep sound is positive or negative.
+/// * `enabled` - `false` to mute all the beeps.
+/// * `volume` - The volume, from 0 to 100.
+pub fn set_audio_settings(enabled: bool, volume: u8) {
+    *AUDIO_SETTINGS.write().unwrap() = (enabled, volume.min(100) as f32 / 100.0);
+}
+
+/// Sets the sound files (wav, mp3, ogg, flac) to play for some events, instead of the bundled beeps.
 ///
-/// # Returns
+/// # Arguments
 ///
-/// A `JoinHandle` to the spawned thread.
-pub fn emit_beep(is_positive: bool) -> JoinHandle<()> {
-    // Emit a beep sound in a separate thread and get the handle
-    thread::spawn(move || {
-        beep(is_positive).expect("Failed to play beep sound");
-    })
+/// * `sounds` - The sound file of each event; the missing events use the bundled beeps.
+pub fn set_custom_sounds(sounds: HashMap<SoundEvent, PathBuf>) {
+    *CUSTOM_SOUNDS.write().unwrap() = Some(sounds);
 }
 
-/// Plays a beep sound using the `rodio` crate.
+/// Sets the folder containing the sound files.
 ///
 /// # Arguments
 ///
-/// * `is_positive` - A boolean indicating whether the beep sound is positive or negative.
+/// * `dir` - The folder containing `positive-beep.wav` and `negative-beep.wav`.
+pub fn set_resources_dir(dir: &Path) {
+    *RESOURCES_DIR.lock().unwrap() = Some(dir.to_path_buf());
+}
+
+/// Channel towards the audio thread, started at the first sound.
+static AUDIO_THREAD: OnceLock<Mutex<Sender<SoundEvent>>> = OnceLock::new();
+
+/// Emits the sound of an event without blocking the caller: the sound is played by the audio thread,
+/// which owns a single long-lived output stream.
 ///
-/// # Returns
+/// # Arguments
 ///
-/// A `Result` which is `Ok` if the sound was played successfully, or an `Error` if it failed.
-pub fn beep(is_positive: bool) -> Result<(), Error> {
-    // Create an output stream
-    let (_stream, stream_handle) = OutputStream::try_default().unwrap();
-
-    let path_buf = retrieve_path_wav().clone();
-    let wav_buf;
-    if is_positive {
-        wav_buf = path_buf.join("positive-beep.wav");
+/// * `event` - The event to signal.
+pub fn emit_sound(event: SoundEvent) {
+    if let Some(text) = event.announcement() {
+        speech::announce(text);
+    }
+
+    let sender = AUDIO_THREAD.get_or_init(|| Mutex::new(start_audio_thread()));
+    if sender.lock().unwrap().send(event).is_err() {
+        eprintln!("Failed to play beep sound: the audio thread is not running");
+    }
+}
+
+/// Starts the thread that plays the sounds, returning the channel to send them to it.
+/// The output stream cannot be moved between threads, so it is created and kept by this thread;
+/// if no audio device is available, it is looked for again at the next sound.
+/// The thread is restarted by the watchdog if it stops, with the same channel.
+fn start_audio_thread() -> Sender<SoundEvent> {
+    let (tx, rx) = channel::<SoundEvent>();
+    let rx = Mutex::new(rx);
+
+    supervisor::supervise("audio thread", move || {
+        // A thread that panicked has left the channel usable
+        let rx = rx.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut stream: Option<(OutputStream, OutputStreamHandle)> = None;
+
+        while let Ok(event) = rx.recv() {
+            let (enabled, volume) = *AUDIO_SETTINGS.read().unwrap();
+            if !enabled {
+                continue;
+            }
+            // Respect the system mute, and lower the volume during calls, presentations and quiet hours
+            let volume = match system_audio_state() {
+                SystemAudioState::Muted => continue,
+                SystemAudioState::Quiet => volume * DUCKED_VOLUME,
+                SystemAudioState::Normal => volume,
+            };
+
+            if stream.is_none() {
+                stream = match OutputStream::try_default() {
+                    Ok(stream) => Some(stream),
+                    Err(e) => {
+                        eprintln!("No audio device available ({}), using the console bell", e);
+                        None
+                    }
+                };
+            }
+
+            let result = match &stream {
+                Some((_, stream_handle)) => play_sound(stream_handle, event, volume),
+                None => console_bell(),
+            };
+            if let Err(e) = result {
+                eprintln!("Failed to play beep sound: {}", e);
+            }
+        }
+    });
+
+    tx
+}
+
+/// Fraction of the configured volume used when the user should not be disturbed.
+const DUCKED_VOLUME: f32 = 0.2;
+
+/// State of the system audio, which decides whether (and how loud) the beeps are played.
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+enum SystemAudioState {
+    /// The beeps are played at the configured volume
+    Normal,
+    /// The user is in a call, presenting or in quiet hours: the beeps are played at a lower volume
+    Quiet,
+    /// The output device is muted: the beeps are not played
+    Muted,
+}
+
+/// Returns the state of the system audio, from the mute of the default output device and
+/// the notification state of the user (Focus Assist, presentation mode, full-screen apps).
+/// The beeps are played through WASAPI, so they already appear as an entry of this program
+/// in the volume mixer and are ducked by Windows while a communication app is in use.
+#[cfg(target_os = "windows")]
+fn system_audio_state() -> SystemAudioState {
+    use winapi::um::shellapi::{
+        SHQueryUserNotificationState, QUERY_USER_NOTIFICATION_STATE, QUNS_BUSY, QUNS_PRESENTATION_MODE,
+        QUNS_QUIET_TIME, QUNS_RUNNING_D3D_FULL_SCREEN,
+    };
+
+    if is_output_muted() {
+        return SystemAudioState::Muted;
+    }
+
+    let mut state: QUERY_USER_NOTIFICATION_STATE = 0;
+    let result = unsafe { SHQueryUserNotificationState(&mut state) };
+    if result >= 0
+        && matches!(state, QUNS_BUSY | QUNS_PRESENTATION_MODE | QUNS_QUIET_TIME | QUNS_RUNNING_D3D_FULL_SCREEN)
+    {
+        SystemAudioState::Quiet
     } else {
-        wav_buf = path_buf.join("negative-beep.wav");
+        SystemAudioState::Normal
     }
-    let wav: &str = wav_buf.to_str().unwrap();
+}
+
+/// Returns whether the default output device is muted (`false` if it cannot be known).
+#[cfg(target_os = "windows")]
+fn is_output_muted() -> bool {
+    use std::ptr::null_mut;
+    use winapi::shared::minwindef::BOOL;
+    use winapi::um::combaseapi::{CoCreateInstance, CoInitializeEx, CLSCTX_ALL};
+    use winapi::um::endpointvolume::IAudioEndpointVolume;
+    use winapi::um::mmdeviceapi::{eConsole, eRender, IMMDevice, IMMDeviceEnumerator, MMDeviceEnumerator};
+    use winapi::um::objbase::COINIT_MULTITHREADED;
+    use winapi::{Class, Interface};
+
+    unsafe {
+        // The audio thread may already be initialized (by the output stream): the result is not relevant
+        CoInitializeEx(null_mut(), COINIT_MULTITHREADED);
 
-    // Load the sound file
-    let file = BufReader::new(File::open(wav)?);
+        let mut enumerator: *mut IMMDeviceEnumerator = null_mut();
+        if CoCreateInstance(
+            &MMDeviceEnumerator::uuidof(),
+            null_mut(),
+            CLSCTX_ALL,
+            &IMMDeviceEnumerator::uuidof(),
+            &mut enumerator as *mut _ as *mut _,
+        ) < 0
+        {
+            return false;
+        }
 
-    let source = Decoder::new(file).unwrap();
+        let mut device: *mut IMMDevice = null_mut();
+        if (*enumerator).GetDefaultAudioEndpoint(eRender, eConsole, &mut device) < 0 {
+            (*enumerator).Release();
+            return false;
+        }
 
-    // Play the sound
-    stream_handle.play_raw(source.convert_samples()).unwrap();
+        let mut endpoint_volume: *mut IAudioEndpointVolume = null_mut();
+        let mut muted: BOOL = 0;
+        if (*device).Activate(&IAudioEndpointVolume::uuidof(), CLSCTX_ALL, null_mut(), &mut endpoint_volume as *mut _ as *mut _) >= 0 {
+            (*endpoint_volume).GetMute(&mut muted);
+            (*endpoint_volume).Release();
+        }
+        (*device).Release();
+        (*enumerator).Release();
+
+        muted != 0
+    }
+}
 
-    // Keep the program running long enough to hear the sound
-    thread::sleep(std::time::Duration::from_secs(1));
+/// On the other systems the beeps always follow the configured volume.
+#[cfg(not(target_os = "windows"))]
+fn system_audio_state() -> SystemAudioState {
+    SystemAudioState::Normal
+}
+
+/// Plays the sound of an event using the `rodio` crate: the file chosen by the user if any
+/// (falling back to the bundled beep if it cannot be played), otherwise the bundled beep.
+/// If no sound can be decoded a tone is synthesized, and if the audio device cannot be used
+/// (e.g. in a remote desktop session) the console bell is used.
+/// The sound is played in the background for its whole length, without waiting for it.
+///
+/// # Arguments
+///
+/// * `stream_handle` - The handle of the output stream.
+/// * `event` - The event to signal.
+/// * `volume` - The volume, from 0.0 to 1.0.
+///
+/// # Returns
+///
+/// A `Result` which is `Ok` if the sound was played successfully, or an `Error` if it failed.
+fn play_sound(stream_handle: &OutputStreamHandle, event: SoundEvent, volume: f32) -> Result<(), Error> {
+    let custom_sound = CUSTOM_SOUNDS.read().unwrap().as_ref().and_then(|sounds| sounds.get(&event).cloned());
+    let source = match custom_sound.map(|path| decode_file(&path)) {
+        Some(Ok(source)) => source,
+        Some(Err(e)) => {
+            eprintln!("Unable to play the sound of {:?}, using the default one: {}", event, e);
+            default_sound(event).unwrap_or_else(|_| synthesized_tone(event))
+        }
+        None => default_sound(event).unwrap_or_else(|_| synthesized_tone(event)),
+    };
+
+    // Play the sound at the configured volume
+    let sink = match Sink::try_new(stream_handle) {
+        Ok(sink) => sink,
+        Err(e) => {
+            eprintln!("Unable to play on the audio device ({}), using the console bell", e);
+            return console_bell();
+        }
+    };
+    sink.set_volume(volume);
+    sink.append(source);
+
+    // The sink keeps playing until the end of the sound, since the stream is kept alive by the audio thread
+    sink.detach();
 
     Ok(())
 }
 
-#[cfg(not(debug_assertions))]
-fn retrieve_path_wav() -> PathBuf {
-    use std::env;
+/// Opens and decodes a sound file.
+fn decode_file(path: &Path) -> Result<Sound, Error> {
+    decode(BufReader::new(File::open(path)?))
+}
 
-    let exe_path = env::current_exe().expect("Failed to get current executable path");
-    let exe_dir = exe_path.parent().expect("Failed to get executable directory");
+/// Decodes a sound from any source of bytes.
+fn decode<R: Read + Seek + Send + Sync + 'static>(data: R) -> Result<Sound, Error> {
+    let decoder = Decoder::new(data).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    Ok(Box::new(decoder.convert_samples()))
+}
+
+/// Generates a short sine-wave beep, with a different pitch for each kind of event.
+fn synthesized_tone(event: SoundEvent) -> Sound {
+    Box::new(
+        SineWave::new(event.tone_frequency())
+            .take_duration(Duration::from_millis(300))
+            .amplify(0.3),
+    )
+}
 
-    exe_dir.join("resources/")
+/// Rings the console bell, the last resort when no audio device is available
+/// (on stderr, since stdout may carry the JSON summary of `backup now`).
+fn console_bell() -> Result<(), Error> {
+    let mut stderr = io::stderr();
+    stderr.write_all(b"\x07")?;
+    stderr.flush()
+}
+
+/// Returns the default sound of an event: the file in the resources folder if present
+/// (so that the bundled beeps can still be replaced), otherwise the one embedded in the binary.
+/// The completion jingle and the progress ticks are synthesized.
+fn default_sound(event: SoundEvent) -> Result<Sound, Error> {
+    let file_name = match event {
+        SoundEvent::Done => return Ok(completion_jingle()),
+        SoundEvent::Milestone => return Ok(progress_tick()),
+        SoundEvent::Error => return Ok(error_alarm()),
+        _ => event.default_file_name().unwrap_or_default(),
+    };
+
+    let path_buf = RESOURCES_DIR.lock().unwrap().clone().unwrap_or_else(retrieve_path_wav);
+    let path = path_buf.join(file_name);
+    if path.exists() {
+        decode_file(&path)
+    } else {
+        decode(Cursor::new(event.default_sound()))
+    }
+}
+
+/// A short high tick, played at the progress milestones.
+fn progress_tick() -> Sound {
+    Box::new(
+        SineWave::new(SoundEvent::Milestone.tone_frequency())
+            .take_duration(Duration::from_millis(80))
+            .amplify(0.3),
+    )
+}
+
+/// Three ascending notes (C, E, G), played when the backup is completed.
+fn completion_jingle() -> Sound {
+    let notes = [523.25, 659.25, 783.99]
+        .into_iter()
+        .map(|frequency| SineWave::new(frequency).take_duration(Duration::from_millis(180)).amplify(0.3));
+    Box::new(rodio::source::from_iter(notes))
+}
+
+/// Two short low buzzes followed by a longer, lower one, played when something goes wrong:
+/// clearly different from both the success and the cancel beeps.
+fn error_alarm() -> Sound {
+    let buzz = |frequency: f32, millis: u64| -> Sound {
+        Box::new(SineWave::new(frequency).take_duration(Duration::from_millis(millis)).amplify(0.4))
+    };
+    let pause = || -> Sound { Box::new(Zero::<f32>::new(1, 48000).take_duration(Duration::from_millis(80))) };
+    Box::new(rodio::source::from_iter(vec![buzz(220.0, 150), pause(), buzz(220.0, 150), pause(), buzz(165.0, 450)]))
+}
+
+/// Starts a thread that plays a tick every time the backup progress crosses 25, 50 or 75 percent.
+///
+/// # Arguments
+///
+/// * `progress` - The channel where the backup sends its percentage of progress.
+pub fn start_progress_cues(progress: Receiver<usize>) {
+    thread::spawn(move || {
+        let mut next_milestone = 25;
+        while let Ok(percent) = progress.recv() {
+            if next_milestone < 100 && percent >= next_milestone {
+                emit_sound(SoundEvent::Milestone);
+                next_milestone = (percent / 25 + 1) * 25;
+            }
+        }
+    });
+}
+
+#[cfg(not(debug_assertions))]
+fn retrieve_path_wav() -> PathBuf {
+    crate::data_dirs::resources_dir()
 }
 
 #[cfg(debug_assertions)]
 fn retrieve_path_wav() -> PathBuf {
     PathBuf::from("resources/")
-}
\ No newline at end of file
+}
@@ -1,9 +1,9 @@
-use crate::beeper::emit_beep;
-use crate::notification_popup;
-use crate::notification_popup::NotificationType;
-use rdev::{listen, EventType, Key};
-use std::sync::{Arc, Barrier, Condvar, Mutex};
-use std::thread;
+use crate::backup;
+use crate::config::SharedConfig;
+use crate::events::{self, ActivationPattern, AppEvent};
+use crate::{input_hook, quick_status, session, supervisor, trigger};
+use rdev::{EventType, Key};
+use std::sync::{Arc, Condvar, Mutex};
 use std::time::{Duration, Instant};
 
 #[derive(Debug)]
@@ -19,18 +19,31 @@ enum State {
 /// This function spawns a new thread that listens for specific key's combination and click events.
 /// After pressing for 5 seconds ctrl+alt+b, the user can choose to confirm throughout three consecutive
 /// left clicks or to cancel (throughout 3 right ones), restarting the pattern.
-pub fn start_button_and_clicks_pattern_recognizer() {
+///
+/// # Arguments
+///
+/// * `config` - The shared configuration, used to estimate the backup once the pattern is activated.
+pub fn start_button_and_clicks_pattern_recognizer(config: SharedConfig) {
     let terminate_pair = Arc::new((Mutex::new(false), Condvar::new()));
     let terminate_pair_clone = Arc::clone(&terminate_pair);
 
-    thread::spawn(move || {
+    // Restarted by the watchdog if it stops, from the beginning of the pattern
+    supervisor::supervise("keyboard and mouse listener", move || {
         let mut state = State::Waiting;
+        let config = config.clone();
+        let terminate_pair_clone = terminate_pair_clone.clone();
 
-        listen(move |event| {
+        let result = input_hook::listen(move |event| {
+            quick_status::handle_event(&event.event_type);
             match &mut state {
                 State::Waiting => {
+                    // Ignore the keys while the session is locked or remote, or a full-screen app is running
+                    let blocked = {
+                        let config = config.read().unwrap();
+                        session::arming_blocked(config.pause_when_locked, config.pause_in_fullscreen)
+                    };
                     // Check for Ctrl + Alt + B key press
-                    if let EventType::KeyPress(key) = event.event_type {
+                    if let (false, EventType::KeyPress(key)) = (blocked, event.event_type) {
                         if key == Key::ControlLeft || key == Key::Alt || key == Key::KeyB {
                             state = State::CtrlAltBPressed(Instant::now());
                         }
@@ -40,8 +53,8 @@ pub fn start_button_and_clicks_pattern_recognizer() {
                     // Check if 5 seconds have passed
                     if start_time.elapsed() >= Duration::from_secs(5) {
                         state = State::Activated(0, 0);
-                        emit_beep(true);
-                        notification_popup::show_popup(NotificationType::FirstStepDoneBC, None);
+                        events::publish(AppEvent::Armed(ActivationPattern::ButtonsAndClicks));
+                        backup::estimate_backup_in_background(config.read().unwrap().clone(), ActivationPattern::ButtonsAndClicks);
                     } else if let EventType::KeyRelease(key) = event.event_type {
                         // Reset state if any key other than Ctrl, Alt, or B is released
                         if key != Key::ControlLeft && key != Key::Alt && key != Key::KeyB {
@@ -65,8 +78,7 @@ pub fn start_button_and_clicks_pattern_recognizer() {
                         }
                         // Confirmed if 3 consecutive left clicks
                         if *left_clicks >= 3 {
-                            emit_beep(true);
-                            notification_popup::show_popup(NotificationType::BackupStarted, None);
+                            events::publish(AppEvent::Started);
 
                             let (lock, cvar) = &*terminate_pair_clone;
                             let mut terminated = lock.lock().unwrap();
@@ -75,29 +87,25 @@ pub fn start_button_and_clicks_pattern_recognizer() {
                             state = State::Sleeping;
                             // Canceled if 3 consecutive right clicks
                         } else if *right_clicks >= 3 {
-                            emit_beep(false);
-                            notification_popup::show_popup(NotificationType::BackupCanceled, None);
+                            events::publish(AppEvent::Canceled);
                             state = State::Waiting;
                         }
                     }
                 },
-                State::Sleeping => {
-                    let barrier = Barrier::new(2);
-                    barrier.wait();     // <- NOTE:
-                    // since there aren't any other instance of the barrier,
-                    // it will wait until the end of the program, without consuming any cpu cycle
-
-                    // alternative strategy
-                    /*thread::sleep(Duration::from_secs(123_456));*/
-                }
+                // The events are ignored until the end of the program: blocking here would stall the input
+                // of the whole system, and would keep the hook from being removed at exit
+                State::Sleeping => {}
             }
-        }).unwrap();
+        });
+        if let Err(e) = result {
+            println!("Unable to listen to the keyboard and the mouse: {:?}", e);
+        }
     });
 
-    // Wait for the condition variable
+    // Wait for the condition variable, or for the backup to be started without the pattern (e.g. by a power event)
     let (lock, cvar) = &*terminate_pair;
     let mut terminated = lock.lock().unwrap();
-    while !*terminated {
-        terminated = cvar.wait(terminated).unwrap();
+    while !*terminated && !trigger::is_triggered() {
+        terminated = cvar.wait_timeout(terminated, Duration::from_secs(1)).unwrap().0;
     }
 }
\ No newline at end of file
@@ -0,0 +1,277 @@
+use crate::config::Config;
+use crate::schedule::Schedule;
+use std::path::PathBuf;
+
+/// Values passed on the command line, overriding the ones in the configuration file.
+#[derive(Debug, Default, Clone)]
+pub struct CliArgs {
+    pub config_path: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub source: Option<PathBuf>,
+    pub dest: Option<PathBuf>,
+    pub types: Option<Vec<String>>,
+    pub btn_rec: Option<bool>,
+    /// `backup now`: run the backup immediately, without waiting for the activation pattern,
+    /// and exit with a code describing the result.
+    pub backup_now: bool,
+    /// `--scheduled`: `backup now` has been started by the scheduler, so it runs only inside `trigger_hours`.
+    pub scheduled: bool,
+    /// `--retry-files <path>`: `backup now` copies only the source files listed in this file, one per line.
+    pub retry_files: Option<PathBuf>,
+    /// `--install-schedule`: create a scheduled task running `backup now`, then exit.
+    pub install_schedule: Option<Schedule>,
+    /// `--remove-schedule`: remove the scheduled task, then exit.
+    pub remove_schedule: bool,
+    /// `restore`: choose one of the backups found and copy it back, then exit.
+    pub restore: bool,
+    /// `--from`: where to look for the backups to restore, besides the destinations of the configuration.
+    pub restore_from: Option<PathBuf>,
+    /// `--to`: the folder where the backup is restored.
+    pub restore_to: Option<PathBuf>,
+    /// `search <pattern>`: list the backups containing the files matching the pattern, then exit.
+    pub search: Option<String>,
+    /// `--only` (repeatable): paths or patterns of the files to restore; all the files if empty.
+    pub restore_only: Vec<String>,
+    /// `--settings`: open the settings window on the configuration file, then exit.
+    pub settings: bool,
+    /// `--tutorial`: practice the activation gesture over the screen, then exit.
+    pub tutorial: bool,
+    /// `--analyze`: print the largest folders and extensions that the backup would copy, then exit.
+    pub analyze: bool,
+    /// `self-check`: run a whole backup of generated files into a temporary folder, report PASS or FAIL, then exit.
+    pub self_check: bool,
+    /// `--verify-log <path>`: verify the chain of hashes of a log written with `log_hash_chain`, then exit.
+    pub verify_log: Option<PathBuf>,
+    /// `--service`: run as a system service, starting the program in the session of the user at the console.
+    pub service: bool,
+    /// `prune`: delete the backups in the destinations (and in `--from`) not kept by `retention`, then exit.
+    pub prune: bool,
+    /// `--dry-run`: with `prune`, only show what would be deleted.
+    pub dry_run: bool,
+    /// `remote <address> <command>`: send a command to the remote control of another machine, then exit.
+    pub remote: Option<(String, String)>,
+}
+
+pub const USAGE: &str = "Usage: group_39 [backup now] [OPTIONS]
+
+Commands:
+  backup now           Run the backup immediately, print a JSON summary (the only output on stdout,
+                       the progress and the messages go to stderr) and exit with:
+                       0 success, 1 some files failed, 2 configuration error or source larger
+                       than expected, 3 destination missing
+  restore              List the backups found in the destinations (and in --from), choose one and
+                       copy it back to a folder (--to, or asked)
+  search <pattern>     List the backups (in the destinations and in --from) containing the files
+                       named like the pattern, e.g. budget.xlsx or \"*.xlsx\", with their versions
+  self-check           Check the installation: simulate the gesture, back up some generated files to a
+                       temporary folder, verify their copies, the manifest and the notifications, print
+                       PASS or FAIL for each step and exit with 0 if all passed, 1 otherwise
+  prune                Delete the backups found in the destinations (and in --from) that the retention
+                       policy of the configuration does not keep, after asking for confirmation
+  remote <address> <command>
+                       Send status, arm, confirm or cancel to the remote control of another machine,
+                       e.g. remote 192.168.1.10:47020 arm, with the key of remote_control in --config
+
+Options:
+  --config <path>      Configuration file to use instead of the one in the folder of the user
+  --profile <name>     Profile of the configuration file to use (overrides active_profile)
+  --source <path>      Folder to back up (overrides path_orig_backup)
+  --dest <path>        Destination of the backup (overrides path_dest_backup and dest_volume_label)
+  --types <list>       Comma separated file extensions to back up, e.g. mp3,wav (overrides type_files)
+  --btn-rec <bool>     true to use ctrl+alt+b and clicks, false to use the rectangle gesture (overrides btn_rec)
+  --install-schedule <when>
+                       Run `backup now` periodically (Task Scheduler on Windows, crontab elsewhere):
+                       \"hourly\", \"daily 21:00\" or \"weekly mon 21:00\"; --config is passed along
+  --remove-schedule    Remove the periodic backup
+  --scheduled          With `backup now`, skip the backup outside trigger_hours (added to the scheduled task)
+  --retry-files <path> With `backup now`, copy only the source files listed in this file, one per line (used
+                       to copy elevated the files denied to a backup without privileges)
+  --analyze            Print the largest folders and file types that the backup would copy with the
+                       current filters, without copying anything
+  --verify-log <path>  Verify that a log written with log_hash_chain has not been modified, then exit
+                       with 0 if it is intact, 1 otherwise
+  --service            Run as a system service (e.g. started at boot as SYSTEM or root): the program is
+                       started in the session of the user at the console, with the configuration in
+                       the .emergency_backup folder of their home if any (otherwise --config)
+  --tutorial           Practice the rectangle gesture, guided by arrows along the edges of the screen
+  --settings           Open the settings window to edit the configuration file (with --config, that file)
+  --from <path>        Folder or drive where `restore` and `search` look for backups
+  --to <path>          Folder where `restore` copies the backup
+  --dry-run            With prune, only list the backups and the files that would be deleted and the
+                       space freed
+  --only <pattern>     Restore only these files or folders, e.g. \"Documents/Thesis/**\" or \"**/*.xlsx\";
+                       it can be repeated
+  --help               Print this help";
+
+impl CliArgs {
+    /// Parses the command line arguments (without the program name).
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - The arguments to parse.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the parsed `CliArgs`, or a message describing the wrong argument.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<CliArgs, String> {
+        let mut cli = CliArgs::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            // Both `--flag value` and `--flag=value` are accepted
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg.clone(), None),
+            };
+            let mut value = || {
+                inline_value.clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("Missing value for {}", flag))
+            };
+
+            match flag.as_str() {
+                "--config" => cli.config_path = Some(PathBuf::from(value()?)),
+                "--profile" => cli.profile = Some(value()?),
+                "--source" => cli.source = Some(PathBuf::from(value()?)),
+                "--dest" => cli.dest = Some(PathBuf::from(value()?)),
+                "--types" => {
+                    cli.types = Some(value()?
+                        .split(',')
+                        .map(|t| t.trim().to_string())
+                        .filter(|t| !t.is_empty())
+                        .collect())
+                }
+                "--btn-rec" => {
+                    let v = value()?;
+                    cli.btn_rec = Some(v.parse().map_err(|_| format!("Invalid value for --btn-rec: {}", v))?)
+                }
+                "--install-schedule" => cli.install_schedule = Some(Schedule::parse(&value()?)?),
+                "--remove-schedule" => cli.remove_schedule = true,
+                "--scheduled" => cli.scheduled = true,
+                "--retry-files" => cli.retry_files = Some(PathBuf::from(value()?)),
+                "--settings" => cli.settings = true,
+                "--tutorial" => cli.tutorial = true,
+                "--analyze" => cli.analyze = true,
+                "--service" => cli.service = true,
+                "--verify-log" => cli.verify_log = Some(PathBuf::from(value()?)),
+                "--from" => cli.restore_from = Some(PathBuf::from(value()?)),
+                "--to" => cli.restore_to = Some(PathBuf::from(value()?)),
+                "--only" => cli.restore_only.push(value()?),
+                "restore" => cli.restore = true,
+                "prune" => cli.prune = true,
+                "self-check" => cli.self_check = true,
+                "--dry-run" => cli.dry_run = true,
+                "search" => match args.next() {
+                    Some(pattern) => cli.search = Some(pattern),
+                    None => return Err(format!("Missing pattern for search\n\n{}", USAGE)),
+                },
+                "remote" => match (args.next(), args.next()) {
+                    (Some(address), Some(command)) => cli.remote = Some((address, command)),
+                    _ => return Err(format!("Missing address or command for remote\n\n{}", USAGE)),
+                },
+                "--help" | "-h" => return Err(USAGE.to_string()),
+                "backup" => match args.next().as_deref() {
+                    Some("now") => cli.backup_now = true,
+                    _ => return Err(format!("Unknown command: backup (did you mean `backup now`?)\n\n{}", USAGE)),
+                },
+                _ => return Err(format!("Unknown argument: {}\n\n{}", arg, USAGE)),
+            }
+        }
+        Ok(cli)
+    }
+
+    /// Returns the options as command line arguments, to run the program again with the same options.
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        let mut push = |flag: &str, value: String| {
+            args.push(flag.to_string());
+            args.push(value);
+        };
+        if let Some(config_path) = &self.config_path {
+            push("--config", config_path.display().to_string());
+        }
+        if let Some(profile) = &self.profile {
+            push("--profile", profile.clone());
+        }
+        if let Some(source) = &self.source {
+            push("--source", source.display().to_string());
+        }
+        if let Some(dest) = &self.dest {
+            push("--dest", dest.display().to_string());
+        }
+        if let Some(types) = &self.types {
+            push("--types", types.join(","));
+        }
+        if let Some(btn_rec) = self.btn_rec {
+            push("--btn-rec", btn_rec.to_string());
+        }
+        args
+    }
+
+    /// Overrides the values of the configuration with the ones passed on the command line.
+    /// A destination given with `--dest` replaces the volume of `dest_volume_label` too.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(source) = &self.source {
+            config.path_orig_backup = source.clone();
+        }
+        if let Some(dest) = &self.dest {
+            config.path_dest_backup = dest.clone();
+            config.dest_volume_label = None;
+        }
+        if let Some(types) = &self.types {
+            config.type_files = types.clone();
+            config.normalize_type_files();
+        }
+        if let Some(btn_rec) = self.btn_rec {
+            config.btn_rec = btn_rec;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CliArgs, String> {
+        CliArgs::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn accepts_the_values_after_a_space_or_an_equal_sign() {
+        let cli = parse(&["--source", "/home/user", "--dest=/media/usb", "--types", " mp3, .wav,,", "--btn-rec=true"]).unwrap();
+
+        assert_eq!(cli.source, Some(PathBuf::from("/home/user")));
+        assert_eq!(cli.dest, Some(PathBuf::from("/media/usb")));
+        assert_eq!(cli.types, Some(vec!["mp3".to_string(), ".wav".to_string()]));
+        assert_eq!(cli.btn_rec, Some(true));
+    }
+
+    #[test]
+    fn rejects_the_wrong_arguments() {
+        assert!(parse(&["--unknown"]).is_err());
+        assert!(parse(&["--dest"]).is_err());
+        assert!(parse(&["--btn-rec", "yes"]).is_err());
+    }
+
+    #[test]
+    fn the_arguments_are_parsed_back_to_the_same_options() {
+        let cli = CliArgs {
+            config_path: Some(PathBuf::from("/etc/backup config.toml")),
+            profile: Some("work".to_string()),
+            source: Some(PathBuf::from("/home/user/a=b")),
+            dest: Some(PathBuf::from("/media/usb")),
+            types: Some(vec![".mp3".to_string(), ".wav".to_string()]),
+            btn_rec: Some(false),
+            ..Default::default()
+        };
+
+        let parsed = CliArgs::parse(cli.to_args()).unwrap();
+
+        assert_eq!(parsed.config_path, cli.config_path);
+        assert_eq!(parsed.profile, cli.profile);
+        assert_eq!(parsed.source, cli.source);
+        assert_eq!(parsed.dest, cli.dest);
+        assert_eq!(parsed.types, cli.types);
+        assert_eq!(parsed.btn_rec, cli.btn_rec);
+    }
+}
@@ -1,23 +1,518 @@
-use crate::notification_popup::{show_popup, NotificationType};
+use crate::accessibility;
+use crate::app_hooks;
+use crate::archive::ArchiveFormat;
+use crate::backup::{ConflictPolicy, PlaceholderPolicy, VerifyMode};
+use crate::beeper::{self, SoundEvent};
+#[cfg(not(debug_assertions))]
+use crate::data_dirs;
+use crate::elevation::Elevation;
+use crate::encryption::{self, EncryptionKey};
+use crate::filesystem;
+use crate::locale;
+use crate::metadata_crypto::{self, MetadataRecipient};
+use crate::network::{ConnectionType, NetworkPolicy};
+use crate::notification_popup::{self, show_popup, NotificationMode, NotificationType};
+use crate::power::LowBatteryAction;
+use crate::ransomware;
+use crate::remote_control::RemoteControlConfig;
+use crate::restore::RetentionPolicy;
+use crate::speech;
+use crate::trigger;
+use crate::two_factor;
+use crate::uring;
+use crate::volume;
 use notify::{Config as NotifyConfig, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_yaml;
 use std::collections::HashMap;
 use std::error::Error;
+use std::fmt;
 use std::fs::File;
 use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Supported names of the configuration file, in order of preference.
+const CONFIG_FILE_NAMES: [&str; 4] = ["config.yaml", "config.yml", "config.toml", "config.json"];
+
+/// The configuration shared between the threads, replaced when the file is modified.
+pub type SharedConfig = Arc<RwLock<Config>>;
+
+/// Profile selected on the command line, which takes precedence over `active_profile`.
+static PROFILE_OVERRIDE: Mutex<Option<String>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
+	// The main fields can be omitted when they are set by the active profile:
+	// the validation checks that they are set in the end.
+	#[serde(default)]
 	pub path_dest_backup: PathBuf,
+	#[serde(default)]
 	pub path_orig_backup: PathBuf,
+	#[serde(default)]
 	pub type_files: Vec<String>,
-	pub btn_rec: bool
+	#[serde(default)]
+	pub btn_rec: bool,
+	/// Creates in the destination also the source folders without files to copy (e.g. empty folders),
+	/// for tools that rely on the folder structure.
+	#[serde(default)]
+	pub preserve_empty_dirs: bool,
+	/// What to do when a file already exists in the destination: `overwrite` (the default), `skip`,
+	/// `rename` (copy it as `name (1).ext`) or `newer_wins` (replace it only if the source is newer).
+	#[serde(default)]
+	pub on_conflict: ConflictPolicy,
+	/// Evacuation mode: each source file is removed once its copy has been read back identical,
+	/// to get the data off a failing disk instead of mirroring it.
+	#[serde(default)]
+	pub evacuate: bool,
+	/// What to do with the cloud placeholders (e.g. OneDrive "online-only" files, whose content would be downloaded):
+	/// `skip` (the default), `hydrate` (download and copy them) or `stub` (create empty files with the same names).
+	#[serde(default)]
+	pub cloud_placeholders: PlaceholderPolicy,
+	/// Seconds a copy can go without reading any data (e.g. a stuck USB controller or a dead network share)
+	/// before it is canceled and tried once more; the files that stall again are reported as failed.
+	#[serde(default = "default_stall_timeout")]
+	pub stall_timeout_secs: u64,
+	/// Records every copied file, with the duration and the throughput of its copy, in the `files.log`
+	/// of the run directory, to find which folders make the backup slow.
+	#[serde(default)]
+	pub verbose_file_log: bool,
+	/// Maximum duration of a backup, in minutes: once exceeded, the files being copied are finished and the others
+	/// are left to the next backup, which resumes from there (an emergency backup should save *something* in time).
+	#[serde(default)]
+	pub max_run_minutes: Option<u64>,
+	/// Maximum memory of the copies in progress (their buffers), in megabytes: once reached, the next copies wait
+	/// for the running ones to end, so that a machine with little memory does not swap. If not set, there is no cap.
+	#[serde(default)]
+	pub max_memory_mb: Option<u64>,
+	/// Reads and writes the files with io_uring (Linux only, in a build with the `io_uring` feature),
+	/// faster than the default copy with many small files.
+	#[serde(default)]
+	pub io_uring: bool,
+	/// Memory used to read ahead the source files in order when the source is a rotational disk (HDD), in megabytes,
+	/// instead of reading many of them at the same time; 0 to disable the read-ahead.
+	#[serde(default = "default_read_ahead_mb")]
+	pub read_ahead_mb: u64,
+	/// Adjusts the parallel copies (up to the limit of the open files) and the size of their buffers during the backup,
+	/// keeping the settings that copy faster to the destination drive.
+	#[serde(default)]
+	pub auto_tune: bool,
+	/// Looks for duplicate files in the source (same size and content) while backing it up, and reports
+	/// how much space removing them would save. The files with the same size are read once more to compare them.
+	#[serde(default)]
+	pub find_duplicates: bool,
+	/// Copies once more the files modified while they were copied; the ones modified again are marked as "fuzzy"
+	/// in the manifest (their copy may be internally inconsistent) either way.
+	#[serde(default)]
+	pub recopy_changed: bool,
+	/// Names of files and folders not to back up; `*` and `?` wildcards are allowed (e.g. `*.tmp`, `node_modules`).
+	#[serde(default)]
+	pub exclude: Vec<String>,
+	/// Other files with filter lists (`type_files` and `exclude`) merged into this configuration,
+	/// so that they can be shared between machines.
+	#[serde(default)]
+	pub include: Includes,
+	/// A second destination (e.g. a network share besides the USB drive) where every file is copied as well,
+	/// so that the backup survives the failure of one of them.
+	#[serde(default)]
+	pub secondary_dest: Option<PathBuf>,
+	/// Number of files above which the source is considered larger than expected (e.g. a recursive link exploded it):
+	/// the backup starts only after the activation pattern is repeated. If not set, the number of files is not checked.
+	#[serde(default)]
+	pub max_expected_files: Option<usize>,
+	/// Size (in GB) above which the source is considered larger than expected, as for `max_expected_files`.
+	#[serde(default)]
+	pub max_expected_size_gb: Option<f64>,
+	/// Reads back the copied files after the backup to detect drives that return garbage:
+	/// `off` (the default), `sample` (`verify_sample_size` files spread over the backup) or `all`.
+	#[serde(default)]
+	pub verify: VerifyMode,
+	/// Number of files read back with `verify: sample`.
+	#[serde(default = "default_verify_sample_size")]
+	pub verify_sample_size: usize,
+	/// Compares the whole content of the files read back through their hashes, instead of their first and last bytes.
+	/// The hashes are cached in the destination, so the files unchanged since the last verification are not hashed again.
+	#[serde(default)]
+	pub verify_hash: bool,
+	/// Reads back the copied files (`verify`) only after the user has not used the keyboard and the mouse
+	/// for these minutes, so that the copy ends as fast as possible and the verification does not slow down
+	/// the work; within `max_run_minutes`, if set, otherwise the verification is skipped.
+	#[serde(default)]
+	pub verify_when_idle_minutes: Option<u64>,
+	/// Label of the volume (or filesystem UUID on Linux) to use as destination, e.g. the USB drive,
+	/// resolved to its mount point when the backup starts; it takes precedence over `path_dest_backup`.
+	#[serde(default)]
+	pub dest_volume_label: Option<String>,
+	/// Folder inside the volume of `dest_volume_label` where the backup is copied.
+	#[serde(default)]
+	pub dest_volume_subdir: Option<PathBuf>,
+	/// How each type of notification is shown: `popup`, `toast`, `silent` or `beep_only`
+	/// (e.g. `FirstStepDone: toast`); the missing types are shown as popups.
+	#[serde(default)]
+	pub notifications: HashMap<NotificationType, NotificationMode>,
+	/// Shows the popups (on Windows) and the windows of the application with extra-large text, for low-vision users;
+	/// the high-contrast mode and the text size of the system are always followed.
+	#[serde(default)]
+	pub large_notifications: bool,
+	/// Titles of the popups and of the system notifications, by notification type (e.g. `BackupDone: Backup completato`);
+	/// the missing types use the default titles.
+	#[serde(default)]
+	pub popup_titles: HashMap<NotificationType, String>,
+	/// Locale of the numbers, sizes and dates in the reports and in the notifications (e.g. `it-IT`, `en-US`);
+	/// the one of the system if not set.
+	#[serde(default)]
+	pub locale: Option<String>,
+	/// `false` to disable the Ctrl+Alt+S hotkey, which shows the last backup, the free space in the destination
+	/// and whether the backup is armed.
+	#[serde(default = "default_quick_status_hotkey")]
+	pub quick_status_hotkey: bool,
+	/// `false` to mute all the beeps.
+	#[serde(default = "default_beep_enabled")]
+	pub beep_enabled: bool,
+	/// Volume of the beeps, from 0 to 100.
+	#[serde(default = "default_beep_volume")]
+	pub beep_volume: u8,
+	/// Sound files (wav, mp3, ogg, flac) for the events `armed`, `started`, `done`, `canceled`, `error` and `milestone`;
+	/// the missing events use the bundled beeps.
+	#[serde(default)]
+	pub sounds: HashMap<SoundEvent, PathBuf>,
+	/// Plays a short tick at 25, 50 and 75% of the backup (the `milestone` sound).
+	#[serde(default)]
+	pub progress_sounds: bool,
+	/// Speaks the key events aloud ("Backup armed", "Backup complete, ... copied"), using
+	/// the Windows speech API, `say` on macOS or speech-dispatcher on Linux.
+	#[serde(default)]
+	pub speech_enabled: bool,
+	/// Portable mode: logs, resources and (if not set) the destination are taken from the folder of the executable,
+	/// e.g. the USB drive the application is run from, regardless of the current directory.
+	#[serde(default)]
+	pub portable: bool,
+	/// Named profiles (e.g. `work`, `home`), each overriding some of the fields above.
+	#[serde(default)]
+	pub profiles: HashMap<String, Profile>,
+	/// The profile in use; it can be overridden with the `--profile` command line flag.
+	#[serde(default)]
+	pub active_profile: Option<String>,
+	/// Root folder for the per-run log directories and the `runs.index` file.
+	/// If not set, the `log` folder of the user is used (`log/` in the current directory in debug builds, next to the executable in portable mode).
+	#[serde(default)]
+	pub log_dir: Option<PathBuf>,
+	/// Path of the status file periodically written for external monitoring.
+	/// If not set, `status.json` in the log root is used.
+	#[serde(default)]
+	pub status_file: Option<PathBuf>,
+	/// Seconds between two writes of the status file.
+	#[serde(default = "default_status_interval")]
+	pub status_interval_secs: u64,
+	/// Seconds between two checks of the destination (existence and writability).
+	#[serde(default = "default_dest_check_interval")]
+	pub dest_check_interval_secs: u64,
+	/// On laptops, the battery charge (from 0 to 100) below which the backup is not started silently.
+	/// If not set, the battery is not checked.
+	#[serde(default)]
+	pub min_battery_percent: Option<u8>,
+	/// What to do when the battery is below `min_battery_percent`: `warn` (the default) or `delay`
+	/// (wait for the charger or for the battery to be charged again).
+	#[serde(default)]
+	pub low_battery_action: LowBatteryAction,
+	/// Starts the backup automatically, without the activation pattern, when the battery drops
+	/// to this charge (from 0 to 100) while discharging. If not set, the battery never starts the backup.
+	#[serde(default)]
+	pub emergency_battery_percent: Option<u8>,
+	/// Starts the backup automatically as soon as the machine is no longer powered by the mains
+	/// (e.g. a UPS reporting the loss of the mains power).
+	#[serde(default)]
+	pub backup_on_mains_loss: bool,
+	/// Encrypts the manifest and the backup logs written in the destination (not the copied files) for this recipient:
+	/// an age public key (`age1...`) or a GPG key (id or e-mail), e.g. for a drive shared with other people.
+	#[serde(default)]
+	pub metadata_recipient: Option<String>,
+	/// The age identity file (private key) used to read the encrypted manifest back, when it is updated or restored;
+	/// not needed with GPG, which uses its own agent.
+	#[serde(default)]
+	pub metadata_identity: Option<PathBuf>,
+	/// Follows each entry of the logs with a hash chaining it to the previous one, so that the logs written
+	/// on the destination can be shown to be untampered with `--verify-log`.
+	#[serde(default)]
+	pub log_hash_chain: bool,
+	/// Times of the day when the automatic triggers (the power events and the scheduled backups) may start the backup,
+	/// e.g. `["19:00-07:00"]`, so that they do not compete with the work; always if empty.
+	/// The activation pattern always works.
+	#[serde(default)]
+	pub trigger_hours: Vec<String>,
+	/// When the destination is on a network share, what to do for each kind of connection
+	/// (`ethernet`, `wifi`, `metered`), e.g. `metered: { defer: true }` or `wifi: { max_mbps: 20 }`.
+	#[serde(default)]
+	pub network_policies: HashMap<ConnectionType, NetworkPolicy>,
+	/// How to read the files protected by missing privileges: `never` (the default, they are reported as failed),
+	/// `privilege` (enable the backup privilege, if the user has it) or `relaunch` (also run the backup
+	/// again elevated if some files were denied).
+	#[serde(default)]
+	pub elevation: Elevation,
+	/// Distance (in pixels) from the screen edges within which the rectangle gesture is accepted.
+	#[serde(default = "default_gesture_tolerance")]
+	pub gesture_tolerance: f32,
+	/// Minimum mouse movement (in pixels) between two sampled points of the rectangle gesture.
+	#[serde(default = "default_gesture_sampling")]
+	pub gesture_sampling: f32,
+	/// Ignores the activation pattern while the workstation is locked or the session is remote.
+	#[serde(default)]
+	pub pause_when_locked: bool,
+	/// Ignores the activation pattern while a full-screen app (e.g. a game or a presentation) is running.
+	#[serde(default)]
+	pub pause_in_fullscreen: bool,
+	/// Starts the backup only if the rectangle gesture is confirmed together with Ctrl+Alt+B held down for 5 seconds,
+	/// in either order, for the machines where the gesture alone could be drawn by accident (e.g. shared with kids).
+	/// Works with the gesture (`btn_rec: false`).
+	#[serde(default)]
+	pub two_factor_arming: bool,
+	/// Longest time in seconds between the gesture and the Ctrl+Alt+B hold, with `two_factor_arming`.
+	#[serde(default = "default_two_factor_window_secs")]
+	pub two_factor_window_secs: u64,
+	/// Size of the screen for the gesture, e.g. `2560x1440`, used on Linux when it cannot be asked to X11 or Wayland;
+	/// the `SCREEN_SIZE` environment variable takes precedence.
+	#[serde(default)]
+	pub screen_size: Option<String>,
+	/// Lets a companion on the LAN (a phone app or another PC) arm and confirm the backup when the keyboard and
+	/// the mouse do not work, e.g. `{ listen: "0.0.0.0:47020", key: "keyring:emergency_backup/remote" }`;
+	/// the commands are authenticated with the pre-shared key. Applied at the next start of the application.
+	#[serde(default)]
+	pub remote_control: Option<RemoteControlConfig>,
+	/// Before copying the files of the known applications, asks them to flush their data, to reduce the chance
+	/// of copying a database half written: the SQLite databases are checkpointed (with the `sqlite3` command)
+	/// and a warning is shown if Thunderbird or Outlook is running while its mail is copied.
+	#[serde(default = "default_app_flush_hooks")]
+	pub app_flush_hooks: bool,
+	/// Seconds the popups and the system notifications stay on screen; 0 to keep them until they are closed.
+	/// At the end of the backup the application exits once its last notification has been closed.
+	#[serde(default = "default_popup_duration_secs")]
+	pub popup_duration_secs: u64,
+	/// Which of the backups found in the destinations `prune` keeps, e.g. `{ keep_last: 3, keep_days: 30 }`;
+	/// nothing is deleted without it. Run `prune --dry-run` to see what would be deleted.
+	#[serde(default)]
+	pub retention: Option<RetentionPolicy>,
+	/// Warns the user when the source has far fewer files (or bytes) than at the previous backup: the largest
+	/// decrease allowed, in percent (e.g. 50 warns if more than half of the files are gone); 0 to never warn.
+	#[serde(default = "default_drift_alarm_percent")]
+	pub drift_alarm_percent: u8,
+	/// Before copying, looks for the signs of a ransomware among the files changed since the last backup (many files
+	/// renamed with a new extension, or whose content now looks encrypted); if found, the user is alerted, the copies
+	/// already in the destinations are kept (`on_conflict` becomes `rename`), `evacuate` is suspended and `prune`
+	/// deletes nothing until a backup finds nothing suspicious.
+	#[serde(default = "default_ransomware_check")]
+	pub ransomware_check: bool,
+	/// Reads the source leaving it observably untouched, for the users who may need it for forensics: the access
+	/// times of its files and folders are not updated (where the system permits it), no file is locked, and the
+	/// application hooks (which write in the databases) are not run. Not allowed with `evacuate`.
+	#[serde(default)]
+	pub read_only_source: bool,
+	/// Writes the backup as a single compressed archive in `path_dest_backup` (`zip` or `tar_gz`), named after
+	/// the time of the backup, instead of copying the files one by one: it saves space on a small drive, but
+	/// the files cannot be verified or evacuated, and there is no secondary copy.
+	#[serde(default)]
+	pub archive: Option<ArchiveFormat>,
+	/// Encrypts the copied files with age, so that a lost drive cannot be read by whoever finds it. The key of each
+	/// destination is stored in it (`.emergency_backup_key.age`), encrypted with `encrypt_passphrase`, unless
+	/// `encrypt_keyfile` is set. The files keep their names; `restore` decrypts them.
+	#[serde(default)]
+	pub encrypt: bool,
+	/// Reference to the passphrase of the encryption: `keyring:<service>/<account>` or `file:<path>`.
+	#[serde(default)]
+	pub encrypt_passphrase: Option<String>,
+	/// An age identity file (as written by `age-keygen`) used as the key instead of a passphrase;
+	/// it must be kept away from the destination.
+	#[serde(default)]
+	pub encrypt_keyfile: Option<PathBuf>,
+}
+
+/// One or more files included in the configuration (`include: filters.yaml` or `include: [a.yaml, b.yaml]`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Includes {
+	One(PathBuf),
+	Many(Vec<PathBuf>),
+}
+
+impl Default for Includes {
+	fn default() -> Self {
+		Includes::Many(Vec::new())
+	}
+}
+
+impl Includes {
+	fn paths(&self) -> Vec<PathBuf> {
+		match self {
+			Includes::One(path) => vec![path.clone()],
+			Includes::Many(paths) => paths.clone(),
+		}
+	}
+}
+
+/// Content of an included filters file.
+#[derive(Debug, Default, Deserialize)]
+struct FilterList {
+	#[serde(default)]
+	type_files: Vec<String>,
+	#[serde(default)]
+	exclude: Vec<String>,
+}
+
+/// The only field read from the configuration next to the executable before choosing the folder of the configuration.
+#[cfg(not(debug_assertions))]
+#[derive(Deserialize)]
+struct PortableFlag {
+	#[serde(default)]
+	portable: bool,
+}
+
+/// The fields of the configuration whose relative paths are taken from the folder of the configuration file.
+#[cfg(not(debug_assertions))]
+#[derive(Deserialize)]
+struct PathFields {
+	#[serde(default)]
+	path_orig_backup: Option<PathBuf>,
+	#[serde(default)]
+	path_dest_backup: Option<PathBuf>,
+	#[serde(default)]
+	secondary_dest: Option<PathBuf>,
+	#[serde(default)]
+	log_dir: Option<PathBuf>,
+	#[serde(default)]
+	status_file: Option<PathBuf>,
+	#[serde(default)]
+	sounds: HashMap<String, PathBuf>,
+	#[serde(default)]
+	include: Includes,
+	#[serde(default)]
+	profiles: HashMap<String, Profile>,
+}
+
+#[cfg(not(debug_assertions))]
+impl PathFields {
+	/// Returns the names of the fields holding a relative path.
+	fn relative(&self) -> Vec<String> {
+		let is_relative = |path: &Path| !path.as_os_str().is_empty() && expand_path(path).is_relative();
+		let mut fields: Vec<(String, &Path)> = [
+			("path_orig_backup", &self.path_orig_backup),
+			("path_dest_backup", &self.path_dest_backup),
+			("secondary_dest", &self.secondary_dest),
+			("log_dir", &self.log_dir),
+			("status_file", &self.status_file),
+		]
+		.into_iter()
+		.filter_map(|(name, path)| path.as_deref().map(|path| (name.to_string(), path)))
+		.collect();
+		fields.extend(self.sounds.iter().map(|(event, path)| (format!("sounds.{}", event), path.as_path())));
+		let includes = self.include.paths();
+		fields.extend(includes.iter().map(|path| ("include".to_string(), path.as_path())));
+		for (name, profile) in &self.profiles {
+			fields.extend(profile.path_orig_backup.as_deref().map(|path| (format!("profiles.{}.path_orig_backup", name), path)));
+			fields.extend(profile.path_dest_backup.as_deref().map(|path| (format!("profiles.{}.path_dest_backup", name), path)));
+		}
+		let mut relative: Vec<String> = fields.into_iter().filter(|(_, path)| is_relative(path)).map(|(name, _)| name).collect();
+		relative.sort();
+		relative.dedup();
+		relative
+	}
+}
+
+/// A named set of values overriding the main ones of the configuration.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Profile {
+	pub path_dest_backup: Option<PathBuf>,
+	pub path_orig_backup: Option<PathBuf>,
+	pub type_files: Option<Vec<String>>,
+	pub btn_rec: Option<bool>,
+	pub gesture_tolerance: Option<f32>,
+	pub gesture_sampling: Option<f32>,
+}
+
+fn default_status_interval() -> u64 {
+	30
+}
+
+fn default_beep_enabled() -> bool {
+	true
+}
+
+fn default_quick_status_hotkey() -> bool {
+	true
+}
+
+fn default_popup_duration_secs() -> u64 {
+	10
+}
+
+fn default_ransomware_check() -> bool {
+	true
+}
+
+fn default_drift_alarm_percent() -> u8 {
+	50
 }
+
+fn default_app_flush_hooks() -> bool {
+	true
+}
+
+fn default_two_factor_window_secs() -> u64 {
+	30
+}
+
+fn default_beep_volume() -> u8 {
+	100
+}
+
+fn default_dest_check_interval() -> u64 {
+	60
+}
+
+fn default_gesture_tolerance() -> f32 {
+	70.0
+}
+
+fn default_gesture_sampling() -> f32 {
+	10.0
+}
+
+fn default_stall_timeout() -> u64 {
+	120
+}
+
+fn default_read_ahead_mb() -> u64 {
+	64
+}
+
+fn default_verify_sample_size() -> usize {
+	100
+}
+
+/// All the semantic problems found in a configuration file, each with the name of the field it refers to.
+#[derive(Debug)]
+pub struct ConfigValidationError {
+	pub config_path: PathBuf,
+	pub errors: Vec<(&'static str, String)>,
+}
+
+/// Beginning of the message of a `ConfigValidationError`, used to recognize it.
+const INVALID_CONFIG_PREFIX: &str = "Invalid configuration";
+
+impl fmt::Display for ConfigValidationError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} in {}:", INVALID_CONFIG_PREFIX, self.config_path.display())?;
+		for (field, error) in &self.errors {
+			write!(f, "\n - {}: {}", field, error)?;
+		}
+		Ok(())
+	}
+}
+
+impl Error for ConfigValidationError {}
 impl Config {
 	/// Reads the configuration from a file.
 	///
@@ -29,18 +524,365 @@ impl Config {
 	///
 	/// A `Result` containing the `Config` object if the file is read successfully, or a boxed `Error` if an error occurs.
 	///
+	/// The format is chosen by the file extension: `.toml` is parsed as TOML, `.json` as JSON,
+	/// anything else (`.yaml`, `.yml`) as YAML.
+	///
 	/// # Errors
 	///
-	/// This function will return an error if the file cannot be opened, read, or if the contents cannot be parsed as YAML.
-
+	/// This function will return an error if the file cannot be opened, read, or if the contents cannot be parsed.
 	pub fn read_from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
-		let mut file = File::open(path)?;
-		let mut contents = String::new();
-		file.read_to_string(&mut contents)?;
-		let mut config:Config = serde_yaml::from_str(&contents)?;
+		let path_ref = path.as_ref();
+		let mut config: Config = parse_file(path_ref)?;
+		let config_dir = path_ref.parent().unwrap_or(Path::new(""));
+
+		config.apply_active_profile();
+
+		// Merge the filter lists of the included files
+		for include in config.include.paths() {
+			let include_path = resolve_relative(config_dir, &expand_path(&include));
+			let filters: FilterList = parse_file(&include_path)
+				.map_err(|e| format!("Unable to read the included file {}: {}", include_path.display(), e))?;
+			config.type_files.extend(filters.type_files);
+			config.exclude.extend(filters.exclude);
+		}
+
+		config.normalize_type_files();
+		config.path_orig_backup = expand_path(&config.path_orig_backup);
+		config.path_dest_backup = expand_path(&config.path_dest_backup);
+		config.secondary_dest = config.secondary_dest.as_deref().map(expand_path);
+		config.log_dir = config.log_dir.as_deref().map(expand_path);
+		config.status_file = config.status_file.as_deref().map(expand_path);
+
+		// Relative paths are relative to the folder of the configuration file, not to the current directory
+		config.path_orig_backup = resolve_relative(config_dir, &config.path_orig_backup);
+		config.path_dest_backup = resolve_relative(config_dir, &config.path_dest_backup);
+		config.secondary_dest = config.secondary_dest.as_deref().map(|p| resolve_relative(config_dir, p));
+		config.log_dir = config.log_dir.as_deref().map(|p| resolve_relative(config_dir, p));
+		config.status_file = config.status_file.as_deref().map(|p| resolve_relative(config_dir, p));
+		for sound in config.sounds.values_mut() {
+			*sound = resolve_relative(config_dir, &expand_path(sound));
+		}
+
+		if config.portable {
+			let exe_dir = exe_dir();
+			if config.path_dest_backup.as_os_str().is_empty() {
+				config.path_dest_backup = exe_dir.join("backup");
+			}
+			if config.log_dir.is_none() {
+				config.log_dir = Some(exe_dir.join("log"));
+			}
+		}
+
+		config.validate(path_ref)?;
+		Ok(config)
+	}
+
+	/// Checks the semantic constraints of the configuration, collecting all the problems found.
+	///
+	/// # Arguments
+	///
+	/// * `config_path` - The path of the configuration file, reported in the error.
+	///
+	/// # Errors
+	///
+	/// This function will return a `ConfigValidationError` listing every invalid field.
+	pub fn validate(&self, config_path: &Path) -> Result<(), ConfigValidationError> {
+		let mut errors: Vec<(&'static str, String)> = Vec::new();
+
+		if let Some(profile) = self.selected_profile() {
+			if !self.profiles.contains_key(&profile) {
+				errors.push(("active_profile", format!("profile '{}' is not defined in profiles", profile)));
+			}
+		}
+
+		if self.path_orig_backup.as_os_str().is_empty() {
+			errors.push(("path_orig_backup", "must be set".to_string()));
+		}
+		if self.path_dest_backup.as_os_str().is_empty() && self.dest_volume_label.is_none() {
+			errors.push(("path_dest_backup", "must be set (or dest_volume_label)".to_string()));
+		}
+		if self.dest_volume_label.as_deref().is_some_and(|label| label.trim().is_empty()) {
+			errors.push(("dest_volume_label", "must not be empty".to_string()));
+		}
+		if self.dest_volume_subdir.as_deref().is_some_and(|subdir| subdir.is_absolute()) {
+			errors.push(("dest_volume_subdir", "must be a relative path".to_string()));
+		}
+		if !self.path_orig_backup.as_os_str().is_empty() && !self.path_dest_backup.as_os_str().is_empty() {
+			// Compare the real paths when they exist, so that different spellings of the same folder are detected
+			let source = self.path_orig_backup.canonicalize().unwrap_or_else(|_| self.path_orig_backup.clone());
+			let dest = self.path_dest_backup.canonicalize().unwrap_or_else(|_| self.path_dest_backup.clone());
+			if source == dest {
+				errors.push(("path_dest_backup", "must be different from path_orig_backup".to_string()));
+			} else if dest.starts_with(&source) {
+				errors.push(("path_dest_backup", "must not be inside path_orig_backup".to_string()));
+			}
+		}
+
+		if let Some(secondary_dest) = &self.secondary_dest {
+			let canonical = |path: &PathBuf| path.canonicalize().unwrap_or_else(|_| path.clone());
+			let secondary = canonical(secondary_dest);
+			if secondary_dest.as_os_str().is_empty() {
+				errors.push(("secondary_dest", "must not be empty".to_string()));
+			} else if secondary == canonical(&self.path_dest_backup) {
+				errors.push(("secondary_dest", "must be different from path_dest_backup".to_string()));
+			} else if !self.path_orig_backup.as_os_str().is_empty() && secondary.starts_with(canonical(&self.path_orig_backup)) {
+				errors.push(("secondary_dest", "must not be inside path_orig_backup".to_string()));
+			}
+		}
+
+		for type_file in &self.type_files {
+			let ext = &type_file[1..];
+			if ext.is_empty() || !ext.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+				errors.push(("type_files", format!("'{}' is not a valid file extension", type_file)));
+			}
+		}
+
+		if self.beep_volume > 100 {
+			errors.push(("beep_volume", format!("must be between 0 and 100 (found {})", self.beep_volume)));
+		}
+		if let Some(min_battery_percent) = self.min_battery_percent {
+			if min_battery_percent > 100 {
+				errors.push(("min_battery_percent", format!("must be between 0 and 100 (found {})", min_battery_percent)));
+			}
+		}
+		if let Some(emergency_battery_percent) = self.emergency_battery_percent {
+			if emergency_battery_percent > 100 {
+				errors.push(("emergency_battery_percent", format!("must be between 0 and 100 (found {})", emergency_battery_percent)));
+			}
+		}
+		for (connection, policy) in &self.network_policies {
+			if policy.max_mbps == Some(0) {
+				errors.push(("network_policies", format!("max_mbps of {:?} must be greater than 0", connection)));
+			}
+		}
+		if self.max_expected_files == Some(0) {
+			errors.push(("max_expected_files", "must be greater than 0".to_string()));
+		}
+		if let Some(max_expected_size_gb) = self.max_expected_size_gb {
+			if max_expected_size_gb.is_nan() || max_expected_size_gb <= 0.0 {
+				errors.push(("max_expected_size_gb", format!("must be greater than 0 (found {})", max_expected_size_gb)));
+			}
+		}
+		if self.verify == VerifyMode::Sample && self.verify_sample_size == 0 {
+			errors.push(("verify_sample_size", "must be greater than 0".to_string()));
+		}
+		if self.locale.as_deref().is_some_and(|locale| locale.trim().is_empty()) {
+			errors.push(("locale", "must not be empty".to_string()));
+		}
+		if let Some(retention) = &self.retention {
+			if retention.keep_last.is_none() && retention.keep_days.is_none() {
+				errors.push(("retention", "set keep_last or keep_days".to_string()));
+			}
+			if retention.keep_last == Some(0) {
+				errors.push(("retention", "keep_last must be greater than 0".to_string()));
+			}
+		}
+		if self.archive.is_some() {
+			if self.evacuate {
+				errors.push(("archive", "cannot be used with evacuate".to_string()));
+			}
+			if self.secondary_dest.is_some() {
+				errors.push(("archive", "cannot be used with secondary_dest".to_string()));
+			}
+			if self.verify != VerifyMode::Off {
+				errors.push(("archive", "the files in an archive cannot be verified: set verify to off".to_string()));
+			}
+		}
+		if self.encrypt {
+			match (&self.encrypt_passphrase, &self.encrypt_keyfile) {
+				(None, None) => errors.push(("encrypt", "set encrypt_passphrase or encrypt_keyfile".to_string())),
+				(Some(_), Some(_)) => errors.push(("encrypt", "set only one of encrypt_passphrase and encrypt_keyfile".to_string())),
+				(Some(passphrase), None) if !passphrase.starts_with("keyring:") && !passphrase.starts_with("file:") => {
+					errors.push(("encrypt_passphrase", "must be a reference: keyring:<service>/<account> or file:<path>".to_string()));
+				}
+				(None, Some(keyfile)) if !keyfile.is_file() => errors.push(("encrypt_keyfile", format!("{:?} does not exist", keyfile))),
+				_ => {}
+			}
+			if self.archive.is_some() {
+				errors.push(("encrypt", "cannot be used with archive".to_string()));
+			}
+			if self.evacuate {
+				errors.push(("encrypt", "cannot be used with evacuate, which compares the copies with the source byte by byte".to_string()));
+			}
+		}
+		if self.read_only_source && self.evacuate {
+			errors.push(("read_only_source", "cannot be used with evacuate, which removes the source files".to_string()));
+		}
+		if self.drift_alarm_percent >= 100 {
+			errors.push(("drift_alarm_percent", "must be less than 100".to_string()));
+		}
+		if self.verify_when_idle_minutes == Some(0) {
+			errors.push(("verify_when_idle_minutes", "must be greater than 0".to_string()));
+		}
+		if self.max_run_minutes == Some(0) {
+			errors.push(("max_run_minutes", "must be greater than 0".to_string()));
+		}
+		if self.io_uring && !uring::AVAILABLE {
+			errors.push(("io_uring", "requires Linux and a build with the io_uring feature".to_string()));
+		}
+		if let Some(Err(msg)) = self.metadata_recipient.as_deref().map(MetadataRecipient::parse) {
+			errors.push(("metadata_recipient", msg));
+		}
+		if let Some(identity) = &self.metadata_identity {
+			if !identity.is_file() {
+				errors.push(("metadata_identity", format!("{:?} does not exist", identity)));
+			}
+		}
+		for window in &self.trigger_hours {
+			if let Err(msg) = trigger::TimeWindow::parse(window) {
+				errors.push(("trigger_hours", msg));
+			}
+		}
+		if self.two_factor_arming && self.btn_rec {
+			errors.push(("two_factor_arming", "requires the gesture (btn_rec: false), since Ctrl+Alt+B is the second factor".to_string()));
+		}
+		if let Some(remote_control) = &self.remote_control {
+			if remote_control.listen.parse::<std::net::SocketAddr>().is_err() {
+				errors.push(("remote_control", format!("'{}' is not an address like 0.0.0.0:47020", remote_control.listen)));
+			}
+		}
+		if let Some(Err(msg)) = self.screen_size.as_deref().map(parse_screen_size) {
+			errors.push(("screen_size", msg));
+		}
+		if self.two_factor_window_secs == 0 {
+			errors.push(("two_factor_window_secs", "must be greater than 0".to_string()));
+		}
+		if self.max_memory_mb == Some(0) {
+			errors.push(("max_memory_mb", "must be greater than 0".to_string()));
+		}
+		if self.stall_timeout_secs == 0 {
+			errors.push(("stall_timeout_secs", "must be greater than 0".to_string()));
+		}
+		if self.dest_check_interval_secs == 0 {
+			errors.push(("dest_check_interval_secs", "must be greater than 0".to_string()));
+		}
+		if self.status_interval_secs == 0 {
+			errors.push(("status_interval_secs", "must be greater than 0".to_string()));
+		}
+		if !(self.gesture_tolerance > 0.0 && self.gesture_tolerance <= 500.0) {
+			errors.push(("gesture_tolerance", format!("must be between 0 and 500 pixels (found {})", self.gesture_tolerance)));
+		}
+		if !(self.gesture_sampling > 0.0 && self.gesture_sampling < self.gesture_tolerance) {
+			errors.push(("gesture_sampling", format!("must be greater than 0 and less than gesture_tolerance (found {})", self.gesture_sampling)));
+		}
+
+		if errors.is_empty() {
+			Ok(())
+		} else {
+			Err(ConfigValidationError { config_path: config_path.to_path_buf(), errors })
+		}
+	}
+
+	/// Selects the profile to use regardless of the `active_profile` key (e.g. from the command line).
+	/// It is used for every configuration read from now on.
+	pub fn set_profile_override(profile: Option<String>) {
+		*PROFILE_OVERRIDE.lock().unwrap() = profile;
+	}
+
+	/// Returns the name of the profile in use, if any.
+	pub fn selected_profile(&self) -> Option<String> {
+		PROFILE_OVERRIDE.lock().unwrap().clone().or_else(|| self.active_profile.clone())
+	}
+
+	/// Overrides the main fields with the ones set in the selected profile.
+	/// An unknown profile is left untouched and reported by the validation.
+	fn apply_active_profile(&mut self) {
+		let profile = match self.selected_profile().and_then(|name| self.profiles.get(&name).cloned()) {
+			Some(profile) => profile,
+			None => return,
+		};
+
+		if let Some(path_dest_backup) = profile.path_dest_backup {
+			self.path_dest_backup = path_dest_backup;
+		}
+		if let Some(path_orig_backup) = profile.path_orig_backup {
+			self.path_orig_backup = path_orig_backup;
+		}
+		if let Some(type_files) = profile.type_files {
+			self.type_files = type_files;
+		}
+		if let Some(btn_rec) = profile.btn_rec {
+			self.btn_rec = btn_rec;
+		}
+		if let Some(gesture_tolerance) = profile.gesture_tolerance {
+			self.gesture_tolerance = gesture_tolerance;
+		}
+		if let Some(gesture_sampling) = profile.gesture_sampling {
+			self.gesture_sampling = gesture_sampling;
+		}
+	}
+
+	/// Applies the settings used by the whole program (e.g. the notification modes).
+	/// It is called at startup and every time the configuration is reloaded.
+	pub fn apply_global_settings(&self) {
+		notification_popup::set_notification_modes(self.notifications.clone());
+		notification_popup::set_popup_titles(self.popup_titles.clone());
+		notification_popup::set_popup_duration((self.popup_duration_secs > 0).then(|| Duration::from_secs(self.popup_duration_secs)));
+		beeper::set_audio_settings(self.beep_enabled, self.beep_volume);
+		beeper::set_custom_sounds(self.sounds.clone());
+		speech::set_speech_enabled(self.speech_enabled);
+		accessibility::set_large_text(self.large_notifications);
+		locale::set_locale(self.locale.as_deref());
+		uring::set_enabled(self.io_uring);
+		// Checkpointing a database writes in it
+		app_hooks::set_enabled(self.app_flush_hooks && !self.read_only_source);
+		filesystem::set_read_only_source(self.read_only_source);
+		ransomware::set_enabled(self.ransomware_check);
+		metadata_crypto::set_keys(
+			self.metadata_recipient.as_deref().and_then(|recipient| MetadataRecipient::parse(recipient).ok()),
+			self.metadata_identity.clone(),
+		);
+		encryption::set_key(self.encryption_key());
+		two_factor::set_two_factor(self.two_factor_arming, Duration::from_secs(self.two_factor_window_secs));
+		trigger::set_allowed_windows(self.trigger_hours.iter().filter_map(|window| trigger::TimeWindow::parse(window).ok()).collect());
+	}
+
+	/// Returns how the copied files are encrypted, if `encrypt` is set.
+	pub fn encryption_key(&self) -> Option<EncryptionKey> {
+		if !self.encrypt {
+			return None;
+		}
+		match (&self.encrypt_keyfile, &self.encrypt_passphrase) {
+			(Some(keyfile), _) => Some(EncryptionKey::KeyFile(keyfile.clone())),
+			(None, Some(passphrase)) => Some(EncryptionKey::Passphrase(passphrase.clone())),
+			(None, None) => None,
+		}
+	}
+
+	/// Resolves `dest_volume_label` (if set) to the mount point of the volume, updating `path_dest_backup`.
+	///
+	/// # Errors
+	///
+	/// If the volume is not connected, `path_dest_backup` is left as it is (the fallback) and
+	/// a message for the user is returned.
+	pub fn resolve_destination_volume(&mut self) -> Result<(), String> {
+		let label = match &self.dest_volume_label {
+			Some(label) => label.clone(),
+			None => return Ok(()),
+		};
 
-		if !config.type_files.is_empty() {
-			config.type_files = config.type_files.iter().map(|f| {
+		match volume::find_mount_point(&label) {
+			Some(mount_point) => {
+				self.path_dest_backup = match &self.dest_volume_subdir {
+					Some(subdir) => mount_point.join(subdir),
+					None => mount_point,
+				};
+				Ok(())
+			}
+			None if self.path_dest_backup.as_os_str().is_empty() => {
+				Err(format!("The destination drive '{}' is not connected", label))
+			}
+			None => Err(format!(
+				"The destination drive '{}' is not connected, the backup will be copied to {:?}",
+				label, self.path_dest_backup
+			)),
+		}
+	}
+
+	/// Adds the leading dot to the file types that miss it (e.g. `mp3` becomes `.mp3`).
+	pub fn normalize_type_files(&mut self) {
+		if !self.type_files.is_empty() {
+			self.type_files = self.type_files.iter().map(|f| {
 				if !f.starts_with('.') {
 					format!(".{}", f)
 				} else {
@@ -48,28 +890,94 @@ impl Config {
 				}
 			}).collect();
 		}
-		Ok(config)
 	}
 
 
 	/// Reads and checks the configuration file, and sets up a file watcher to monitor changes.
 	///
+	/// # Arguments
+	///
+	/// * `config_path` - The path of the configuration file; if `None`, the default one is used.
+	///
 	/// # Returns
 	///
 	/// * `Config` - The configuration object read from the file.
-	pub fn retrieve_and_check_config_file() -> Config {
-		let binding = Self::retrieve_path_config().clone();
+	pub fn retrieve_and_check_config_file(config_path: Option<PathBuf>) -> Config {
+		let binding = Self::path_config(config_path);
   		let path_config: &str = binding.to_str().unwrap();
 
-		// Initial attempt to read the configuration file
-		match Config::read_from_file(path_config) {
-			// if ok, then it returns the config
-			Ok(config) => return config,
-			Err(e) => Self::handle_config_error(&e.to_string()),
+		if !binding.exists() {
+			// First run: create a template to be completed by the user, then wait for it to be saved
+			match Self::write_default_config(&binding) {
+				Ok(()) => show_popup(NotificationType::ConfigError, Some(format!(
+					"A default configuration has been created in {}: set the destination of the backup and save the file",
+					binding.display()
+				))),
+				Err(e) => Self::handle_config_error(&format!("Unable to create the configuration file {}: {}", binding.display(), e)),
+			}
+		} else {
+			// Initial attempt to read the configuration file
+			match Config::read_from_file(path_config) {
+				// if ok, then it returns the config
+				Ok(config) => return config,
+				Err(e) => Self::handle_config_error(&e.to_string()),
+			}
 		}
 
 		// otherwise, we need to watch for a modification (correction) of the file
-		let mut last_event: HashMap<String, Instant> = HashMap::new();
+		let mut result = None;
+		Self::watch_config_file(path_config, |config| {
+			result = Some(config);
+			true // if ok, stop the watcher
+		});
+
+		result.expect("Failed to read initial configuration")
+	}
+
+	/// Keeps watching the configuration file for the whole life of the program, in a separate thread:
+	/// every time the file is saved with a valid configuration, the shared configuration is replaced,
+	/// so that the running recognizers and the next backup use the new values.
+	///
+	/// # Arguments
+	///
+	/// * `config_path` - The path of the configuration file; if `None`, the default one is used.
+	/// * `shared_config` - The configuration shared with the rest of the program.
+	/// * `adjust` - A function applied to every new configuration before it is used (e.g. the command line overrides).
+	pub fn watch_for_changes<F>(config_path: Option<PathBuf>, shared_config: SharedConfig, adjust: F)
+	where
+		F: Fn(&mut Config) + Send + 'static,
+	{
+		let binding = Self::path_config(config_path);
+
+		thread::spawn(move || {
+			let path_config: &str = binding.to_str().unwrap();
+			Self::watch_config_file(path_config, |mut config| {
+				adjust(&mut config);
+				let mut current = shared_config.write().unwrap();
+				if current.btn_rec != config.btn_rec {
+					show_popup(NotificationType::GenericError, Some(
+						"The activation mode (btn_rec) will change at the next start of the application".to_string()
+					));
+				}
+				println!("Configuration reloaded");
+				config.apply_global_settings();
+				*current = config;
+				false // keep watching
+			});
+		});
+	}
+
+	/// Watches the configuration file and, every time it is modified, reads it again.
+	/// Invalid configurations are notified to the user; valid ones are passed to `on_valid`.
+	/// The folder of the file is watched rather than the file itself, since many editors save by writing
+	/// a new file and renaming it over the old one, which a watch on the old file would not follow.
+	///
+	/// # Arguments
+	///
+	/// * `path_config` - The path of the configuration file.
+	/// * `on_valid` - Called with every valid configuration; returning `true` stops the watcher.
+	fn watch_config_file<F: FnMut(Config) -> bool>(path_config: &str, mut on_valid: F) {
+		let mut last_event: Option<Instant> = None;
 		let debounce_duration = Duration::from_millis(500);
 		let (tx, rx) = channel();
 
@@ -77,12 +985,25 @@ impl Config {
 		let notify_config = NotifyConfig::default().with_poll_interval(Duration::from_secs(2));
 		let mut watcher: RecommendedWatcher = Watcher::new(tx.clone(), notify_config).unwrap();
 
-		// Add a path to be watched. All files and directories at that path and below will be monitored for changes.
-		watcher.watch(path_config.as_ref(), RecursiveMode::NonRecursive).unwrap();
+		// The folder of the file is watched, and its events are filtered by the name of the file
+		let config_file = Path::new(path_config);
+		let file_name = config_file.file_name().map(|name| name.to_os_string());
+		let folder = match config_file.parent() {
+			Some(parent) if !parent.as_os_str().is_empty() => parent,
+			_ => Path::new("."),
+		};
+		watcher.watch(folder, RecursiveMode::NonRecursive).unwrap();
 
 		while let Ok(event) = rx.recv() {
 			if let Ok(event) = event {
-				let path = event.paths[0].to_str().unwrap().to_string();
+				if !event.paths.iter().any(|path| path.file_name().map(|name| name.to_os_string()) == file_name) {
+					continue; // another file of the folder
+				}
+				// A file created, written or renamed to the name of the configuration; while it is missing
+				// (removed, or renamed away by an editor before the new one takes its name) the old values are kept
+				if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) || !config_file.exists() {
+					continue;
+				}
 				let now = Instant::now();
 
 				// Check if the event is too close to the last one and ignore it if so
@@ -91,47 +1012,164 @@ impl Config {
 				// they write the contents into a temporary file and then rename the temporary file
 				// with the name of the original file.
 				// This can generate two edit events (Modify).
-				if let Some(last_time) = last_event.get(&path) {
-					if now.duration_since(*last_time) < debounce_duration {
+				if let Some(last_time) = last_event {
+					if now.duration_since(last_time) < debounce_duration {
 						continue; // Ignore this event as it is too close to the last one
 					}
 				}
 
-				// Update the last event time for the path
-				last_event.insert(path.clone(), now);
-
-				// If the event was a modify one, I can read again the file to check that everything is ok
-				match event.kind {
-					EventKind::Modify(_) => {
-						match Config::read_from_file(path_config) {
-							Ok(config) => {
-								drop(tx); // if ok, drop the sender to stop the watcher
-								return config;
-							}
-							Err(e) => Self::handle_config_error(&e.to_string()),
+				// Update the last event time
+				last_event = Some(now);
+
+				match Config::read_from_file(path_config) {
+					Ok(config) => {
+						if on_valid(config) {
+							drop(tx); // drop the sender to stop the watcher
+							return;
 						}
 					}
-					_ => {}
+					Err(e) => Self::handle_config_error(&e.to_string()),
 				}
 			}
 		}
+	}
+
+	/// Writes a configuration template, with the home directory of the user as source.
+	/// The destination is left empty, since it must be chosen by the user.
+	/// The format is chosen by the extension, as when the file is read: the TOML and YAML templates
+	/// are commented, the JSON one cannot be.
+	///
+	/// # Arguments
+	///
+	/// * `path` - The path of the configuration file to create.
+	fn write_default_config(path: &Path) -> std::io::Result<()> {
+		let home = home_dir().unwrap_or_default().replace('\\', "/");
+		let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("").to_lowercase();
+		let template = match extension.as_str() {
+			"toml" => format!(
+"# Configuration of the emergency backup application.
+# Paths can contain environment variables ($HOME, %USERPROFILE%, ~).
+
+# Folder to back up
+path_orig_backup = \"{}\"
+# Where the backup is copied (e.g. the USB drive): set it before using the application
+path_dest_backup = \"\"
+# Extensions of the files to back up, e.g. [\".mp3\", \".wav\"]; empty to back up every file
+type_files = []
+# true: activate with ctrl+alt+b for 5 seconds and confirm with 3 clicks
+# false: activate and confirm by drawing a clockwise rectangle with the mouse
+btn_rec = false
+# Tolerance and sampling (in pixels) of the rectangle gesture; run with --tutorial to practice it
+gesture_tolerance = 70
+gesture_sampling = 10
+", home),
+			"json" => serde_json::to_string_pretty(&serde_json::json!({
+				"path_orig_backup": home,
+				"path_dest_backup": "",
+				"type_files": [],
+				"btn_rec": false,
+				"gesture_tolerance": 70,
+				"gesture_sampling": 10,
+			}))? + "\n",
+			_ => format!(
+"# Configuration of the emergency backup application.
+# Paths can contain environment variables ($HOME, %USERPROFILE%, ~).
+
+# Folder to back up
+path_orig_backup: \"{}\"
+# Where the backup is copied (e.g. the USB drive): set it before using the application
+path_dest_backup: \"\"
+# Extensions of the files to back up, e.g. [.mp3, .wav]; empty to back up every file
+type_files: []
+# true: activate with ctrl+alt+b for 5 seconds and confirm with 3 clicks
+# false: activate and confirm by drawing a clockwise rectangle with the mouse
+btn_rec: false
+# Tolerance and sampling (in pixels) of the rectangle gesture; run with --tutorial to practice it
+gesture_tolerance: 70
+gesture_sampling: 10
+", home),
+		};
+
+		if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+			std::fs::create_dir_all(parent)?;
+		}
+		std::fs::write(path, template)
+	}
 
-		panic!("Failed to read initial configuration");
+	/// Returns the path of the configuration file: the given one if any, otherwise the default one.
+	pub fn path_config(config_path: Option<PathBuf>) -> PathBuf {
+		config_path.unwrap_or_else(Self::retrieve_path_config)
 	}
 
-	#[cfg(not(debug_assertions))]
+	/// Returns the path of the configuration file: the first existing one among
+	/// `config.yaml`, `config.yml`, `config.toml` and `config.json`, or `config.yaml` if none exists.
 	fn retrieve_path_config() -> PathBuf {
-		use std::env;
+		let config_dir = Self::retrieve_config_dir();
+
+		Self::config_file_in(&config_dir).unwrap_or_else(|| config_dir.join(CONFIG_FILE_NAMES[0]))
+	}
+
+	/// Returns the first existing configuration file in a folder, among
+	/// `config.yaml`, `config.yml`, `config.toml` and `config.json`.
+	pub fn config_file_in(dir: &Path) -> Option<PathBuf> {
+		CONFIG_FILE_NAMES.iter()
+			.map(|name| dir.join(name))
+			.find(|path| path.exists())
+	}
 
-		let exe_path = env::current_exe().expect("Failed to get current executable path");
-		let exe_dir = exe_path.parent().expect("Failed to get executable directory");
+	/// Returns the folder of the configuration of the user (see `data_dirs::config_dir`), or the folder of the
+	/// executable if the configuration is still there: a portable installation (`portable: true` in the
+	/// configuration next to the executable), or one that `migrate_legacy_files` could not move.
+	#[cfg(not(debug_assertions))]
+	fn retrieve_config_dir() -> PathBuf {
+		let exe_dir = exe_dir();
+		let user_dir = data_dirs::config_dir();
+		if let Some(old) = Self::config_file_in(&exe_dir) {
+			if parse_file::<PortableFlag>(&old).is_ok_and(|flag| flag.portable) || Self::config_file_in(&user_dir).is_none() {
+				return exe_dir;
+			}
+		}
+		user_dir
+	}
+
+	/// Moves to the folder of the user the configuration and the logs that an older version kept next to the
+	/// executable. It is run once at startup, before the configuration is looked for. A portable installation
+	/// keeps its files where they are, and so does a configuration with paths relative to its folder (including
+	/// the `include` entries), which would point elsewhere once moved: the user is asked to make them absolute.
+	///
+	/// # Errors
+	///
+	/// Returns a message for the user if something has not been moved; the files next to the executable
+	/// are then used as before.
+	#[cfg(not(debug_assertions))]
+	pub fn migrate_legacy_files() -> Result<(), String> {
+		let exe_dir = exe_dir();
+		let user_dir = data_dirs::config_dir();
+		if let Some(old) = Self::config_file_in(&exe_dir) {
+			let portable = parse_file::<PortableFlag>(&old).is_ok_and(|flag| flag.portable);
+			if !portable && Self::config_file_in(&user_dir).is_none() {
+				let relative = parse_file::<PathFields>(&old).map(|fields| fields.relative()).unwrap_or_default();
+				if !relative.is_empty() {
+					return Err(format!(
+						"The configuration {} has not been moved to {}: {} relative to its folder. Make them absolute and start the program again",
+						old.display(), user_dir.display(), relative.join(", ")
+					));
+				}
+				data_dirs::migrate_config(&old)
+					.map_err(|e| format!("Unable to move the configuration from {} to {}: {}", old.display(), user_dir.display(), e))?;
+			}
+		}
+		data_dirs::migrate_logs().map_err(|e| format!("Unable to move the logs to {}: {}", data_dirs::log_dir().display(), e))
+	}
 
-		exe_dir.join("config.yaml")
+	#[cfg(debug_assertions)]
+	pub fn migrate_legacy_files() -> Result<(), String> {
+		Ok(())
 	}
 
 	#[cfg(debug_assertions)]
-	fn retrieve_path_config() -> PathBuf {
-		PathBuf::from("config.yaml")
+	fn retrieve_config_dir() -> PathBuf {
+		PathBuf::new()
 	}
 
 	/// Handles configuration errors by displaying the appropriate notifications.
@@ -145,13 +1183,221 @@ impl Config {
 			// Show a configuration error popup with the missing field information
 			show_popup(NotificationType::ConfigError,
 					   Some(format!(
-						   " {} non presente nel file di configurazione",
-						   error_message.split('`').nth(1).unwrap_or("unknown field").to_string(),
+						   "{} non presente nel file di configurazione",
+						   error_message.split('`').nth(1).unwrap_or("unknown field"),
 					   ))
 			);
+		} else if error_message.starts_with(INVALID_CONFIG_PREFIX) {
+			// Show a configuration error popup with all the invalid fields
+			show_popup(NotificationType::ConfigError, Some(error_message.to_string()));
 		} else {
 			// Show a generic error popup with the error message
 			show_popup(NotificationType::GenericError, Some(error_message.to_string()));
 		}
 	}
-}
\ No newline at end of file
+}
+
+/// Expands the environment variables in a path: `~` at the beginning (home directory),
+/// `$VAR`, `${VAR}` and `%VAR%`. Variables that are not defined are left as they are.
+///
+/// # Arguments
+///
+/// * `path` - The path to expand.
+///
+/// # Returns
+///
+/// * The expanded path.
+pub fn expand_path(path: &Path) -> PathBuf {
+	let raw = match path.to_str() {
+		Some(raw) => raw,
+		None => return path.to_path_buf(),
+	};
+
+	let mut expanded = String::new();
+	let mut rest = raw;
+
+	// `~` is the home directory only at the beginning of the path
+	if rest == "~" || rest.starts_with("~/") || rest.starts_with("~\\") {
+		if let Some(home) = home_dir() {
+			expanded.push_str(&home);
+			rest = &rest[1..];
+		}
+	}
+
+	while let Some(pos) = rest.find(['$', '%']) {
+		expanded.push_str(&rest[..pos]);
+		let after = &rest[pos + 1..];
+
+		// Find the name of the variable and the remaining part of the path
+		let (name, remaining) = if rest[pos..].starts_with('%') {
+			match after.find('%') {
+				Some(end) => (&after[..end], &after[end + 1..]),
+				None => ("", after),
+			}
+		} else if let Some(braced) = after.strip_prefix('{') {
+			match braced.find('}') {
+				Some(end) => (&braced[..end], &braced[end + 1..]),
+				None => ("", after),
+			}
+		} else {
+			let end = after.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(after.len());
+			(&after[..end], &after[end..])
+		};
+
+		match std::env::var(name) {
+			Ok(value) if !name.is_empty() => {
+				expanded.push_str(&value);
+				rest = remaining;
+			}
+			_ => {
+				// Not a variable (or not defined): keep the character and go on
+				expanded.push_str(&rest[pos..pos + 1]);
+				rest = after;
+			}
+		}
+	}
+	expanded.push_str(rest);
+
+	PathBuf::from(expanded)
+}
+
+/// Parses a screen size written as `WIDTHxHEIGHT` (e.g. `1920x1080`).
+///
+/// # Errors
+///
+/// Returns a message for the user if the size is not in this form or is empty.
+pub fn parse_screen_size(text: &str) -> Result<(u32, u32), String> {
+	let invalid = || format!("'{}' is not a size like 1920x1080", text);
+	let (width, height) = text.trim().split_once(['x', 'X']).ok_or_else(invalid)?;
+	match (width.trim().parse(), height.trim().parse()) {
+		(Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+		_ => Err(invalid()),
+	}
+}
+
+/// Reads and parses a configuration file, choosing the format by its extension:
+/// `.toml` is parsed as TOML, `.json` as JSON, anything else (`.yaml`, `.yml`) as YAML.
+fn parse_file<T: DeserializeOwned>(path: &Path) -> Result<T, Box<dyn Error>> {
+	let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("").to_lowercase();
+	let mut file = File::open(path)?;
+	let mut contents = String::new();
+	file.read_to_string(&mut contents)?;
+	Ok(match extension.as_str() {
+		"toml" => toml::from_str(&contents)?,
+		"json" => serde_json::from_str(&contents)?,
+		_ => serde_yaml::from_str(&contents)?,
+	})
+}
+
+/// Makes a relative path relative to the given base folder; absolute and empty paths are returned as they are.
+fn resolve_relative(base: &Path, path: &Path) -> PathBuf {
+	if path.as_os_str().is_empty() || path.is_absolute() {
+		path.to_path_buf()
+	} else {
+		base.join(path)
+	}
+}
+
+/// Returns the folder containing the executable.
+pub fn exe_dir() -> PathBuf {
+	std::env::current_exe()
+		.expect("Failed to get current executable path")
+		.parent()
+		.expect("Failed to get executable directory")
+		.to_path_buf()
+}
+
+/// Returns the home directory of the current user.
+fn home_dir() -> Option<String> {
+	std::env::var("HOME").or_else(|_| std::env::var("USERPROFILE")).ok()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn config(yaml: &str) -> Config {
+		serde_yaml::from_str(yaml).unwrap()
+	}
+
+	/// The fields reported by the validation, in order.
+	fn invalid_fields(config: &Config) -> Vec<&'static str> {
+		match config.validate(Path::new("config.yaml")) {
+			Ok(()) => Vec::new(),
+			Err(e) => e.errors.iter().map(|(field, _)| *field).collect(),
+		}
+	}
+
+	/// The default configuration is written in the format its extension is read with
+	#[test]
+	fn default_config_matches_the_extension() {
+		let dir = std::env::temp_dir().join(format!("group_39_test_default_config_{}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&dir);
+		for extension in ["yaml", "toml", "json"] {
+			let path = dir.join(format!("config.{}", extension));
+			Config::write_default_config(&path).unwrap();
+			let config: Config = parse_file(&path).unwrap_or_else(|e| panic!("{}: {}", extension, e));
+			assert_eq!(config.path_dest_backup, PathBuf::new());
+			assert!(!config.btn_rec);
+		}
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn expands_the_variables_in_the_paths() {
+		std::env::set_var("GROUP_39_TEST_DRIVE", "/media/usb");
+		assert_eq!(expand_path(Path::new("$GROUP_39_TEST_DRIVE/backup")), PathBuf::from("/media/usb/backup"));
+		assert_eq!(expand_path(Path::new("${GROUP_39_TEST_DRIVE}_old/backup")), PathBuf::from("/media/usb_old/backup"));
+		assert_eq!(expand_path(Path::new("%GROUP_39_TEST_DRIVE%/backup")), PathBuf::from("/media/usb/backup"));
+	}
+
+	#[test]
+	fn leaves_the_undefined_variables_as_they_are() {
+		std::env::remove_var("GROUP_39_TEST_UNDEFINED");
+		assert_eq!(expand_path(Path::new("$GROUP_39_TEST_UNDEFINED/backup")), PathBuf::from("$GROUP_39_TEST_UNDEFINED/backup"));
+		assert_eq!(expand_path(Path::new("%GROUP_39_TEST_UNDEFINED%/backup")), PathBuf::from("%GROUP_39_TEST_UNDEFINED%/backup"));
+		assert_eq!(expand_path(Path::new("/music/100% hits/$")), PathBuf::from("/music/100% hits/$"));
+	}
+
+	#[test]
+	fn expands_the_home_only_at_the_beginning() {
+		let home = home_dir().unwrap();
+		assert_eq!(expand_path(Path::new("~/Documents")), PathBuf::from(format!("{}/Documents", home)));
+		assert_eq!(expand_path(Path::new("~")), PathBuf::from(&home));
+		assert_eq!(expand_path(Path::new("/backup/~old")), PathBuf::from("/backup/~old"));
+		assert_eq!(expand_path(Path::new("~user/Documents")), PathBuf::from("~user/Documents"));
+	}
+
+	#[test]
+	fn a_valid_configuration_has_no_errors() {
+		let config = config("path_orig_backup: /home/user\npath_dest_backup: /media/usb/backup\ntype_files: [.mp3, .tar-gz]");
+		assert!(config.validate(Path::new("config.yaml")).is_ok());
+	}
+
+	#[test]
+	fn reports_all_the_invalid_fields_at_once() {
+		let config = config("path_orig_backup: /home/user\npath_dest_backup: /home/user\ntype_files: [.mp3, .m p3, .]\nbeep_volume: 150");
+
+		let error = config.validate(Path::new("/etc/backup/config.yaml")).unwrap_err();
+
+		let fields: Vec<&str> = error.errors.iter().map(|(field, _)| *field).collect();
+		assert_eq!(fields, vec!["path_dest_backup", "type_files", "type_files", "beep_volume"]);
+		let message = error.to_string();
+		assert!(message.starts_with(INVALID_CONFIG_PREFIX));
+		assert!(message.contains("/etc/backup/config.yaml"));
+		assert!(message.contains("beep_volume: must be between 0 and 100 (found 150)"));
+	}
+
+	#[test]
+	fn the_destination_must_be_outside_of_the_source() {
+		assert_eq!(invalid_fields(&config("path_orig_backup: /home/user\npath_dest_backup: /home/user/backup")), vec!["path_dest_backup"]);
+		assert!(invalid_fields(&config("path_orig_backup: /home/user\npath_dest_backup: /home/user2")).is_empty());
+	}
+
+	#[test]
+	fn the_source_and_the_destination_must_be_set() {
+		assert_eq!(invalid_fields(&config("type_files: []")), vec!["path_orig_backup", "path_dest_backup"]);
+		assert_eq!(invalid_fields(&config("path_orig_backup: /home/user\ndest_volume_label: BACKUP")), Vec::<&str>::new());
+	}
+}
+
@@ -0,0 +1,91 @@
+use crate::logger::{RunLog, RunStatus};
+use chrono::Local;
+use group_39::notification_popup::{show_popup, NotificationType};
+use std::backtrace::Backtrace;
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+
+/// Folder where the crash reports are written.
+static CRASH_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+/// Description of what the application is doing, reported in case of a crash.
+static CURRENT_STATE: Mutex<String> = Mutex::new(String::new());
+/// The run in progress, marked as crashed if the main flow panics.
+static CURRENT_RUN: Mutex<Option<RunLog>> = Mutex::new(None);
+/// The message describing the last crash, shown by `notify_crash`.
+static LAST_CRASH: Mutex<Option<String>> = Mutex::new(None);
+
+/// Installs a panic hook that, for a panic in any thread, writes a crash report
+/// (panic message, thread, current state and backtrace) in the crash folder. The hook does nothing else,
+/// since it runs inside the panicking thread: the popup is shown by `notify_crash` once the panic has unwound.
+///
+/// # Arguments
+///
+/// * `crash_dir` - The folder where the crash reports are written.
+pub fn install_panic_hook(crash_dir: &Path) {
+    set_crash_dir(crash_dir);
+
+    panic::set_hook(Box::new(|info| {
+        let backtrace = Backtrace::force_capture();
+        let current_thread = thread::current();
+        let thread_name = current_thread.name().unwrap_or("<unnamed>");
+        let state = CURRENT_STATE.lock().unwrap_or_else(|e| e.into_inner()).clone();
+
+        let report = format!(
+            "Crash report - {}\n\nThread: \t{}\nState: \t\t{}\nPanic: \t\t{}\n\nBacktrace:\n{}\n",
+            Local::now().format("%Y-%m-%d %H:%M:%S"),
+            thread_name,
+            if state.is_empty() { "unknown" } else { &state },
+            info,
+            backtrace
+        );
+        eprintln!("{}", report);
+
+        let crash_dir = CRASH_DIR.lock().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default();
+        // Two panics in the same second (e.g. in two threads) must not overwrite each other's report
+        let report_path = crash_dir.join(format!("crash_{}_{}.txt", Local::now().format("%Y-%m-%d_%H-%M-%S-%3f"), std::process::id()));
+        let msg = match fs::create_dir_all(&crash_dir).and_then(|_| fs::write(&report_path, report)) {
+            Ok(()) => format!("The application stopped working ({}).\nCrash report: {}", thread_name, report_path.display()),
+            Err(_) => format!("The application stopped working ({}): {}", thread_name, info),
+        };
+        *LAST_CRASH.lock().unwrap_or_else(|e| e.into_inner()) = Some(msg);
+    }));
+}
+
+/// Shows the popup of the last crash, once its panic has been caught.
+pub fn notify_crash() {
+    if let Some(msg) = LAST_CRASH.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        show_popup(NotificationType::GenericError, Some(msg));
+    }
+}
+
+/// Changes the folder where the crash reports are written (e.g. once the run directory is known).
+pub fn set_crash_dir(crash_dir: &Path) {
+    *CRASH_DIR.lock().unwrap_or_else(|e| e.into_inner()) = Some(crash_dir.to_path_buf());
+}
+
+/// Updates the description of what the application is doing.
+pub fn set_state(state: &str) {
+    *CURRENT_STATE.lock().unwrap_or_else(|e| e.into_inner()) = state.to_string();
+}
+
+/// Returns the description of what the application is doing.
+pub fn current_state() -> String {
+    CURRENT_STATE.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Registers the run in progress, so that it can be marked as crashed.
+pub fn set_current_run(run_log: &RunLog) {
+    *CURRENT_RUN.lock().unwrap_or_else(|e| e.into_inner()) = Some(run_log.clone());
+}
+
+/// Marks the run in progress (if any) as crashed in the runs index.
+pub fn mark_run_crashed() {
+    if let Some(run_log) = CURRENT_RUN.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        if let Err(e) = run_log.finish(RunStatus::Crashed) {
+            eprintln!("Unable to mark the run as crashed: {}", e);
+        }
+    }
+}
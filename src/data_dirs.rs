@@ -0,0 +1,156 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Name of the folder of the program inside the folders of the system.
+#[cfg(windows)]
+const APP_DIR_NAME: &str = "EmergencyBackup";
+#[cfg(target_os = "macos")]
+const APP_DIR_NAME: &str = "EmergencyBackup";
+#[cfg(not(any(windows, target_os = "macos")))]
+const APP_DIR_NAME: &str = "emergency_backup";
+
+/// Marker left in the old folder of the logs once they have been moved, so that they are not moved again.
+const MIGRATED_MARKER: &str = "MOVED.txt";
+
+fn env_dir(name: &str) -> Option<PathBuf> {
+    env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from)
+}
+
+#[cfg(not(windows))]
+fn home_dir() -> Option<PathBuf> {
+    env_dir("HOME").or_else(|| env_dir("USERPROFILE"))
+}
+
+/// Returns a folder of the user, falling back to a folder shared by all the users when the user has none
+/// (e.g. a service running without a profile).
+#[cfg(windows)]
+fn base_dir(kind: DirKind) -> PathBuf {
+    let user = match kind {
+        DirKind::Config => env_dir("APPDATA"),
+        DirKind::Logs | DirKind::Data => env_dir("LOCALAPPDATA").or_else(|| env_dir("APPDATA")),
+    };
+    user.or_else(|| env_dir("PROGRAMDATA"))
+        .unwrap_or_else(|| PathBuf::from(r"C:\ProgramData"))
+}
+
+#[cfg(target_os = "macos")]
+fn base_dir(kind: DirKind) -> PathBuf {
+    let home = home_dir().unwrap_or_else(|| PathBuf::from("/Library"));
+    match kind {
+        DirKind::Config | DirKind::Data => home.join("Library/Application Support"),
+        DirKind::Logs => home.join("Library/Logs"),
+    }
+}
+
+/// The XDG base directories: `$XDG_CONFIG_HOME` (`~/.config`), `$XDG_STATE_HOME` (`~/.local/state`)
+/// and `$XDG_DATA_HOME` (`~/.local/share`).
+#[cfg(not(any(windows, target_os = "macos")))]
+fn base_dir(kind: DirKind) -> PathBuf {
+    let (variable, default) = match kind {
+        DirKind::Config => ("XDG_CONFIG_HOME", ".config"),
+        DirKind::Logs => ("XDG_STATE_HOME", ".local/state"),
+        DirKind::Data => ("XDG_DATA_HOME", ".local/share"),
+    };
+    env_dir(variable)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| home_dir().map(|home| home.join(default)))
+        .unwrap_or_else(|| PathBuf::from("/var/lib"))
+}
+
+#[derive(Clone, Copy)]
+enum DirKind {
+    Config,
+    Logs,
+    Data,
+}
+
+/// Returns the folder of the configuration file of the user: `%APPDATA%\EmergencyBackup` on Windows,
+/// `~/Library/Application Support/EmergencyBackup` on macOS, `$XDG_CONFIG_HOME/emergency_backup` elsewhere.
+pub fn config_dir() -> PathBuf {
+    base_dir(DirKind::Config).join(APP_DIR_NAME)
+}
+
+/// Returns the root folder of the logs of the user: `%LOCALAPPDATA%\EmergencyBackup\log` on Windows,
+/// `~/Library/Logs/EmergencyBackup` on macOS, `$XDG_STATE_HOME/emergency_backup/log` elsewhere.
+pub fn log_dir() -> PathBuf {
+    let dir = base_dir(DirKind::Logs).join(APP_DIR_NAME);
+    if cfg!(target_os = "macos") { dir } else { dir.join("log") }
+}
+
+/// Returns the folder of the sounds: the `resources` folder of the user, where custom sounds can be put,
+/// if it exists, otherwise the one installed next to the executable (read only, so it can stay there).
+pub fn resources_dir() -> PathBuf {
+    let user = base_dir(DirKind::Data).join(APP_DIR_NAME).join("resources");
+    if user.is_dir() {
+        user
+    } else {
+        exe_adjacent("resources")
+    }
+}
+
+/// Returns a path next to the executable, where the older versions kept their files.
+pub fn exe_adjacent(name: &str) -> PathBuf {
+    env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(name)))
+        .unwrap_or_else(|| PathBuf::from(name))
+}
+
+/// Copies the configuration file written next to the executable by an older version in the folder of the user,
+/// which must have none yet. The old file is removed when possible (it is not, under Program Files without
+/// elevation, and then it is just left there).
+///
+/// # Returns
+///
+/// The path of the configuration in the folder of the user.
+pub fn migrate_config(old: &Path) -> io::Result<PathBuf> {
+    let new_dir = config_dir();
+    let new = new_dir.join(old.file_name().unwrap_or_default());
+    fs::create_dir_all(&new_dir)?;
+    fs::copy(old, &new)?;
+    if fs::remove_file(old).is_ok() {
+        eprintln!("Configuration moved from {} to {}", old.display(), new.display());
+    } else {
+        eprintln!("Configuration copied from {} to {}", old.display(), new.display());
+    }
+    Ok(new)
+}
+
+/// Moves the logs written next to the executable by an older version to the folder of the user, if it does
+/// not exist yet: with a rename when both are on the same drive, otherwise with a copy (the old files are left
+/// where they are, with a note saying where the new ones are).
+pub fn migrate_logs() -> io::Result<()> {
+    let old = exe_adjacent("log");
+    let new = log_dir();
+    if !old.is_dir() || new.exists() || old.join(MIGRATED_MARKER).exists() {
+        return Ok(());
+    }
+    if let Some(parent) = new.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(&old, &new).is_ok() {
+        eprintln!("Logs moved from {} to {}", old.display(), new.display());
+        return Ok(());
+    }
+    copy_dir(&old, &new)?;
+    // The old folder may be read only: the copy is done anyway
+    let _ = fs::write(old.join(MIGRATED_MARKER), format!("The logs are now in {}\n", new.display()));
+    eprintln!("Logs copied from {} to {}", old.display(), new.display());
+    Ok(())
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
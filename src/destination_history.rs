@@ -0,0 +1,136 @@
+use crate::backup::{bytes_to_human_readable, BackupReport};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Name of the history of the destinations, written in the log root next to `runs.index`.
+pub const HISTORY_FILE_NAME: &str = "destinations.json";
+
+/// Backups remembered for each destination.
+const MAX_SAMPLES: usize = 30;
+
+/// Latest backups compared with the whole history, to notice a drive that is slowing down.
+const RECENT_SAMPLES: usize = 3;
+
+/// Least bytes a backup has to copy to measure the speed of the destination (below, the time is mostly
+/// spent opening the files).
+const MIN_MEASURED_BYTES: u64 = 16 * 1024 * 1024;
+
+/// How the copies towards a destination went in a backup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sample {
+    pub time: String,
+    /// Bytes written per second, if the backup copied enough to measure it.
+    pub bytes_per_sec: Option<u64>,
+    pub copied: usize,
+    pub failed: usize,
+    /// Whether the backup stopped with an error (e.g. the drive was disconnected).
+    pub aborted: bool,
+}
+
+/// The performance of a destination over the backups remembered.
+#[derive(Debug, Clone)]
+pub struct DestinationStats {
+    pub backups: usize,
+    /// Median speed of all the backups, and of the latest ones.
+    pub median_bytes_per_sec: Option<u64>,
+    pub recent_bytes_per_sec: Option<u64>,
+    /// Share of the copies that failed, from 0 to 1.
+    pub failure_rate: f64,
+    /// Backups stopped by an error.
+    pub aborted: usize,
+}
+
+impl DestinationStats {
+    /// Returns whether the destination looks like it is failing: its latest backups are much slower than usual,
+    /// or many of its copies fail.
+    pub fn is_degrading(&self) -> bool {
+        let slower = match (self.median_bytes_per_sec, self.recent_bytes_per_sec) {
+            (Some(median), Some(recent)) => self.backups > RECENT_SAMPLES && recent < median / 2,
+            _ => false,
+        };
+        slower || self.failure_rate > 0.01 || self.aborted * 4 > self.backups
+    }
+
+    /// Describes the performance, e.g. "24.1 MB/s (median of 8 backups, latest 23.5 MB/s), 0.0% copies failed".
+    pub fn describe(&self) -> String {
+        let speed = match (self.median_bytes_per_sec, self.recent_bytes_per_sec) {
+            (Some(median), Some(recent)) => format!(
+                "{}/s (median of {} backups, latest {}/s)",
+                bytes_to_human_readable(median), self.backups, bytes_to_human_readable(recent)
+            ),
+            _ => format!("speed unknown ({} backups)", self.backups),
+        };
+        let mut text = format!("{}, {:.1}% copies failed", speed, self.failure_rate * 100.0);
+        if self.aborted > 0 {
+            text.push_str(&format!(", {} backups stopped by an error", self.aborted));
+        }
+        if self.is_degrading() {
+            text.push_str("\nThe drive may be failing: replace it before an emergency");
+        }
+        text
+    }
+}
+
+fn median(mut values: Vec<u64>) -> Option<u64> {
+    values.sort_unstable();
+    values.get(values.len() / 2).copied()
+}
+
+/// Reads the history of all the destinations, by path.
+fn load(log_root: &Path) -> BTreeMap<String, Vec<Sample>> {
+    fs::read_to_string(log_root.join(HISTORY_FILE_NAME))
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Adds a backup to the history of its destination, forgetting the oldest ones beyond `MAX_SAMPLES`.
+///
+/// # Arguments
+///
+/// * `log_root` - The folder of the logs, where the history is written.
+/// * `destination` - The main destination of the backup.
+/// * `report` - The report of the backup (even a partial one, if it stopped with an error).
+/// * `aborted` - Whether the backup stopped with an error.
+pub fn record(log_root: &Path, destination: &Path, report: &BackupReport, aborted: bool) -> io::Result<()> {
+    let seconds = report.copy_time.as_secs_f64();
+    let sample = Sample {
+        time: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        bytes_per_sec: (report.copied_bytes >= MIN_MEASURED_BYTES && seconds > 0.0).then(|| (report.copied_bytes as f64 / seconds) as u64),
+        copied: report.copied,
+        failed: report.failed,
+        aborted,
+    };
+    let mut history = load(log_root);
+    let samples = history.entry(destination.display().to_string()).or_default();
+    samples.push(sample);
+    let excess = samples.len().saturating_sub(MAX_SAMPLES);
+    samples.drain(..excess);
+    let contents = serde_json::to_string_pretty(&history).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fs::write(log_root.join(HISTORY_FILE_NAME), contents)
+}
+
+/// Returns the performance of a destination over the backups remembered, if it has been used.
+///
+/// # Arguments
+///
+/// * `log_root` - The folder of the logs, where the history is written.
+/// * `destination` - The destination.
+pub fn stats(log_root: &Path, destination: &Path) -> Option<DestinationStats> {
+    let samples = load(log_root).remove(&destination.display().to_string()).filter(|samples| !samples.is_empty())?;
+    let speeds: Vec<u64> = samples.iter().filter_map(|sample| sample.bytes_per_sec).collect();
+    let recent: Vec<u64> = speeds.iter().rev().take(RECENT_SAMPLES).copied().collect();
+    let copied: usize = samples.iter().map(|sample| sample.copied).sum();
+    let failed: usize = samples.iter().map(|sample| sample.failed).sum();
+    Some(DestinationStats {
+        backups: samples.len(),
+        median_bytes_per_sec: median(speeds),
+        recent_bytes_per_sec: median(recent),
+        failure_rate: if copied + failed > 0 { failed as f64 / (copied + failed) as f64 } else { 0.0 },
+        aborted: samples.iter().filter(|sample| sample.aborted).count(),
+    })
+}
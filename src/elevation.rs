@@ -0,0 +1,213 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+/// How the backup gets the privileges to read protected files (e.g. the folders of other users).
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Elevation {
+    /// The files that cannot be read are reported as failed (the default)
+    #[default]
+    Never,
+    /// The backup privilege (SeBackupPrivilege) is enabled, if the user has it, to read any file
+    Privilege,
+    /// As `privilege`, and if some files were still denied, the backup is run again elevated
+    /// (after the confirmation of the user: UAC on Windows, pkexec elsewhere)
+    Relaunch,
+}
+
+/// Category of the failures due to missing privileges, as recorded in the `BackupReport`.
+pub const ACCESS_DENIED: &str = "PermissionDenied";
+
+/// Enables the backup privilege (SeBackupPrivilege) of the process, which lets it read any file
+/// regardless of its permissions. Only administrators and backup operators own this privilege.
+///
+/// # Returns
+///
+/// A `Result` which is `Ok` if the privilege is enabled, or a message describing why it is not.
+#[cfg(target_os = "windows")]
+pub fn enable_backup_privilege() -> Result<(), String> {
+    use std::ptr::null_mut;
+    use winapi::shared::winerror::ERROR_NOT_ALL_ASSIGNED;
+    use winapi::um::errhandlingapi::GetLastError;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{GetCurrentProcess, OpenProcessToken};
+    use winapi::um::securitybaseapi::AdjustTokenPrivileges;
+    use winapi::um::winbase::LookupPrivilegeValueW;
+    use winapi::um::winnt::{SE_PRIVILEGE_ENABLED, TOKEN_ADJUST_PRIVILEGES, TOKEN_PRIVILEGES, TOKEN_QUERY};
+
+    let name: Vec<u16> = "SeBackupPrivilege".encode_utf16().chain(Some(0)).collect();
+    unsafe {
+        let mut token = null_mut();
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY, &mut token) == 0 {
+            return Err("Unable to open the token of the process".to_string());
+        }
+
+        let mut privileges: TOKEN_PRIVILEGES = std::mem::zeroed();
+        privileges.PrivilegeCount = 1;
+        privileges.Privileges[0].Attributes = SE_PRIVILEGE_ENABLED;
+        let result = if LookupPrivilegeValueW(null_mut(), name.as_ptr(), &mut privileges.Privileges[0].Luid) == 0 {
+            Err("Unable to find the backup privilege".to_string())
+        } else if AdjustTokenPrivileges(token, 0, &mut privileges, 0, null_mut(), null_mut()) == 0
+            || GetLastError() == ERROR_NOT_ALL_ASSIGNED
+        {
+            Err("The user does not have the backup privilege".to_string())
+        } else {
+            Ok(())
+        };
+        CloseHandle(token);
+        result
+    }
+}
+
+/// There is no backup privilege outside Windows: only the superuser can read any file.
+#[cfg(not(target_os = "windows"))]
+pub fn enable_backup_privilege() -> Result<(), String> {
+    if is_elevated() {
+        Ok(())
+    } else {
+        Err("The backup is not running as root".to_string())
+    }
+}
+
+/// Returns whether the process is running elevated (as administrator on Windows, as root elsewhere).
+#[cfg(target_os = "windows")]
+pub fn is_elevated() -> bool {
+    use std::ptr::null_mut;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{GetCurrentProcess, OpenProcessToken};
+    use winapi::um::securitybaseapi::GetTokenInformation;
+    use winapi::um::winnt::{TokenElevation, TOKEN_ELEVATION, TOKEN_QUERY};
+
+    unsafe {
+        let mut token = null_mut();
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) == 0 {
+            return false;
+        }
+        let mut elevation: TOKEN_ELEVATION = std::mem::zeroed();
+        let mut size = 0;
+        let result = GetTokenInformation(
+            token,
+            TokenElevation,
+            &mut elevation as *mut _ as *mut _,
+            std::mem::size_of::<TOKEN_ELEVATION>() as u32,
+            &mut size,
+        );
+        CloseHandle(token);
+        result != 0 && elevation.TokenIsElevated != 0
+    }
+}
+
+/// Returns whether the process is running elevated (as administrator on Windows, as root elsewhere).
+#[cfg(not(target_os = "windows"))]
+pub fn is_elevated() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Starts this program again, elevated, with the given arguments: the user is asked to confirm
+/// (UAC on Windows, pkexec elsewhere). The current process does not wait for it.
+///
+/// # Arguments
+///
+/// * `args` - The arguments of the new process (e.g. `backup now`). The paths must be absolute: pkexec
+///   resets the environment (`HOME` included), so the default configuration would be the one of root.
+///
+/// # Returns
+///
+/// A `Result` which is `Ok` if the elevated process has been started, or a message describing the problem.
+#[cfg(target_os = "windows")]
+pub fn relaunch_elevated(args: &[String]) -> Result<(), String> {
+    use std::ptr::null_mut;
+    use winapi::um::shellapi::ShellExecuteW;
+    use winapi::um::winuser::SW_SHOWNORMAL;
+
+    let to_wide = |text: &str| -> Vec<u16> { text.encode_utf16().chain(Some(0)).collect() };
+    let exe = std::env::current_exe().map_err(|e| format!("Unable to find the executable: {}", e))?;
+    let parameters = args.iter().map(|arg| format!("\"{}\"", arg)).collect::<Vec<_>>().join(" ");
+
+    let (verb, exe, parameters) = (to_wide("runas"), to_wide(&exe.display().to_string()), to_wide(&parameters));
+    // ShellExecute returns a value greater than 32 on success
+    let result = unsafe { ShellExecuteW(null_mut(), verb.as_ptr(), exe.as_ptr(), parameters.as_ptr(), null_mut(), SW_SHOWNORMAL) };
+    if result as usize > 32 {
+        Ok(())
+    } else {
+        Err(format!("The elevation has been refused or has failed (code {})", result as usize))
+    }
+}
+
+/// Starts this program again, elevated, with the given arguments: the user is asked to confirm
+/// (UAC on Windows, pkexec elsewhere). The current process does not wait for it.
+///
+/// # Arguments
+///
+/// * `args` - The arguments of the new process (e.g. `backup now`). The paths must be absolute: pkexec
+///   resets the environment (`HOME` included), so the default configuration would be the one of root.
+///
+/// # Returns
+///
+/// A `Result` which is `Ok` if the elevated process has been started, or a message describing the problem.
+#[cfg(not(target_os = "windows"))]
+pub fn relaunch_elevated(args: &[String]) -> Result<(), String> {
+    use std::process::Command;
+
+    let exe = std::env::current_exe().map_err(|e| format!("Unable to find the executable: {}", e))?;
+    Command::new("pkexec")
+        .arg(exe)
+        .args(args)
+        .spawn()
+        .map(|_| ())
+        .map_err(|e| format!("Unable to run pkexec: {}", e))
+}
+
+/// Gives back to the user who started the elevated backup (through pkexec, `PKEXEC_UID`) the files and folders
+/// created as root in the destinations, which otherwise only root could modify or remove.
+/// Nothing is done if the process has not been started by pkexec.
+///
+/// # Arguments
+///
+/// * `roots` - The root folders of the destinations.
+///
+/// # Returns
+///
+/// The number of files and folders given back, or the error that stopped the walk.
+#[cfg(not(target_os = "windows"))]
+pub fn return_ownership(roots: &[&Path]) -> io::Result<usize> {
+    let Some(uid) = std::env::var("PKEXEC_UID").ok().and_then(|uid| uid.parse::<u32>().ok()) else {
+        return Ok(0);
+    };
+    // The primary group of the user, as for the files they create themselves
+    let passwd = unsafe { libc::getpwuid(uid) };
+    let gid = (!passwd.is_null()).then(|| unsafe { (*passwd).pw_gid });
+    let mut count = 0;
+    for root in roots {
+        count += return_ownership_of_tree(root, uid, gid)?;
+    }
+    Ok(count)
+}
+
+#[cfg(not(target_os = "windows"))]
+fn return_ownership_of_tree(dir: &Path, uid: u32, gid: Option<u32>) -> io::Result<usize> {
+    use std::os::unix::fs::MetadataExt;
+
+    let mut count = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        // The links are not followed: only the link itself is given back
+        let metadata = path.symlink_metadata()?;
+        if metadata.uid() == 0 {
+            std::os::unix::fs::lchown(&path, Some(uid), gid)?;
+            count += 1;
+        }
+        if metadata.is_dir() {
+            count += return_ownership_of_tree(&path, uid, gid)?;
+        }
+    }
+    Ok(count)
+}
+
+/// On Windows the files created elevated belong to the Administrators group, and the user keeps access to them
+/// through the permissions inherited from the destination folder: nothing is changed.
+#[cfg(target_os = "windows")]
+pub fn return_ownership(_roots: &[&Path]) -> io::Result<usize> {
+    Ok(0)
+}
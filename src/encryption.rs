@@ -0,0 +1,243 @@
+use crate::metadata_crypto;
+use crate::secrets;
+use age::secrecy::{ExposeSecret, SecretString};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Name of the key of a destination, written in its root encrypted with the passphrase.
+pub const KEY_FILE_NAME: &str = ".emergency_backup_key.age";
+
+/// First bytes of every file encrypted with age.
+const AGE_HEADER: &[u8] = b"age-encryption.org/v1\n";
+
+/// How the copied files are encrypted (`encrypt` in the configuration).
+#[derive(Debug, Clone, PartialEq)]
+pub enum EncryptionKey {
+    /// A reference to a passphrase (`keyring:<service>/<account>` or `file:<path>`): each destination has its own
+    /// age key, stored in its root (`KEY_FILE_NAME`) encrypted with the passphrase.
+    Passphrase(String),
+    /// An age identity file (as written by `age-keygen`), kept by the user away from the destination.
+    KeyFile(PathBuf),
+}
+
+/// The key chosen in the configuration, if the copies are encrypted.
+static KEY: RwLock<Option<EncryptionKey>> = RwLock::new(None);
+
+/// The age identity of the destinations of the current backup (or restore), once unlocked.
+static UNLOCKED: RwLock<Option<age::x25519::Identity>> = RwLock::new(None);
+
+/// Sets how the copied files are encrypted from now on, or `None` to copy them in clear.
+pub fn set_key(key: Option<EncryptionKey>) {
+    *KEY.write().unwrap() = key;
+    *UNLOCKED.write().unwrap() = None;
+}
+
+/// Returns whether the copied files are encrypted.
+pub fn is_enabled() -> bool {
+    KEY.read().unwrap().is_some()
+}
+
+fn other_error<E: std::fmt::Display>(e: E) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+fn passphrase(reference: &str) -> io::Result<SecretString> {
+    secrets::resolve_secret(reference)
+        .map(SecretString::from)
+        .map_err(|e| io::Error::new(io::ErrorKind::PermissionDenied, e.to_string()))
+}
+
+/// Reads the age key stored in a destination, decrypting it with the passphrase.
+fn read_key_file(path: &Path, passphrase: &SecretString) -> io::Result<age::x25519::Identity> {
+    let decryptor = age::Decryptor::new(File::open(path)?).map_err(other_error)?;
+    if !decryptor.is_scrypt() {
+        return Err(other_error(format!("{} is not encrypted with a passphrase", path.display())));
+    }
+    let identity = age::scrypt::Identity::new(passphrase.clone());
+    let mut reader = decryptor
+        .decrypt(std::iter::once(&identity as &dyn age::Identity))
+        .map_err(|e| io::Error::new(io::ErrorKind::PermissionDenied, format!("unable to read {}: {} (wrong passphrase?)", path.display(), e)))?;
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+    text.trim().parse().map_err(|e| other_error(format!("invalid key in {}: {}", path.display(), e)))
+}
+
+/// Writes the age key of a destination, encrypted with the passphrase: with the `age` tool it can be decrypted
+/// (`age -d`) and then used to decrypt the files (`age -d -i`), even without this program.
+fn write_key_file(path: &Path, identity: &age::x25519::Identity, passphrase: &SecretString) -> io::Result<()> {
+    let mut encrypted = Vec::new();
+    let mut writer = age::Encryptor::with_user_passphrase(passphrase.clone()).wrap_output(&mut encrypted).map_err(other_error)?;
+    writer.write_all(identity.to_string().expose_secret().as_bytes())?;
+    writer.finish()?;
+    fs::write(path, encrypted)
+}
+
+/// Reads the key stored in the destinations that have one, checking that it is the same in all of them.
+fn find_key(roots: &[&Path], passphrase: &SecretString) -> io::Result<Option<age::x25519::Identity>> {
+    let mut identity: Option<age::x25519::Identity> = None;
+    for root in roots {
+        let key_file = root.join(KEY_FILE_NAME);
+        if !key_file.exists() {
+            continue;
+        }
+        let found = read_key_file(&key_file, passphrase)?;
+        match &identity {
+            Some(identity) if identity.to_public().to_string() != found.to_public().to_string() => {
+                return Err(other_error(format!("{} holds another key than the other destination: use another folder", root.display())));
+            }
+            Some(_) => {}
+            None => identity = Some(found),
+        }
+    }
+    Ok(identity)
+}
+
+/// Unlocks the key of the destinations before a backup. With a passphrase, the key stored in the first
+/// destination that has one is decrypted and written in the others (a new key is made for new destinations);
+/// with a key file, the identity is read from it.
+///
+/// # Arguments
+///
+/// * `roots` - The root folders of the destinations.
+///
+/// # Errors
+///
+/// Returns an error if the passphrase cannot be read or is wrong, or if two destinations have different keys.
+pub fn unlock(roots: &[&Path]) -> io::Result<()> {
+    let identity = match KEY.read().unwrap().clone() {
+        None => return Ok(()),
+        Some(EncryptionKey::KeyFile(path)) => metadata_crypto::read_identities(&path)?.remove(0),
+        Some(EncryptionKey::Passphrase(reference)) => {
+            let passphrase = passphrase(&reference)?;
+            let identity = find_key(roots, &passphrase)?.unwrap_or_else(age::x25519::Identity::generate);
+            for root in roots {
+                let key_file = root.join(KEY_FILE_NAME);
+                if !key_file.exists() {
+                    write_key_file(&key_file, &identity, &passphrase)?;
+                }
+            }
+            identity
+        }
+    };
+    *UNLOCKED.write().unwrap() = Some(identity);
+    Ok(())
+}
+
+/// Unlocks the key of a backup before restoring it, without writing anything in it.
+///
+/// # Errors
+///
+/// Returns an error if the backup is encrypted with a passphrase but `encrypt` is not configured,
+/// or if the passphrase cannot be read or is wrong.
+pub fn unlock_backup(root: &Path) -> io::Result<()> {
+    let identity = match KEY.read().unwrap().clone() {
+        Some(EncryptionKey::KeyFile(path)) => Some(metadata_crypto::read_identities(&path)?.remove(0)),
+        Some(EncryptionKey::Passphrase(reference)) => find_key(&[root], &passphrase(&reference)?)?,
+        None if root.join(KEY_FILE_NAME).exists() => {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "the backup is encrypted: set encrypt and encrypt_passphrase to restore it"));
+        }
+        None => None,
+    };
+    *UNLOCKED.write().unwrap() = identity;
+    Ok(())
+}
+
+fn unlocked() -> io::Result<age::x25519::Identity> {
+    UNLOCKED.read().unwrap().clone().ok_or_else(|| other_error("the key of the backup has not been unlocked"))
+}
+
+/// The bytes written by the encryption, taken out after each chunk.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Encrypts a file while it is copied: the chunks read from the source go in, the bytes to write in the
+/// destination come out. Each file has its own random file key (the format of age).
+pub struct FileEncryptor {
+    stream: age::stream::StreamWriter<SharedBuffer>,
+    output: SharedBuffer,
+}
+
+impl FileEncryptor {
+    /// Starts the encryption of a file for the unlocked key.
+    pub fn new() -> io::Result<FileEncryptor> {
+        let recipient = unlocked()?.to_public();
+        let encryptor = age::Encryptor::with_recipients(std::iter::once(&recipient as &dyn age::Recipient)).map_err(other_error)?;
+        let output = SharedBuffer::default();
+        let stream = encryptor.wrap_output(output.clone()).map_err(other_error)?;
+        Ok(FileEncryptor { stream, output })
+    }
+
+    /// Encrypts a chunk of the file, returning the bytes ready to be written (the encryption works on blocks
+    /// of 64 KiB, so they may be fewer than the chunk).
+    pub fn update(&mut self, plain: &[u8]) -> io::Result<Vec<u8>> {
+        self.stream.write_all(plain)?;
+        Ok(self.output.take())
+    }
+
+    /// Ends the encryption, returning the last bytes to write.
+    pub fn finish(self) -> io::Result<Vec<u8>> {
+        self.stream.finish()?;
+        Ok(self.output.take())
+    }
+}
+
+/// Returns whether a file in a backup has been encrypted.
+pub fn is_encrypted(path: &Path) -> io::Result<bool> {
+    let mut header = [0u8; AGE_HEADER.len()];
+    let mut file = File::open(path)?;
+    match file.read_exact(&mut header) {
+        Ok(()) => Ok(header == AGE_HEADER),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Opens an encrypted file of a backup, returning a reader of its content in clear.
+pub fn open_decrypted(path: &Path) -> io::Result<impl Read> {
+    let identity = unlocked()?;
+    let decryptor = age::Decryptor::new(File::open(path)?).map_err(other_error)?;
+    if decryptor.is_scrypt() {
+        return Err(other_error(format!("{} is encrypted with a passphrase", path.display())));
+    }
+    decryptor
+        .decrypt(std::iter::once(&identity as &dyn age::Identity))
+        .map_err(|e| other_error(format!("unable to decrypt {}: {}", path.display(), e)))
+}
+
+/// Returns the BLAKE3 hash of the content in clear of an encrypted file, in hexadecimal.
+pub fn hash_decrypted(path: &Path) -> io::Result<String> {
+    let mut reader = open_decrypted(path)?;
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Copies a file out of a backup, decrypting it if it has been encrypted (and the key has been unlocked).
+pub fn restore_file(stored: &Path, target: &Path) -> io::Result<()> {
+    if UNLOCKED.read().unwrap().is_none() || !is_encrypted(stored)? {
+        return fs::copy(stored, target).map(|_| ());
+    }
+    let mut reader = open_decrypted(stored)?;
+    let mut writer = io::BufWriter::new(File::create(target)?);
+    io::copy(&mut reader, &mut writer)?;
+    writer.flush()
+}
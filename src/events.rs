@@ -0,0 +1,159 @@
+use crate::app_state::{self, StateEvent};
+use crate::beeper::{self, SoundEvent};
+use crate::notification_popup::{self, show_popup, NotificationType};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError, Receiver, Sender};
+
+/// Events kept for a subscriber that is late in reading them; the older ones are lost.
+const BUS_CAPACITY: usize = 64;
+
+/// The activation pattern in use, whose notifications tell how to confirm it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ActivationPattern {
+    /// The rectangle drawn with the mouse
+    Gesture,
+    /// Ctrl+Alt+B held down, then the clicks
+    ButtonsAndClicks,
+}
+
+/// What happened in the application, published on the bus: the recognizers and the backup publish the events,
+/// the outputs (notifications, sounds, the state of the application, ...) subscribe to them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AppEvent {
+    /// The first step of the activation pattern has been done.
+    Armed(ActivationPattern),
+    /// The estimate of the backup armed (files, size and duration), known some time after the first step.
+    Estimated(ActivationPattern, String),
+    /// The gesture has been confirmed, but the backup also waits for the Ctrl+Alt+B hold (`two_factor_arming`).
+    SecondFactorNeeded,
+    /// The backup has been confirmed with the pattern, or started without it.
+    Started,
+    /// The backup has been canceled with the pattern.
+    Canceled,
+    /// The backup has ended, with the details to show (e.g. whether each replica is complete).
+    Done(Option<String>),
+    /// The backup has failed.
+    Failed(String),
+    /// An error to report to the user.
+    Error(String),
+}
+
+/// The bus, created with its default subscribers by the first event or subscription.
+static BUS: OnceLock<Sender<AppEvent>> = OnceLock::new();
+
+/// Events published but not yet notified to the user.
+static UNNOTIFIED: AtomicUsize = AtomicUsize::new(0);
+
+fn bus() -> &'static Sender<AppEvent> {
+    BUS.get_or_init(|| {
+        let (tx, _) = broadcast::channel(BUS_CAPACITY);
+        // Subscribed before any event is published, so that none is lost
+        spawn_notifier(tx.subscribe());
+        spawn_subscriber(tx.subscribe(), drive_state);
+        tx
+    })
+}
+
+/// Publishes an event to all the subscribers.
+pub fn publish(event: AppEvent) {
+    UNNOTIFIED.fetch_add(1, Ordering::SeqCst);
+    // The default subscribers are always there, so the event cannot be dropped
+    let _ = bus().send(event);
+}
+
+/// Waits until the events published so far have been notified to the user and their popups have been closed,
+/// by the user or at the end of `popup_duration_secs`: the process can then exit without hiding them.
+pub fn wait_until_notified() {
+    while UNNOTIFIED.load(Ordering::SeqCst) > 0 {
+        thread::sleep(Duration::from_millis(50));
+    }
+    notification_popup::wait_for_notifications();
+}
+
+/// Returns a new subscription to the events published from now on, e.g. for a new output.
+pub fn subscribe() -> Receiver<AppEvent> {
+    bus().subscribe()
+}
+
+/// Starts a thread calling `handle` with every event received by a subscription.
+///
+/// # Arguments
+///
+/// * `events` - The subscription.
+/// * `handle` - The function handling each event.
+pub fn spawn_subscriber(mut events: Receiver<AppEvent>, handle: fn(&AppEvent)) {
+    thread::spawn(move || loop {
+        match events.blocking_recv() {
+            Ok(event) => handle(&event),
+            Err(RecvError::Lagged(missed)) => println!("{} events lost by a slow subscriber", missed),
+            Err(RecvError::Closed) => break,
+        }
+    });
+}
+
+/// Starts the thread notifying the user of the events (`notify_user`), counting the events notified.
+fn spawn_notifier(mut events: Receiver<AppEvent>) {
+    thread::spawn(move || loop {
+        match events.blocking_recv() {
+            Ok(event) => {
+                notify_user(&event);
+                UNNOTIFIED.fetch_sub(1, Ordering::SeqCst);
+            }
+            Err(RecvError::Lagged(missed)) => {
+                println!("{} notifications lost", missed);
+                UNNOTIFIED.fetch_sub(missed as usize, Ordering::SeqCst);
+            }
+            Err(RecvError::Closed) => break,
+        }
+    });
+}
+
+/// The notification of the first step of a pattern.
+fn first_step(pattern: ActivationPattern) -> NotificationType {
+    match pattern {
+        ActivationPattern::Gesture => NotificationType::FirstStepDone,
+        ActivationPattern::ButtonsAndClicks => NotificationType::FirstStepDoneBC,
+    }
+}
+
+/// The default output: the sounds and the notifications shown to the user.
+fn notify_user(event: &AppEvent) {
+    match event {
+        AppEvent::Armed(pattern) => {
+            beeper::emit_sound(SoundEvent::Armed);
+            show_popup(first_step(*pattern), None);
+        }
+        AppEvent::Estimated(pattern, estimate) => notification_popup::update_popup(first_step(*pattern), estimate.clone()),
+        AppEvent::SecondFactorNeeded => show_popup(NotificationType::SecondFactorNeeded, None),
+        AppEvent::Started => {
+            beeper::emit_sound(SoundEvent::Started);
+            show_popup(NotificationType::BackupStarted, None);
+        }
+        AppEvent::Canceled => {
+            beeper::emit_sound(SoundEvent::Canceled);
+            show_popup(NotificationType::BackupCanceled, None);
+        }
+        AppEvent::Done(details) => {
+            beeper::emit_sound(SoundEvent::Done);
+            show_popup(NotificationType::BackupDone, details.clone());
+        }
+        AppEvent::Failed(msg) => show_popup(NotificationType::GenericError, Some(format!("Backup failed: {}", msg))),
+        AppEvent::Error(msg) => show_popup(NotificationType::GenericError, Some(msg.clone())),
+    }
+}
+
+/// Keeps the state of the application in line with the events.
+fn drive_state(event: &AppEvent) {
+    let state_event = match event {
+        AppEvent::Armed(_) => StateEvent::Armed,
+        AppEvent::Started => StateEvent::Started,
+        AppEvent::Canceled => StateEvent::Canceled,
+        AppEvent::Done(_) => StateEvent::Finished,
+        AppEvent::Failed(msg) => StateEvent::Failed(msg.clone()),
+        AppEvent::Estimated(..) | AppEvent::SecondFactorNeeded | AppEvent::Error(_) => return,
+    };
+    app_state::send(state_event);
+}
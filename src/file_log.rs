@@ -0,0 +1,57 @@
+use crate::locale;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Name of the per-file log, written in the directory of the run with `verbose_file_log`.
+pub const FILE_LOG_NAME: &str = "files.log";
+
+/// The log of every file copied by a backup, with how long it took, to find which folders make the backup slow.
+/// Each line has the format `duration | size | throughput | result | path`, separated by tabs.
+pub struct FileLog {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl FileLog {
+    /// Creates the log, replacing an existing one.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the log file.
+    pub fn create(path: &Path) -> io::Result<FileLog> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "duration\tsize\tthroughput\tresult\tpath")?;
+        Ok(FileLog { writer: Mutex::new(writer) })
+    }
+
+    /// Records the copy of a file. A failure to write is reported on the console, since it must not stop the backup.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The source file.
+    /// * `size` - The size of the file, if known.
+    /// * `duration` - How long the copy took.
+    /// * `result` - How the copy ended, e.g. `copied` or the error.
+    pub fn record(&self, path: &Path, size: Option<u64>, duration: Duration, result: &str) {
+        let throughput = match size {
+            Some(size) if duration.as_secs_f64() > 0.0 => {
+                format!("{}/s", locale::format_size((size as f64 / duration.as_secs_f64()) as u64))
+            }
+            _ => "-".to_string(),
+        };
+        let size = size.map_or_else(|| "-".to_string(), locale::format_size);
+        let line = format!("{}\t{}\t{}\t{}\t{}", locale::format_duration(duration), size, throughput, result, path.display());
+        if let Err(e) = writeln!(self.writer.lock().unwrap(), "{}", line) {
+            eprintln!("Unable to write the per-file log: {}", e);
+        }
+    }
+
+    /// Writes the lines still buffered, at the end of the backup.
+    pub fn flush(&self) {
+        if let Err(e) = self.writer.lock().unwrap().flush() {
+            eprintln!("Unable to write the per-file log: {}", e);
+        }
+    }
+}
@@ -0,0 +1,671 @@
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::fs::{self, File};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+use crate::uring;
+use crate::uring::OnProgress;
+
+/// What the backup needs to know about a file or a folder.
+#[derive(Debug, Clone)]
+pub struct FileInfo {
+    pub is_dir: bool,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+    /// Whether the file is a cloud placeholder (e.g. OneDrive "online-only"), whose content is downloaded when read.
+    pub placeholder: bool,
+}
+
+/// A file opened for reading.
+pub type Reader = Box<dyn AsyncRead + Send + Unpin>;
+
+/// A file opened for writing.
+pub type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// The file operations used by the backup engine, so that it can run on the disk (`DiskFilesystem`)
+/// or in memory (`MemoryFilesystem`), where failures can be simulated.
+#[async_trait]
+pub trait Filesystem: Send + Sync {
+    /// Returns the paths of the entries of a folder.
+    async fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Returns the information about a file or a folder.
+    async fn metadata(&self, path: &Path) -> io::Result<FileInfo>;
+
+    /// Creates a folder and all its missing parents.
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Opens a file for reading.
+    async fn open_read(&self, path: &Path) -> io::Result<Reader>;
+
+    /// Creates (or truncates) a file and opens it for writing.
+    async fn create(&self, path: &Path) -> io::Result<Writer>;
+
+    /// Removes a file.
+    async fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Asks the system to forget the cached content of a file, so that it is read again from the drive
+    /// and not from the memory where it was just written.
+    async fn forget_cached(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Returns whether a path exists and is a folder.
+    async fn is_dir(&self, path: &Path) -> bool {
+        self.metadata(path).await.is_ok_and(|info| info.is_dir)
+    }
+
+    /// Copies a file with io_uring, if enabled and supported by this file system; `None` if the file
+    /// must be copied through `open_read` and `create`.
+    async fn copy_with_uring(&self, _src: &Path, _dest: &Path, _on_progress: OnProgress) -> Option<io::Result<()>> {
+        None
+    }
+}
+
+/// Whether the source is read leaving it observably untouched (`read_only_source` in the configuration).
+static READ_ONLY_SOURCE: AtomicBool = AtomicBool::new(false);
+
+/// Sets whether the files and the folders of the source are read without updating their access time.
+pub fn set_read_only_source(enabled: bool) {
+    READ_ONLY_SOURCE.store(enabled, Ordering::SeqCst);
+}
+
+/// Returns whether the source is read without updating the access time of its files and folders.
+pub fn is_read_only_source() -> bool {
+    READ_ONLY_SOURCE.load(Ordering::SeqCst)
+}
+
+/// Opens a source file for reading, never locking it against the other programs.
+/// On Windows the file is opened with the backup semantics, so that it can be read regardless of its permissions
+/// once the backup privilege is enabled. With `read_only_source` its access time is not updated: on Linux
+/// with `O_NOATIME` (allowed only to the owner of the file and to root), on Windows by suspending the update
+/// of the times for the handle; where this is not permitted the file is opened normally.
+pub async fn open_source(path: &Path) -> io::Result<File> {
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::io::AsRawHandle;
+        use winapi::shared::minwindef::FILETIME;
+        use winapi::um::fileapi::SetFileTime;
+        use winapi::um::winnt::{FILE_SHARE_DELETE, FILE_SHARE_READ, FILE_SHARE_WRITE, FILE_WRITE_ATTRIBUTES, GENERIC_READ};
+
+        const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x02000000;
+        let open = |access: u32| async move {
+            fs::OpenOptions::new()
+                .read(true)
+                .access_mode(access)
+                .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE)
+                .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+                .open(path)
+                .await
+        };
+        if is_read_only_source() {
+            // Setting the access time to 0xFFFFFFFF suspends its update for the operations made with the handle
+            if let Ok(file) = open(GENERIC_READ | FILE_WRITE_ATTRIBUTES).await {
+                let keep = FILETIME { dwLowDateTime: 0xFFFF_FFFF, dwHighDateTime: 0xFFFF_FFFF };
+                unsafe {
+                    SetFileTime(file.as_raw_handle() as _, std::ptr::null(), &keep, std::ptr::null());
+                }
+                return Ok(file);
+            }
+        }
+        open(GENERIC_READ).await
+    }
+    #[cfg(target_os = "linux")]
+    {
+        if is_read_only_source() {
+            if let Ok(file) = fs::OpenOptions::new().read(true).custom_flags(libc::O_NOATIME).open(path).await {
+                return Ok(file);
+            }
+        }
+        File::open(path).await
+    }
+    #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+    {
+        File::open(path).await
+    }
+}
+
+/// Lists a folder opened with `O_NOATIME`, so that its access time is not updated.
+#[cfg(target_os = "linux")]
+fn read_dir_noatime(path: &Path) -> io::Result<Vec<PathBuf>> {
+    use std::ffi::{CStr, CString};
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    unsafe {
+        let mut fd = libc::open(c_path.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC | libc::O_NOATIME);
+        if fd < 0 {
+            fd = libc::open(c_path.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC);
+        }
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let dir = libc::fdopendir(fd);
+        if dir.is_null() {
+            let error = io::Error::last_os_error();
+            libc::close(fd);
+            return Err(error);
+        }
+        let mut paths = Vec::new();
+        loop {
+            let entry = libc::readdir(dir);
+            if entry.is_null() {
+                break;
+            }
+            let name = CStr::from_ptr((*entry).d_name.as_ptr()).to_bytes();
+            if name != b"." && name != b".." {
+                paths.push(path.join(std::ffi::OsString::from_vec(name.to_vec())));
+            }
+        }
+        // Also closes the descriptor
+        libc::closedir(dir);
+        Ok(paths)
+    }
+}
+
+/// Asks the system to forget the cached content of a file, so that it is read again from the drive
+/// and not from the memory where it was just written.
+#[cfg(target_os = "linux")]
+pub(crate) fn drop_cached_pages(file: &File) {
+    use std::os::unix::io::AsRawFd;
+
+    unsafe {
+        libc::fsync(file.as_raw_fd());
+        libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+    }
+}
+
+/// The cache cannot be dropped for a single file on the other systems.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn drop_cached_pages(_file: &File) {}
+
+/// The real file system.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DiskFilesystem;
+
+#[async_trait]
+impl Filesystem for DiskFilesystem {
+    async fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        #[cfg(target_os = "linux")]
+        if is_read_only_source() {
+            let path = path.to_path_buf();
+            return tokio::task::spawn_blocking(move || read_dir_noatime(&path))
+                .await
+                .unwrap_or_else(|e| Err(io::Error::other(e)));
+        }
+        let mut paths = Vec::new();
+        let mut entries = fs::read_dir(path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            paths.push(entry.path());
+        }
+        Ok(paths)
+    }
+
+    async fn metadata(&self, path: &Path) -> io::Result<FileInfo> {
+        let metadata = fs::metadata(path).await?;
+        #[cfg(target_os = "windows")]
+        let placeholder = {
+            use std::os::windows::fs::MetadataExt;
+            const FILE_ATTRIBUTE_RECALL_ON_OPEN: u32 = 0x00040000;
+            const FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS: u32 = 0x00400000;
+            metadata.file_attributes() & (FILE_ATTRIBUTE_RECALL_ON_OPEN | FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS) != 0
+        };
+        #[cfg(not(target_os = "windows"))]
+        let placeholder = false;
+        Ok(FileInfo { is_dir: metadata.is_dir(), len: metadata.len(), modified: metadata.modified().ok(), placeholder })
+    }
+
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path).await
+    }
+
+    /// The files are opened as the sources of the backup (see `open_source`).
+    async fn open_read(&self, path: &Path) -> io::Result<Reader> {
+        Ok(Box::new(open_source(path).await?))
+    }
+
+    async fn create(&self, path: &Path) -> io::Result<Writer> {
+        Ok(Box::new(File::create(path).await?))
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path).await
+    }
+
+    async fn forget_cached(&self, path: &Path) -> io::Result<()> {
+        drop_cached_pages(&File::open(path).await?);
+        Ok(())
+    }
+
+    #[cfg(all(target_os = "linux", feature = "io_uring"))]
+    async fn copy_with_uring(&self, src: &Path, dest: &Path, on_progress: OnProgress) -> Option<io::Result<()>> {
+        // The reads of io_uring would update the access time of the source
+        if uring::is_enabled() && !is_read_only_source() {
+            Some(uring::copy_file(src, dest, on_progress).await)
+        } else {
+            None
+        }
+    }
+}
+
+/// Content of the in-memory file system.
+#[derive(Debug, Default)]
+struct MemoryState {
+    files: HashMap<PathBuf, (Vec<u8>, SystemTime)>,
+    dirs: HashSet<PathBuf>,
+    /// Paths whose operations fail, with the kind of the error.
+    failures: HashMap<PathBuf, io::ErrorKind>,
+}
+
+impl MemoryState {
+    fn check(&self, path: &Path) -> io::Result<()> {
+        match self.failures.get(path) {
+            Some(kind) => Err(io::Error::new(*kind, format!("simulated failure on {:?}", path))),
+            None => Ok(()),
+        }
+    }
+
+    fn add_parents(&mut self, path: &Path) {
+        for ancestor in path.ancestors().skip(1) {
+            self.dirs.insert(ancestor.to_path_buf());
+        }
+    }
+}
+
+/// A file system kept in memory, to run the backup engine without touching the disk.
+/// Failures (e.g. a permission denied) can be injected on any path with `fail_on`.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryFilesystem {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+impl MemoryFilesystem {
+    /// Returns an empty file system.
+    pub fn new() -> MemoryFilesystem {
+        MemoryFilesystem::default()
+    }
+
+    /// Adds a file (and its missing parent folders) with the given content.
+    pub fn add_file(&self, path: impl AsRef<Path>, content: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        state.add_parents(path.as_ref());
+        state.files.insert(path.as_ref().to_path_buf(), (content.to_vec(), SystemTime::now()));
+    }
+
+    /// Adds a folder and its missing parents.
+    pub fn add_dir(&self, path: impl AsRef<Path>) {
+        let mut state = self.state.lock().unwrap();
+        state.add_parents(path.as_ref());
+        state.dirs.insert(path.as_ref().to_path_buf());
+    }
+
+    /// Makes every operation on a path fail with the given kind of error.
+    pub fn fail_on(&self, path: impl AsRef<Path>, kind: io::ErrorKind) {
+        self.state.lock().unwrap().failures.insert(path.as_ref().to_path_buf(), kind);
+    }
+
+    /// Returns the content of a file, if it exists.
+    pub fn file(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().files.get(path.as_ref()).map(|(content, _)| content.clone())
+    }
+}
+
+#[async_trait]
+impl Filesystem for MemoryFilesystem {
+    async fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let state = self.state.lock().unwrap();
+        state.check(path)?;
+        if !state.dirs.contains(path) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("{:?} is not a folder", path)));
+        }
+        let mut paths: Vec<PathBuf> = state
+            .files
+            .keys()
+            .chain(state.dirs.iter())
+            .filter(|child| child.parent() == Some(path))
+            .cloned()
+            .collect();
+        // Sorted, so that the runs are deterministic
+        paths.sort();
+        Ok(paths)
+    }
+
+    async fn metadata(&self, path: &Path) -> io::Result<FileInfo> {
+        let state = self.state.lock().unwrap();
+        state.check(path)?;
+        if let Some((content, modified)) = state.files.get(path) {
+            Ok(FileInfo { is_dir: false, len: content.len() as u64, modified: Some(*modified), placeholder: false })
+        } else if state.dirs.contains(path) {
+            Ok(FileInfo { is_dir: true, len: 0, modified: None, placeholder: false })
+        } else {
+            Err(io::Error::new(io::ErrorKind::NotFound, format!("{:?} does not exist", path)))
+        }
+    }
+
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check(path)?;
+        state.add_parents(path);
+        state.dirs.insert(path.to_path_buf());
+        Ok(())
+    }
+
+    async fn open_read(&self, path: &Path) -> io::Result<Reader> {
+        let state = self.state.lock().unwrap();
+        state.check(path)?;
+        match state.files.get(path) {
+            Some((content, _)) => Ok(Box::new(io::Cursor::new(content.clone()))),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, format!("{:?} does not exist", path))),
+        }
+    }
+
+    async fn create(&self, path: &Path) -> io::Result<Writer> {
+        let mut state = self.state.lock().unwrap();
+        state.check(path)?;
+        if !path.parent().is_none_or(|parent| state.dirs.contains(parent)) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("the folder of {:?} does not exist", path)));
+        }
+        state.files.insert(path.to_path_buf(), (Vec::new(), SystemTime::now()));
+        Ok(Box::new(MemoryWriter { state: self.state.clone(), path: path.to_path_buf() }))
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check(path)?;
+        state
+            .files
+            .remove(path)
+            .map(|_| ())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{:?} does not exist", path)))
+    }
+}
+
+/// A file of the in-memory file system opened for writing: the bytes are appended to its content as they are written.
+struct MemoryWriter {
+    state: Arc<Mutex<MemoryState>>,
+    path: PathBuf,
+}
+
+impl AsyncWrite for MemoryWriter {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let mut state = self.state.lock().unwrap();
+        if let Err(e) = state.check(&self.path) {
+            return Poll::Ready(Err(e));
+        }
+        match state.files.get_mut(&self.path) {
+            Some((content, modified)) => {
+                content.extend_from_slice(buf);
+                *modified = SystemTime::now();
+                Poll::Ready(Ok(buf.len()))
+            }
+            None => Poll::Ready(Err(io::Error::new(io::ErrorKind::NotFound, "the file has been removed"))),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Failures that a `FaultyFilesystem` simulates.
+#[derive(Debug, Default)]
+struct Faults {
+    /// Number of writes done so far, on all the files.
+    writes: usize,
+    /// Number of bytes written so far, on all the files.
+    written: u64,
+    /// The write with this number (starting from 1) fails.
+    failing_write: Option<usize>,
+    /// Bytes that can be written before the disk is full.
+    capacity: Option<u64>,
+    /// The device mounted in this folder disappears after this number of writes.
+    removal: Option<(PathBuf, usize)>,
+}
+
+impl Faults {
+    /// Returns the error of an operation on a path, if the device of the path has been removed.
+    fn check_device(&self, path: &Path) -> io::Result<()> {
+        match &self.removal {
+            Some((root, after)) if self.writes >= *after && path.starts_with(root) => {
+                Err(io::Error::new(io::ErrorKind::NotFound, format!("the device of {:?} has been removed", path)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks a write of `len` bytes on a path before it is done, returning the simulated error if it must fail,
+    /// or how many of the bytes fit on the disk.
+    fn check_write(&mut self, path: &Path, len: usize) -> io::Result<usize> {
+        self.check_device(path)?;
+        if self.failing_write == Some(self.writes + 1) {
+            // The failed write is counted, so that the next one goes through
+            self.writes += 1;
+            return Err(io::Error::other(format!("simulated failure of write {}", self.writes)));
+        }
+        match self.capacity {
+            Some(capacity) if len > 0 && self.written >= capacity => Err(disk_full()),
+            Some(capacity) => Ok(len.min((capacity - self.written) as usize)),
+            None => Ok(len),
+        }
+    }
+
+    /// Counts a write that has been done, with the bytes actually written.
+    fn count_write(&mut self, written: usize) {
+        self.writes += 1;
+        self.written += written as u64;
+    }
+}
+
+/// The error returned by the system when there is no space left on the device.
+fn disk_full() -> io::Error {
+    #[cfg(target_os = "windows")]
+    const DISK_FULL: i32 = 112; // ERROR_DISK_FULL
+    #[cfg(not(target_os = "windows"))]
+    const DISK_FULL: i32 = libc::ENOSPC;
+    io::Error::from_raw_os_error(DISK_FULL)
+}
+
+/// A file system that forwards the operations to another one, injecting failures at deterministic points:
+/// the Nth write, a full disk after a number of bytes, or the removal of a device after a number of writes.
+#[derive(Clone)]
+pub struct FaultyFilesystem {
+    inner: Arc<dyn Filesystem>,
+    faults: Arc<Mutex<Faults>>,
+}
+
+impl FaultyFilesystem {
+    /// Wraps a file system, without failures until they are configured.
+    pub fn new(inner: Arc<dyn Filesystem>) -> FaultyFilesystem {
+        FaultyFilesystem { inner, faults: Arc::new(Mutex::new(Faults::default())) }
+    }
+
+    /// Makes the Nth write (starting from 1, counting the writes on all the files) fail.
+    pub fn fail_nth_write(self, n: usize) -> FaultyFilesystem {
+        self.faults.lock().unwrap().failing_write = Some(n);
+        self
+    }
+
+    /// Makes the writes fail with "no space left on device" once `bytes` bytes have been written
+    /// (the write reaching the limit writes only the bytes that fit, as a real disk would).
+    pub fn disk_full_after(self, bytes: u64) -> FaultyFilesystem {
+        self.faults.lock().unwrap().capacity = Some(bytes);
+        self
+    }
+
+    /// Makes every operation under `root` fail, as if the device had been unplugged, after `writes` writes.
+    pub fn remove_device_after(self, root: impl AsRef<Path>, writes: usize) -> FaultyFilesystem {
+        self.faults.lock().unwrap().removal = Some((root.as_ref().to_path_buf(), writes));
+        self
+    }
+
+    /// Returns the number of writes done so far.
+    pub fn writes(&self) -> usize {
+        self.faults.lock().unwrap().writes
+    }
+
+    fn check_device(&self, path: &Path) -> io::Result<()> {
+        self.faults.lock().unwrap().check_device(path)
+    }
+}
+
+#[async_trait]
+impl Filesystem for FaultyFilesystem {
+    async fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        self.check_device(path)?;
+        self.inner.read_dir(path).await
+    }
+
+    async fn metadata(&self, path: &Path) -> io::Result<FileInfo> {
+        self.check_device(path)?;
+        self.inner.metadata(path).await
+    }
+
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.check_device(path)?;
+        self.inner.create_dir_all(path).await
+    }
+
+    async fn open_read(&self, path: &Path) -> io::Result<Reader> {
+        self.check_device(path)?;
+        self.inner.open_read(path).await
+    }
+
+    async fn create(&self, path: &Path) -> io::Result<Writer> {
+        self.check_device(path)?;
+        let inner = self.inner.create(path).await?;
+        Ok(Box::new(FaultyWriter { inner, faults: self.faults.clone(), path: path.to_path_buf() }))
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.check_device(path)?;
+        self.inner.remove_file(path).await
+    }
+
+    async fn forget_cached(&self, path: &Path) -> io::Result<()> {
+        self.check_device(path)?;
+        self.inner.forget_cached(path).await
+    }
+}
+
+/// A file of a `FaultyFilesystem` opened for writing.
+struct FaultyWriter {
+    inner: Writer,
+    faults: Arc<Mutex<Faults>>,
+    path: PathBuf,
+}
+
+impl AsyncWrite for FaultyWriter {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let fits = match self.faults.lock().unwrap().check_write(&self.path, buf.len()) {
+            Ok(fits) => fits,
+            Err(e) => return Poll::Ready(Err(e)),
+        };
+        // Only what the inner file accepted is counted: a pending or failed write is tried again
+        let result = Pin::new(&mut self.inner).poll_write(cx, &buf[..fits]);
+        if let Poll::Ready(Ok(written)) = result {
+            self.faults.lock().unwrap().count_write(written);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Err(e) = self.faults.lock().unwrap().check_device(&self.path) {
+            return Poll::Ready(Err(e));
+        }
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// A file system that forwards the operations to another one, recording when data was last read from it,
+/// so that a copy that stopped making progress (e.g. a stuck USB controller or a dead network share) can be detected.
+#[derive(Clone)]
+pub struct ProgressFilesystem {
+    inner: Arc<dyn Filesystem>,
+    last_progress: Arc<Mutex<Instant>>,
+}
+
+impl ProgressFilesystem {
+    /// Wraps a file system; the idle time is counted from now.
+    pub fn new(inner: Arc<dyn Filesystem>) -> ProgressFilesystem {
+        ProgressFilesystem { inner, last_progress: Arc::new(Mutex::new(Instant::now())) }
+    }
+
+    /// Returns how long it has been since data was last read (or since the file system was wrapped).
+    pub fn idle_time(&self) -> Duration {
+        self.last_progress.lock().unwrap().elapsed()
+    }
+}
+
+#[async_trait]
+impl Filesystem for ProgressFilesystem {
+    async fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        self.inner.read_dir(path).await
+    }
+
+    async fn metadata(&self, path: &Path) -> io::Result<FileInfo> {
+        self.inner.metadata(path).await
+    }
+
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.inner.create_dir_all(path).await
+    }
+
+    async fn open_read(&self, path: &Path) -> io::Result<Reader> {
+        let inner = self.inner.open_read(path).await?;
+        Ok(Box::new(ProgressReader { inner, last_progress: self.last_progress.clone() }))
+    }
+
+    async fn create(&self, path: &Path) -> io::Result<Writer> {
+        self.inner.create(path).await
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_file(path).await
+    }
+
+    async fn forget_cached(&self, path: &Path) -> io::Result<()> {
+        self.inner.forget_cached(path).await
+    }
+
+    async fn copy_with_uring(&self, src: &Path, dest: &Path, on_progress: OnProgress) -> Option<io::Result<()>> {
+        let last_progress = self.last_progress.clone();
+        let on_progress: OnProgress = Arc::new(move || {
+            *last_progress.lock().unwrap() = Instant::now();
+            on_progress();
+        });
+        self.inner.copy_with_uring(src, dest, on_progress).await
+    }
+}
+
+/// A file of a `ProgressFilesystem` opened for reading. The copies read only as fast as they write,
+/// so a destination that stopped accepting data stops the reads as well.
+struct ProgressReader {
+    inner: Reader,
+    last_progress: Arc<Mutex<Instant>>,
+}
+
+impl AsyncRead for ProgressReader {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if buf.filled().len() > before {
+            *self.last_progress.lock().unwrap() = Instant::now();
+        }
+        result
+    }
+}
@@ -0,0 +1,123 @@
+use crate::filesystem;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tokio::io::AsyncReadExt;
+
+/// Name of the cache, written in the root of the destination.
+pub const HASH_CACHE_FILE_NAME: &str = ".emergency_backup_hashes.json";
+
+/// Bytes read at a time while hashing a file.
+const HASH_CHUNK: usize = 1024 * 1024;
+
+/// Hash of a file, valid as long as its size and modification time do not change.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    size: u64,
+    modified_secs: u64,
+    modified_nanos: u32,
+    /// BLAKE3 hash of the content, in hexadecimal.
+    hash: String,
+}
+
+/// Hashes of the files already read by the previous verifications, so that the unchanged files
+/// (e.g. multi-gigabyte files that are not modified between two backups) are not hashed again.
+#[derive(Debug)]
+pub struct HashCache {
+    /// Where the cache is saved.
+    path: PathBuf,
+    /// Cached hashes by path of the file.
+    entries: HashMap<String, Entry>,
+    /// Whether some hashes have been added or replaced since the cache was loaded.
+    changed: bool,
+}
+
+impl HashCache {
+    /// Reads the cache saved in a folder. A missing or unreadable cache is replaced by an empty one,
+    /// since it only makes the verification faster.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The folder of the cache, i.e. the root of the destination.
+    pub fn load(dir: &Path) -> HashCache {
+        let path = dir.join(HASH_CACHE_FILE_NAME);
+        let entries = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        HashCache { path, entries, changed: false }
+    }
+
+    /// Returns the hash of a file: the cached one if its size and modification time are unchanged,
+    /// otherwise the file is read and its new hash is cached.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file to hash.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` containing the BLAKE3 hash of the file in hexadecimal, or an error if the file cannot be read.
+    pub async fn hash(&mut self, path: &Path) -> io::Result<String> {
+        let metadata = tokio::fs::metadata(path).await?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let key = path.to_string_lossy().into_owned();
+
+        if let Some(entry) = self.entries.get(&key) {
+            if entry.size == metadata.len()
+                && entry.modified_secs == modified.as_secs()
+                && entry.modified_nanos == modified.subsec_nanos()
+            {
+                return Ok(entry.hash.clone());
+            }
+        }
+
+        let hash = hash_file(path).await?;
+        self.entries.insert(
+            key,
+            Entry {
+                size: metadata.len(),
+                modified_secs: modified.as_secs(),
+                modified_nanos: modified.subsec_nanos(),
+                hash: hash.clone(),
+            },
+        );
+        self.changed = true;
+        Ok(hash)
+    }
+
+    /// Saves the cache, if it changed, leaving out the files that do not exist anymore.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` indicating whether the cache has been saved.
+    pub fn save(&mut self) -> io::Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        self.entries.retain(|path, _| Path::new(path).exists());
+        let contents = serde_json::to_string(&self.entries).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(&self.path, contents)?;
+        self.changed = false;
+        Ok(())
+    }
+}
+
+/// Reads a whole file and returns its BLAKE3 hash in hexadecimal.
+/// The file is opened as a source of the backup, since it may be one (see `filesystem::open_source`).
+async fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = filesystem::open_source(path).await?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0u8; HASH_CHUNK];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
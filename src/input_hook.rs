@@ -0,0 +1,96 @@
+use rdev::{Event, ListenError};
+use std::sync::Mutex;
+#[cfg(target_os = "windows")]
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long the exit waits for the listeners to remove their hooks.
+#[cfg(target_os = "windows")]
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The threads running a global input listener, by their id, to stop them at exit.
+static LISTENERS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// When the listeners have received the last input event, and when the first one started listening.
+static LAST_INPUT: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Listens to the global input events (keyboard and mouse) like `rdev::listen`, blocking the thread,
+/// so that the hooks can be removed by `shutdown` when the application exits.
+///
+/// # Arguments
+///
+/// * `callback` - Called with every input event, on this thread; it must return quickly, since the input
+///   of the whole system waits for it.
+///
+/// # Errors
+///
+/// Returns an error if the hooks cannot be installed.
+pub fn listen<T>(mut callback: T) -> Result<(), ListenError>
+where
+    T: FnMut(Event) + 'static,
+{
+    let id = current_thread_id();
+    LISTENERS.lock().unwrap().push(id);
+    LAST_INPUT.lock().unwrap().get_or_insert_with(Instant::now);
+    let result = rdev::listen(move |event| {
+        *LAST_INPUT.lock().unwrap() = Some(Instant::now());
+        callback(event)
+    });
+    LISTENERS.lock().unwrap().retain(|listener| *listener != id);
+    result
+}
+
+/// Stops the input listeners, removing their hooks, and waits (briefly) until they are gone.
+/// On Windows a low-level hook left behind by a thread that does not pump its messages anymore slows down
+/// the input of the whole system; on Linux and macOS the listeners cannot be stopped, and their hooks
+/// are removed with the process.
+pub fn shutdown() {
+    #[cfg(target_os = "windows")]
+    {
+        use winapi::um::winuser::{PostThreadMessageA, WM_QUIT};
+
+        // The listener waits in the message loop of its thread: ending the loop makes `rdev::listen` return,
+        // and the hooks of a thread are removed by the system when the thread ends
+        for listener in LISTENERS.lock().unwrap().iter() {
+            unsafe {
+                PostThreadMessageA(*listener, WM_QUIT, 0, 0);
+            }
+        }
+        let start = Instant::now();
+        while !LISTENERS.lock().unwrap().is_empty() && start.elapsed() < SHUTDOWN_TIMEOUT {
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+/// Returns how long the user has not used the keyboard and the mouse: on Windows as reported by the system,
+/// elsewhere as seen by the input listeners of the application.
+///
+/// # Returns
+///
+/// The time since the last input, or `None` if it cannot be known (no listener is running).
+pub fn idle_time() -> Option<Duration> {
+    #[cfg(target_os = "windows")]
+    {
+        use winapi::um::sysinfoapi::GetTickCount;
+        use winapi::um::winuser::{GetLastInputInfo, LASTINPUTINFO};
+
+        let mut info = LASTINPUTINFO { cbSize: std::mem::size_of::<LASTINPUTINFO>() as u32, dwTime: 0 };
+        if unsafe { GetLastInputInfo(&mut info) } != 0 {
+            // The ticks wrap around every 49 days, as the time of the last input
+            return Some(Duration::from_millis(unsafe { GetTickCount() }.wrapping_sub(info.dwTime) as u64));
+        }
+    }
+    LAST_INPUT.lock().unwrap().map(|last| last.elapsed())
+}
+
+#[cfg(target_os = "windows")]
+fn current_thread_id() -> u32 {
+    unsafe { winapi::um::processthreadsapi::GetCurrentThreadId() }
+}
+
+/// Only the listeners of Windows are stopped, so their id is not needed.
+#[cfg(not(target_os = "windows"))]
+fn current_thread_id() -> u32 {
+    0
+}
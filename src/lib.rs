@@ -1,5 +1,40 @@
+pub mod accessibility;
+pub mod app_hooks;
+pub mod app_state;
+pub mod archive;
 pub mod backup;
 pub mod config;
+pub mod data_dirs;
+pub mod destination_history;
+pub mod elevation;
+pub mod encryption;
+pub mod events;
+pub mod file_log;
+pub mod filesystem;
+pub mod hash_cache;
+pub mod input_hook;
+pub mod locale;
+pub mod manifest;
+pub mod memory;
+pub mod metadata_crypto;
+pub mod network;
 pub mod notification_popup;
+pub mod power;
+pub mod quick_status;
+pub mod ransomware;
+pub mod read_ahead;
+pub mod remote_control;
+pub mod restore;
+pub mod resume;
 pub mod buttons_and_clicks_pattern_recognizer;
-pub mod beeper;
\ No newline at end of file
+pub mod beeper;
+pub mod secrets;
+pub mod session;
+pub mod snapshot;
+pub mod speech;
+pub mod supervisor;
+pub mod trigger;
+pub mod tuning;
+pub mod two_factor;
+pub mod uring;
+pub mod volume;
\ No newline at end of file
@@ -0,0 +1,165 @@
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// How numbers and dates are written in a locale.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LocaleFormat {
+    decimal: char,
+    group: char,
+    /// `chrono` format of the date and time, e.g. `%d/%m/%Y %H:%M`.
+    date_time: &'static str,
+}
+
+/// The format used when the locale is unknown: the one of the logs (e.g. `1,234.56` and `2026-10-16 10:00:00`).
+const DEFAULT_FORMAT: LocaleFormat = LocaleFormat { decimal: '.', group: ',', date_time: "%Y-%m-%d %H:%M:%S" };
+
+/// The format of the reports and of the notifications, detected from the system on first use unless `locale` is configured.
+static FORMAT: RwLock<Option<LocaleFormat>> = RwLock::new(None);
+
+/// Sets the locale of the reports and of the notifications.
+///
+/// # Arguments
+///
+/// * `tag` - The configured locale (e.g. `it-IT`, `en_US.UTF-8`), or `None` to use the one of the system.
+pub fn set_locale(tag: Option<&str>) {
+    let format = match tag {
+        Some(tag) => format_for(tag),
+        None => detect(),
+    };
+    *FORMAT.write().unwrap() = Some(format);
+}
+
+/// Returns the format of the current locale, detecting it the first time.
+fn current() -> LocaleFormat {
+    if let Some(format) = *FORMAT.read().unwrap() {
+        return format;
+    }
+    let format = detect();
+    *FORMAT.write().unwrap() = Some(format);
+    format
+}
+
+/// Returns the format of the locale of the system.
+fn detect() -> LocaleFormat {
+    system_locale().map_or(DEFAULT_FORMAT, |tag| format_for(&tag))
+}
+
+#[cfg(target_os = "windows")]
+fn system_locale() -> Option<String> {
+    use winapi::um::winnls::GetUserDefaultLocaleName;
+    use winapi::um::winnt::LOCALE_NAME_MAX_LENGTH;
+
+    let mut name = [0u16; LOCALE_NAME_MAX_LENGTH];
+    let len = unsafe { GetUserDefaultLocaleName(name.as_mut_ptr(), name.len() as i32) };
+    // The length includes the terminating null character
+    (len > 1).then(|| String::from_utf16_lossy(&name[..len as usize - 1]))
+}
+
+#[cfg(not(target_os = "windows"))]
+fn system_locale() -> Option<String> {
+    ["LC_ALL", "LC_NUMERIC", "LANG"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty() && value != "C" && value != "POSIX")
+}
+
+/// Returns the format of a locale, e.g. `it-IT` or `de_CH.UTF-8`; the unknown ones use `DEFAULT_FORMAT`.
+fn format_for(tag: &str) -> LocaleFormat {
+    // `en_US.UTF-8@euro` -> `en-us`
+    let tag = tag.split(['.', '@']).next().unwrap_or("").replace('_', "-").to_lowercase();
+    let (language, region) = tag.split_once('-').unwrap_or((tag.as_str(), ""));
+    let format = |decimal, group, date_time| LocaleFormat { decimal, group, date_time };
+    match (language, region) {
+        ("en", "us") | ("en", "") => format('.', ',', "%m/%d/%Y %I:%M %p"),
+        ("en", _) => format('.', ',', "%d/%m/%Y %H:%M"),
+        ("de", "ch") | ("it", "ch") | ("fr", "ch") => format('.', '\'', "%d.%m.%Y %H:%M"),
+        ("de", _) => format(',', '.', "%d.%m.%Y %H:%M"),
+        ("it", _) | ("es", _) | ("pt", _) | ("el", _) => format(',', '.', "%d/%m/%Y %H:%M"),
+        ("nl", _) => format(',', '.', "%d-%m-%Y %H:%M"),
+        ("da", _) | ("tr", _) | ("id", _) => format(',', '.', "%d.%m.%Y %H:%M"),
+        // A narrow no-break space, so that the numbers are not split across lines
+        ("fr", _) => format(',', '\u{202F}', "%d/%m/%Y %H:%M"),
+        ("ru", _) | ("uk", _) | ("pl", _) | ("cs", _) | ("sk", _) | ("fi", _) | ("nb", _) | ("no", _) => {
+            format(',', '\u{A0}', "%d.%m.%Y %H:%M")
+        }
+        ("sv", _) => format(',', '\u{A0}', "%Y-%m-%d %H:%M"),
+        ("ja", _) | ("zh", _) | ("ko", _) => format('.', ',', "%Y/%m/%d %H:%M"),
+        _ => DEFAULT_FORMAT,
+    }
+}
+
+/// Formats a count with the separator of the thousands of the locale, e.g. `12,400` or `12.400`.
+pub fn format_count(n: u64) -> String {
+    group_digits(&n.to_string(), current().group)
+}
+
+/// Formats a number with the given decimals and the separators of the locale, e.g. `1,234.56` or `1.234,56`.
+pub fn format_decimal(value: f64, decimals: usize) -> String {
+    let format = current();
+    let text = format!("{:.*}", decimals, value.abs());
+    let (integer, fraction) = text.split_once('.').unwrap_or((&text, ""));
+    let mut formatted = String::new();
+    if value < 0.0 && text.chars().any(|c| c.is_ascii_digit() && c != '0') {
+        formatted.push('-');
+    }
+    formatted.push_str(&group_digits(integer, format.group));
+    if !fraction.is_empty() {
+        formatted.push(format.decimal);
+        formatted.push_str(fraction);
+    }
+    formatted
+}
+
+/// Formats a percentage with two decimals, e.g. `12.50%`.
+pub fn format_percent(value: f64) -> String {
+    format!("{}%", format_decimal(value, 2))
+}
+
+/// Formats a size in bytes in the most readable unit, e.g. `8.30 GB` or `8,30 GB`.
+pub fn format_size(bytes: u64) -> String {
+    const KIB: u64 = 1024;
+    const MIB: u64 = 1024 * KIB;
+    const GIB: u64 = 1024 * MIB;
+
+    if bytes >= GIB {
+        format!("{} GB", format_decimal(bytes as f64 / GIB as f64, 2))
+    } else if bytes >= MIB {
+        format!("{} MB", format_decimal(bytes as f64 / MIB as f64, 2))
+    } else if bytes >= KIB {
+        format!("{} KB", format_decimal(bytes as f64 / KIB as f64, 2))
+    } else {
+        format!("{} bytes", bytes)
+    }
+}
+
+/// Formats a duration in seconds with two decimals, e.g. `12.34 s`.
+pub fn format_duration(duration: Duration) -> String {
+    format!("{} s", format_decimal(duration.as_secs_f64(), 2))
+}
+
+/// Formats a date and time as usual in the locale, e.g. `10/16/2026 09:30 PM` or `16/10/2026 21:30`.
+pub fn format_date_time(date_time: &DateTime<Local>) -> String {
+    date_time.format(current().date_time).to_string()
+}
+
+/// Formats a timestamp written by the application (`%Y-%m-%d %H:%M:%S`, e.g. in `runs.index` or in
+/// `snapshot_info.yaml`) as usual in the locale; any other text is returned as it is.
+pub fn format_timestamp(timestamp: &str) -> String {
+    NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S")
+        .ok()
+        .and_then(|naive| Local.from_local_datetime(&naive).earliest())
+        .map_or_else(|| timestamp.to_string(), |date_time| format_date_time(&date_time))
+}
+
+/// Inserts the separator of the thousands in a string of digits.
+fn group_digits(digits: &str, group: char) -> String {
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(group);
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
@@ -1,6 +1,8 @@
-use std::fs::OpenOptions;
-use std::io::Write;
-use std::path::PathBuf;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 use std::thread;
 use std::time::Duration;
 
@@ -8,6 +10,84 @@ use sysinfo::{Pid, System};
 
 use chrono::Local;
 
+use crate::backup::{bytes_to_human_readable, BackupReport};
+use group_39::locale::{format_count, format_duration, format_percent};
+use group_39::memory;
+use crate::manifest::MANIFEST_FILE_NAME;
+
+/// Whether each log entry is followed by the hash of the chain (`log_hash_chain`).
+static HASH_CHAIN: AtomicBool = AtomicBool::new(false);
+
+/// Held while an entry is chained, since the hash depends on the previous entry in the file.
+static CHAIN_LOCK: Mutex<()> = Mutex::new(());
+
+/// Beginning of the line with the hash of the chain, written after each entry.
+const CHAIN_PREFIX: &str = "#chain ";
+
+/// Length of a chain line: the prefix, a BLAKE3 hash in hexadecimal and the new line.
+const CHAIN_LINE_LEN: usize = CHAIN_PREFIX.len() + 64 + 1;
+
+/// Sets whether the log entries are chained by their hashes, so that a log can be shown to be untampered
+/// with `--verify-log`.
+pub fn set_hash_chain(enabled: bool) {
+    HASH_CHAIN.store(enabled, Ordering::SeqCst);
+}
+
+/// Returns the hash of an entry of the chain: the hash of the previous entry followed by the text of this one.
+fn chain_hash(previous: &str, entry: &str) -> String {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(previous.as_bytes());
+    hasher.update(entry.as_bytes());
+    hasher.finalize().to_hex().to_string()
+}
+
+/// Returns the hash of the last entry of a chained log, or an empty string if the log is empty.
+fn last_chain_hash(file: &mut File) -> io::Result<String> {
+    let len = file.metadata()?.len();
+    if len < CHAIN_LINE_LEN as u64 {
+        return Ok(String::new());
+    }
+    let mut line = String::new();
+    file.seek(SeekFrom::Start(len - CHAIN_LINE_LEN as u64))?;
+    file.read_to_string(&mut line)?;
+    Ok(line.strip_prefix(CHAIN_PREFIX).map(|hash| hash.trim_end().to_string()).unwrap_or_default())
+}
+
+/// Verifies the chain of hashes of a log written with `log_hash_chain`.
+///
+/// # Arguments
+///
+/// * `path` - The log file.
+///
+/// # Returns
+///
+/// A `Result` containing the number of entries verified, or a message describing the first entry that has been
+/// changed, removed or added (or the unchained text at the end of the log).
+pub fn verify_hash_chain(path: &Path) -> Result<usize, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    let mut previous = String::new();
+    let mut entry = String::new();
+    let mut entries = 0;
+    for (number, line) in content.split_inclusive('\n').enumerate() {
+        match line.strip_prefix(CHAIN_PREFIX) {
+            Some(hash) => {
+                let expected = chain_hash(&previous, &entry);
+                if hash.trim_end() != expected {
+                    return Err(format!("The entry ending at line {} has been modified (or an entry before it)", number + 1));
+                }
+                previous = expected;
+                entry.clear();
+                entries += 1;
+            }
+            None => entry.push_str(line),
+        }
+    }
+    if !entry.is_empty() {
+        return Err(format!("The text after the entry {} is not protected by the chain", entries));
+    }
+    Ok(entries)
+}
+
 /// A logger for recording CPU usage and backup details to a file.
 #[derive(Clone)]
 pub struct Logger {
@@ -29,12 +109,7 @@ impl Logger {
         let now = Local::now();
         // Format the date and time
         let formatted_time = now.format("%Y-%m-%d_%H-%M-%S").to_string();
-        let name;
-        if is_cpu {
-            name = "cpu"
-        } else {
-            name = "backup"
-        }
+        let name = if is_cpu { "cpu" } else { "backup" };
         // Create the log file name with date and time
         let log_file_name = format!("{}/{}_log_{}.txt", log_file_path, name, formatted_time);
 
@@ -43,6 +118,43 @@ impl Logger {
         }
     }
 
+    /// Creates a new `Logger` in the first writable directory among the given candidates.
+    ///
+    /// # Arguments
+    ///
+    /// * `candidates` - The directories to try, in order of preference.
+    /// * `is_cpu` - A boolean indicating whether the logger is for CPU usage or backup details.
+    ///
+    /// # Returns
+    ///
+    /// A tuple with the new `Logger` instance and a boolean that is `true` if the preferred directory
+    /// (the first candidate) was not usable and a fallback has been used.
+    /// If no candidate is writable, the logger of the last one is returned anyway.
+    pub fn with_fallback(candidates: &[PathBuf], is_cpu: bool) -> (Logger, bool) {
+        let mut logger = Logger::new("", is_cpu);
+        for (i, dir) in candidates.iter().enumerate() {
+            logger = Logger::new(dir.to_str().unwrap_or(""), is_cpu);
+            if logger.is_writable() {
+                return (logger, i > 0);
+            }
+        }
+        (logger, true)
+    }
+
+    /// Returns the path of the log file.
+    pub fn log_file_path(&self) -> &str {
+        &self.log_file_path
+    }
+
+    /// Checks whether the log file can be created (or opened) for writing.
+    fn is_writable(&self) -> bool {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.log_file_path)
+            .is_ok()
+    }
+
     /// Logs the CPU usage to the log file.
     ///
     /// This function runs in a loop, logging the CPU usage every 2 minutes.
@@ -57,15 +169,20 @@ impl Logger {
 
         loop {
             system.refresh_all();
-            let cpu_usage = system.global_cpu_usage();
+            let cpu_usage = system.global_cpu_info().cpu_usage();
             let process = system.process(pid).expect("Process not found");
             let process_cpu_usage = process.cpu_usage();
             let num_cpus = system.cpus().len() as f32;
             println!("CORE: {}", num_cpus);
-            let log_entry = format!(
-                "Global CPU Usage: {:.2}%\t\tProcess CPU Usage: {:.2}%\n",
-                cpu_usage, process_cpu_usage/num_cpus
+            let mut log_entry = format!(
+                "Global CPU Usage: {}\t\tProcess CPU Usage: {}",
+                format_percent(cpu_usage as f64), format_percent((process_cpu_usage / num_cpus) as f64)
             );
+            // During a backup with `max_memory_mb`, how much of the cap its copies are using
+            if let Some((in_use, cap)) = memory::usage() {
+                log_entry.push_str(&format!("\t\tBackup Memory: {} of {}", bytes_to_human_readable(in_use), bytes_to_human_readable(cap)));
+            }
+            log_entry.push('\n');
             /*let log_entry = format!("CPU Usage: {:.2}%\n", cpu_usage);*/
             self.write_log(&log_entry);
             thread::sleep(Duration::from_secs(1)); // Sleep for 2 minutes
@@ -76,74 +193,381 @@ impl Logger {
     ///
     /// # Arguments
     ///
-    /// * `total_size` - The total size of the backup in bytes.
-    /// * `total_file` - The total number of files moved during the backup.
-    /// * `cpu_time` - The duration of CPU time taken for the backup.
-    pub fn log_backup_details(&self, total_size: u64, total_file: usize, cpu_time: Duration) {
-        let readable_size = Self::bytes_to_human_readable(total_size);
-        let log_entry = format!(
-            "Backup completed. \n\nTotal size: \t\t{} ({} bytes) \nNumber of files: \t{} \nCPU time: \t\t{:.2?}\n",
-            readable_size, total_size, total_file, cpu_time
+    /// * `report` - The report of the backup, with totals and copied/skipped/failed counts.
+    /// * `wall_time` - The elapsed (wall-clock) duration of the backup.
+    /// * `cpu_time` - The CPU time (user + system) consumed by the process during the backup.
+    pub fn log_backup_details(&self, report: &BackupReport, wall_time: Duration, cpu_time: Duration) {
+        let readable_size = bytes_to_human_readable(report.total_size);
+        // Average usage over all the cores, as done for the periodic CPU log
+        let num_cpus = thread::available_parallelism().map(|n| n.get()).unwrap_or(1) as f64;
+        let avg_cpu_usage = if wall_time.as_secs_f64() > 0.0 {
+            cpu_time.as_secs_f64() / wall_time.as_secs_f64() / num_cpus * 100.0
+        } else {
+            0.0
+        };
+        let mut log_entry = format!(
+            "Backup completed. \n\nTotal size: \t\t{} ({} bytes) \nNumber of files: \t{} \nCopied files: \t\t{} \nSkipped files: \t\t{} \nFailed files: \t\t{} \nWall-clock time: \t{} \nCPU time: \t\t{} \nAverage CPU usage: \t{}\n",
+            readable_size, format_count(report.total_size), format_count(report.total_files as u64), format_count(report.copied as u64),
+            format_count(report.skipped as u64), format_count(report.failed as u64), format_duration(wall_time), format_duration(cpu_time),
+            format_percent(avg_cpu_usage)
         );
+        if report.evacuated > 0 || report.evacuation_failed > 0 {
+            log_entry.push_str(&format!(
+                "\nSource files removed: \t{} \nSource files kept: \t{}\n",
+                report.evacuated, report.evacuation_failed
+            ));
+        }
+        if report.resumed > 0 {
+            log_entry.push_str(&format!(
+                "\nAlready copied by the interrupted backup: \t{}\n",
+                report.resumed
+            ));
+        }
+        if report.placeholders > 0 {
+            log_entry.push_str(&format!(
+                "\nCloud placeholders not downloaded: \t{}\n",
+                report.placeholders
+            ));
+        }
+        if report.sanitized > 0 {
+            log_entry.push_str(&format!(
+                "\nRenamed for the destination: \t{} (original names in {})\n",
+                report.sanitized, MANIFEST_FILE_NAME
+            ));
+        }
+        if report.conflicts.total() > 0 {
+            log_entry.push_str(&format!(
+                "\nAlready existing: \t{} \nOverwritten: \t\t{} \nRenamed: \t\t{} \nKept: \t\t\t{}\n",
+                report.conflicts.total(), report.conflicts.overwritten, report.conflicts.renamed, report.conflicts.skipped
+            ));
+        }
+        if let Some(replica) = &report.secondary {
+            log_entry.push_str(&format!(
+                "\nSecondary destination: \t{} \nCopied files: \t\t{} \nFailed files: \t\t{} \nReplica: \t\t{}\n",
+                replica.path.display(), replica.copied, replica.failed,
+                if replica.is_complete() { "complete" } else { "INCOMPLETE" }
+            ));
+        }
+        if let Some(verification) = &report.verification {
+            log_entry.push_str(&format!(
+                "\nFiles verified: \t{} \nMismatched files: \t{} \nUnreadable files: \t{}\n",
+                verification.checked, verification.mismatched.len(), verification.unreadable.len()
+            ));
+            for path in verification.mismatched.iter().chain(&verification.unreadable) {
+                log_entry.push_str(&format!("\t{}\n", path.display()));
+            }
+        }
+        if !report.stalled.is_empty() {
+            log_entry.push_str(&format!("\nStalled copies canceled: \t{}\n", report.stalled.len()));
+            for path in &report.stalled {
+                log_entry.push_str(&format!("\t{}\n", path.display()));
+            }
+        }
+        if report.unfinished > 0 {
+            log_entry.push_str(&format!("\nMaximum duration reached: \t{} files left to the next backup\n", report.unfinished));
+        }
+        if !report.fuzzy.is_empty() {
+            log_entry.push_str(&format!("\nModified during the copy: \t{} (marked in {})\n", report.fuzzy.len(), MANIFEST_FILE_NAME));
+            for path in &report.fuzzy {
+                log_entry.push_str(&format!("\t{}\n", path.display()));
+            }
+        }
+        if let Some(drift) = &report.drift {
+            log_entry.push_str(&format!("\n{}\n", drift.message()));
+        }
+        if let Some(suspicion) = &report.suspicion {
+            log_entry.push_str(&format!("\n{}\n", suspicion.message()));
+            for path in suspicion.renamed.iter().chain(&suspicion.encrypted) {
+                log_entry.push_str(&format!("\t{}\n", path.display()));
+            }
+        }
+        if report.app_flushed > 0 {
+            log_entry.push_str(&format!("\nFlushed by their application: \t{}\n", report.app_flushed));
+        }
+        if let Some(tuning) = &report.tuning {
+            log_entry.push_str(&format!(
+                "\nTuned copies: \t{} in parallel, {} buffers ({}/s)\n",
+                tuning.concurrency, bytes_to_human_readable(tuning.buffer_size as u64), bytes_to_human_readable(tuning.throughput)
+            ));
+        }
+        if !report.duplicates.is_empty() {
+            log_entry.push_str(&format!(
+                "\nDuplicate files: \t{} groups, {} could be saved\n",
+                report.duplicates.len(), bytes_to_human_readable(report.duplicate_savings())
+            ));
+            for group in &report.duplicates {
+                log_entry.push_str(&format!("\t{} x {}\n", group.paths.len(), bytes_to_human_readable(group.size)));
+                for path in &group.paths {
+                    log_entry.push_str(&format!("\t\t{}\n", path.display()));
+                }
+            }
+        }
+        if !report.phases.is_empty() {
+            // Where the time went, to tell a slow source from a slow destination or a slow verification
+            log_entry.push_str("\nPhases:\n");
+            for (phase, duration) in &report.phases {
+                let share = if wall_time.as_secs_f64() > 0.0 { duration.as_secs_f64() / wall_time.as_secs_f64() * 100.0 } else { 0.0 };
+                log_entry.push_str(&format!("\t{}: \t{} ({})\n", phase.name(), format_duration(*duration), format_percent(share)));
+            }
+        }
+        let top_errors = report.top_errors(3);
+        if !top_errors.is_empty() {
+            log_entry.push_str("\nTop error categories:\n");
+            for (category, count) in top_errors {
+                log_entry.push_str(&format!("\t{}: {}\n", category, count));
+            }
+        }
         self.write_log(&log_entry);
     }
 
-    /// Converts a size in bytes to a human-readable string format.
+    /// Writes a log entry to the log file.
     ///
-    /// This function takes a size in bytes and converts it to a more readable format,
-    /// such as KB, MB, or GB, depending on the size.
+    /// # Arguments
+    ///
+    /// * `log_entry` - The log entry to be written to the file.
+    ///
+    /// A failure to write is reported on the console instead of stopping the program,
+    /// since losing a log entry must not abort the backup.
+    /// With `log_hash_chain`, the entry is followed by the hash of the previous one and of its text.
+    pub fn write_log(&self, log_entry: &str) {
+        let result = if HASH_CHAIN.load(Ordering::SeqCst) {
+            let _chain = CHAIN_LOCK.lock().unwrap();
+            OpenOptions::new()
+                .create(true)
+                .read(true)
+                .append(true)
+                .open(&self.log_file_path)
+                .and_then(|mut file| {
+                    // The chain line must start a line of its own
+                    let entry = if log_entry.ends_with('\n') { log_entry.to_string() } else { format!("{}\n", log_entry) };
+                    let hash = chain_hash(&last_chain_hash(&mut file)?, &entry);
+                    file.write_all(format!("{}{}{}\n", entry, CHAIN_PREFIX, hash).as_bytes())
+                })
+        } else {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.log_file_path)
+                .and_then(|mut file| file.write_all(log_entry.as_bytes()))
+        };
+
+        if let Err(e) = result {
+            eprintln!("Unable to write to log file {}: {}", self.log_file_path, e);
+        }
+    }
+}
+
+/// Returns the CPU time (user + system) consumed by the current process so far.
+#[cfg(target_os = "windows")]
+pub fn process_cpu_time() -> Duration {
+    use winapi::shared::minwindef::FILETIME;
+    use winapi::um::processthreadsapi::{GetCurrentProcess, GetProcessTimes};
+
+    let mut creation = FILETIME { dwLowDateTime: 0, dwHighDateTime: 0 };
+    let mut exit = FILETIME { dwLowDateTime: 0, dwHighDateTime: 0 };
+    let mut kernel = FILETIME { dwLowDateTime: 0, dwHighDateTime: 0 };
+    let mut user = FILETIME { dwLowDateTime: 0, dwHighDateTime: 0 };
+
+    let ok = unsafe { GetProcessTimes(GetCurrentProcess(), &mut creation, &mut exit, &mut kernel, &mut user) };
+    if ok == 0 {
+        return Duration::ZERO;
+    }
+
+    // FILETIME values are expressed in 100-nanosecond intervals
+    let to_ticks = |ft: FILETIME| ((ft.dwHighDateTime as u64) << 32) | ft.dwLowDateTime as u64;
+    Duration::from_nanos((to_ticks(kernel) + to_ticks(user)) * 100)
+}
+
+/// Returns the CPU time (user + system) consumed by the current process so far.
+#[cfg(not(target_os = "windows"))]
+pub fn process_cpu_time() -> Duration {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return Duration::ZERO;
+    }
+
+    let to_duration = |tv: libc::timeval| Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000);
+    to_duration(usage.ru_utime) + to_duration(usage.ru_stime)
+}
+
+/// Status of a run, as recorded in the `runs.index` file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RunStatus {
+    Running,
+    Completed,
+    /// Stopped by the maximum duration of the backup, with some files left to the next one
+    Partial,
+    Failed,
+    Crashed,
+}
+
+impl RunStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RunStatus::Running => "RUNNING",
+            RunStatus::Completed => "COMPLETED",
+            RunStatus::Partial => "PARTIAL",
+            RunStatus::Failed => "FAILED",
+            RunStatus::Crashed => "CRASHED",
+        }
+    }
+}
+
+/// A single execution of the application.
+///
+/// Every run gets its own log directory (named after its start time) inside the log root,
+/// and a line in the `runs.index` file of the log root summarizing when it ran and how it ended.
+#[derive(Clone)]
+pub struct RunLog {
+    run_id: String,
+    run_dir: PathBuf,
+    index_path: PathBuf,
+}
+
+impl RunLog {
+    /// Creates the directory of a new run and registers it in `runs.index` as running.
     ///
     /// # Arguments
     ///
-    /// * `bytes` - The size in bytes to be converted.
+    /// * `log_root` - The folder containing all the run directories and the index.
     ///
     /// # Returns
     ///
-    /// A `String` representing the size in a human-readable format.
-    fn bytes_to_human_readable(bytes: u64) -> String {
-        const KIB: u64 = 1024;
-        const MIB: u64 = 1024 * KIB;
-        const GIB: u64 = 1024 * MIB;
-
-        if bytes >= GIB {
-            format!("{:.2} GB", bytes as f64 / GIB as f64)
-        } else if bytes >= MIB {
-            format!("{:.2} MB", bytes as f64 / MIB as f64)
-        } else if bytes >= KIB {
-            format!("{:.2} KB", bytes as f64 / KIB as f64)
-        } else {
-            format!("{} bytes", bytes) // Se meno di 1KB, mostra solo in byte
-        }
+    /// A new `RunLog` instance, or the error if the run directory or the index cannot be written.
+    pub fn start(log_root: &Path) -> io::Result<RunLog> {
+        // The milliseconds keep apart the runs started in the same second (e.g. a --backup-now next to the resident instance)
+        let run_id = Local::now().format("%Y-%m-%d_%H-%M-%S-%3f").to_string();
+        let run_dir = log_root.join(&run_id);
+        fs::create_dir_all(&run_dir)?;
+
+        let run_log = RunLog {
+            run_id,
+            run_dir,
+            index_path: log_root.join("runs.index"),
+        };
+        run_log.update_index(RunStatus::Running)?;
+        Ok(run_log)
     }
 
-    /// Writes a log entry to the log file.
+    /// Returns the directory where the logs of this run are written.
+    pub fn run_dir(&self) -> &Path {
+        &self.run_dir
+    }
+
+    /// Records the final status of the run in `runs.index`.
     ///
     /// # Arguments
     ///
-    /// * `log_entry` - The log entry to be written to the file.
-    pub fn write_log(&self, log_entry: &str) {
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.log_file_path)
-            .expect("Unable to open log file");
+    /// * `status` - The status the run ended with.
+    pub fn finish(&self, status: RunStatus) -> io::Result<()> {
+        self.update_index(status)
+    }
 
-        file.write_all(log_entry.as_bytes())
-            .expect("Unable to write to log file");
+    /// Writes (or replaces) the line of this run in `runs.index`.
+    /// Each line has the run id, the last update, the status and the run directory, separated by tabs.
+    fn update_index(&self, status: RunStatus) -> io::Result<()> {
+        let prefix = format!("{}\t", self.run_id);
+        let mut lines: Vec<String> = fs::read_to_string(&self.index_path)
+            .map(|content| content.lines().filter(|l| !l.starts_with(&prefix)).map(String::from).collect())
+            .unwrap_or_default();
+
+        lines.push(format!(
+            "{}\t{}\t{}\t{}",
+            self.run_id,
+            Local::now().format("%Y-%m-%d %H:%M:%S"),
+            status.as_str(),
+            self.run_dir.display()
+        ));
+
+        fs::write(&self.index_path, lines.join("\n") + "\n")
     }
 }
 
 #[cfg(not(debug_assertions))]
 pub fn retrieve_path_cpu_log() -> PathBuf {
-    use std::env;
-
-    let exe_path = env::current_exe().expect("Failed to get current executable path");
-    let exe_dir = exe_path.parent().expect("Failed to get executable directory");
-
-    exe_dir.join("log/")
+    group_39::data_dirs::log_dir()
 }
 
 #[cfg(debug_assertions)]
 pub fn retrieve_path_cpu_log() -> PathBuf {
     PathBuf::from("log/")
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a chained log of three entries in a new folder and returns its path.
+    fn chained_log(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("group_39_test_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        set_hash_chain(true);
+        let logger = Logger::new(dir.to_str().unwrap(), false);
+        logger.write_log("Backup avviato\n");
+        logger.write_log("File copiati: 10\n");
+        logger.write_log("Backup completato");
+        PathBuf::from(logger.log_file_path())
+    }
+
+    /// Returns the entries of a chained log, each with its chain line.
+    fn entries(path: &Path) -> Vec<String> {
+        let content = fs::read_to_string(path).unwrap();
+        let lines: Vec<&str> = content.split_inclusive('\n').collect();
+        lines.chunks(2).map(|entry| entry.concat()).collect()
+    }
+
+    fn remove_log(path: &Path) {
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn an_intact_chain_is_verified() {
+        let path = chained_log("chain_intact");
+
+        assert_eq!(verify_hash_chain(&path), Ok(3));
+        remove_log(&path);
+    }
+
+    #[test]
+    fn a_changed_entry_breaks_the_chain() {
+        let path = chained_log("chain_changed");
+        let content = fs::read_to_string(&path).unwrap();
+        fs::write(&path, content.replace("File copiati: 10", "File copiati: 12")).unwrap();
+
+        assert!(verify_hash_chain(&path).is_err());
+        remove_log(&path);
+    }
+
+    #[test]
+    fn a_removed_entry_breaks_the_chain() {
+        let path = chained_log("chain_removed");
+        let mut entries = entries(&path);
+        entries.remove(1);
+        fs::write(&path, entries.concat()).unwrap();
+
+        assert!(verify_hash_chain(&path).is_err());
+        remove_log(&path);
+    }
+
+    #[test]
+    fn reordered_entries_break_the_chain() {
+        let path = chained_log("chain_reordered");
+        let mut entries = entries(&path);
+        entries.swap(0, 1);
+        fs::write(&path, entries.concat()).unwrap();
+
+        assert!(verify_hash_chain(&path).is_err());
+        remove_log(&path);
+    }
+
+    #[test]
+    fn the_text_added_after_the_chain_is_reported() {
+        let path = chained_log("chain_appended");
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"Backup completato senza errori\n").unwrap();
+
+        assert!(verify_hash_chain(&path).is_err());
+        remove_log(&path);
+    }
+}
@@ -1,64 +1,1103 @@
 //#![windows_subsystem = "windows"]
+use std::env;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{mpsc, Arc, RwLock};
 use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 
-use crate::backup::wrapper_backup;
-use crate::config::Config;
-use crate::logger::Logger;
+use crate::backup::{wrapper_backup, BackupReport, VerifyMode};
+use crate::cli::CliArgs;
+use crate::config::{Config, SharedConfig};
+use crate::logger::{Logger, RunLog, RunStatus};
 use group_39::buttons_and_clicks_pattern_recognizer::start_button_and_clicks_pattern_recognizer;
-use group_39::notification_popup::{show_popup, NotificationType};
+use group_39::notification_popup::{self, show_popup, NotificationType};
 use pattern_recognizer::PatternRecognizer;
 use tokio::runtime;
-mod notification_popup;
+// The modules of the library are used (not compiled again) so that their state is shared
+use group_39::app_state::{self, StateEvent};
+use group_39::events::{self, AppEvent};
+use group_39::elevation::{self, Elevation};
+use group_39::filesystem::DiskFilesystem;
+use group_39::metadata_crypto;
+use group_39::restore::{self as restore_points, RestorePoint};
+use group_39::resume::ProgressJournal;
+use group_39::snapshot::{SnapshotSummary, SNAPSHOT_INFO_FILE_NAME};
+use group_39::{backup, beeper, config, destination_history, encryption, input_hook, locale, manifest, power, quick_status, remote_control, session, speech, supervisor, trigger, two_factor, volume};
 mod pattern_recognizer;
 mod logger;
-mod beeper;
-mod backup;
-mod config;
+mod cli;
+mod crash_handler;
+mod status;
+mod schedule;
+mod service;
+mod settings;
+mod tutorial;
 
 fn main() {
-    let cpu_log_path = logger::retrieve_path_cpu_log().clone();
-    let cpu_logger = Logger::new(cpu_log_path.to_str().unwrap(), true);
-    let mut total_files = 0;
-    let mut total_size = 0u64;
+    // The phases of the backup are traced on the console when asked with RUST_LOG (e.g. RUST_LOG=group_39=info)
+    let _ = tracing_subscriber::fmt().with_env_filter(tracing_subscriber::EnvFilter::from_default_env()).try_init();
 
-    // Start logging CPU usage in a separate thread <- no needs to wait
+    // Until the run directory is known, crash reports go in the log root
+    crash_handler::install_panic_hook(&logger::retrieve_path_cpu_log());
+
+    // Top-level error boundary: the panic hook has already written the crash report, the popup is shown here
+    if panic::catch_unwind(run).is_err() {
+        crash_handler::mark_run_crashed();
+        crash_handler::notify_crash();
+        thread::sleep(Duration::from_secs(10));
+    }
+    remove_input_hooks();
+}
+
+/// Removes the global input hooks before the process exits, so that they do not slow down the input of the system;
+/// their threads are not restarted by the watchdog anymore.
+fn remove_input_hooks() {
+    supervisor::shutdown();
+    input_hook::shutdown();
+}
+
+fn run() {
+    let cli = match CliArgs::parse(env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(msg) => {
+            println!("{}", msg);
+            process::exit(2);
+        }
+    };
+
+    // The files left next to the executable by an older version go to the folders of the user,
+    // before the configuration is looked for
+    if let Err(msg) = Config::migrate_legacy_files() {
+        show_popup(NotificationType::GenericError, Some(msg));
+    }
+
+    if let Some(schedule) = &cli.install_schedule {
+        match schedule::install_schedule(schedule, cli.config_path.as_deref()) {
+            Ok(()) => println!("Scheduled backup installed"),
+            Err(e) => {
+                println!("Unable to install the scheduled backup: {}", e);
+                process::exit(1);
+            }
+        }
+        process::exit(0);
+    }
+    if cli.remove_schedule {
+        match schedule::remove_schedule() {
+            Ok(()) => println!("Scheduled backup removed"),
+            Err(e) => {
+                println!("Unable to remove the scheduled backup: {}", e);
+                process::exit(1);
+            }
+        }
+        process::exit(0);
+    }
+
+    if cli.service {
+        process::exit(service::run_service(&cli));
+    }
+    if let Some(log) = &cli.verify_log {
+        match logger::verify_hash_chain(log) {
+            Ok(entries) => println!("{}: intact ({} entries)", log.display(), entries),
+            Err(msg) => {
+                println!("{}: {}", log.display(), msg);
+                process::exit(1);
+            }
+        }
+        process::exit(0);
+    }
+
+    if let Some((address, command)) = &cli.remote {
+        process::exit(send_remote_command(&cli, address, command));
+    }
+
+    if cli.settings {
+        process::exit(settings::open_settings(&Config::path_config(cli.config_path.clone())));
+    }
+
+    Config::set_profile_override(cli.profile.clone());
+    if cli.tutorial {
+        if let Some(reason) = session::headless_reason() {
+            println!("The tutorial needs a display: {}", reason);
+            process::exit(2);
+        }
+        // The tutorial uses the tolerance of the configured gesture
+        match Config::read_from_file(Config::path_config(cli.config_path.clone())) {
+            Ok(config) => {
+                let code = tutorial::run_tutorial(config.gesture_tolerance, config.large_notifications);
+                remove_input_hooks();
+                process::exit(code)
+            }
+            Err(e) => {
+                println!("{}", e);
+                process::exit(2);
+            }
+        }
+    }
+    if cli.analyze {
+        process::exit(analyze(&cli));
+    }
+    if cli.self_check {
+        process::exit(self_check(&cli));
+    }
+    if cli.backup_now {
+        process::exit(backup_now(&cli));
+    }
+    if cli.restore {
+        process::exit(restore(&cli));
+    }
+    if let Some(pattern) = &cli.search {
+        process::exit(search(&cli, pattern));
+    }
+    if cli.prune {
+        process::exit(prune(&cli));
+    }
+
+    crash_handler::set_state("Reading configuration");
+    let mut config = Config::retrieve_and_check_config_file(cli.config_path.clone());
+    cli.apply(&mut config);
+    if let Err(e) = config.validate(Path::new("command line arguments")) {
+        println!("{}", e);
+        show_popup(NotificationType::ConfigError, Some(e.to_string()));
+        notification_popup::wait_for_notifications();
+        process::exit(2);
+    }
+
+    config.apply_global_settings();
+    logger::set_hash_chain(config.log_hash_chain);
+    if config.portable {
+        beeper::set_resources_dir(&config::exe_dir().join("resources"));
+    }
+
+    // From now on the configuration is reloaded every time the file is saved
+    let shared_config: SharedConfig = Arc::new(RwLock::new(config.clone()));
+    let cli_overrides = cli.clone();
+    Config::watch_for_changes(cli.config_path.clone(), shared_config.clone(), move |config| cli_overrides.apply(config));
+
+    // Warn as soon as the destination is missing or read-only, not only when the backup starts
+    volume::start_destination_monitor(shared_config.clone(), Duration::from_secs(config.dest_check_interval_secs));
+    power::start_sleep_monitor();
+    power::start_power_event_monitor(shared_config.clone());
+    if let Some(remote_control) = &config.remote_control {
+        remote_control::start(remote_control.clone());
+    }
+    //println!("Configuration loaded: {:?}", config);
+
+    // Every run gets its own log directory, registered in the runs index
+    let log_root = config.log_dir.clone().unwrap_or_else(logger::retrieve_path_cpu_log);
+    let (run_log, run_dir) = start_run_log(&log_root);
+
+    // Heartbeat for external monitoring
+    let status_path = config.status_file.clone().unwrap_or_else(|| log_root.join("status.json"));
+    status::start_heartbeat(status_path, Duration::from_secs(config.status_interval_secs));
+    // Ctrl+Alt+S shows the last backup and the destination, read by the listener of the pattern
+    quick_status::enable(shared_config.clone(), log_root.clone());
+
+    // The process was killed during the last backup: the next one resumes it (the journal is in the drive of the label, if connected)
+    let mut last_destination = config.clone();
+    let _ = last_destination.resolve_destination_volume();
+    if let Some(journal) = ProgressJournal::load(&last_destination.path_dest_backup).filter(|journal| journal.source == config.path_orig_backup) {
+        show_popup(NotificationType::ResumeAvailable, Some(journal.description()));
+    }
+
+    let cpu_logger = Logger::new(run_dir.to_str().unwrap(), true);
+    let run_logger = Logger::new(run_dir.to_str().unwrap(), false);
+    let mut report = BackupReport::default();
+
+    // Start logging CPU usage in a separate thread <- no needs to wait; restarted by the watchdog if it stops
     let cpu_logger_clone = cpu_logger.clone();
-    thread::spawn(move || {
+    supervisor::supervise("CPU logger", move || {
         cpu_logger_clone.log_cpu_usage();
     });
+    // Every change of the state of the application is logged, except the progress of the backup
+    let state_logger = cpu_logger.clone();
+    let states = app_state::subscribe();
+    thread::spawn(move || {
+        let mut last = None;
+        for state in states {
+            let kind = std::mem::discriminant(&state);
+            if last != Some(kind) {
+                state_logger.write_log(&format!("Stato: {}\n", state));
+                last = Some(kind);
+            }
+        }
+    });
 
-    let config = Config::retrieve_and_check_config_file();
-    //println!("Configuration loaded: {:?}", config);
-
-    if config.btn_rec {
-        start_button_and_clicks_pattern_recognizer();
+    crash_handler::set_state("Waiting for the activation pattern");
+    let mut pat_pat = None;
+    if let Some(reason) = session::headless_reason() {
+        // No display or input hook (server, container, SSH): the recognizers could not even start
+        println!("Activation pattern not available ({}): the backup starts with --backup-now, the schedule, the power events or the remote control", reason);
+        cpu_logger.write_log(&format!("Pattern di attivazione non disponibile ({}): in attesa di un trigger automatico\n", reason));
+        crash_handler::set_state("Waiting for an automatic trigger (no display)");
+        trigger::wait_for_trigger();
+    } else if config.btn_rec {
+        start_button_and_clicks_pattern_recognizer(shared_config.clone());
+    } else {
+        let recognizer = pat_pat.insert(PatternRecognizer::new(shared_config.clone()));
+        recognizer.recognize_pattern();
+        cpu_logger.write_log(&recognizer.stats().summary());
+    }
+    let triggered_by = trigger::trigger_reason();
+    if let Some(reason) = &triggered_by {
+        // Started without the pattern: notify it as if the pattern had been confirmed
+        cpu_logger.write_log(&format!("Backup avviato automaticamente: {}\n", reason));
+        events::publish(AppEvent::Started);
     } else {
-        let mut pat_pat = PatternRecognizer::new();
-        pat_pat.recognize_pattern();
+        status::record_gesture();
     }
 
-    // Start of the backup operations
-    let usb_logger = Logger::new(config.path_dest_backup.to_str().unwrap(), false);
+    // Start of the backup operations, with the latest version of the configuration
+    let mut config = shared_config.read().unwrap().clone();
+    let rt = runtime::Runtime::new().unwrap();
+    // A source much larger than expected (e.g. exploded by a recursive link) must be confirmed again;
+    // an emergency triggered without the pattern cannot wait for it
+    if triggered_by.is_none() {
+        crash_handler::set_state("Checking the size of the source");
+        if let Some(excess) = rt.block_on(backup::check_expected_size(&config)) {
+            cpu_logger.write_log(&format!("{}: in attesa di una nuova conferma\n", excess));
+            show_popup(NotificationType::GenericError, Some(format!(
+                "{}.\nRepeat the activation pattern to start the backup anyway.", excess
+            )));
+            crash_handler::set_state("Waiting for the activation pattern to be repeated");
+            app_state::send(StateEvent::Canceled);
+            match pat_pat.as_mut() {
+                Some(recognizer) => recognizer.recognize_pattern(),
+                None => start_button_and_clicks_pattern_recognizer(shared_config.clone()),
+            }
+            config = shared_config.read().unwrap().clone();
+        }
+    }
+    // An emergency triggered by the power cannot wait for the charger
+    if let (Some(min_battery_percent), None) = (config.min_battery_percent, &triggered_by) {
+        crash_handler::set_state("Checking the battery");
+        power::check_battery_before_backup(min_battery_percent, config.low_battery_action);
+    }
+    if let Err(msg) = config.resolve_destination_volume() {
+        show_popup(NotificationType::GenericError, Some(msg));
+    }
+    // The backup log goes in the destination; if it is not writable, fall back to the run directory and then to the temp dir
+    let (usb_logger, log_degraded) = Logger::with_fallback(
+        &[config.path_dest_backup.clone(), run_dir.clone(), std::env::temp_dir()],
+        false,
+    );
+    if log_degraded {
+        show_popup(NotificationType::GenericError, Some(format!(
+            "Destination {:?} is not writable, the backup log will be written to {}",
+            config.path_dest_backup, usb_logger.log_file_path()
+        )));
+    }
     let start_time = Instant::now();
+    let start_cpu_time = logger::process_cpu_time();
 
+    crash_handler::set_state("Backup in progress");
     cpu_logger.write_log("Inizia Backup\n");
     // backup
-    let rt = runtime::Runtime::new().unwrap();
-    rt.block_on(wrapper_backup(config, &mut total_files, &mut total_size)).unwrap();
+    let progress_cues = if config.progress_sounds {
+        let (tx, rx) = mpsc::channel();
+        beeper::start_progress_cues(rx);
+        Some(tx)
+    } else {
+        None
+    };
+    // The progress drives the state of the application, and the sound cues if enabled
+    let (progress, progress_rx) = mpsc::channel();
+    thread::spawn(move || {
+        for percent in progress_rx {
+            app_state::send(StateEvent::Progress(percent));
+            if let Some(cues) = &progress_cues {
+                let _ = cues.send(percent);
+            }
+        }
+    });
+    let elevation = config.elevation;
+    let destination = config.path_dest_backup.clone();
+    let shutdown_guard = power::ShutdownGuard::acquire("Emergency backup in progress");
+    let result = rt.block_on(wrapper_backup(config, &mut report, Some(progress), Some(&run_dir)));
+    drop(shutdown_guard);
+    // The speed of the drive over the backups, shown in the quick status
+    if let Err(e) = destination_history::record(&log_root, &destination, &report, result.is_err()) {
+        cpu_logger.write_log(&format!("Impossibile aggiornare lo storico della destinazione: {}\n", e));
+    }
+    if let Err(e) = result {
+        cpu_logger.write_log(&format!("Backup fallito: {}\n", e));
+        finish_run(run_log.as_ref(), RunStatus::Failed);
+        status::record_backup_result("FAILED");
+        events::publish(AppEvent::Failed(e.to_string()));
+        events::wait_until_notified();
+        return;
+    }
 
-    let cpu_time = start_time.elapsed();
+    let wall_time = start_time.elapsed();
+    let cpu_time = logger::process_cpu_time().saturating_sub(start_cpu_time);
     cpu_logger.write_log("Finisce Backup\n");
-    // Emit a beep sound in a separate thread and get the handle
-    let beep_thread = beeper::emit_beep(true);
+    crash_handler::set_state("Backup completed");
+    speech::announce(&format!("Backup complete, {} copied", backup::bytes_to_human_readable(report.total_size)));
 
     // Log backup details
-    usb_logger.log_backup_details(total_size, total_files, cpu_time);
+    usb_logger.log_backup_details(&report, wall_time, cpu_time);
+    if usb_logger.log_file_path() != run_logger.log_file_path() {
+        run_logger.log_backup_details(&report, wall_time, cpu_time);
+    }
+    // The log in the destination is readable only by `metadata_recipient`, if configured
+    if !log_degraded {
+        if let Err(e) = metadata_crypto::seal(Path::new(usb_logger.log_file_path())) {
+            cpu_logger.write_log(&format!("Impossibile cifrare il log del backup: {}\n", e));
+        }
+    }
+    // Stopped by `max_run_minutes`: the next backup resumes from the files left
+    let run_status = if report.unfinished > 0 { RunStatus::Partial } else { RunStatus::Completed };
+    finish_run(run_log.as_ref(), run_status);
+    status::record_backup_result(if report.unfinished > 0 { "PARTIAL" } else { "COMPLETED" });
+
+    // The sound and the notification of the end, and the state of the application
+    events::publish(AppEvent::Done(backup_summary(&report)));
+
+    // Files denied for missing privileges: run the backup again elevated, if allowed
+    let denied = report.denied.len();
+    if denied > 0 && elevation == Elevation::Relaunch && !elevation::is_elevated() {
+        cpu_logger.write_log(&format!("{} file negati per permessi: nuovo backup con privilegi elevati\n", denied));
+        let relaunched = elevated_retry_args(&cli, &report.denied, &run_dir)
+            .and_then(|args| elevation::relaunch_elevated(&args));
+        if let Err(e) = relaunched {
+            show_popup(NotificationType::GenericError, Some(format!(
+                "{} files could not be read without administrator privileges: {}", denied, e
+            )));
+        }
+    }
+    // The notification of the end stays on screen as configured (`popup_duration_secs`), then the application exits
+    events::wait_until_notified();
+}
+
+/// Starts the run log in the log root and registers it for the crash handler.
+/// If the log root is not writable the backup goes on without a run log: the logs and the crash reports
+/// of this run are written in the temp dir.
+fn start_run_log(log_root: &Path) -> (Option<RunLog>, PathBuf) {
+    let (run_log, run_dir) = match RunLog::start(log_root) {
+        Ok(run_log) => {
+            crash_handler::set_current_run(&run_log);
+            let run_dir = run_log.run_dir().to_path_buf();
+            (Some(run_log), run_dir)
+        }
+        Err(e) => {
+            eprintln!("Unable to create the run log in {}: {}, the logs of this run go in the temp dir", log_root.display(), e);
+            (None, std::env::temp_dir())
+        }
+    };
+    crash_handler::set_crash_dir(&run_dir);
+    (run_log, run_dir)
+}
+
+/// Records the final status of the run in the runs index, if the run log has been started.
+fn finish_run(run_log: Option<&RunLog>, status: RunStatus) {
+    if let Some(Err(e)) = run_log.map(|run_log| run_log.finish(status)) {
+        eprintln!("Unable to update the runs index: {}", e);
+    }
+}
+
+/// Name of the list of the files denied to a backup, in its run directory.
+const DENIED_FILES_NAME: &str = "denied_files.txt";
+
+/// Returns the arguments of the elevated `backup now` that copies again only the files denied to this backup,
+/// listed in a file of the run directory. The paths are absolute, since pkexec starts it in the environment
+/// of root (with another `HOME`, so another default configuration, and maybe another working directory).
+fn elevated_retry_args(cli: &CliArgs, denied: &[PathBuf], run_dir: &Path) -> Result<Vec<String>, String> {
+    let absolute = |path: &Path| std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let list = absolute(&run_dir.join(DENIED_FILES_NAME));
+    let text: String = denied.iter().map(|path| format!("{}\n", absolute(path).display())).collect();
+    std::fs::write(&list, text).map_err(|e| format!("Unable to write the list of the denied files: {}", e))?;
+
+    let mut retry = cli.clone();
+    retry.config_path = Some(absolute(&Config::path_config(cli.config_path.clone())));
+    retry.source = cli.source.as_deref().map(absolute);
+    retry.dest = cli.dest.as_deref().map(absolute);
+    let mut args = vec!["backup".to_string(), "now".to_string(), "--retry-files".to_string(), list.display().to_string()];
+    args.extend(retry.to_args());
+    Ok(args)
+}
+
+/// Returns the details shown in the popup at the end of the backup, if any:
+/// whether each replica is complete and the result of the verification.
+fn backup_summary(report: &BackupReport) -> Option<String> {
+    let state = |complete: bool| if complete { "complete" } else { "INCOMPLETE" };
+    let mut lines = Vec::new();
+    // First, so that the end of the backup does not look like any other
+    if let Some(suspicion) = &report.suspicion {
+        lines.push(suspicion.message());
+    }
+    if let Some(drift) = &report.drift {
+        lines.push(drift.message());
+    }
+    if report.evacuated > 0 || report.evacuation_failed > 0 {
+        lines.push(format!(
+            "Evacuation: {} source files removed, {} kept because their copy could not be verified",
+            report.evacuated, report.evacuation_failed
+        ));
+    }
+    if report.resumed > 0 {
+        lines.push(format!("Resumed: {} files had already been copied by the interrupted backup", report.resumed));
+    }
+    if report.placeholders > 0 {
+        lines.push(format!("{} cloud files not downloaded (online-only placeholders)", report.placeholders));
+    }
+    if report.unfinished > 0 {
+        lines.push(format!(
+            "Maximum duration reached: {} files not copied, the next backup will resume from them",
+            report.unfinished
+        ));
+    }
+    if !report.fuzzy.is_empty() {
+        lines.push(format!("{} files modified during the copy: their copy may be inconsistent", report.fuzzy.len()));
+    }
+    if !report.duplicates.is_empty() {
+        lines.push(format!(
+            "Duplicate files: {} groups, removing the copies would save {} (see the log)",
+            report.duplicates.len(), backup::bytes_to_human_readable(report.duplicate_savings())
+        ));
+    }
+    if report.sanitized > 0 {
+        lines.push(format!(
+            "{} files renamed because the destination cannot store their names (see {})",
+            report.sanitized, manifest::MANIFEST_FILE_NAME
+        ));
+    }
+    if report.conflicts.total() > 0 {
+        lines.push(format!(
+            "Already in the destination: {} ({} overwritten, {} renamed, {} kept)",
+            report.conflicts.total(), report.conflicts.overwritten, report.conflicts.renamed, report.conflicts.skipped
+        ));
+    }
+    if let Some(replica) = &report.secondary {
+        lines.push(format!("Main destination: {} ({} failed)", state(report.failed == 0), report.failed));
+        lines.push(format!("Secondary destination: {} ({} failed)", state(replica.is_complete()), replica.failed));
+    }
+    if let Some(verification) = &report.verification {
+        if verification.is_ok() {
+            lines.push(format!("Verification: {} files read back correctly", verification.checked));
+        } else {
+            lines.push(format!(
+                "Verification FAILED: {} of {} files differ or cannot be read back, the destination drive may be faulty",
+                verification.mismatched.len() + verification.unreadable.len(), verification.checked
+            ));
+        }
+    }
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
+/// Exit codes of `backup now`.
+const EXIT_SUCCESS: i32 = 0;
+const EXIT_PARTIAL_FAILURE: i32 = 1;
+const EXIT_CONFIG_ERROR: i32 = 2;
+const EXIT_DESTINATION_MISSING: i32 = 3;
+
+/// Runs the backup immediately (`backup now`), for scripts and task schedulers: no pattern is awaited,
+/// the configuration is read once (without waiting for it to be fixed) and a JSON summary is printed on stdout.
+/// It is the only output on stdout: the progress and the messages of the backup go to stderr.
+///
+/// # Arguments
+///
+/// * `cli` - The command line arguments.
+///
+/// # Returns
+///
+/// The exit code: 0 success, 1 some files failed or were left out by `max_run_minutes`, 2 configuration error (or source larger than expected), 3 destination missing.
+fn backup_now(cli: &CliArgs) -> i32 {
+    crash_handler::set_state("Reading configuration");
+    let config_path = Config::path_config(cli.config_path.clone());
+    let mut config = match Config::read_from_file(&config_path) {
+        Ok(config) => config,
+        Err(e) => return print_summary(EXIT_CONFIG_ERROR, "config_error", None, Some(e.to_string())),
+    };
+    cli.apply(&mut config);
+    if let Err(e) = config.validate(Path::new("command line arguments")) {
+        return print_summary(EXIT_CONFIG_ERROR, "config_error", None, Some(e.to_string()));
+    }
+    // The files denied to a backup without privileges, copied again by this one elevated
+    if let Some(list) = &cli.retry_files {
+        match std::fs::read_to_string(list) {
+            Ok(text) => backup::set_retry_files(Some(text.lines().filter(|line| !line.is_empty()).map(PathBuf::from).collect())),
+            Err(e) => {
+                let msg = format!("Unable to read the files to retry from {}: {}", list.display(), e);
+                return print_summary(EXIT_CONFIG_ERROR, "config_error", None, Some(msg));
+            }
+        }
+    }
+    if !config.path_orig_backup.exists() {
+        let msg = format!("Source path does not exist: {:?}", config.path_orig_backup);
+        return print_summary(EXIT_CONFIG_ERROR, "config_error", None, Some(msg));
+    }
+    config.apply_global_settings();
+    logger::set_hash_chain(config.log_hash_chain);
+    if config.portable {
+        beeper::set_resources_dir(&config::exe_dir().join("resources"));
+    }
+    // A scheduled backup must not compete with the work outside `trigger_hours`; a manual one always runs
+    if cli.scheduled && !trigger::trigger_allowed_now() {
+        return print_summary(EXIT_SUCCESS, "outside_trigger_hours", None, None);
+    }
+
+    let destination = config.resolve_destination_volume()
+        .and_then(|_| volume::check_writable(&config.path_dest_backup));
+    if let Err(msg) = destination {
+        return print_summary(EXIT_DESTINATION_MISSING, "destination_missing", None, Some(msg));
+    }
+    // Nobody can confirm a source much larger than expected in a scripted run
+    let rt = runtime::Runtime::new().unwrap();
+    if let Some(excess) = rt.block_on(backup::check_expected_size(&config)) {
+        return print_summary(EXIT_CONFIG_ERROR, "size_exceeded", None, Some(excess));
+    }
+
+    let log_root = config.log_dir.clone().unwrap_or_else(logger::retrieve_path_cpu_log);
+    let (run_log, run_dir) = start_run_log(&log_root);
+    let run_logger = Logger::new(run_dir.to_str().unwrap(), false);
+
+    crash_handler::set_state("Backup in progress");
+    let mut report = BackupReport::default();
+    let start_time = Instant::now();
+    let start_cpu_time = logger::process_cpu_time();
+    let shutdown_guard = power::ShutdownGuard::acquire("Emergency backup in progress");
+    app_state::send(StateEvent::Started);
+    let destination = config.path_dest_backup.clone();
+    let secondary_destination = config.secondary_dest.clone();
+    let result = rt.block_on(wrapper_backup(config, &mut report, None, Some(&run_dir)));
+    drop(shutdown_guard);
+    let wall_time = start_time.elapsed();
+    // Run elevated by pkexec: what has been written as root goes back to the user
+    if elevation::is_elevated() {
+        let roots: Vec<&Path> = std::iter::once(destination.as_path()).chain(secondary_destination.as_deref()).collect();
+        if let Err(e) = elevation::return_ownership(&roots) {
+            eprintln!("Unable to give the copies back to the user: {}", e);
+        }
+    }
+    if let Err(e) = destination_history::record(&log_root, &destination, &report, result.is_err()) {
+        eprintln!("Unable to update the history of the destination: {}", e);
+    }
+
+    if let Err(e) = result {
+        app_state::send(StateEvent::Failed(e.to_string()));
+        finish_run(run_log.as_ref(), RunStatus::Failed);
+        return print_summary(EXIT_PARTIAL_FAILURE, "failed", Some((&report, wall_time)), Some(e.to_string()));
+    }
+    app_state::send(StateEvent::Finished);
+    run_logger.log_backup_details(&report, wall_time, logger::process_cpu_time().saturating_sub(start_cpu_time));
+    let replica_incomplete = report.secondary.as_ref().is_some_and(|replica| !replica.is_complete());
+    let verification_failed = report.verification.as_ref().is_some_and(|verification| !verification.is_ok());
+    if report.failed > 0 || replica_incomplete || verification_failed {
+        finish_run(run_log.as_ref(), RunStatus::Failed);
+        print_summary(EXIT_PARTIAL_FAILURE, "partial_failure", Some((&report, wall_time)), None)
+    } else if report.unfinished > 0 {
+        // What was copied is fine, but the rest is left to the next backup
+        finish_run(run_log.as_ref(), RunStatus::Partial);
+        print_summary(EXIT_PARTIAL_FAILURE, "partial_success", Some((&report, wall_time)), None)
+    } else {
+        finish_run(run_log.as_ref(), RunStatus::Completed);
+        print_summary(EXIT_SUCCESS, "success", Some((&report, wall_time)), None)
+    }
+}
+
+/// Prints the JSON summary of `backup now` on stdout.
+///
+/// # Arguments
+///
+/// * `exit_code` - The exit code of the process.
+/// * `result` - The result, in words (e.g. `success`, `destination_missing`).
+/// * `backup` - The report and the duration of the backup, if it has been run.
+/// * `error` - The message of the error, if any.
+///
+/// # Returns
+///
+/// The exit code, to be returned by the caller.
+fn print_summary(exit_code: i32, result: &str, backup: Option<(&BackupReport, Duration)>, error: Option<String>) -> i32 {
+    let mut summary = serde_json::json!({
+        "result": result,
+        "exit_code": exit_code,
+    });
+    if let Some((report, wall_time)) = backup {
+        summary["report"] = serde_json::json!(report);
+        summary["duration_secs"] = serde_json::json!(wall_time.as_secs_f64());
+    }
+    if let Some(error) = error {
+        summary["error"] = serde_json::json!(error);
+    }
+    println!("{}", summary);
+    exit_code
+}
+
+
+/// Lets the user choose one of the backups found and restores it (`restore`): the backups are looked for
+/// in the destinations of the configuration and in the folder given with `--from` (e.g. the root of a drive).
+/// With `--only`, just the matching files are restored.
+///
+/// # Arguments
+///
+/// * `cli` - The command line arguments.
+///
+/// # Returns
+///
+/// The exit code: 0 success, 1 some files could not be restored, 2 no backup found or nothing chosen.
+fn restore(cli: &CliArgs) -> i32 {
+    let roots = restore_roots(cli);
+    let points = restore_points::find_restore_points(&roots);
+    if points.is_empty() {
+        println!("No backup found in {:?}", roots);
+        return 2;
+    }
+    println!("Backups found:");
+    for (i, point) in points.iter().enumerate() {
+        println!("  {}) {}", i + 1, describe_restore_point(point));
+    }
+    let point = match ask(&format!("Backup to restore [1-{}]: ", points.len()))
+        .and_then(|answer| answer.parse::<usize>().ok())
+        .and_then(|n| n.checked_sub(1))
+        .and_then(|i| points.get(i))
+    {
+        Some(point) => point,
+        None => {
+            println!("No backup chosen");
+            return 2;
+        }
+    };
+    let target = match cli.restore_to.clone().or_else(|| ask("Folder where the files are restored: ").map(PathBuf::from)) {
+        Some(target) => target,
+        None => {
+            println!("No folder chosen");
+            return 2;
+        }
+    };
+
+    println!("Restoring {} to {}...", point.root.display(), target.display());
+    match restore_points::restore(point, &target, &cli.restore_only) {
+        Ok(report) => {
+            println!("{} files restored", report.restored);
+            if report.stubs > 0 {
+                println!("{} files not restored: they were online-only cloud files, stored without content", report.stubs);
+            }
+            for (path, error) in &report.failed {
+                println!("Failed to restore {}: {}", path.display(), error);
+            }
+            if report.failed.is_empty() { 0 } else { 1 }
+        }
+        Err(e) => {
+            println!("Unable to restore {}: {}", point.root.display(), e);
+            1
+        }
+    }
+}
+
+/// Deletes the backups not kept by the retention policy (`prune`), after showing them and asking for confirmation;
+/// with `--dry-run` only shows what would be deleted, with the files of each backup and the space freed.
+///
+/// # Returns
+///
+/// The exit code: 0 success (or nothing to delete), 1 some backups could not be deleted, 2 no retention policy,
+/// no backup found or not confirmed.
+fn prune(cli: &CliArgs) -> i32 {
+    let mut config = match Config::read_from_file(Config::path_config(cli.config_path.clone())) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{}", e);
+            return 2;
+        }
+    };
+    cli.apply(&mut config);
+    let _ = config.resolve_destination_volume();
+    let policy = match &config.retention {
+        Some(policy) => policy.clone(),
+        None => {
+            println!("No retention policy in the configuration (retention): nothing to prune");
+            return 2;
+        }
+    };
+    let roots = restore_roots(cli);
+    let points = restore_points::find_restore_points(&roots);
+    if points.is_empty() {
+        println!("No backup found in {:?}", roots);
+        return 2;
+    }
+    // The destinations in use hold the latest backup, which is never deleted
+    let protected: Vec<PathBuf> = std::iter::once(config.path_dest_backup.clone()).chain(config.secondary_dest.clone()).collect();
+    let mut plan = restore_points::prune_plan(&points, &policy, &protected);
+    // A backup has found the signs of a ransomware: the older backups may be the only clean copies.
+    // A manifest that cannot be read may hide such an alert, so its backup is kept
+    let mut suspicion = None;
+    for root in points.iter().map(|point| &point.root).chain(&protected) {
+        match manifest::Manifest::load(root) {
+            Ok(manifest) => suspicion = suspicion.or(manifest.suspicion.map(|found| format!("the last backup has found this: {}", found))),
+            Err(e) => {
+                println!("Unable to read the manifest of {}: {}", root.display(), e);
+                if let Some(position) = plan.delete.iter().position(|point| &point.root == root) {
+                    plan.keep.push(plan.delete.remove(position));
+                }
+                if protected.contains(root) {
+                    suspicion = suspicion.or(Some(format!("the manifest of {} cannot be read", root.display())));
+                }
+            }
+        }
+    }
+
+    for point in &plan.keep {
+        println!("Kept     {}", describe_restore_point(point));
+    }
+    for point in &plan.delete {
+        println!("Deleted  {}", describe_restore_point(point));
+        if cli.dry_run {
+            for file in restore_points::backup_files(&point.root) {
+                println!("    {}", file.display());
+            }
+        }
+    }
+    let summary = format!("{} backups, {} freed", plan.delete.len(), backup::bytes_to_human_readable(plan.freed()));
+    if plan.delete.is_empty() {
+        println!("Nothing to delete");
+        return 0;
+    }
+    if let Some(suspicion) = suspicion {
+        println!("Deletions paused, {}", suspicion);
+        if !cli.dry_run {
+            return 2;
+        }
+    }
+    if cli.dry_run {
+        println!("Would delete {} (dry run: nothing has been deleted)", summary);
+        return 0;
+    }
+    if ask(&format!("Delete {}? Type yes to confirm: ", summary)).as_deref() != Some("yes") {
+        println!("Nothing deleted");
+        return 2;
+    }
+    let mut failed = 0;
+    for point in &plan.delete {
+        if let Err(e) = restore_points::delete_restore_point(point) {
+            println!("Unable to delete {}: {}", point.root.display(), e);
+            failed += 1;
+        }
+    }
+    println!("{} backups deleted", plan.delete.len() - failed);
+    if failed == 0 { 0 } else { 1 }
+}
+
+/// Returns where `restore` and `search` look for the backups: the folder given with `--from`
+/// and the destinations of the configuration, if it can be read.
+fn restore_roots(cli: &CliArgs) -> Vec<PathBuf> {
+    let mut roots: Vec<PathBuf> = cli.restore_from.iter().cloned().collect();
+    if let Ok(mut config) = Config::read_from_file(Config::path_config(cli.config_path.clone())) {
+        cli.apply(&mut config);
+        let _ = config.resolve_destination_volume();
+        locale::set_locale(config.locale.as_deref());
+        // The encrypted backups are decrypted while they are restored
+        encryption::set_key(config.encryption_key());
+        roots.push(config.path_dest_backup);
+        roots.extend(config.secondary_dest);
+    }
+    roots
+}
+
+/// Lists the backups containing the files that match a pattern (`search`), with the version of each file.
+///
+/// # Arguments
+///
+/// * `cli` - The command line arguments.
+/// * `pattern` - The name (or path) of the files to look for.
+///
+/// # Returns
+///
+/// The exit code: 0 some files found, 1 no file found, 2 no backup found.
+fn search(cli: &CliArgs, pattern: &str) -> i32 {
+    let roots = restore_roots(cli);
+    let points = restore_points::find_restore_points(&roots);
+    if points.is_empty() {
+        println!("No backup found in {:?}", roots);
+        return 2;
+    }
+    let matches = restore_points::search(&points, pattern);
+    if matches.is_empty() {
+        println!("No file matching \"{}\" in {} backups", pattern, points.len());
+        return 1;
+    }
+    let mut last_root = None;
+    for found in &matches {
+        if last_root != Some(&found.root) {
+            println!("{}  {}", locale::format_timestamp(&found.created), found.root.display());
+            last_root = Some(&found.root);
+        }
+        let modified = found.modified
+            .map(|modified| locale::format_date_time(&chrono::DateTime::<chrono::Local>::from(modified)))
+            .unwrap_or_default();
+        println!("    {}  ({}, modified {})", found.path, backup::bytes_to_human_readable(found.size), modified);
+    }
+    0
+}
+
+/// Sends a command to the remote control of another machine (`remote <address> <command>`), with the pre-shared key
+/// of `remote_control` in the configuration, and prints the answer.
+///
+/// # Returns
+///
+/// The exit code: 0 the command has been executed, 1 it has been refused or the machine cannot be reached,
+/// 2 configuration error.
+fn send_remote_command(cli: &CliArgs, address: &str, command: &str) -> i32 {
+    let config = match Config::read_from_file(Config::path_config(cli.config_path.clone())) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{}", e);
+            return 2;
+        }
+    };
+    let key = match config.remote_control.as_ref().map(|remote_control| group_39::secrets::resolve_secret(&remote_control.key)) {
+        Some(Ok(key)) => key,
+        Some(Err(e)) => {
+            println!("{}", e);
+            return 2;
+        }
+        None => {
+            println!("remote_control is not set in the configuration: its key is needed to send commands");
+            return 2;
+        }
+    };
+    match remote_control::send_command(address, &key, command) {
+        Ok(answer) => {
+            println!("{}", answer);
+            0
+        }
+        Err(msg) => {
+            println!("{}", msg);
+            1
+        }
+    }
+}
+
+/// The files generated by `self-check`: relative path and size. The names and the sizes are the ones that
+/// went wrong in the past (spaces, accents, empty files, files larger than the copy buffers).
+const SELF_CHECK_FILES: [(&str, usize); 6] = [
+    ("Documents/letter.txt", 2 * 1024),
+    ("Documents/Thesis/chapter 1.txt", 40 * 1024),
+    ("Photos/2024/IMG_0001.jpg", 1024 * 1024),
+    ("Music/canzone è.mp3", 3 * 1024 * 1024 + 17),
+    ("Notes/empty.txt", 0),
+    ("readme", 300),
+];
+
+/// How long `self-check` waits for its notification to be shown and closed.
+const SELF_CHECK_NOTIFICATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Checks the installation (`self-check`), e.g. right after installing the program on the computer of a relative:
+/// the gesture is simulated, some generated files are backed up from a temporary folder to another one with
+/// the settings of the configuration, and their copies, the manifest and the notifications are verified.
+/// Nothing outside the temporary folders is read or written.
+///
+/// # Arguments
+///
+/// * `cli` - The command line arguments, overriding the configuration.
+///
+/// # Returns
+///
+/// The exit code: 0 every step passed, 1 some step failed.
+fn self_check(cli: &CliArgs) -> i32 {
+    let mut passed = true;
+    let mut check = |step: &str, result: Result<String, String>| {
+        match result {
+            Ok(detail) => println!("PASS  {:<14} {}", step, detail),
+            Err(detail) => {
+                println!("FAIL  {:<14} {}", step, detail);
+                passed = false;
+            }
+        }
+    };
+
+    let config = Config::read_from_file(Config::path_config(cli.config_path.clone()))
+        .map_err(|e| e.to_string())
+        .and_then(|mut config| {
+            cli.apply(&mut config);
+            config.validate(Path::new("command line arguments")).map_err(|e| e.to_string())?;
+            Ok(config)
+        });
+    let mut config = match config {
+        Ok(config) => {
+            check("Configuration", Ok(Config::path_config(cli.config_path.clone()).display().to_string()));
+            config
+        }
+        Err(e) => {
+            check("Configuration", Err(e));
+            return 1;
+        }
+    };
+    config.apply_global_settings();
+    // The popups must close by themselves, and the hotkey of the second factor cannot be simulated
+    notification_popup::set_popup_duration(Some(Duration::from_secs(5)));
+    two_factor::set_two_factor(false, Duration::from_secs(config.two_factor_window_secs));
+
+    let root = env::temp_dir().join(format!("group_39_self_check_{}", process::id()));
+    let (source, destination) = (root.join("source"), root.join("destination"));
+    let generated = SELF_CHECK_FILES.iter().try_for_each(|(name, size)| {
+        let path = source.join(name);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        // Content that cannot be compressed, different for each file
+        let mut content = vec![0u8; *size];
+        blake3::Hasher::new().update(name.as_bytes()).finalize_xof().fill(&mut content);
+        std::fs::write(path, content)
+    });
+    let generated = generated.and_then(|()| std::fs::create_dir_all(&destination));
+    check("Test files", generated.map(|()| format!("{} files in {}", SELF_CHECK_FILES.len(), root.display())).map_err(|e| e.to_string()));
+
+    let mut recognizer = PatternRecognizer::simulated(1920.0, 1080.0, config.gesture_tolerance, config.gesture_sampling);
+    let started = recognizer.simulate_gesture();
+    check("Gesture", if started { Ok("rectangle drawn twice, backup confirmed".to_string()) } else { Err(recognizer.stats().summary()) });
+
+    // The settings that would touch something outside the temporary folders are left out
+    config.path_orig_backup = source.clone();
+    config.path_dest_backup = destination.clone();
+    config.secondary_dest = None;
+    config.type_files.clear();
+    config.exclude.clear();
+    config.evacuate = false;
+    // The copies are compared one by one
+    config.archive = None;
+    config.network_policies.clear();
+    config.max_run_minutes = None;
+    config.verify = VerifyMode::All;
+    config.verify_hash = true;
+    config.verify_when_idle_minutes = None;
+    let mut report = BackupReport::default();
+    let rt = runtime::Runtime::new().unwrap();
+    let result = rt.block_on(wrapper_backup(config, &mut report, None, None));
+    check("Copy", match result {
+        Err(e) => Err(e.to_string()),
+        Ok(()) if report.copied != SELF_CHECK_FILES.len() || report.failed > 0 => {
+            Err(format!("{} of {} files copied, {} failed", report.copied, SELF_CHECK_FILES.len(), report.failed))
+        }
+        Ok(()) => Ok(format!("{} files, {}", report.copied, backup::bytes_to_human_readable(report.total_size))),
+    });
+    check("Verification", match &report.verification {
+        Some(verification) if verification.is_ok() => Ok(format!("{} files read back", verification.checked)),
+        Some(verification) => Err(format!("{} mismatched, {} unreadable", verification.mismatched.len(), verification.unreadable.len())),
+        None => Err("the copies have not been read back".to_string()),
+    });
+
+    // Compared here too, independently of the verification of the backup
+    let mismatched: Vec<&str> = SELF_CHECK_FILES.iter()
+        .map(|(name, _)| *name)
+        .filter(|name| {
+            let hash = |path: PathBuf| std::fs::read(path).map(|content| blake3::hash(&content));
+            !matches!((hash(source.join(name)), hash(destination.join(name))), (Ok(src), Ok(dest)) if src == dest)
+        })
+        .collect();
+    check("Checksums", if mismatched.is_empty() { Ok("BLAKE3 of every copy matches".to_string()) } else { Err(format!("different: {}", mismatched.join(", "))) });
+
+    let total_size: u64 = SELF_CHECK_FILES.iter().map(|(_, size)| *size as u64).sum();
+    check("Manifest", match manifest::Manifest::load(&destination) {
+        Err(e) => Err(format!("unreadable: {}", e)),
+        Ok(manifest) if manifest.last_totals != Some(manifest::RunTotals { files: SELF_CHECK_FILES.len(), size: total_size }) => {
+            Err(format!("wrong totals: {:?}", manifest.last_totals))
+        }
+        Ok(_) if SnapshotSummary::load(&destination).is_none() => Err(format!("{} missing or unreadable", SNAPSHOT_INFO_FILE_NAME)),
+        Ok(_) => Ok(format!("{} and {} written", manifest::MANIFEST_FILE_NAME, SNAPSHOT_INFO_FILE_NAME)),
+    });
+
+    let notified = match session::headless_reason() {
+        Some(reason) => Ok(format!("skipped, no display: {}", reason)),
+        None => {
+            events::publish(AppEvent::Done(Some("Self-check: this is a test, no backup has been made".to_string())));
+            let (done, notified) = mpsc::channel();
+            thread::spawn(move || {
+                events::wait_until_notified();
+                let _ = done.send(());
+            });
+            notified.recv_timeout(SELF_CHECK_NOTIFICATION_TIMEOUT)
+                .map(|()| "shown and closed".to_string())
+                .map_err(|_| format!("not shown within {} seconds", SELF_CHECK_NOTIFICATION_TIMEOUT.as_secs()))
+        }
+    };
+    check("Notifications", notified);
+
+    if let Err(e) = std::fs::remove_dir_all(&root) {
+        println!("Unable to remove {}: {}", root.display(), e);
+    }
+    println!("\nSelf-check {}", if passed { "PASSED" } else { "FAILED" });
+    if passed { 0 } else { 1 }
+}
+
+/// Number of folders and of extensions listed by `--analyze`.
+const ANALYZE_TOP: usize = 15;
+
+/// Prints what the backup would copy with the current configuration (`--analyze`): the largest folders and
+/// extensions, to trim the types and the exclusions before the real backup.
+///
+/// # Arguments
+///
+/// * `cli` - The command line arguments, overriding the configuration.
+///
+/// # Returns
+///
+/// The exit code: 0 success, 1 the source cannot be read, 2 configuration error.
+fn analyze(cli: &CliArgs) -> i32 {
+    let mut config = match Config::read_from_file(Config::path_config(cli.config_path.clone())) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{}", e);
+            return 2;
+        }
+    };
+    cli.apply(&mut config);
+    if let Err(e) = config.validate(Path::new("command line arguments")) {
+        println!("{}", e);
+        return 2;
+    }
+    config.apply_global_settings();
+    let rt = runtime::Runtime::new().unwrap();
+    let breakdown = match rt.block_on(backup::analyze_source(&DiskFilesystem, &config.path_orig_backup, &config.type_files, &config.exclude)) {
+        Ok(breakdown) => breakdown,
+        Err(e) => {
+            println!("Unable to read {:?}: {}", config.path_orig_backup, e);
+            return 1;
+        }
+    };
+    println!(
+        "{}: {} files, {}",
+        config.path_orig_backup.display(), locale::format_count(breakdown.total_files as u64), backup::bytes_to_human_readable(breakdown.total_size)
+    );
+    let share = |size: u64| if breakdown.total_size > 0 { size as f64 * 100.0 / breakdown.total_size as f64 } else { 0.0 };
+    for (title, entries) in [("Largest folders", &breakdown.directories), ("Largest file types", &breakdown.extensions)] {
+        println!("\n{}:", title);
+        for entry in entries.iter().take(ANALYZE_TOP) {
+            let name = if entry.name.is_empty() { "(no extension)" } else { &entry.name };
+            println!(
+                "{:>12}  {:>8}  {:>10} files  {}",
+                backup::bytes_to_human_readable(entry.size), locale::format_percent(share(entry.size)),
+                locale::format_count(entry.files as u64), name
+            );
+        }
+    }
+    0
+}
+
+/// Describes a backup in the list of `restore`, e.g. "2026-10-16 10:00:00  pc-office (mario)  1234 files, 2.30 GB  E:\backup".
+fn describe_restore_point(point: &RestorePoint) -> String {
+    let machine = match (&point.info.hostname, &point.info.username) {
+        (Some(hostname), Some(username)) => format!("{} ({})", hostname, username),
+        (Some(hostname), None) => hostname.clone(),
+        _ => "unknown machine".to_string(),
+    };
+    format!(
+        "{}  {}  {} files, {}  {}",
+        locale::format_timestamp(&point.info.created), machine, locale::format_count(point.files as u64),
+        backup::bytes_to_human_readable(point.size), point.root.display()
+    )
+}
 
-    // Wait for the beep threads to finish
-    beep_thread.join().expect("Beep thread panicked");
+/// Asks a question on the console and returns the answer, or `None` if it is empty.
+fn ask(question: &str) -> Option<String> {
+    use std::io::Write;
 
-    show_popup(NotificationType::BackupDone, None);
-    thread::sleep(Duration::from_secs(10));
-}
\ No newline at end of file
+    print!("{}", question);
+    let _ = std::io::stdout().flush();
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).ok()?;
+    let answer = answer.trim();
+    (!answer.is_empty()).then(|| answer.to_string())
+}
@@ -0,0 +1,211 @@
+use crate::metadata_crypto;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Name of the manifest, written in the root of the destination.
+pub const MANIFEST_FILE_NAME: &str = ".emergency_backup_manifest.json";
+
+/// Information about a destination that is needed to restore it as it was in the source.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The files stored with a different name because the destination file system cannot represent
+    /// the original one: relative path in the destination -> relative path in the source.
+    #[serde(default)]
+    pub renamed: BTreeMap<String, String>,
+    /// The files stored as empty stubs because their content was only in the cloud (relative paths in the destination).
+    #[serde(default)]
+    pub placeholders: BTreeSet<String>,
+    /// The files modified while they were copied, whose copy may be internally inconsistent (relative paths in the destination).
+    #[serde(default)]
+    pub fuzzy: BTreeSet<String>,
+    /// How many files the last backup found in the source, and their size, to notice when the next one finds far fewer.
+    #[serde(default)]
+    pub last_totals: Option<RunTotals>,
+    /// Why the last backup suspected a ransomware, if it did: the backups in the destination are not pruned
+    /// until a backup finds nothing suspicious.
+    #[serde(default)]
+    pub suspicion: Option<String>,
+    /// The files written in the destination by the backups (relative paths in the destination):
+    /// `prune` deletes only these, never the other files that may be in the same folder.
+    #[serde(default)]
+    pub stored: BTreeSet<String>,
+}
+
+/// The files a backup found in the source.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RunTotals {
+    pub files: usize,
+    pub size: u64,
+}
+
+impl Manifest {
+    /// Reads the manifest of a destination, or returns an empty one if the destination has none yet.
+    /// An encrypted manifest (`metadata_recipient`) is decrypted.
+    ///
+    /// # Arguments
+    ///
+    /// * `destination` - The root folder of the destination.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` containing the manifest, or an error if the existing manifest cannot be read.
+    pub fn load(destination: &Path) -> io::Result<Manifest> {
+        match metadata_crypto::read(&destination.join(MANIFEST_FILE_NAME)) {
+            Ok(contents) => serde_json::from_slice(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Manifest::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes the manifest in the root of a destination, replacing the previous one;
+    /// it is encrypted if `metadata_recipient` is configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `destination` - The root folder of the destination.
+    pub fn save(&self, destination: &Path) -> io::Result<()> {
+        let contents = serde_json::to_string_pretty(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        metadata_crypto::write(&destination.join(MANIFEST_FILE_NAME), contents.as_bytes())
+    }
+
+    /// Returns the original relative path of a file stored in the destination.
+    ///
+    /// # Arguments
+    ///
+    /// * `stored` - The relative path of the file in the destination.
+    pub fn original_path<'a>(&'a self, stored: &'a str) -> &'a str {
+        self.renamed.get(stored).map_or(stored, String::as_str)
+    }
+}
+
+/// Adds the renamed files of a backup to the manifest of a destination, keeping the ones recorded by the previous backups.
+///
+/// # Arguments
+///
+/// * `destination` - The root folder of the destination.
+/// * `renamed` - The renamed files: relative path in the destination -> relative path in the source.
+///
+/// # Returns
+///
+/// An `io::Result` indicating whether the manifest has been updated.
+pub fn record_renamed(destination: &Path, renamed: BTreeMap<String, String>) -> io::Result<()> {
+    if renamed.is_empty() {
+        return Ok(());
+    }
+    let mut manifest = Manifest::load(destination)?;
+    manifest.renamed.extend(renamed);
+    manifest.save(destination)
+}
+
+/// Adds the files stored as empty stubs by a backup to the manifest of a destination, forgetting the previous stubs
+/// that have been replaced by a full copy (or removed) in the meantime.
+///
+/// # Arguments
+///
+/// * `destination` - The root folder of the destination.
+/// * `stubs` - The files stored as stubs by this backup (relative paths in the destination).
+///
+/// # Returns
+///
+/// An `io::Result` indicating whether the manifest has been updated.
+pub fn record_placeholders(destination: &Path, stubs: BTreeSet<String>) -> io::Result<()> {
+    let mut manifest = Manifest::load(destination)?;
+    let before = manifest.placeholders.clone();
+    manifest.placeholders.retain(|path| fs::metadata(destination.join(path)).is_ok_and(|metadata| metadata.len() == 0));
+    manifest.placeholders.extend(stubs);
+    if manifest.placeholders == before {
+        return Ok(());
+    }
+    manifest.save(destination)
+}
+
+/// Records the files modified while a backup copied them, forgetting the ones recorded by the previous backups
+/// that this backup has copied again (the files of the destination modified since it started).
+///
+/// # Arguments
+///
+/// * `destination` - The root folder of the destination.
+/// * `fuzzy` - The files modified during their copy (relative paths in the destination).
+/// * `backup_started` - When the backup started.
+///
+/// # Returns
+///
+/// An `io::Result` indicating whether the manifest has been updated.
+pub fn record_fuzzy(destination: &Path, fuzzy: BTreeSet<String>, backup_started: SystemTime) -> io::Result<()> {
+    let mut manifest = Manifest::load(destination)?;
+    let before = manifest.fuzzy.clone();
+    manifest.fuzzy.retain(|path| {
+        fs::metadata(destination.join(path))
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| modified < backup_started)
+    });
+    manifest.fuzzy.extend(fuzzy);
+    if manifest.fuzzy == before {
+        return Ok(());
+    }
+    manifest.save(destination)
+}
+
+/// Records the files found in the source by a backup, replacing the ones of the previous backup.
+///
+/// # Arguments
+///
+/// * `destination` - The root folder of the destination.
+/// * `totals` - The files found by this backup.
+///
+/// # Returns
+///
+/// An `io::Result` containing the files found by the previous backup, if it recorded them.
+pub fn record_totals(destination: &Path, totals: RunTotals) -> io::Result<Option<RunTotals>> {
+    let mut manifest = Manifest::load(destination)?;
+    let previous = manifest.last_totals.replace(totals);
+    if previous != Some(totals) {
+        manifest.save(destination)?;
+    }
+    Ok(previous)
+}
+
+/// Records whether a backup has found the signs of a ransomware, replacing what the previous backup found.
+///
+/// # Arguments
+///
+/// * `destination` - The root folder of the destination.
+/// * `suspicion` - The alert of this backup, or `None` if it found nothing suspicious.
+///
+/// # Returns
+///
+/// An `io::Result` indicating whether the manifest has been updated.
+pub fn record_suspicion(destination: &Path, suspicion: Option<String>) -> io::Result<()> {
+    let mut manifest = Manifest::load(destination)?;
+    if manifest.suspicion == suspicion {
+        return Ok(());
+    }
+    manifest.suspicion = suspicion;
+    manifest.save(destination)
+}
+
+/// Adds the files written by a backup to the manifest of a destination, keeping the ones written by the previous
+/// backups that are still there.
+///
+/// # Arguments
+///
+/// * `destination` - The root folder of the destination.
+/// * `stored` - The files written by this backup (relative paths in the destination).
+///
+/// # Returns
+///
+/// An `io::Result` indicating whether the manifest has been updated.
+pub fn record_stored(destination: &Path, stored: BTreeSet<String>) -> io::Result<()> {
+    let mut manifest = Manifest::load(destination)?;
+    let before = manifest.stored.clone();
+    manifest.stored.retain(|path| destination.join(path).exists());
+    manifest.stored.extend(stored);
+    if manifest.stored == before {
+        return Ok(());
+    }
+    manifest.save(destination)
+}
@@ -0,0 +1,73 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Unit of the permits of the budget: a semaphore counts at most `u32::MAX` permits.
+const UNIT: u64 = 1024;
+
+/// The memory budget of the backup in progress, if capped, to report its usage.
+static CURRENT: Mutex<Option<Arc<MemoryBudget>>> = Mutex::new(None);
+
+/// A cap on the memory used by the copies of the backup (their buffers and the chunks waiting to be written):
+/// a copy starts only when its memory is available, so the backup slows down instead of making the machine swap.
+pub struct MemoryBudget {
+    semaphore: Arc<Semaphore>,
+    cap: u64,
+    in_use: AtomicU64,
+}
+
+/// Memory reserved by a copy, given back when dropped.
+pub struct MemoryReservation {
+    budget: Arc<MemoryBudget>,
+    bytes: u64,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl MemoryBudget {
+    /// Creates the budget of a backup and makes it the one reported by `usage`.
+    ///
+    /// # Arguments
+    ///
+    /// * `cap` - The maximum memory of the copies, in bytes.
+    pub fn start(cap: u64) -> Arc<MemoryBudget> {
+        let units = (cap / UNIT).clamp(1, u32::MAX as u64);
+        let budget = Arc::new(MemoryBudget {
+            semaphore: Arc::new(Semaphore::new(units as usize)),
+            cap: units * UNIT,
+            in_use: AtomicU64::new(0),
+        });
+        *CURRENT.lock().unwrap() = Some(budget.clone());
+        budget
+    }
+
+    /// Stops reporting the usage of this budget, at the end of the backup.
+    pub fn finish(&self) {
+        *CURRENT.lock().unwrap() = None;
+    }
+
+    /// Waits until some memory is available and reserves it. A request larger than the cap reserves the whole
+    /// budget, so that the copy is made alone instead of never.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The memory needed.
+    pub async fn reserve(self: &Arc<Self>, bytes: u64) -> MemoryReservation {
+        let units = bytes.div_ceil(UNIT).clamp(1, self.cap / UNIT);
+        let permit = self.semaphore.clone().acquire_many_owned(units as u32).await.unwrap();
+        let bytes = units * UNIT;
+        self.in_use.fetch_add(bytes, Ordering::SeqCst);
+        MemoryReservation { budget: self.clone(), bytes, _permit: permit }
+    }
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        self.budget.in_use.fetch_sub(self.bytes, Ordering::SeqCst);
+    }
+}
+
+/// Returns the memory reserved by the copies of the backup in progress and its cap, in bytes,
+/// or `None` if no capped backup is running.
+pub fn usage() -> Option<(u64, u64)> {
+    CURRENT.lock().unwrap().as_ref().map(|budget| (budget.in_use.load(Ordering::SeqCst), budget.cap))
+}
@@ -0,0 +1,213 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::RwLock;
+
+/// Extensions added to the encrypted files, by the tool that can decrypt them.
+pub const AGE_SUFFIX: &str = ".age";
+pub const GPG_SUFFIX: &str = ".gpg";
+
+/// Who can read the metadata written in the destination (the manifest and the backup logs).
+#[derive(Clone)]
+pub enum MetadataRecipient {
+    /// An age public key (`age1...`).
+    Age(age::x25519::Recipient),
+    /// A GPG key (its id, fingerprint or e-mail), in the keyring of the user.
+    Gpg(String),
+}
+
+impl MetadataRecipient {
+    /// Parses a recipient: an age public key if it starts with `age1`, otherwise a GPG key.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The recipient, e.g. `age1ql3z...` or `alice@example.com`.
+    pub fn parse(text: &str) -> Result<MetadataRecipient, String> {
+        let text = text.trim();
+        if text.starts_with("age1") {
+            text.parse().map(MetadataRecipient::Age).map_err(|e| format!("invalid age public key: {}", e))
+        } else if text.is_empty() {
+            Err("must not be empty".to_string())
+        } else {
+            Ok(MetadataRecipient::Gpg(text.to_string()))
+        }
+    }
+
+    fn suffix(&self) -> &'static str {
+        match self {
+            MetadataRecipient::Age(_) => AGE_SUFFIX,
+            MetadataRecipient::Gpg(_) => GPG_SUFFIX,
+        }
+    }
+}
+
+/// The recipient of the metadata (`metadata_recipient`) and the age identity file able to read it back
+/// (`metadata_identity`), if the metadata is encrypted.
+static KEYS: RwLock<Option<(MetadataRecipient, Option<PathBuf>)>> = RwLock::new(None);
+
+/// Sets who can read the metadata written in the destination from now on.
+///
+/// # Arguments
+///
+/// * `recipient` - The recipient of the metadata, or `None` to write it in clear.
+/// * `identity` - The age identity file used to read the encrypted manifest back (GPG uses its own agent).
+pub fn set_keys(recipient: Option<MetadataRecipient>, identity: Option<PathBuf>) {
+    *KEYS.write().unwrap() = recipient.map(|recipient| (recipient, identity));
+}
+
+/// Returns whether the metadata written in the destination is encrypted.
+pub fn is_enabled() -> bool {
+    KEYS.read().unwrap().is_some()
+}
+
+/// Returns the path of the encrypted copy of a file, with the extension of the tool that decrypts it.
+fn sealed_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut sealed = path.as_os_str().to_owned();
+    sealed.push(suffix);
+    PathBuf::from(sealed)
+}
+
+/// Returns whether a file name is the encrypted copy of a metadata file (e.g. the encrypted manifest).
+pub fn is_sealed_copy(name: &str, metadata_name: &str) -> bool {
+    [AGE_SUFFIX, GPG_SUFFIX].iter().any(|suffix| name.strip_suffix(suffix) == Some(metadata_name))
+}
+
+fn other_error<E: std::fmt::Display>(e: E) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+/// Runs GPG on some data, returning its output.
+fn run_gpg(args: &[&str], input: &[u8]) -> io::Result<Vec<u8>> {
+    let mut child = Command::new("gpg")
+        .args(["--batch", "--yes", "--quiet"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    // Written by another thread, since GPG writes its output while reading the input
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output()?;
+    writer.join().map_err(|_| other_error("unable to write to gpg"))??;
+    if !output.status.success() {
+        return Err(other_error(format!("gpg failed: {}", String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(output.stdout)
+}
+
+fn encrypt(recipient: &MetadataRecipient, plain: &[u8]) -> io::Result<Vec<u8>> {
+    match recipient {
+        MetadataRecipient::Age(recipient) => {
+            let encryptor = age::Encryptor::with_recipients(std::iter::once(recipient as &dyn age::Recipient)).map_err(other_error)?;
+            let mut encrypted = Vec::new();
+            let mut writer = encryptor.wrap_output(&mut encrypted).map_err(other_error)?;
+            writer.write_all(plain)?;
+            writer.finish()?;
+            Ok(encrypted)
+        }
+        MetadataRecipient::Gpg(key) => run_gpg(&["--trust-model", "always", "--encrypt", "--recipient", key], plain),
+    }
+}
+
+/// Reads the age identities (the private keys) of an identity file, skipping the comments.
+pub(crate) fn read_identities(path: &Path) -> io::Result<Vec<age::x25519::Identity>> {
+    let identities: Vec<age::x25519::Identity> = fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with("AGE-SECRET-KEY-"))
+        .filter_map(|line| line.parse().ok())
+        .collect();
+    if identities.is_empty() {
+        return Err(other_error(format!("no age identity in {}", path.display())));
+    }
+    Ok(identities)
+}
+
+fn decrypt_age(identity: Option<&Path>, encrypted: &[u8]) -> io::Result<Vec<u8>> {
+    let identity = identity.ok_or_else(|| other_error("the metadata is encrypted with age: metadata_identity is needed to read it"))?;
+    let identities = read_identities(identity)?;
+    let decryptor = age::Decryptor::new(encrypted).map_err(other_error)?;
+    if decryptor.is_scrypt() {
+        return Err(other_error("the metadata is encrypted with a passphrase"));
+    }
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|identity| identity as &dyn age::Identity))
+        .map_err(other_error)?;
+    let mut plain = Vec::new();
+    reader.read_to_end(&mut plain)?;
+    Ok(plain)
+}
+
+/// Reads a metadata file written with `write`: its encrypted copy if there is one, otherwise the file in clear.
+///
+/// # Arguments
+///
+/// * `path` - The path of the file in clear.
+///
+/// # Errors
+///
+/// Returns a `NotFound` error if neither the file nor its encrypted copy exist, or an error if the encrypted copy
+/// cannot be decrypted (e.g. without `metadata_identity`).
+pub fn read(path: &Path) -> io::Result<Vec<u8>> {
+    let identity = KEYS.read().unwrap().as_ref().and_then(|(_, identity)| identity.clone());
+    match fs::read(sealed_path(path, AGE_SUFFIX)) {
+        Ok(encrypted) => return decrypt_age(identity.as_deref(), &encrypted),
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        Err(_) => {}
+    }
+    match fs::read(sealed_path(path, GPG_SUFFIX)) {
+        Ok(encrypted) => return run_gpg(&["--decrypt"], &encrypted),
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        Err(_) => {}
+    }
+    fs::read(path)
+}
+
+/// Writes a metadata file, encrypted for `metadata_recipient` if configured (replacing the file in clear, if any).
+///
+/// # Arguments
+///
+/// * `path` - The path of the file in clear.
+/// * `contents` - The contents of the file.
+pub fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let recipient = KEYS.read().unwrap().as_ref().map(|(recipient, _)| recipient.clone());
+    match recipient {
+        Some(recipient) => {
+            fs::write(sealed_path(path, recipient.suffix()), encrypt(&recipient, contents)?)?;
+            // The copy in clear, and the one encrypted with the other tool, would be read instead of this one
+            let other_suffix = if recipient.suffix() == AGE_SUFFIX { GPG_SUFFIX } else { AGE_SUFFIX };
+            for stale in [path.to_path_buf(), sealed_path(path, other_suffix)] {
+                match fs::remove_file(stale) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            Ok(())
+        }
+        None => fs::write(path, contents),
+    }
+}
+
+/// Replaces a file written in clear (e.g. the log of a finished backup) with its encrypted copy,
+/// if `metadata_recipient` is configured.
+///
+/// # Arguments
+///
+/// * `path` - The file to encrypt.
+///
+/// # Returns
+///
+/// The path of the encrypted copy, or `None` if the metadata is not encrypted.
+pub fn seal(path: &Path) -> io::Result<Option<PathBuf>> {
+    let recipient = match KEYS.read().unwrap().as_ref() {
+        Some((recipient, _)) => recipient.clone(),
+        None => return Ok(None),
+    };
+    let sealed = sealed_path(path, recipient.suffix());
+    fs::write(&sealed, encrypt(&recipient, &fs::read(path)?)?)?;
+    fs::remove_file(path)?;
+    Ok(Some(sealed))
+}
@@ -0,0 +1,164 @@
+use crate::notification_popup::{show_popup, NotificationType};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Kind of the network connection of the machine.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionType {
+    /// A wired connection
+    Ethernet,
+    /// A wireless connection
+    Wifi,
+    /// A connection billed by the amount of data (e.g. a mobile hotspot), whatever its medium
+    Metered,
+}
+
+/// What to do when the destination is on a network share and the machine uses a given kind of connection.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NetworkPolicy {
+    /// Maximum throughput of the backup, in megabits per second; if not set, there is no limit.
+    #[serde(default)]
+    pub max_mbps: Option<u32>,
+    /// `true` to wait for a different connection before starting the backup.
+    #[serde(default)]
+    pub defer: bool,
+}
+
+/// Time between two checks of the connection while the backup is deferred.
+const DEFER_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// State of the throughput cap: the cap (bytes per second), when the counting started and the bytes written since then.
+struct Throttle {
+    bytes_per_sec: u64,
+    start: Instant,
+    bytes: u64,
+}
+
+/// Throughput cap of the backup, if any.
+static THROTTLE: Mutex<Option<Throttle>> = Mutex::new(None);
+
+/// Sets the throughput cap of the backup.
+///
+/// # Arguments
+///
+/// * `max_mbps` - The maximum throughput in megabits per second, or `None` to remove the cap.
+pub fn set_throughput_cap(max_mbps: Option<u32>) {
+    *THROTTLE.lock().unwrap() = max_mbps.map(|mbps| Throttle {
+        bytes_per_sec: (mbps as u64 * 1_000_000 / 8).max(1),
+        start: Instant::now(),
+        bytes: 0,
+    });
+}
+
+/// Returns whether a throughput cap is set.
+pub fn is_throttled() -> bool {
+    THROTTLE.lock().unwrap().is_some()
+}
+
+/// Accounts some written bytes and waits as long as needed to keep the throughput under the cap (if any).
+///
+/// # Arguments
+///
+/// * `bytes` - The bytes just written.
+pub async fn throttle(bytes: usize) {
+    let delay = {
+        let mut throttle = THROTTLE.lock().unwrap();
+        match throttle.as_mut() {
+            Some(throttle) => {
+                throttle.bytes += bytes as u64;
+                let expected = Duration::from_secs_f64(throttle.bytes as f64 / throttle.bytes_per_sec as f64);
+                expected.saturating_sub(throttle.start.elapsed())
+            }
+            None => Duration::ZERO,
+        }
+    };
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Applies the policy configured for the current connection before a backup towards a network share:
+/// the backup is deferred until the connection changes, and its throughput is capped.
+/// If the connection cannot be detected, no policy is applied.
+///
+/// # Arguments
+///
+/// * `policies` - The policy of each kind of connection.
+pub async fn apply_network_policy(policies: &HashMap<ConnectionType, NetworkPolicy>) {
+    let is_deferred = |connection: Option<ConnectionType>| {
+        connection.and_then(|c| policies.get(&c)).is_some_and(|policy| policy.defer)
+    };
+
+    let mut connection = connection_type();
+    if is_deferred(connection) {
+        show_popup(NotificationType::GenericError, Some(format!(
+            "The backup will start when the machine is no longer on a {:?} connection", connection.unwrap_or(ConnectionType::Metered)
+        )));
+        while is_deferred(connection) {
+            tokio::time::sleep(DEFER_CHECK_INTERVAL).await;
+            connection = connection_type();
+        }
+    }
+
+    set_throughput_cap(connection.and_then(|c| policies.get(&c)).and_then(|policy| policy.max_mbps));
+}
+
+/// Returns the kind of the connection used to reach the network (metered first, then wireless or wired),
+/// or `None` if it cannot be detected.
+#[cfg(target_os = "linux")]
+pub fn connection_type() -> Option<ConnectionType> {
+    use std::fs;
+    use std::path::Path;
+    use std::process::Command;
+
+    // The interface of the default route
+    let routes = fs::read_to_string("/proc/net/route").ok()?;
+    let interface = routes.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        (fields.get(1) == Some(&"00000000")).then(|| fields[0].to_string())
+    })?;
+
+    // NetworkManager knows whether the connection is metered ("yes" or "yes (guessed)")
+    let metered = Command::new("nmcli")
+        .args(["-g", "GENERAL.METERED", "device", "show", &interface])
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).starts_with("yes"));
+
+    let wireless = Path::new("/sys/class/net").join(&interface).join("wireless").exists();
+    Some(match (metered, wireless) {
+        (true, _) => ConnectionType::Metered,
+        (false, true) => ConnectionType::Wifi,
+        (false, false) => ConnectionType::Ethernet,
+    })
+}
+
+/// Returns the kind of the connection used to reach the network (metered first, then wireless or wired),
+/// or `None` if it cannot be detected.
+#[cfg(target_os = "windows")]
+pub fn connection_type() -> Option<ConnectionType> {
+    use std::process::Command;
+
+    // The connection profile of Windows tells both whether it is wireless and its cost
+    let script = "$p = [Windows.Networking.Connectivity.NetworkInformation,Windows.Networking.Connectivity,ContentType=WindowsRuntime]::GetInternetConnectionProfile(); \
+                  if ($p) { \"$($p.IsWlanConnectionProfile) $($p.GetConnectionCost().NetworkCostType)\" }";
+    let output = Command::new("powershell").args(["-NoProfile", "-NonInteractive", "-Command", script]).output().ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let mut fields = text.split_whitespace();
+    let wireless = fields.next()?.eq_ignore_ascii_case("true");
+    let cost = fields.next()?;
+
+    Some(match (cost != "Unrestricted" && cost != "Unknown", wireless) {
+        (true, _) => ConnectionType::Metered,
+        (false, true) => ConnectionType::Wifi,
+        (false, false) => ConnectionType::Ethernet,
+    })
+}
+
+/// The connection cannot be detected on the other systems.
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+pub fn connection_type() -> Option<ConnectionType> {
+    None
+}
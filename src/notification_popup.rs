@@ -1,10 +1,19 @@
 #[cfg(target_os = "windows")]
+use crate::accessibility;
+use crate::beeper::{self, SoundEvent};
+#[cfg(target_os = "windows")]
 use native_dialog::{MessageDialog, MessageType};
+use serde::{Deserialize, Serialize};
 use std::cmp::PartialEq;
+use std::collections::HashMap;
 #[cfg(target_os = "windows")]
 use std::ffi::CStr;
 #[cfg(target_os = "windows")]
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
+use std::time::{Duration, Instant};
 #[cfg(target_os = "windows")]
 use winapi::shared::minwindef::{BOOL, LPARAM};
 #[cfg(target_os = "windows")]
@@ -12,54 +21,435 @@ use winapi::shared::windef::HWND;
 #[cfg(target_os = "windows")]
 use winapi::um::winuser::{EnumChildWindows, SendMessageA, BM_CLICK};
 #[cfg(target_os = "windows")]
-use winapi::um::winuser::{EnumWindows, GetClassNameA, GetWindowTextA, IsWindowVisible};
+use winapi::um::processthreadsapi::GetCurrentThreadId;
+#[cfg(target_os = "windows")]
+use winapi::um::winuser::{EnumThreadWindows, GetClassNameA, GetWindowTextA, IsWindowVisible};
 
 
 /// Enum representing different types of notifications.
-#[derive(PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
 pub enum NotificationType {
     FirstStepDone,
     FirstStepDoneBC,
+    /// The gesture has been confirmed, the Ctrl+Alt+B hold is still needed
+    SecondFactorNeeded,
     BackupCanceled,
     BackupStarted,
     BackupDone,
+    /// A backup interrupted by the end of the process can be resumed
+    ResumeAvailable,
+    /// The state of the application, asked with the hotkey
+    QuickStatus,
+    /// A message that is not an error, e.g. the destination available again
+    Info,
     GenericError,
     ConfigError,
 }
 
-/// Shows a popup notification based on the notification type and an optional message.
+/// How a type of notification is shown to the user.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationMode {
+    /// A popup window (the default)
+    Popup,
+    /// A system notification (toast) that does not steal the focus
+    Toast,
+    /// Nothing is shown
+    Silent,
+    /// Only a beep is emitted
+    BeepOnly,
+}
+
+/// Mode of the notification types configured by the user; the missing ones are shown as popups.
+static NOTIFICATION_MODES: RwLock<Option<HashMap<NotificationType, NotificationMode>>> = RwLock::new(None);
+
+/// Sets how each type of notification is shown.
+///
+/// # Arguments
+///
+/// * `modes` - The mode of each notification type; the missing ones are shown as popups.
+pub fn set_notification_modes(modes: HashMap<NotificationType, NotificationMode>) {
+    *NOTIFICATION_MODES.write().unwrap() = Some(modes);
+}
+
+/// Titles of the popups configured by the user, by notification type; the missing ones use `DEFAULT_TITLE_PREFIX`.
+static POPUP_TITLES: RwLock<Option<HashMap<NotificationType, String>>> = RwLock::new(None);
+
+/// Beginning of the default titles of the popups, followed by the kind of popup (e.g. `BackupDone`).
+#[cfg(target_os = "windows")]
+const DEFAULT_TITLE_PREFIX: &str = "Backup di Emergenza - ";
+
+/// Sets the titles of the popups and of the system notifications (e.g. translated). The popups are found by the thread showing them,
+/// not by their title, so any title can be used, even the same one for all of them.
+///
+/// # Arguments
+///
+/// * `titles` - The title of each notification type; the missing ones use the default titles.
+pub fn set_popup_titles(titles: HashMap<NotificationType, String>) {
+    *POPUP_TITLES.write().unwrap() = Some(titles);
+}
+
+/// How long the popups and the system notifications stay on screen (`popup_duration_secs`); `None` until the user closes them.
+static POPUP_DURATION: RwLock<Option<Duration>> = RwLock::new(Some(Duration::from_secs(10)));
+
+/// Sets how long the popups and the system notifications stay on screen.
+///
+/// # Arguments
+///
+/// * `duration` - The time on screen, or `None` to keep them until the user closes them.
+pub fn set_popup_duration(duration: Option<Duration>) {
+    *POPUP_DURATION.write().unwrap() = duration;
+}
+
+/// Notifications not delivered yet: the popups on screen and the sinks still sending one.
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+/// A notification being delivered, counted in `IN_FLIGHT` until it is dropped.
+struct InFlight;
+
+impl InFlight {
+    fn start() -> InFlight {
+        IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+        InFlight
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Waits until the notifications sent so far have been delivered by all the sinks and their popups closed,
+/// by the user or at the end of their duration.
+pub fn wait_for_notifications() {
+    while IN_FLIGHT.load(Ordering::SeqCst) > 0 {
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Type of the last notification shown, to know whether it can still be updated.
+static LAST_NOTIFICATION: Mutex<Option<NotificationType>> = Mutex::new(None);
+
+/// Returns how the given type of notification has to be shown.
+fn notification_mode(notification_type: NotificationType) -> NotificationMode {
+    NOTIFICATION_MODES.read().unwrap()
+        .as_ref()
+        .and_then(|modes| modes.get(&notification_type).copied())
+        .unwrap_or(NotificationMode::Popup)
+}
+
+/// An output of the notifications. The popups of the application (`PopupSink`) are always the first one;
+/// other outputs (e.g. a company chat or a ticketing system) are added with `register_sink`,
+/// without changing this module.
+pub trait NotificationSink: Send + Sync {
+    /// Shows or sends a notification.
+    ///
+    /// # Arguments
+    ///
+    /// * `notification_type` - The type of notification.
+    /// * `text` - The text of the notification, including the message if any.
+    /// * `msg` - The optional message of the notification (e.g. the error).
+    fn notify(&self, notification_type: NotificationType, text: &str, msg: Option<&str>);
+
+    /// Adds some details to a notification already sent (e.g. the estimate of the backup, after the first step).
+    /// By default the details are ignored.
+    fn update(&self, _notification_type: NotificationType, _details: &str) {}
+}
+
+/// The sinks registered besides the popups.
+static SINKS: RwLock<Vec<Arc<dyn NotificationSink>>> = RwLock::new(Vec::new());
+
+/// Adds an output of the notifications. Each notification is sent to it in a separate thread,
+/// so that a slow sink (e.g. a web service) does not delay the popups.
+///
+/// # Arguments
+///
+/// * `sink` - The output to add.
+pub fn register_sink(sink: Arc<dyn NotificationSink>) {
+    SINKS.write().unwrap().push(sink);
+}
+
+/// The popups of the application: each type of notification is shown in the way configured for it
+/// (popup, toast, beep or nothing).
+pub struct PopupSink;
+
+impl NotificationSink for PopupSink {
+    fn notify(&self, notification_type: NotificationType, _text: &str, msg: Option<&str>) {
+        let msg = msg.map(str::to_string);
+        let mode = notification_mode(notification_type);
+
+        // Errors are always signaled with their own sound, so that they are noticed even without looking at the screen
+        let is_error = matches!(notification_type, NotificationType::GenericError | NotificationType::ConfigError);
+        if is_error && mode != NotificationMode::BeepOnly {
+            beeper::emit_sound(SoundEvent::Error);
+        }
+
+        match mode {
+            NotificationMode::Popup => show_popup_window(notification_type, msg),
+            NotificationMode::Toast => show_toast(notification_type, msg),
+            NotificationMode::BeepOnly => {
+                beeper::emit_sound(sound_event(notification_type));
+            }
+            NotificationMode::Silent => {
+                eprintln!("{}", notification_text(notification_type, msg.as_deref()).0);
+            }
+        }
+    }
+
+    /// No beep is emitted, since the user has already been notified.
+    fn update(&self, notification_type: NotificationType, details: &str) {
+        let details = details.to_string();
+        match notification_mode(notification_type) {
+            NotificationMode::Popup => show_popup_window(notification_type, Some(details)),
+            NotificationMode::Toast => show_toast(notification_type, Some(details)),
+            NotificationMode::BeepOnly | NotificationMode::Silent => {
+                eprintln!("{}", details);
+            }
+        }
+    }
+}
+
+/// Errors similar to one already shown within this time are not shown, but counted in a single notification
+/// at the end of the time.
+const COALESCE_WINDOW: Duration = Duration::from_secs(10);
+
+/// The errors recently shown, with the similar ones not shown since then.
+struct CoalescedError {
+    key: String,
+    shown_at: Instant,
+    suppressed: usize,
+    /// The last error not shown, as an example in the summary.
+    last: String,
+}
+
+static COALESCED: Mutex<Vec<CoalescedError>> = Mutex::new(Vec::new());
+
+/// Returns what makes two errors similar: their text without the quoted parts (e.g. the paths) and the numbers.
+fn similarity_key(msg: &str) -> String {
+    let mut key = String::new();
+    let mut quoted = false;
+    for c in msg.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            _ if quoted || c.is_ascii_digit() => {}
+            _ => key.push(c),
+        }
+    }
+    key
+}
+
+/// Returns whether an error has to be shown now: the errors similar to one shown less than `COALESCE_WINDOW` ago
+/// are counted instead, and summarized in one notification when the time is over.
+fn coalesce_error(msg: &str) -> bool {
+    let key = similarity_key(msg);
+    let mut coalesced = COALESCED.lock().unwrap();
+    coalesced.retain(|error| error.shown_at.elapsed() < COALESCE_WINDOW || error.suppressed > 0);
+    match coalesced.iter_mut().find(|error| error.key == key) {
+        Some(error) => {
+            error.suppressed += 1;
+            error.last = msg.to_string();
+            if error.suppressed == 1 {
+                let (key, wait) = (key.clone(), COALESCE_WINDOW.saturating_sub(error.shown_at.elapsed()));
+                let in_flight = InFlight::start();
+                thread::spawn(move || {
+                    thread::sleep(wait);
+                    show_summary(&key);
+                    drop(in_flight);
+                });
+            }
+            false
+        }
+        None => {
+            coalesced.push(CoalescedError { key, shown_at: Instant::now(), suppressed: 0, last: String::new() });
+            true
+        }
+    }
+}
+
+/// Shows how many similar errors have not been shown, starting a new time for them.
+fn show_summary(key: &str) {
+    let summary = {
+        let mut coalesced = COALESCED.lock().unwrap();
+        let error = match coalesced.iter_mut().find(|error| error.key == key) {
+            Some(error) => error,
+            None => return,
+        };
+        let summary = format!("{} similar errors, see the report. The last one:\n{}", error.suppressed, error.last);
+        error.shown_at = Instant::now();
+        error.suppressed = 0;
+        summary
+    };
+    deliver(NotificationType::GenericError, Some(summary));
+}
+
+/// Shows a notification based on the notification type and an optional message, with the popups
+/// and with the other registered sinks. The errors similar to a recent one are coalesced, so that
+/// a stream of them (e.g. one per file) does not flood the screen.
 ///
 /// # Arguments
 ///
 /// * `notification_type` - The type of notification to show.
 /// * `msg` - An optional message to display in the popup.
-#[cfg(target_os = "windows")]
 pub fn show_popup(notification_type: NotificationType, msg: Option<String>) {
-    close_related_popups(notification_type);
-    show_notification_popup(notification_type, msg);
+    if let (NotificationType::GenericError, Some(text)) = (notification_type, &msg) {
+        if !coalesce_error(text) {
+            return;
+        }
+    }
+    deliver(notification_type, msg);
 }
 
-#[cfg(not(target_os = "windows"))]
-pub fn show_popup(notification_type: NotificationType, msg: Option<String>) {
-    use notify_rust::Notification;
+/// Sends a notification to the popups and to the other registered sinks.
+fn deliver(notification_type: NotificationType, msg: Option<String>) {
+    *LAST_NOTIFICATION.lock().unwrap() = Some(notification_type);
+    let text = notification_text(notification_type, msg.as_deref()).0;
+    PopupSink.notify(notification_type, &text, msg.as_deref());
+    let sinks = SINKS.read().unwrap().clone();
+    for sink in sinks {
+        let (text, msg, in_flight) = (text.clone(), msg.clone(), InFlight::start());
+        thread::spawn(move || {
+            sink.notify(notification_type, &text, msg.as_deref());
+            drop(in_flight);
+        });
+    }
+}
+
+/// Shows again a notification with more details, but only if it is still the last one shown
+/// (e.g. the estimate of the backup, as long as the user has not confirmed or canceled it).
+///
+/// # Arguments
+///
+/// * `notification_type` - The type of notification to update.
+/// * `details` - The details to add to the notification.
+pub fn update_popup(notification_type: NotificationType, details: String) {
+    if *LAST_NOTIFICATION.lock().unwrap() != Some(notification_type) {
+        return;
+    }
+    PopupSink.update(notification_type, &details);
+    let sinks = SINKS.read().unwrap().clone();
+    for sink in sinks {
+        let (details, in_flight) = (details.clone(), InFlight::start());
+        thread::spawn(move || {
+            sink.update(notification_type, &details);
+            drop(in_flight);
+        });
+    }
+}
 
-    let n = match notification_type {
+/// Returns the sound event corresponding to a type of notification.
+fn sound_event(notification_type: NotificationType) -> SoundEvent {
+    match notification_type {
+        NotificationType::FirstStepDone | NotificationType::FirstStepDoneBC | NotificationType::SecondFactorNeeded => SoundEvent::Armed,
+        NotificationType::BackupStarted => SoundEvent::Started,
+        NotificationType::BackupDone => SoundEvent::Done,
+        NotificationType::ResumeAvailable | NotificationType::QuickStatus | NotificationType::Info => SoundEvent::Armed,
+        NotificationType::BackupCanceled => SoundEvent::Canceled,
+        NotificationType::GenericError | NotificationType::ConfigError => SoundEvent::Error,
+    }
+}
+
+/// Returns the text and the icon name of a notification.
+fn notification_text(notification_type: NotificationType, msg: Option<&str>) -> (String, &'static str) {
+    let (text, icon) = match notification_type {
         NotificationType::BackupDone => ("Backup done", "face-smile"),
         NotificationType::BackupStarted => ("Backup started", "dialog-information"),
         NotificationType::BackupCanceled => ("Backup canceled", "dialog-warning"),
+        NotificationType::ResumeAvailable => (msg.unwrap_or("An interrupted backup can be resumed"), "dialog-warning"),
+        NotificationType::QuickStatus => (msg.unwrap_or("Status unknown"), "dialog-information"),
+        NotificationType::Info => (msg.unwrap_or_default(), "dialog-information"),
         NotificationType::FirstStepDoneBC => ("Emergency backup software was activated. By making 3 consecutive quick clicks:\n- left clicks you will confirm\n- right clicks you will cancel", "dialog-information"),
         NotificationType::FirstStepDone => ("Emergency backup software was activated. By drawing a:\n- clockwise rectangle you will confirm\n- counterclockwise rectangle you will cancel", "dialog-information"),
-        _ => (msg.as_deref().unwrap_or("An error occurred"), "dialog-error"),
+        NotificationType::SecondFactorNeeded => ("Gesture confirmed. Hold Ctrl+Alt+B for 5 seconds to start the backup", "dialog-information"),
+        _ => (msg.unwrap_or("An error occurred"), "dialog-error"),
     };
+    match (notification_type, msg) {
+        // The first step can carry the estimate of the backup
+        (NotificationType::FirstStepDone | NotificationType::FirstStepDoneBC, Some(details)) => (format!("{}\n\n{}", text, details), icon),
+        // The summary of the backup, e.g. the state of the replicas
+        (NotificationType::BackupDone, Some(details)) => (format!("{}\n\n{}", text, details), icon),
+        _ => (text.to_string(), icon),
+    }
+}
+
+/// Shows a system notification (toast).
+///
+/// # Arguments
+///
+/// * `notification_type` - The type of notification to show.
+/// * `msg` - An optional message to display in the notification.
+fn show_toast(notification_type: NotificationType, msg: Option<String>) {
+    use notify_rust::{Notification, Timeout};
+
+    let (text, icon) = notification_text(notification_type, msg.as_deref());
+    let summary = POPUP_TITLES.read().unwrap()
+        .as_ref()
+        .and_then(|titles| titles.get(&notification_type).cloned())
+        .unwrap_or_else(|| "Emergency backup".to_string());
 
-    Notification::new()
-        .summary("Emergency backup")
-        .body(n.0)
-        .icon(n.1)
+    let timeout = match *POPUP_DURATION.read().unwrap() {
+        Some(duration) => Timeout::Milliseconds(duration.as_millis() as u32),
+        None => Timeout::Never,
+    };
+    if let Err(e) = Notification::new()
+        .summary(&summary)
+        .body(&text)
+        .icon(icon)
+        .timeout(timeout)
         .show()
-        .unwrap();
+    {
+        eprintln!("Unable to show the notification: {}", e);
+    }
 }
 
+/// Shows a popup notification based on the notification type and an optional message.
+///
+/// # Arguments
+///
+/// * `notification_type` - The type of notification to show.
+/// * `msg` - An optional message to display in the popup.
+#[cfg(target_os = "windows")]
+fn show_popup_window(notification_type: NotificationType, msg: Option<String>) {
+    close_related_popups(notification_type);
+    let id = show_notification_popup(notification_type, msg);
+    if accessibility::large_text() {
+        thread::spawn(move || enlarge_popup(id));
+    }
+    if let Some(duration) = *POPUP_DURATION.read().unwrap() {
+        thread::spawn(move || {
+            thread::sleep(duration);
+            let thread = OPEN_POPUPS.lock().unwrap().iter().find(|popup| popup.id == id).map(|popup| popup.thread);
+            if let Some(thread) = thread {
+                close_popup(thread);
+            }
+        });
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn show_popup_window(notification_type: NotificationType, msg: Option<String>) {
+    // Outside Windows the popups are system notifications
+    show_toast(notification_type, msg);
+}
+
+/// A popup on screen, from when its thread starts showing it until it is closed.
+#[cfg(target_os = "windows")]
+struct OpenPopup {
+    /// Identity of the popup, unique for the whole run.
+    id: u64,
+    kind: &'static str,
+    /// The thread showing the popup, which owns its window.
+    thread: u32,
+}
+
+/// The popups on screen, tracked by the notifier itself so that they can be found whatever their title.
+#[cfg(target_os = "windows")]
+static OPEN_POPUPS: Mutex<Vec<OpenPopup>> = Mutex::new(Vec::new());
+
+/// Identity of the next popup.
+#[cfg(target_os = "windows")]
+static NEXT_POPUP_ID: AtomicU64 = AtomicU64::new(0);
+
 /// Closes related popups based on the notification type.
 ///
 /// # Arguments
@@ -67,17 +457,23 @@ pub fn show_popup(notification_type: NotificationType, msg: Option<String>) {
 /// * `notification_type` - The type of notification to handle.
 #[cfg(target_os = "windows")]
 fn close_related_popups(notification_type: NotificationType) {
-    match notification_type {
-        NotificationType::BackupStarted | NotificationType::BackupCanceled => {
-            close_popup("Backup di Emergenza - FirstStepDone");
-        }
-        NotificationType::BackupDone => {
-            close_popup("Backup di Emergenza - BackupStarted");
-        }
-        NotificationType::FirstStepDone | NotificationType::FirstStepDoneBC => {
-            close_popup("Backup di Emergenza - BackupCanceled");
-        }
-        _ => {}
+    let kinds: &[&str] = match notification_type {
+        NotificationType::BackupStarted | NotificationType::BackupCanceled => &["FirstStepDone", "SecondFactorNeeded", "ResumeAvailable"],
+        NotificationType::BackupDone => &["BackupStarted"],
+        // The first step is shown again when the estimate of the backup is known
+        NotificationType::FirstStepDone | NotificationType::FirstStepDoneBC => &["BackupCanceled", "FirstStepDone"],
+        NotificationType::SecondFactorNeeded => &["FirstStepDone"],
+        // Only the latest status is shown
+        NotificationType::QuickStatus => &["QuickStatus"],
+        _ => &[],
+    };
+    let threads: Vec<u32> = OPEN_POPUPS.lock().unwrap()
+        .iter()
+        .filter(|popup| kinds.contains(&popup.kind))
+        .map(|popup| popup.thread)
+        .collect();
+    for thread in threads {
+        close_popup(thread);
     }
 }
 
@@ -87,42 +483,165 @@ fn close_related_popups(notification_type: NotificationType) {
 ///
 /// * `notification_type` - The type of notification to show.
 /// * `msg` - An optional message to display in the popup.
+///
+/// # Returns
+///
+/// The identity of the popup.
 #[cfg(target_os = "windows")]
-fn show_notification_popup(notification_type: NotificationType, msg: Option<String>) {
+fn show_notification_popup(notification_type: NotificationType, msg: Option<String>) -> u64 {
+    let id = NEXT_POPUP_ID.fetch_add(1, Ordering::SeqCst);
+    // Counted from now, so that the process waits for the popup even before it is on screen
+    let in_flight = InFlight::start();
     thread::spawn(move || {
+        let kind = popup_kind(notification_type);
+        let thread = unsafe { GetCurrentThreadId() };
+        OPEN_POPUPS.lock().unwrap().push(OpenPopup { id, kind, thread });
+        let title = popup_title(notification_type);
+        // The estimate of the backup (first step) or its summary (backup done), if any
+        let estimate = msg.as_ref().map(|details| format!("\n\n  {}", details)).unwrap_or_default();
         match notification_type {
             NotificationType::FirstStepDone => show_popup_without_btn(
                 MessageType::Warning,
-                "FirstStepDone",
-                "  Emergency backup software was activated. By drawing a:\n  - clockwise rectangle you will confirm\n  - counterclockwise rectangle you will cancel",
+                &title,
+                &format!("  Emergency backup software was activated. By drawing a:\n  - clockwise rectangle you will confirm\n  - counterclockwise rectangle you will cancel{}", estimate),
             ),
             NotificationType::FirstStepDoneBC => show_popup_without_btn(
                 MessageType::Warning,
-                "FirstStepDone",
-                "  Emergency backup software was activated. By making 3 consecutive quick clicks:\n  - left clicks you will confirm\n  - right clicks you will cancel",
+                &title,
+                &format!("  Emergency backup software was activated. By making 3 consecutive quick clicks:\n  - left clicks you will confirm\n  - right clicks you will cancel{}", estimate),
+            ),
+            NotificationType::SecondFactorNeeded => show_popup_without_btn(
+                MessageType::Warning,
+                &title,
+                "  Gesture confirmed. Hold Ctrl+Alt+B for 5 seconds to start the backup",
             ),
             NotificationType::BackupDone => show_popup_without_btn(
                 MessageType::Info,
-                "BackupDone",
-                "  Backup done",
+                &title,
+                &format!("  Backup done{}", estimate),
             ),
             NotificationType::BackupStarted => show_popup_without_btn(
                 MessageType::Info,
-                "BackupStarted",
+                &title,
                 "  Backup started",
             ),
             NotificationType::BackupCanceled => show_popup_without_btn(
                 MessageType::Info,
-                "BackupCanceled",
+                &title,
                 "  Backup canceled",
             ),
+            NotificationType::ResumeAvailable => show_popup_without_btn(
+                MessageType::Warning,
+                &title,
+                &format!("  {}", msg.unwrap_or_default()),
+            ),
+            NotificationType::QuickStatus => show_popup_without_btn(
+                MessageType::Info,
+                &title,
+                &format!("  {}", msg.unwrap_or_default().replace('\n', "\n  ")),
+            ),
+            NotificationType::Info => show_popup_without_btn(
+                MessageType::Info,
+                &title,
+                &format!("  {}", msg.unwrap_or_default()),
+            ),
             _ => show_popup_without_btn(
                 MessageType::Error,
-                "Error",
+                &title,
                 &msg.unwrap(),
             )
         }
+        OPEN_POPUPS.lock().unwrap().retain(|popup| popup.id != id);
+        drop(in_flight);
+    });
+    id
+}
+
+/// Returns the kind of the popup of a type of notification, which decides the popups it replaces.
+#[cfg(target_os = "windows")]
+fn popup_kind(notification_type: NotificationType) -> &'static str {
+    match notification_type {
+        NotificationType::FirstStepDone | NotificationType::FirstStepDoneBC => "FirstStepDone",
+        NotificationType::SecondFactorNeeded => "SecondFactorNeeded",
+        NotificationType::BackupDone => "BackupDone",
+        NotificationType::BackupStarted => "BackupStarted",
+        NotificationType::BackupCanceled => "BackupCanceled",
+        NotificationType::ResumeAvailable => "ResumeAvailable",
+        NotificationType::QuickStatus => "QuickStatus",
+        NotificationType::Info => "Info",
+        NotificationType::GenericError | NotificationType::ConfigError => "Error",
+    }
+}
+
+/// Returns the title of the popup of a type of notification: the configured one, or the default one.
+#[cfg(target_os = "windows")]
+fn popup_title(notification_type: NotificationType) -> String {
+    POPUP_TITLES.read().unwrap()
+        .as_ref()
+        .and_then(|titles| titles.get(&notification_type).cloned())
+        .unwrap_or_else(|| format!("{}{}", DEFAULT_TITLE_PREFIX, popup_kind(notification_type)))
+}
+
+/// Enlarges the text of a popup once it is shown (`large_notifications`): the message dialogs of the system
+/// cannot be configured, so the font of their text is replaced and the dialog is resized to fit it.
+/// NOTE: this is a `best effort` approach, like `close_popup`.
+///
+/// # Arguments
+///
+/// * `id` - The identity of the popup to enlarge.
+#[cfg(target_os = "windows")]
+fn enlarge_popup(id: u64) {
+    use std::ptr;
+    use winapi::shared::minwindef::WPARAM;
+    use winapi::shared::windef::{POINT, RECT};
+    use winapi::um::wingdi::{CreateFontA, CLEARTYPE_QUALITY, DEFAULT_CHARSET, FW_NORMAL};
+    use winapi::um::winuser::{EnumChildWindows, GetDpiForWindow, GetWindowRect, MapWindowPoints, SetWindowPos, SWP_NOZORDER, WM_SETFONT};
+
+    // The popup is created by another thread: wait for it to appear
+    let popup = (0..20).find_map(|_| {
+        let owner = OPEN_POPUPS.lock().unwrap().iter().find(|popup| popup.id == id).map(|popup| popup.thread);
+        let window = owner.and_then(get_popup_window);
+        if window.is_none() {
+            thread::sleep(Duration::from_millis(100));
+        }
+        window
     });
+    let hwnd = match popup {
+        Some((hwnd, _, _)) => hwnd,
+        None => return,
+    };
+    let scale = accessibility::LARGE_TEXT_SCALE;
+    let mut children: Vec<(HWND, String, String)> = Vec::new();
+    unsafe {
+        EnumChildWindows(hwnd, Some(enum_child_windows_proc), &mut children as *mut _ as LPARAM);
+        // The text of the message boxes is 9 points Segoe UI (12 pixels at 96 DPI); the font lives as long as the process
+        let height = (12.0 * scale * GetDpiForWindow(hwnd) as f32 / 96.0) as i32;
+        let face = b"Segoe UI\0";
+        let font = CreateFontA(-height, 0, 0, 0, FW_NORMAL, 0, 0, 0, DEFAULT_CHARSET, 0, 0, CLEARTYPE_QUALITY, 0, face.as_ptr() as *const i8);
+
+        for (child, _, class_name) in children {
+            // The position of the control inside the dialog
+            let mut rect: RECT = std::mem::zeroed();
+            GetWindowRect(child, &mut rect);
+            MapWindowPoints(ptr::null_mut(), hwnd, &mut rect as *mut RECT as *mut POINT, 2);
+            let (width, height) = (rect.right - rect.left, rect.bottom - rect.top);
+            if class_name == "Static" {
+                // The text: larger font, larger area
+                SendMessageA(child, WM_SETFONT, font as WPARAM, 1);
+                SetWindowPos(child, ptr::null_mut(), rect.left, rect.top, (width as f32 * scale) as i32, (height as f32 * scale) as i32, SWP_NOZORDER);
+            } else {
+                // The buttons: same size, moved with the edges of the dialog
+                SetWindowPos(child, ptr::null_mut(), (rect.left as f32 * scale) as i32, (rect.top as f32 * scale) as i32, width, height, SWP_NOZORDER);
+            }
+        }
+
+        // The dialog grows around its center
+        let mut rect: RECT = std::mem::zeroed();
+        GetWindowRect(hwnd, &mut rect);
+        let (width, height) = (rect.right - rect.left, rect.bottom - rect.top);
+        let (new_width, new_height) = ((width as f32 * scale) as i32, (height as f32 * scale) as i32);
+        SetWindowPos(hwnd, ptr::null_mut(), rect.left - (new_width - width) / 2, rect.top - (new_height - height) / 2, new_width, new_height, SWP_NOZORDER);
+    }
 }
 
 /// Shows a popup without a button.
@@ -136,23 +655,22 @@ fn show_notification_popup(notification_type: NotificationType, msg: Option<Stri
 fn show_popup_without_btn(message_type: MessageType, title: &str, message: &str) {
     MessageDialog::new()
         .set_type(message_type)
-        .set_title(format!("Backup di Emergenza - {}", title).as_ref())
+        .set_title(title)
         .set_text(message)
         .show_alert()
         .unwrap();
 }
 
-/// Closes a popup with the specified title by simulating a button click.
+/// Closes the popup shown by a thread by simulating a button click.
 /// NOTE: this is a `best effort` approach, it may not be possible to close it.
 ///
 /// # Arguments
 ///
-/// * `title` - The title of the popup to close.
-
+/// * `thread` - The thread showing the popup to close.
 #[cfg(target_os = "windows")]
-fn close_popup(title: &str) {
-    // Retrieve the window handle by its title
-    let window = get_window_by_title(title);
+fn close_popup(thread: u32) {
+    // Retrieve the window handle of the popup
+    let window = get_popup_window(thread);
 
     // If the window is found
     if let Some((hwnd_popup, _, _)) = window {
@@ -164,34 +682,34 @@ fn close_popup(title: &str) {
     }
 }
 
-/// Retrieves a window by its title.
+/// Retrieves the popup shown by a thread: the thread shows only that window.
 ///
 /// # Arguments
 ///
-/// * `title` - The title of the window to retrieve.
+/// * `thread` - The thread showing the popup.
 ///
 /// # Returns
 ///
 /// An `Option` containing a tuple with the window handle, title, and class name if found.
 #[cfg(target_os = "windows")]
-fn get_window_by_title(title: &str) -> Option<(HWND, String, String)> {
+fn get_popup_window(thread: u32) -> Option<(HWND, String, String)> {
     // Vector to store the windows found during enumeration
     let mut windows: Vec<(HWND, String, String)> = Vec::new();
 
-    // Enumerate all top-level windows and store their handles, titles, and class names
+    // Enumerate the top-level windows of the thread and store their handles, titles, and class names
     unsafe {
-        // The EnumWindows function is a Windows API function that enumerates all top-level windows
-        // on the screen by passing the handle of each window, in turn,
+        // The EnumThreadWindows function is a Windows API function that enumerates the top-level windows
+        // of a thread by passing the handle of each window, in turn,
         // to an application-defined callback function.
-        EnumWindows(Some(enum_windows_proc), &mut windows as *mut _ as LPARAM);
+        EnumThreadWindows(thread, Some(enum_windows_proc), &mut windows as *mut _ as LPARAM);
     }
 
-    windows.into_iter().find(|x| x.1 == title)
+    windows.into_iter().next()
 }
 
 /// Callback function for enumerating top-level windows.
 /// This function is called by the Windows API for each top-level window found during enumeration.
-/// It retrieves the window's title and class name, and if the window is visible and has a non-empty title,
+/// It retrieves the window's title and class name, and if the window is visible,
 /// it adds the window's handle, title, and class name to the list of windows.
 ///
 /// # Arguments
@@ -223,8 +741,8 @@ extern "system" fn enum_windows_proc(hwnd: HWND, lparam: LPARAM) -> BOOL {
     let title = unsafe { CStr::from_ptr(title.as_ptr()) }.to_str().unwrap_or("");
     let class_name = unsafe { CStr::from_ptr(class_name.as_ptr()) }.to_str().unwrap_or("");
 
-    // Check if the window is visible and has a non-empty title
-    if !title.is_empty() && unsafe { IsWindowVisible(hwnd) } != 0 {
+    // Check if the window is visible (the title can be empty, if configured so)
+    if unsafe { IsWindowVisible(hwnd) } != 0 {
         // Add the window's handle, title, and class name to the list of windows
         let windows = unsafe { &mut *(lparam as *mut Vec<(HWND, String, String)>) };
         windows.push((hwnd, title.to_string(), class_name.to_string()));
@@ -1,12 +1,53 @@
-use crate::beeper;
+use crate::backup;
+use crate::config::SharedConfig;
+#[cfg(target_os = "linux")]
+use crate::config::parse_screen_size;
 use emath::Pos2;
-use group_39::notification_popup;
-use group_39::notification_popup::NotificationType;
-use rdev::{listen, EventType};
+use group_39::events::{self, ActivationPattern, AppEvent};
+use group_39::{input_hook, quick_status, session, supervisor, trigger, two_factor};
+use rdev::EventType;
+use std::collections::HashMap;
 use std::sync::mpsc::channel;
 use std::sync::{Arc, Mutex};
 use std::thread;
 
+/// Aggregate statistics of the gesture attempts, used to tune `tolerance` and `sampling`
+#[derive(Debug, Default, Clone)]
+pub struct GestureStats {
+    pub successes: usize,
+    /// Number of rejected attempts for each validation rule
+    pub rejections: HashMap<&'static str, usize>,
+}
+
+impl GestureStats {
+    fn succeed(&mut self) {
+        self.successes += 1;
+    }
+
+    fn reject(&mut self, rule: &'static str) {
+        *self.rejections.entry(rule).or_insert(0) += 1;
+    }
+
+    /// Total number of attempts, both successful and rejected
+    pub fn attempts(&self) -> usize {
+        self.successes + self.rejections.values().sum::<usize>()
+    }
+
+    /// Formats the statistics as a log entry
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "Gesture statistics: {} attempts, {} succeeded, {} rejected\n",
+            self.attempts(), self.successes, self.attempts() - self.successes
+        );
+        let mut rejections: Vec<_> = self.rejections.iter().collect();
+        rejections.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (rule, count) in rejections {
+            summary.push_str(&format!("\t{}: {}\n", rule, count));
+        }
+        summary
+    }
+}
+
 /// Define a struct to recognize and handle mouse patterns
 pub struct PatternRecognizer {
     path_points: Vec<Pos2>,
@@ -17,7 +58,11 @@ pub struct PatternRecognizer {
     side: i32,
     direction: i32,
     mouse_command_done: bool,
-    movement_threshold: f32
+    movement_threshold: f32,
+    stats: GestureStats,
+    pause_when_locked: bool,
+    pause_in_fullscreen: bool,
+    config: Option<SharedConfig>
 }
 
 /// Implement default initialization for PatternRecognizer
@@ -37,28 +82,44 @@ impl Default for PatternRecognizer {
             side: 0,
             direction: 0,
             mouse_command_done: false,
-            movement_threshold: 4.0     // Soglia di movimento in pixel
+            movement_threshold: 4.0,    // Soglia di movimento in pixel
+            stats: GestureStats::default(),
+            pause_when_locked: false,
+            pause_in_fullscreen: false,
+            config: None
         }
     }
 }
 
 impl PatternRecognizer {
     /// Helper function to clamp a value between min and max
-    fn clamp(x: f32, min: i32, max: i32) -> f32 {
-        if x < min as f32 {
-            min as f32
-        } else if x > max as f32 {
-            max as f32
+    fn clamp(x: f32, min: f32, max: f32) -> f32 {
+        if x < min {
+            min
+        } else if x > max {
+            max
         } else {
             x
         }
     }
 
     /// Initializes the PatternRecognizer and sets up mouse tracking
-    pub fn new() -> Self {
+    ///
+    /// `tolerance` and `sampling` are read from the shared configuration, so they follow its reloads
+    pub fn new(config: SharedConfig) -> Self {
         let mut pr: PatternRecognizer = Default::default();
+        // Used on Linux when the screen cannot be asked to the display server (`screen_size` or `SCREEN_SIZE`)
+        #[cfg(target_os = "linux")]
+        let fallback_size = std::env::var("SCREEN_SIZE").ok()
+            .or_else(|| config.read().unwrap().screen_size.clone())
+            .and_then(|size| parse_screen_size(&size).ok());
+        pr.config = Some(config);
+        pr.refresh_settings();
 
         // Calculate the expected rectangle corners based on screen size
+        #[cfg(target_os = "linux")]
+        let (width, height) = get_screen_size(fallback_size);
+        #[cfg(not(target_os = "linux"))]
         let (width, height) = get_screen_size(); //(1920,1080);
         pr.rectangle_corners = [
             emath::pos2(0.0, 0.0),
@@ -78,34 +139,97 @@ impl PatternRecognizer {
         let tx_clone = Arc::new(Mutex::new(tx)); // Wrap the transmitter in a mutex
         let tx_clone2 = tx_clone.clone(); // Clone the transmitter for use in the listener thread
 
-        // Spawn a thread to listen to mouse events
-        thread::spawn(move || {
-            listen(move |event| {
-                match event.event_type {
-                    EventType::MouseMove { x, y } => {
-                        tx_clone2.lock().unwrap().send((x as f32, y as f32)).ok();
-                    }
-                    _ => {}
+        // Spawn a thread to listen to mouse events, restarted by the watchdog if it stops
+        supervisor::supervise("gesture listener", move || {
+            let tx = tx_clone2.clone();
+            if let Err(e) = input_hook::listen(move |event| {
+                quick_status::handle_event(&event.event_type);
+                two_factor::handle_event(&event.event_type);
+                if let EventType::MouseMove { x, y } = event.event_type {
+                    tx.lock().unwrap().send((x as f32, y as f32)).ok();
                 }
-            }).unwrap();
+            }) {
+                println!("Unable to listen to the mouse: {:?}", e);
+            }
         });
 
         // Spawn another thread to update the mouse position based on received events
         thread::spawn(move || {
             while let Ok((x, y)) = rx.recv() {
                 let mut pos = mouse_pos_clone.lock().unwrap();
-                *pos = Some(emath::pos2(Self::clamp(x, 0, width.try_into().unwrap()), Self::clamp(y, 0, height.try_into().unwrap())));
+                *pos = Some(emath::pos2(Self::clamp(x, 0.0, width as f32), Self::clamp(y, 0.0, height as f32)));
             }
         });
         pr.mouse_pos = mouse_pos;
         pr
     }
 
+    /// Creates a recognizer fed by `simulate_gesture` instead of the mouse, on a virtual screen
+    /// (used by `self-check`, which must work also without a display)
+    pub fn simulated(width: f32, height: f32, tolerance: f32, sampling: f32) -> Self {
+        PatternRecognizer {
+            rectangle_corners: [
+                emath::pos2(0.0, 0.0),
+                emath::pos2(width, 0.0),
+                emath::pos2(width, height),
+                emath::pos2(0.0, height),
+            ],
+            tolerance,
+            sampling,
+            ..Default::default()
+        }
+    }
+
+    /// Draws the rectangle clockwise along the edges of the screen twice, through the same checks of the mouse
+    /// movements: the first one arms the backup and the second one confirms it
+    ///
+    /// Returns whether the backup would start
+    pub fn simulate_gesture(&mut self) -> bool {
+        let corners = self.rectangle_corners;
+        let step = self.sampling * 2.0;
+        for _ in 0..2 {
+            for (from, to) in corners.iter().zip(corners.iter().cycle().skip(1)) {
+                let steps = (from.distance(*to) / step).ceil().max(1.0) as usize;
+                for i in 0..steps {
+                    if self.pattern_recognition(from.lerp(*to, i as f32 / steps as f32)) {
+                        return true;
+                    }
+                }
+            }
+            // Back to the first corner, closing the rectangle
+            if self.pattern_recognition(corners[0]) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Reads the gesture settings from the (possibly reloaded) configuration
+    fn refresh_settings(&mut self) {
+        if let Some(config) = &self.config {
+            let config = config.read().unwrap();
+            self.tolerance = config.gesture_tolerance;
+            self.sampling = config.gesture_sampling;
+            self.pause_when_locked = config.pause_when_locked;
+            self.pause_in_fullscreen = config.pause_in_fullscreen;
+        }
+    }
+
+    /// Returns the statistics of the gesture attempts made so far
+    pub fn stats(&self) -> &GestureStats {
+        &self.stats
+    }
+
     /// Main method to recognize the pattern
     pub fn recognize_pattern(&mut self) {
         let mut prev_mouse_pos: Option<Pos2> = None;
 
         loop {
+            // The backup may also be started without the pattern (e.g. by a power event)
+            if trigger::is_triggered() {
+                return;
+            }
+
             let mouse_pos = {
                 let pos = self.mouse_pos.lock().unwrap();
                 *pos
@@ -114,6 +238,17 @@ impl PatternRecognizer {
             if let Some(pos) = mouse_pos {
                 if let Some(prev_pos) = prev_mouse_pos {
                     if pos.distance(prev_pos) > self.movement_threshold {
+                        // New settings are applied only between two gestures
+                        if self.side == 0 && self.path_points.is_empty() {
+                            self.refresh_settings();
+                        }
+                        // No gesture while the session is locked or remote, or a full-screen app is running
+                        if session::arming_blocked(self.pause_when_locked, self.pause_in_fullscreen) {
+                            self.path_points.clear();
+                            self.side = 0;
+                            prev_mouse_pos = Some(pos);
+                            continue;
+                        }
                         if self.pattern_recognition(pos) {
                             return;
                         }
@@ -139,45 +274,64 @@ impl PatternRecognizer {
             if !self.path_points.is_empty() && !self.is_near(mouse_pos, *self.path_points.last().unwrap(), self.sampling) {
                 self.path_points.push(mouse_pos);
                 if self.path_points.len() > 1000 {
+                    // Only a path still along the top or the left side was an attempt: any other one
+                    // is the mouse in normal use after passing by the corner, and it is not counted
+                    let along_side = self.path_points.iter().all(|point| point.y < self.tolerance)
+                        || self.path_points.iter().all(|point| point.x < self.tolerance);
                     self.path_points.clear();
+                    if along_side {
+                        self.stats.reject("first side: path too long");
+                    }
                 }
             }
 
             if let Some(last_point) = self.path_points.last() {
                 // Check if the path is moving towards the top-right corner
                 if self.is_near(*last_point, self.rectangle_corners[1], self.tolerance) {
-                    let mut invalid_side = false;
+                    let mut invalid_side = None;
                     let mut prev_x = self.path_points[0].x;
 
                     for point in &self.path_points {
                         // Check if the current point's y-coordinate exceeds the tolerance
                         // or if the x-coordinate is less than the previous x-coordinate minus the sampling value (this ensures we do not come back in the path while drawing the rectangle)
-                        if point.y >= self.tolerance || point.x < prev_x - self.sampling {
-                            invalid_side = true;
-                            self.path_points.clear();
+                        if point.y >= self.tolerance {
+                            invalid_side = Some("top side: out of tolerance");
+                        } else if point.x < prev_x - self.sampling {
+                            invalid_side = Some("top side: going backwards");
+                        }
+                        if invalid_side.is_some() {
                             break;
                         }
                         prev_x = point.x;
                     }
-                    if !invalid_side {
+                    if let Some(rule) = invalid_side {
+                        self.path_points.clear();
+                        self.stats.reject(rule);
+                    } else {
                         self.direction = 0; //clockwise
                         self.side = 1;
                     }
                 } else if self.is_near(*last_point, self.rectangle_corners[3], self.tolerance) {
-                    let mut invalid_side = false;
+                    let mut invalid_side = None;
                     let mut prev_y = self.path_points[0].y;
 
                     for point in &self.path_points {
                         // Check if the current point's x-coordinate exceeds the tolerance
                         // or if the y-coordinate is less than the previous y-coordinate minus the sampling value (this ensures we do not come back in the path while drawing the rectangle)
-                        if point.x >= self.tolerance || point.y < prev_y - self.sampling {
-                            invalid_side = true;
-                            self.path_points.clear();
+                        if point.x >= self.tolerance {
+                            invalid_side = Some("left side: out of tolerance");
+                        } else if point.y < prev_y - self.sampling {
+                            invalid_side = Some("left side: going backwards");
+                        }
+                        if invalid_side.is_some() {
                             break;
                         }
                         prev_y = point.y;
                     }
-                    if !invalid_side {
+                    if let Some(rule) = invalid_side {
+                        self.path_points.clear();
+                        self.stats.reject(rule);
+                    } else {
                         self.direction = 1; //counter-clockwise
                         self.side = 1;
                     }
@@ -192,35 +346,43 @@ impl PatternRecognizer {
                 self.mouse_command_done = true;
                 self.path_points.clear();
                 self.side = 0;
-                beeper::emit_beep(true);
-                notification_popup::show_popup(NotificationType::FirstStepDone, None);
+                events::publish(AppEvent::Armed(ActivationPattern::Gesture));
+                if let Some(config) = &self.config {
+                    backup::estimate_backup_in_background(config.read().unwrap().clone(), ActivationPattern::Gesture);
+                }
                 return false;
             }
         } else {
             // Depending on the direction, we confirm or cancel the backup operation
             if self.direction == 0 {
                 if self.check_rectangle_gesture_clockwise(mouse_pos) {
-                    println!("STARTING BACKUP...");
                     self.mouse_command_done = false;
                     self.path_points.clear();
                     self.side = 0;
+                    // In the high-assurance mode the gesture alone is not enough
+                    if two_factor::is_enabled() {
+                        events::publish(AppEvent::SecondFactorNeeded);
+                        if !two_factor::wait_for_hotkey() {
+                            println!("SECOND FACTOR MISSING, CANCELLING OPERATION...");
+                            events::publish(AppEvent::Canceled);
+                            return false;
+                        }
+                    }
+                    println!("STARTING BACKUP...");
                     //todo: opInizioBackup
-                    beeper::emit_beep(true);
-                    notification_popup::show_popup(NotificationType::BackupStarted, None);
+                    events::publish(AppEvent::Started);
                     return true;
                 }
-            } else if self.direction == 1 {
-                if self.check_rectangle_gesture_counterclockwise(mouse_pos) {
+            } else if self.direction == 1
+                && self.check_rectangle_gesture_counterclockwise(mouse_pos) {
                     println!("CANCELLING OPERATION...");
                     self.mouse_command_done = false;
                     self.path_points.clear();
                     self.side = 0;
                     //todo: opCancellata
-                    beeper::emit_beep(false);
-                    notification_popup::show_popup(NotificationType::BackupCanceled, None);
+                    events::publish(AppEvent::Canceled);
                     return false;
                 }
-            }
         }
         false
     }
@@ -231,11 +393,13 @@ impl PatternRecognizer {
     }
 
     /// Check if the path is valid for a given side and update state
-    fn check_path_validity(&mut self, pointer_pos: Pos2, invalid: bool, rect_corner: Pos2, next_side: i32) -> bool {
-        if invalid {
+    /// (`invalid` holds the validation rule that rejected the path, if any)
+    fn check_path_validity(&mut self, pointer_pos: Pos2, invalid: Option<&'static str>, rect_corner: Pos2, next_side: i32) -> bool {
+        if let Some(rule) = invalid {
             self.path_points.clear();
             println!("INVALID PATH");
             self.side = 0;
+            self.stats.reject(rule);
         } else {
             self.path_points.push(pointer_pos);
         }
@@ -245,13 +409,14 @@ impl PatternRecognizer {
         //When I reach the top left corner, I check that the path on the left side is valid
         if let Some(last_point) = self.path_points.last() {
             if self.is_near(*last_point, rect_corner, self.tolerance) {
-                if self.path_points.len() > 0 {
+                if !self.path_points.is_empty() {
                     if next_side != 4 {
                         self.side = next_side;
                     } else {  //Rectangle completed
                         println!("VALID PATH");
                         self.path_points.clear();
                         self.side = 0;
+                        self.stats.succeed();
                         return true;
                     }
                 } else {
@@ -265,11 +430,13 @@ impl PatternRecognizer {
 
     /// Check if the path drawn is a rectangle (clockwise direction)
     fn check_rectangle_gesture_clockwise(&mut self, pointer_pos: Pos2) -> bool {
-        let mut invalid = false;
+        let mut invalid = None;
         if self.side == 1 { //RIGHT
             if !self.path_points.is_empty() && !self.is_near(pointer_pos, *self.path_points.last().unwrap(), self.sampling) {
-                if pointer_pos.x < self.rectangle_corners[1].x - self.tolerance || pointer_pos.y < self.path_points.last().unwrap().y - self.sampling {
-                    invalid = true;
+                if pointer_pos.x < self.rectangle_corners[1].x - self.tolerance {
+                    invalid = Some("right side: out of tolerance");
+                } else if pointer_pos.y < self.path_points.last().unwrap().y - self.sampling {
+                    invalid = Some("right side: going backwards");
                 }
             }
             self.check_path_validity(pointer_pos, invalid, self.rectangle_corners[2], 2);
@@ -277,8 +444,10 @@ impl PatternRecognizer {
 
         if self.side == 2 { //BOTTOM
             if !self.path_points.is_empty() && !self.is_near(pointer_pos, *self.path_points.last().unwrap(), self.sampling) {
-                if pointer_pos.y < self.rectangle_corners[2].y - self.tolerance || pointer_pos.x > self.path_points.last().unwrap().x + self.sampling {
-                    invalid = true;
+                if pointer_pos.y < self.rectangle_corners[2].y - self.tolerance {
+                    invalid = Some("bottom side: out of tolerance");
+                } else if pointer_pos.x > self.path_points.last().unwrap().x + self.sampling {
+                    invalid = Some("bottom side: going backwards");
                 }
             }
 
@@ -287,8 +456,10 @@ impl PatternRecognizer {
 
         if self.side == 3 { //LEFT
             if !self.path_points.is_empty() && !self.is_near(pointer_pos, *self.path_points.last().unwrap(), self.sampling) {
-                if pointer_pos.x > self.tolerance || pointer_pos.y > self.path_points.last().unwrap().y + self.sampling {
-                    invalid = true;
+                if pointer_pos.x > self.tolerance {
+                    invalid = Some("left side: out of tolerance");
+                } else if pointer_pos.y > self.path_points.last().unwrap().y + self.sampling {
+                    invalid = Some("left side: going backwards");
                 }
             }
             return self.check_path_validity(pointer_pos, invalid, self.rectangle_corners[0], 4);
@@ -298,11 +469,13 @@ impl PatternRecognizer {
 
     /// Check if the path drawn is a rectangle (counter-clockwise direction)
     fn check_rectangle_gesture_counterclockwise(&mut self, pointer_pos: Pos2) -> bool {
-        let mut invalid = false;
+        let mut invalid = None;
         if self.side == 1 { //BOTTOM
             if !self.path_points.is_empty() && !self.is_near(pointer_pos, *self.path_points.last().unwrap(), self.sampling) {
-                if pointer_pos.y < self.rectangle_corners[2].y - self.tolerance || pointer_pos.x < self.path_points.last().unwrap().x - self.sampling {
-                    invalid = true;
+                if pointer_pos.y < self.rectangle_corners[2].y - self.tolerance {
+                    invalid = Some("bottom side: out of tolerance");
+                } else if pointer_pos.x < self.path_points.last().unwrap().x - self.sampling {
+                    invalid = Some("bottom side: going backwards");
                 }
             }
             self.check_path_validity(pointer_pos, invalid, self.rectangle_corners[2], 2);
@@ -310,8 +483,10 @@ impl PatternRecognizer {
 
         if self.side == 2 { //RIGHT
             if !self.path_points.is_empty() && !self.is_near(pointer_pos, *self.path_points.last().unwrap(), self.sampling) {
-                if pointer_pos.x < self.rectangle_corners[1].x - self.tolerance || pointer_pos.y > self.path_points.last().unwrap().y + self.sampling {
-                    invalid = true;
+                if pointer_pos.x < self.rectangle_corners[1].x - self.tolerance {
+                    invalid = Some("right side: out of tolerance");
+                } else if pointer_pos.y > self.path_points.last().unwrap().y + self.sampling {
+                    invalid = Some("right side: going backwards");
                 }
             }
             self.check_path_validity(pointer_pos, invalid, self.rectangle_corners[1], 3);
@@ -319,8 +494,10 @@ impl PatternRecognizer {
 
         if self.side == 3 { //TOP
             if !self.path_points.is_empty() && !self.is_near(pointer_pos, *self.path_points.last().unwrap(), self.sampling) {
-                if pointer_pos.y > self.tolerance || pointer_pos.x > self.path_points.last().unwrap().x + self.sampling {
-                    invalid = true;
+                if pointer_pos.y > self.tolerance {
+                    invalid = Some("top side: out of tolerance");
+                } else if pointer_pos.x > self.path_points.last().unwrap().x + self.sampling {
+                    invalid = Some("top side: going backwards");
                 }
             }
             return self.check_path_validity(pointer_pos, invalid, self.rectangle_corners[0], 4);
@@ -332,28 +509,37 @@ impl PatternRecognizer {
 /// Calculating the physical screen dimensions
 #[cfg(target_os = "windows")]
 fn get_screen_size() -> (u32, u32) {
-    // The Windows function GetSystemMetrics may return values smaller than the actual screen size
-    // if the operating system is configured to use display scaling (DPI scaling).
-    // This happens because GetSystemMetrics returns dimensions in logical pixels, not physical pixels.
-    // To obtain the screen size in physical pixels, the DPI scaling factor must be taken into account.
-    // We used the function GetDpiForWindow or GetDpiForSystem to obtain the DPI scaling factor
-    // and then calculate the physical screen size.
-    use winapi::um::winuser::{GetDpiForWindow, GetSystemMetrics, SM_CXSCREEN, SM_CYSCREEN};
-    use winapi::um::winuser::GetDesktopWindow;
-
-    // Get the logical width and height of the screen
-    let width_logical = unsafe { GetSystemMetrics(SM_CXSCREEN) };
-    let height_logical = unsafe { GetSystemMetrics(SM_CYSCREEN) };
-    // Get the handle to the desktop window
-    let hwnd = unsafe { GetDesktopWindow() };
-    // Get the DPI for the desktop window
-    let dpi = unsafe { GetDpiForWindow(hwnd) };
-
-    // Calculate the physical width and height of the screen
-    let width_physical = (width_logical as f32 * dpi as f32 / 96.0) as u32;
-    let height_physical = (height_logical as f32 * dpi as f32 / 96.0) as u32;
-
-    (width_physical, height_physical)
+    // With display scaling (DPI scaling) the coordinates of a process that is not DPI aware are logical pixels,
+    // scaled by Windows with the DPI of the system, which is wrong for the monitors with another scaling.
+    // The process is made per-monitor DPI aware, so that the mouse positions and the size of the monitor are
+    // in physical pixels on every monitor; the DPI of the monitor is used only if this is not supported.
+    use std::mem;
+    use winapi::shared::windef::{DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2, DPI_AWARENESS_PER_MONITOR_AWARE, POINT};
+    use winapi::um::shellscalingapi::{GetDpiForMonitor, MDT_EFFECTIVE_DPI};
+    use winapi::um::winuser::{GetAwarenessFromDpiAwarenessContext, GetMonitorInfoW, GetThreadDpiAwarenessContext};
+    use winapi::um::winuser::{MonitorFromPoint, SetProcessDpiAwarenessContext, MONITORINFO, MONITOR_DEFAULTTOPRIMARY};
+
+    unsafe {
+        // It fails if the awareness has already been set (e.g. by a previous call), which is checked below
+        SetProcessDpiAwarenessContext(DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2);
+        let per_monitor_aware = GetAwarenessFromDpiAwarenessContext(GetThreadDpiAwarenessContext()) == DPI_AWARENESS_PER_MONITOR_AWARE;
+
+        // The gesture is drawn along the borders of the primary monitor, whose top left corner is (0, 0)
+        let monitor = MonitorFromPoint(POINT { x: 0, y: 0 }, MONITOR_DEFAULTTOPRIMARY);
+        let mut info: MONITORINFO = mem::zeroed();
+        info.cbSize = mem::size_of::<MONITORINFO>() as u32;
+        GetMonitorInfoW(monitor, &mut info);
+        let width = (info.rcMonitor.right - info.rcMonitor.left) as f32;
+        let height = (info.rcMonitor.bottom - info.rcMonitor.top) as f32;
+        if per_monitor_aware {
+            return (width as u32, height as u32);
+        }
+
+        // Get the DPI of the monitor, to calculate its physical width and height from the logical ones
+        let (mut dpi_x, mut dpi_y) = (96, 96);
+        GetDpiForMonitor(monitor, MDT_EFFECTIVE_DPI, &mut dpi_x, &mut dpi_y);
+        ((width * dpi_x as f32 / 96.0) as u32, (height * dpi_y as f32 / 96.0) as u32)
+    }
 }
 
 #[cfg(target_os = "macos")]
@@ -370,23 +556,124 @@ fn get_screen_size() -> (f64, f64) {
     }
 }
 
+/// Screen size assumed when no provider can tell it, so that the gesture can still be drawn on the most common screens.
 #[cfg(target_os = "linux")]
-fn get_screen_size() -> (i32, i32) {
+const DEFAULT_SCREEN_SIZE: (i32, i32) = (1920, 1080);
+
+/// Calculating the screen dimensions, from the first provider that can tell them: XRandR (the primary monitor),
+/// the Wayland outputs, then the size configured by the user; if none can, the user is notified and
+/// `DEFAULT_SCREEN_SIZE` is used.
+///
+/// # Arguments
+///
+/// * `fallback` - The size set by the user (`screen_size` or the `SCREEN_SIZE` variable), if any.
+#[cfg(target_os = "linux")]
+fn get_screen_size(fallback: Option<(u32, u32)>) -> (i32, i32) {
+    if let Some(size) = xrandr_screen_size() {
+        return size;
+    }
+    if let Some(size) = wayland_screen_size() {
+        return size;
+    }
+    if let Some((width, height)) = fallback {
+        return (width as i32, height as i32);
+    }
+    println!("Unable to get the screen size from X11 or Wayland: using {:?}", DEFAULT_SCREEN_SIZE);
+    events::publish(AppEvent::Error(format!(
+        "Unable to get the size of the screen: the gesture expects {}x{}. Set screen_size (e.g. 2560x1440) in the configuration.",
+        DEFAULT_SCREEN_SIZE.0, DEFAULT_SCREEN_SIZE.1
+    )));
+    DEFAULT_SCREEN_SIZE
+}
+
+/// Returns the size of the primary monitor with XRandR, or of the whole X screen if XRandR is not available;
+/// `None` if there is no X display.
+#[cfg(target_os = "linux")]
+fn xrandr_screen_size() -> Option<(i32, i32)> {
     use x11::xlib::*;
+    use x11::xrandr::*;
     use std::ptr;
 
     unsafe {
         let display = XOpenDisplay(ptr::null());
         if display.is_null() {
-            panic!("Unable to open X display");
+            return None;
         }
 
-        let screen = XDefaultScreen(display);
-        let width = XDisplayWidth(display, screen);
-        let height = XDisplayHeight(display, screen);
+        let mut size = None;
+        let root = XDefaultRootWindow(display);
+        let (mut event_base, mut error_base) = (0, 0);
+        // Asking an extension missing in the server would end the process with an X error
+        if XRRQueryExtension(display, &mut event_base, &mut error_base) != 0 {
+            let resources = XRRGetScreenResourcesCurrent(display, root);
+            let primary = XRRGetOutputPrimary(display, root);
+            if !resources.is_null() && primary != 0 {
+                let output = XRRGetOutputInfo(display, resources, primary);
+                if !output.is_null() {
+                    if (*output).crtc != 0 {
+                        let crtc = XRRGetCrtcInfo(display, resources, (*output).crtc);
+                        if !crtc.is_null() {
+                            size = Some(((*crtc).width as i32, (*crtc).height as i32));
+                            XRRFreeCrtcInfo(crtc);
+                        }
+                    }
+                    XRRFreeOutputInfo(output);
+                }
+            }
+            if !resources.is_null() {
+                XRRFreeScreenResources(resources);
+            }
+        }
 
+        // Without a primary monitor, the whole screen
+        let size = size.unwrap_or_else(|| {
+            let screen = XDefaultScreen(display);
+            (XDisplayWidth(display, screen), XDisplayHeight(display, screen))
+        });
         XCloseDisplay(display);
 
-        (width, height)
+        Some(size)
+    }
+}
+
+/// Returns the size of the first Wayland output (the current mode), or `None` without a Wayland compositor.
+#[cfg(target_os = "linux")]
+fn wayland_screen_size() -> Option<(i32, i32)> {
+    use wayland_client::protocol::{wl_output, wl_registry};
+    use wayland_client::{Connection, Dispatch, QueueHandle, WEnum};
+
+    /// The sizes of the outputs announced by the compositor.
+    #[derive(Default)]
+    struct Outputs {
+        sizes: Vec<(i32, i32)>,
+    }
+
+    impl Dispatch<wl_registry::WlRegistry, ()> for Outputs {
+        fn event(_: &mut Self, registry: &wl_registry::WlRegistry, event: wl_registry::Event, _: &(), _: &Connection, qh: &QueueHandle<Self>) {
+            if let wl_registry::Event::Global { name, interface, version } = event {
+                if interface == "wl_output" {
+                    registry.bind::<wl_output::WlOutput, _, _>(name, version.min(2), qh, ());
+                }
+            }
+        }
     }
-}
\ No newline at end of file
+
+    impl Dispatch<wl_output::WlOutput, ()> for Outputs {
+        fn event(outputs: &mut Self, _: &wl_output::WlOutput, event: wl_output::Event, _: &(), _: &Connection, _: &QueueHandle<Self>) {
+            if let wl_output::Event::Mode { flags: WEnum::Value(flags), width, height, .. } = event {
+                if flags.contains(wl_output::Mode::Current) {
+                    outputs.sizes.push((width, height));
+                }
+            }
+        }
+    }
+
+    let connection = Connection::connect_to_env().ok()?;
+    let mut queue = connection.new_event_queue();
+    connection.display().get_registry(&queue.handle(), ());
+    let mut outputs = Outputs::default();
+    // The first round trip announces the outputs, the second one their modes
+    queue.roundtrip(&mut outputs).ok()?;
+    queue.roundtrip(&mut outputs).ok()?;
+    outputs.sizes.first().copied()
+}
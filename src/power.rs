@@ -0,0 +1,356 @@
+use crate::config::SharedConfig;
+use crate::notification_popup::{show_popup, NotificationType};
+use crate::trigger;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// What to do when the battery is below the configured threshold at the start of the backup.
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LowBatteryAction {
+    /// The user is warned, but the backup starts anyway (the default)
+    #[default]
+    Warn,
+    /// The backup waits until the charger is connected or the battery is charged above the threshold
+    Delay,
+}
+
+/// State of the battery of the machine.
+#[derive(Debug, Clone, Copy)]
+pub struct BatteryStatus {
+    /// Charge, from 0 to 100
+    pub percent: u8,
+    /// Whether the machine is connected to the mains
+    pub charging: bool,
+}
+
+/// Time between two checks of the battery while the backup is delayed.
+const BATTERY_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Time between two ticks of the sleep monitor.
+const SLEEP_MONITOR_TICK: Duration = Duration::from_secs(2);
+
+/// A tick longer than this means that the machine has been suspended in the meantime.
+const SLEEP_DETECTION_GAP: Duration = Duration::from_secs(10);
+
+/// Time between two checks of the power events that can trigger the backup.
+const POWER_EVENT_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Number of times the machine has resumed from sleep since the program was started.
+static RESUME_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns how many times the machine has resumed from sleep since the sleep monitor was started:
+/// comparing the value before and after an operation tells whether the machine slept in the meantime.
+pub fn resume_count() -> usize {
+    RESUME_COUNT.load(Ordering::Relaxed)
+}
+
+/// Starts a thread that detects when the machine resumes from sleep (suspend or hibernation):
+/// while the machine sleeps the thread is frozen too, so the wall clock jumps forward between two ticks.
+pub fn start_sleep_monitor() {
+    thread::spawn(|| {
+        let mut last_tick = SystemTime::now();
+        loop {
+            thread::sleep(SLEEP_MONITOR_TICK);
+            let now = SystemTime::now();
+            let elapsed = now.duration_since(last_tick).unwrap_or_default();
+            if elapsed > SLEEP_MONITOR_TICK + SLEEP_DETECTION_GAP {
+                println!("Resumed from sleep after {:?}", elapsed);
+                RESUME_COUNT.fetch_add(1, Ordering::Relaxed);
+            }
+            last_tick = now;
+        }
+    });
+}
+
+/// Starts a thread that triggers the backup automatically on a power emergency: when the battery
+/// drops to `emergency_battery_percent` while discharging, or (with `backup_on_mains_loss`) as soon as
+/// the machine stops being powered by the mains, e.g. when a UPS reports the loss of the mains.
+/// The settings are read at every check, to follow the reloads of the configuration.
+///
+/// # Arguments
+///
+/// * `shared_config` - The configuration with the power triggers.
+pub fn start_power_event_monitor(shared_config: SharedConfig) {
+    thread::spawn(move || {
+        let mut was_charging: Option<bool> = None;
+        loop {
+            let (emergency_percent, on_mains_loss) = {
+                let config = shared_config.read().unwrap();
+                (config.emergency_battery_percent, config.backup_on_mains_loss)
+            };
+
+            if let Some(status) = battery_status() {
+                // Outside `trigger_hours` the battery is checked again, the loss of the mains power is ignored
+                if emergency_percent.is_some_and(|percent| !status.charging && status.percent <= percent)
+                    && trigger::trigger_backup(format!("battery at {}%", status.percent))
+                {
+                    return;
+                }
+                if on_mains_loss && was_charging == Some(true) && !status.charging
+                    && trigger::trigger_backup("loss of the mains power".to_string())
+                {
+                    return;
+                }
+                was_charging = Some(status.charging);
+            }
+
+            thread::sleep(POWER_EVENT_CHECK_INTERVAL);
+        }
+    });
+}
+
+/// Checks the battery before the backup: if it is discharging below `min_percent`, the user is warned
+/// and, with `LowBatteryAction::Delay`, the backup waits until the charger is connected or the battery is charged again.
+/// Machines without a battery are never delayed.
+///
+/// # Arguments
+///
+/// * `min_percent` - The minimum charge to start the backup without warnings.
+/// * `action` - What to do when the battery is below the threshold.
+pub fn check_battery_before_backup(min_percent: u8, action: LowBatteryAction) {
+    let is_low = |status: &BatteryStatus| !status.charging && status.percent < min_percent;
+
+    let status = match battery_status() {
+        Some(status) if is_low(&status) => status,
+        _ => return,
+    };
+
+    match action {
+        LowBatteryAction::Warn => {
+            show_popup(NotificationType::GenericError, Some(format!(
+                "The battery is at {}%: connect the charger, the backup may not complete", status.percent
+            )));
+        }
+        LowBatteryAction::Delay => {
+            show_popup(NotificationType::GenericError, Some(format!(
+                "The battery is at {}%: the backup will start when the charger is connected or the battery is above {}%",
+                status.percent, min_percent
+            )));
+            while battery_status().is_some_and(|status| is_low(&status)) {
+                thread::sleep(BATTERY_CHECK_INTERVAL);
+            }
+        }
+    }
+}
+
+/// Returns the state of the battery, or `None` if the machine has no battery or it cannot be read.
+#[cfg(target_os = "linux")]
+pub fn battery_status() -> Option<BatteryStatus> {
+    use std::fs;
+
+    let read = |path: &std::path::Path, name: &str| fs::read_to_string(path.join(name)).ok().map(|s| s.trim().to_string());
+
+    let mut battery = None;
+    let mut on_mains = false;
+    for entry in fs::read_dir("/sys/class/power_supply").ok()?.flatten() {
+        let path = entry.path();
+        match read(&path, "type").as_deref() {
+            Some("Battery") if battery.is_none() => {
+                let percent = read(&path, "capacity").and_then(|c| c.parse::<u8>().ok());
+                let status = read(&path, "status").unwrap_or_default();
+                battery = percent.map(|percent| (percent, status == "Charging" || status == "Full"));
+            }
+            Some("Mains") => on_mains |= read(&path, "online").as_deref() == Some("1"),
+            _ => {}
+        }
+    }
+
+    battery.map(|(percent, charging)| BatteryStatus { percent, charging: charging || on_mains })
+}
+
+/// Returns the state of the battery, or `None` if the machine has no battery or it cannot be read.
+#[cfg(target_os = "windows")]
+pub fn battery_status() -> Option<BatteryStatus> {
+    use winapi::um::winbase::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+
+    let mut status: SYSTEM_POWER_STATUS = unsafe { std::mem::zeroed() };
+    if unsafe { GetSystemPowerStatus(&mut status) } == 0 {
+        return None;
+    }
+    // 128: no system battery, 255: unknown status
+    if status.BatteryFlag & 128 != 0 || status.BatteryLifePercent == 255 {
+        return None;
+    }
+
+    Some(BatteryStatus { percent: status.BatteryLifePercent, charging: status.ACLineStatus == 1 })
+}
+
+/// Returns the state of the battery, or `None` if the machine has no battery or it cannot be read.
+#[cfg(target_os = "macos")]
+pub fn battery_status() -> Option<BatteryStatus> {
+    use std::process::Command;
+
+    // e.g. "Now drawing from 'Battery Power'\n -InternalBattery-0 (id=...)\t85%; discharging; 3:20 remaining"
+    let output = Command::new("pmset").args(["-g", "batt"]).output().ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let percent = text
+        .split(|c: char| c.is_whitespace() || c == ';')
+        .find_map(|word| word.strip_suffix('%').and_then(|p| p.parse::<u8>().ok()))?;
+
+    Some(BatteryStatus { percent, charging: text.contains("'AC Power'") })
+}
+
+/// While alive, asks the system not to shut down or log off (and on Linux not to sleep) because a backup is running:
+/// the system warns the user with the given reason instead of killing the process in the middle of a copy.
+/// The request is withdrawn when the guard is dropped.
+pub struct ShutdownGuard {
+    /// The hidden window registered with the shutdown block reason
+    #[cfg(target_os = "windows")]
+    window: Option<usize>,
+    /// The inhibitor process, kept running while the backup is in progress
+    #[cfg(not(target_os = "windows"))]
+    inhibitor: Option<std::process::Child>,
+}
+
+impl ShutdownGuard {
+    /// Blocks the shutdown, with a reason shown to the user.
+    /// If the block cannot be registered, the backup goes on anyway.
+    ///
+    /// # Arguments
+    ///
+    /// * `reason` - The reason shown by the system (e.g. "Emergency backup in progress").
+    #[cfg(target_os = "windows")]
+    pub fn acquire(reason: &str) -> ShutdownGuard {
+        let window = windows_shutdown_block::create(reason);
+        if window.is_none() {
+            eprintln!("Unable to block the shutdown during the backup");
+        }
+        ShutdownGuard { window }
+    }
+
+    /// Blocks the shutdown, with a reason shown to the user.
+    /// If the block cannot be registered, the backup goes on anyway.
+    ///
+    /// # Arguments
+    ///
+    /// * `reason` - The reason shown by the system (e.g. "Emergency backup in progress").
+    #[cfg(not(target_os = "windows"))]
+    pub fn acquire(reason: &str) -> ShutdownGuard {
+        use std::process::{Command, Stdio};
+
+        // systemd-logind on Linux, caffeinate (which only prevents the sleep) on macOS
+        let mut command = if cfg!(target_os = "macos") {
+            let mut command = Command::new("caffeinate");
+            command.args(["-i", "-w", &std::process::id().to_string()]);
+            command
+        } else {
+            let mut command = Command::new("systemd-inhibit");
+            command.args([
+                "--what=shutdown:sleep:handle-power-key",
+                "--who=Emergency backup",
+                &format!("--why={}", reason),
+                "--mode=block",
+                "sleep",
+                "infinity",
+            ]);
+            command
+        };
+        let inhibitor = command.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null()).spawn();
+        if let Err(e) = &inhibitor {
+            eprintln!("Unable to block the shutdown during the backup: {}", e);
+        }
+        ShutdownGuard { inhibitor: inhibitor.ok() }
+    }
+}
+
+impl Drop for ShutdownGuard {
+    #[cfg(target_os = "windows")]
+    fn drop(&mut self) {
+        if let Some(window) = self.window.take() {
+            windows_shutdown_block::destroy(window);
+        }
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    fn drop(&mut self) {
+        if let Some(mut inhibitor) = self.inhibitor.take() {
+            let _ = inhibitor.kill();
+            let _ = inhibitor.wait();
+        }
+    }
+}
+
+/// The shutdown block reason of Windows must be registered on a top-level window of the process,
+/// so a hidden window is created, with its own thread running the message loop.
+#[cfg(target_os = "windows")]
+mod windows_shutdown_block {
+    use std::ptr::null_mut;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use winapi::shared::minwindef::{FALSE, LPARAM, LRESULT, UINT, WPARAM};
+    use winapi::shared::windef::HWND;
+    use winapi::um::libloaderapi::GetModuleHandleW;
+    use winapi::um::winuser::{
+        CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW, GetMessageW, PostMessageW, PostQuitMessage,
+        RegisterClassW, ShutdownBlockReasonCreate, ShutdownBlockReasonDestroy, TranslateMessage, MSG, WM_CLOSE,
+        WM_DESTROY, WM_QUERYENDSESSION, WNDCLASSW,
+    };
+
+    /// Converts a string to a null-terminated UTF-16 string.
+    fn to_wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().chain(Some(0)).collect()
+    }
+
+    /// Creates the hidden window with the shutdown block reason, returning its handle.
+    pub fn create(reason: &str) -> Option<usize> {
+        let reason = to_wide(reason);
+        let (tx, rx) = channel();
+
+        thread::spawn(move || unsafe {
+            let class_name = to_wide("EmergencyBackupShutdownBlock");
+            let instance = GetModuleHandleW(null_mut());
+            let class = WNDCLASSW {
+                lpfnWndProc: Some(window_proc),
+                hInstance: instance,
+                lpszClassName: class_name.as_ptr(),
+                ..std::mem::zeroed()
+            };
+            RegisterClassW(&class);
+
+            let window = CreateWindowExW(
+                0, class_name.as_ptr(), class_name.as_ptr(), 0, 0, 0, 0, 0,
+                null_mut(), null_mut(), instance, null_mut(),
+            );
+            if window.is_null() || ShutdownBlockReasonCreate(window, reason.as_ptr()) == FALSE {
+                let _ = tx.send(None);
+                return;
+            }
+            let _ = tx.send(Some(window as usize));
+
+            let mut msg: MSG = std::mem::zeroed();
+            while GetMessageW(&mut msg, null_mut(), 0, 0) > 0 {
+                TranslateMessage(&msg);
+                DispatchMessageW(&msg);
+            }
+        });
+
+        rx.recv().ok().flatten()
+    }
+
+    /// Withdraws the shutdown block reason and closes the hidden window.
+    pub fn destroy(window: usize) {
+        unsafe {
+            PostMessageW(window as HWND, WM_CLOSE, 0, 0);
+        }
+    }
+
+    /// Refuses the end of the session while the window exists, and stops the message loop when it is closed.
+    unsafe extern "system" fn window_proc(window: HWND, msg: UINT, w_param: WPARAM, l_param: LPARAM) -> LRESULT {
+        match msg {
+            WM_QUERYENDSESSION => FALSE as LRESULT,
+            WM_CLOSE => {
+                ShutdownBlockReasonDestroy(window);
+                DestroyWindow(window);
+                0
+            }
+            WM_DESTROY => {
+                PostQuitMessage(0);
+                0
+            }
+            _ => DefWindowProcW(window, msg, w_param, l_param),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use crate::app_state::{self, AppState};
+use crate::backup::bytes_to_human_readable;
+use crate::config::{Config, SharedConfig};
+use crate::destination_history;
+use crate::locale;
+use crate::notification_popup::{show_popup, NotificationType};
+use crate::volume;
+use rdev::{EventType, Key};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+
+/// The configuration and the log root (where `runs.index` is), set when the hotkey is enabled.
+static CONTEXT: Mutex<Option<(SharedConfig, PathBuf)>> = Mutex::new(None);
+
+/// Whether Ctrl and Alt are held down.
+static MODIFIERS: Mutex<(bool, bool)> = Mutex::new((false, false));
+
+/// Statuses of `runs.index` with which a backup ends (the other runs never started one).
+const BACKUP_RESULTS: [&str; 3] = ["COMPLETED", "PARTIAL", "FAILED"];
+
+/// Enables the hotkey (Ctrl+Alt+S) showing the quick status popup.
+/// The keys are read by the listener of the activation pattern, through `handle_event`.
+///
+/// # Arguments
+///
+/// * `config` - The shared configuration, for the destination and to know whether the hotkey is enabled.
+/// * `log_root` - The folder of the logs, containing the `runs.index` of the previous backups.
+pub fn enable(config: SharedConfig, log_root: PathBuf) {
+    *CONTEXT.lock().unwrap() = Some((config, log_root));
+}
+
+/// Handles a keyboard event, showing the quick status popup when Ctrl+Alt+S is pressed.
+///
+/// # Arguments
+///
+/// * `event` - The event received by the global listener.
+pub fn handle_event(event: &EventType) {
+    let mut modifiers = MODIFIERS.lock().unwrap();
+    match *event {
+        EventType::KeyPress(Key::ControlLeft | Key::ControlRight) => modifiers.0 = true,
+        EventType::KeyRelease(Key::ControlLeft | Key::ControlRight) => modifiers.0 = false,
+        EventType::KeyPress(Key::Alt | Key::AltGr) => modifiers.1 = true,
+        EventType::KeyRelease(Key::Alt | Key::AltGr) => modifiers.1 = false,
+        EventType::KeyPress(Key::KeyS) if *modifiers == (true, true) => {
+            let context = CONTEXT.lock().unwrap().clone();
+            if let Some((config, log_root)) = context {
+                let config = config.read().unwrap().clone();
+                if config.quick_status_hotkey {
+                    // The listener must not wait for the disks to be read
+                    thread::spawn(move || show_popup(NotificationType::QuickStatus, Some(status_text(&config, &log_root))));
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Returns the text of the quick status popup: the last backup, the free space in the destination and its speed
+/// in the previous backups, and whether the backup is armed (or running).
+///
+/// # Arguments
+///
+/// * `config` - The current configuration.
+/// * `log_root` - The folder of the logs, containing `runs.index`.
+pub fn status_text(config: &Config, log_root: &Path) -> String {
+    let last_backup = match last_backup(log_root) {
+        Some((time, result)) => format!("{} ({})", locale::format_timestamp(&time), result.to_lowercase()),
+        None => "never".to_string(),
+    };
+    let destination = match volume::available_space(&config.path_dest_backup) {
+        Some(free) if config.path_dest_backup.exists() => {
+            format!("{} ({} free)", config.path_dest_backup.display(), bytes_to_human_readable(free))
+        }
+        _ => format!("{} (not connected)", config.path_dest_backup.display()),
+    };
+    let state = match app_state::current() {
+        AppState::Idle | AppState::Done | AppState::Error(_) => "not armed".to_string(),
+        AppState::Armed => "armed, waiting for the confirmation".to_string(),
+        AppState::BackingUp { progress } => format!("backup in progress ({}%)", progress),
+    };
+    let speed = match destination_history::stats(log_root, &config.path_dest_backup) {
+        Some(stats) => stats.describe(),
+        None => "no backup yet".to_string(),
+    };
+    format!("Last backup: {}\nDestination: {}\nPerformance: {}\nTrigger: {}", last_backup, destination, speed, state)
+}
+
+/// Returns when the last backup ended and its result, from the `runs.index` of the log root.
+fn last_backup(log_root: &Path) -> Option<(String, String)> {
+    let index = fs::read_to_string(log_root.join("runs.index")).ok()?;
+    // Each line has the format `run id | last update | status | run directory`
+    index.lines().rev().find_map(|line| {
+        let fields: Vec<&str> = line.split('\t').collect();
+        match fields.as_slice() {
+            [_, time, status, ..] if BACKUP_RESULTS.contains(status) => Some((time.to_string(), status.to_string())),
+            _ => None,
+        }
+    })
+}
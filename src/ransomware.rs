@@ -0,0 +1,149 @@
+use crate::filesystem::Filesystem;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::io::AsyncReadExt;
+
+/// Bytes read from the beginning of a file to estimate the entropy of its content.
+const SAMPLE_SIZE: usize = 16 * 1024;
+
+/// Most files whose content is sampled at each backup, so that the check takes seconds even on a large source.
+const MAX_SAMPLED: usize = 200;
+
+/// Entropy (bits per byte) above which a content looks encrypted, and below which it surely is not
+/// (compressed files, like photos and archives, are in between or above, and are never counted).
+const ENCRYPTED_ENTROPY: f64 = 7.5;
+const PLAIN_ENTROPY: f64 = 6.0;
+
+/// Least number of suspicious files, and their least share of the changed (or sampled) ones, raising the alarm.
+const MIN_SUSPICIOUS: usize = 10;
+const MIN_SUSPICIOUS_PERCENT: usize = 20;
+
+/// Whether the changes of the source are checked before the copy (`ransomware_check` in the configuration).
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Sets whether the changes of the source are checked for the signs of a ransomware before each backup.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+/// The signs of a ransomware found among the changes since the last backup.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Suspicion {
+    /// Source files looking like a file of the last backup with an extension added (e.g. `report.pdf.locked`),
+    /// while the original is gone.
+    pub renamed: Vec<PathBuf>,
+    /// Source files whose content now looks encrypted, while their copy in the destination did not.
+    pub encrypted: Vec<PathBuf>,
+    /// Files whose content has been sampled.
+    pub sampled: usize,
+}
+
+impl Suspicion {
+    /// Returns the alert shown to the user.
+    pub fn message(&self) -> String {
+        format!(
+            "WARNING: the source may have been encrypted by a ransomware ({} files renamed with a new extension, {} of {} changed files sampled look encrypted). \
+             The files already in the destination have not been overwritten and nothing has been deleted: check the source before the next backup.",
+            self.renamed.len(), self.encrypted.len(), self.sampled
+        )
+    }
+}
+
+/// Returns the Shannon entropy of some bytes, in bits per byte (8 for random or encrypted data).
+fn entropy(bytes: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &byte in bytes {
+        counts[byte as usize] += 1;
+    }
+    let len = bytes.len() as f64;
+    counts.iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Reads the beginning of a file, to estimate the entropy of its content.
+async fn sample(filesystem: &dyn Filesystem, path: &Path) -> Option<Vec<u8>> {
+    let mut reader = filesystem.open_read(path).await.ok()?;
+    let mut bytes = vec![0u8; SAMPLE_SIZE];
+    let mut read = 0;
+    while read < SAMPLE_SIZE {
+        match reader.read(&mut bytes[read..]).await {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(_) => return None,
+        }
+    }
+    bytes.truncate(read);
+    Some(bytes)
+}
+
+/// Returns whether a source file looks like a file of the last backup with an extension added: its name without
+/// the last extension still has one (`report.pdf` for `report.pdf.locked`), it is in the destination and
+/// it is no longer in the source.
+async fn looks_renamed(filesystem: &dyn Filesystem, src: &Path, dest: &Path) -> bool {
+    let original = src.with_extension("");
+    if original.extension().is_none() {
+        return false;
+    }
+    filesystem.metadata(&dest.with_extension("")).await.is_ok() && filesystem.metadata(&original).await.is_err()
+}
+
+/// Looks for the signs of a ransomware among the files changed since the last backup, before they are copied:
+/// many files renamed with a new extension, or many files whose content has turned from plain into random-looking
+/// (sampling the beginning of the source file and of its copy in the destination).
+///
+/// # Arguments
+///
+/// * `filesystem` - The file system of the source and of the destination.
+/// * `tasks` - The files to copy: path in the source and path in the destination.
+///
+/// # Returns
+///
+/// The signs found, if they are enough to suspect a ransomware (or `None` if the check is disabled).
+pub async fn inspect<'a>(filesystem: &dyn Filesystem, tasks: impl Iterator<Item = &'a (PathBuf, PathBuf)>) -> Option<Suspicion> {
+    if !ENABLED.load(Ordering::SeqCst) {
+        return None;
+    }
+    let mut suspicion = Suspicion::default();
+    let mut changed = 0;
+    for (src, dest) in tasks {
+        let src_info = match filesystem.metadata(src).await {
+            // The content of the placeholders is not on the disk
+            Ok(info) if !info.placeholder => info,
+            _ => continue,
+        };
+        let dest_info = match filesystem.metadata(dest).await {
+            Ok(info) => info,
+            Err(_) => {
+                changed += 1;
+                if looks_renamed(filesystem, src, dest).await {
+                    suspicion.renamed.push(src.clone());
+                }
+                continue;
+            }
+        };
+        if src_info.len == dest_info.len && src_info.modified == dest_info.modified {
+            continue;
+        }
+        changed += 1;
+        if suspicion.sampled >= MAX_SAMPLED {
+            continue;
+        }
+        if let (Some(src_bytes), Some(dest_bytes)) = (sample(filesystem, src).await, sample(filesystem, dest).await) {
+            if src_bytes.is_empty() || dest_bytes.is_empty() {
+                continue;
+            }
+            suspicion.sampled += 1;
+            if entropy(&src_bytes) >= ENCRYPTED_ENTROPY && entropy(&dest_bytes) <= PLAIN_ENTROPY {
+                suspicion.encrypted.push(src.clone());
+            }
+        }
+    }
+    let alarming = |count: usize, of: usize| count >= MIN_SUSPICIOUS && count * 100 >= of * MIN_SUSPICIOUS_PERCENT;
+    (alarming(suspicion.renamed.len(), changed) || alarming(suspicion.encrypted.len(), suspicion.sampled)).then_some(suspicion)
+}
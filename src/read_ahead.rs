@@ -0,0 +1,196 @@
+use crate::filesystem::{FileInfo, Filesystem, Reader, Writer};
+use crate::uring::OnProgress;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+use tokio::io::{self, AsyncRead, AsyncReadExt, ReadBuf};
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+
+/// Unit of the permits of the memory of the read-ahead: a semaphore counts at most `u32::MAX` permits.
+const UNIT: u64 = 1024;
+
+/// Fraction of the memory of the read-ahead that a single file can take: the larger files are read by their copy,
+/// since a large file is read sequentially anyway.
+const MAX_FILE_SHARE: u64 = 4;
+
+/// State of a source file in the read-ahead.
+enum Slot {
+    /// Not read yet.
+    Queued,
+    /// Read in memory, with its size and modification time at the moment of the read.
+    Ready(Vec<u8>, u64, Option<SystemTime>, OwnedSemaphorePermit),
+    /// Not read ahead (too large, a cloud placeholder or unreadable): the copy reads it.
+    Unavailable,
+}
+
+/// The read-ahead of a backup from a rotational disk: one task reads the files sequentially, in the order of the
+/// folders, into a bounded amount of memory, and the copies take their content from there. Thousands of copies
+/// reading at the same time would make the heads of the disk seek back and forth, much slower than reading in order.
+pub struct ReadAhead {
+    files: Mutex<HashMap<PathBuf, Slot>>,
+    /// The memory of the files read and not copied yet.
+    memory: Arc<Semaphore>,
+    max_file_units: u64,
+    /// Notified whenever a file is read (or given up).
+    changed: Notify,
+}
+
+impl ReadAhead {
+    /// Creates the read-ahead of some files.
+    ///
+    /// # Arguments
+    ///
+    /// * `memory` - The maximum memory of the files read and not copied yet, in bytes.
+    /// * `files` - The source files to read, in the order in which they are copied.
+    pub fn new(memory: u64, files: &[PathBuf]) -> ReadAhead {
+        let units = (memory / UNIT).clamp(1, u32::MAX as u64);
+        ReadAhead {
+            files: Mutex::new(files.iter().map(|path| (path.clone(), Slot::Queued)).collect()),
+            memory: Arc::new(Semaphore::new(units as usize)),
+            max_file_units: (units / MAX_FILE_SHARE).max(1),
+            changed: Notify::new(),
+        }
+    }
+
+    /// Starts reading the files, in order, from the source file system.
+    ///
+    /// # Arguments
+    ///
+    /// * `filesystem` - The file system of the source.
+    /// * `order` - The files to read, in the order in which they are copied.
+    pub fn start(self: &Arc<Self>, filesystem: Arc<dyn Filesystem>, order: Vec<PathBuf>) -> JoinHandle<()> {
+        let read_ahead = self.clone();
+        tokio::spawn(async move {
+            for path in order {
+                if !matches!(read_ahead.files.lock().unwrap().get(&path), Some(Slot::Queued)) {
+                    continue;
+                }
+                let slot = read_ahead.read(&*filesystem, &path).await;
+                if let Some(queued) = read_ahead.files.lock().unwrap().get_mut(&path) {
+                    *queued = slot;
+                }
+                read_ahead.changed.notify_waiters();
+            }
+        })
+    }
+
+    /// Reads a file in memory, waiting until the copies have freed enough memory.
+    async fn read(&self, filesystem: &dyn Filesystem, path: &Path) -> Slot {
+        let info = match filesystem.metadata(path).await {
+            Ok(info) if !info.placeholder => info,
+            _ => return Slot::Unavailable,
+        };
+        let units = info.len.div_ceil(UNIT).max(1);
+        if units > self.max_file_units {
+            return Slot::Unavailable;
+        }
+        let permit = self.memory.clone().acquire_many_owned(units as u32).await.unwrap();
+        let mut content = Vec::with_capacity(info.len as usize);
+        let read = match filesystem.open_read(path).await {
+            Ok(mut reader) => reader.read_to_end(&mut content).await,
+            Err(e) => Err(e),
+        };
+        match read {
+            Ok(_) => Slot::Ready(content, info.len, info.modified, permit),
+            Err(_) => Slot::Unavailable,
+        }
+    }
+
+    /// Forgets a file that will not be copied (e.g. already in the destination), freeing its memory.
+    pub fn discard(&self, path: &Path) {
+        self.files.lock().unwrap().remove(path);
+    }
+
+    /// Waits for a file to be read ahead and takes its content, if it has been read and is unchanged since then.
+    async fn take(&self, path: &Path, current: &FileInfo) -> Option<(Vec<u8>, OwnedSemaphorePermit)> {
+        loop {
+            // Created before the check, so that a file read in the meantime is not missed
+            let changed = self.changed.notified();
+            {
+                let mut files = self.files.lock().unwrap();
+                match files.get(path) {
+                    None => return None,
+                    Some(Slot::Queued) => {}
+                    Some(_) => match files.remove(path) {
+                        Some(Slot::Ready(content, len, modified, permit)) if len == current.len && modified == current.modified => {
+                            return Some((content, permit));
+                        }
+                        _ => return None,
+                    },
+                }
+            }
+            changed.await;
+        }
+    }
+}
+
+/// A file system reading the source files from a `ReadAhead`, and the ones not read ahead from the source.
+pub struct ReadAheadFilesystem {
+    inner: Arc<dyn Filesystem>,
+    read_ahead: Arc<ReadAhead>,
+}
+
+impl ReadAheadFilesystem {
+    pub fn new(inner: Arc<dyn Filesystem>, read_ahead: Arc<ReadAhead>) -> ReadAheadFilesystem {
+        ReadAheadFilesystem { inner, read_ahead }
+    }
+}
+
+#[async_trait]
+impl Filesystem for ReadAheadFilesystem {
+    async fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        self.inner.read_dir(path).await
+    }
+
+    async fn metadata(&self, path: &Path) -> io::Result<FileInfo> {
+        self.inner.metadata(path).await
+    }
+
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.inner.create_dir_all(path).await
+    }
+
+    /// The content read ahead is used only if the file has not changed since then (same size and modification time).
+    async fn open_read(&self, path: &Path) -> io::Result<Reader> {
+        let current = self.inner.metadata(path).await?;
+        match self.read_ahead.take(path, &current).await {
+            Some((content, permit)) => Ok(Box::new(ReadAheadReader { content: Cursor::new(content), _permit: permit })),
+            None => self.inner.open_read(path).await,
+        }
+    }
+
+    async fn create(&self, path: &Path) -> io::Result<Writer> {
+        self.inner.create(path).await
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_file(path).await
+    }
+
+    async fn forget_cached(&self, path: &Path) -> io::Result<()> {
+        self.inner.forget_cached(path).await
+    }
+
+    // The copies with io_uring would read the source again: they are not made with the read-ahead
+    async fn copy_with_uring(&self, _src: &Path, _dest: &Path, _on_progress: OnProgress) -> Option<io::Result<()>> {
+        None
+    }
+}
+
+/// A file read ahead, whose memory is given back to the read-ahead when the copy ends.
+struct ReadAheadReader {
+    content: Cursor<Vec<u8>>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl AsyncRead for ReadAheadReader {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.content).poll_read(cx, buf)
+    }
+}
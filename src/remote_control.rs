@@ -0,0 +1,323 @@
+use crate::app_state;
+use crate::events::{self, AppEvent};
+use crate::secrets;
+use crate::supervisor;
+use crate::trigger;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// First word of the greeting, and version of the protocol.
+const GREETING: &str = "EMERGENCY-BACKUP 1";
+
+/// Context of the key derivation, so that the pre-shared key is never used directly.
+const KEY_CONTEXT: &str = "group_39 emergency backup remote control v1";
+
+/// How long the backup stays armed waiting for `confirm`.
+const ARM_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long a companion has to send its command, and the wait after a wrong MAC (to slow down guessing).
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+const FAILED_AUTH_DELAY: Duration = Duration::from_secs(1);
+
+/// The shortest pre-shared key accepted, and the least strength it must have (estimated from its characters):
+/// the MACs seen on the network allow guessing the key offline, as fast as BLAKE3 runs.
+const MIN_KEY_LENGTH: usize = 20;
+const MIN_KEY_BITS: f64 = 100.0;
+
+/// How many companions can be served at the same time: the others are turned away.
+const MAX_CONNECTIONS: usize = 8;
+
+/// After this many failed authentications within `FAILURE_WINDOW`, an address is turned away until the window ends.
+const MAX_FAILURES: u32 = 5;
+const FAILURE_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+/// The LAN listener, if enabled (`remote_control` in the configuration).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemoteControlConfig {
+    /// Address and port to listen on, e.g. `0.0.0.0:47020` (every interface) or `192.168.1.10:47020`.
+    pub listen: String,
+    /// Reference to the pre-shared key, known also by the companion: `keyring:<service>/<account>` or `file:<path>`.
+    /// It must be random and at least `MIN_KEY_LENGTH` characters long, e.g. made with `openssl rand -hex 32`.
+    pub key: String,
+}
+
+/// When the backup has been armed remotely, if it is waiting for `confirm`.
+static ARMED_AT: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// The companions being served.
+static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// The failed authentications of each address: how many, and when the first of the current window happened.
+static FAILURES: Mutex<Option<HashMap<IpAddr, (u32, Instant)>>> = Mutex::new(None);
+
+/// Checks that a pre-shared key is long and varied enough not to be guessed from the MACs seen on the network.
+/// Its strength is estimated as its length times the bits of the classes of characters it uses
+/// (lowercase and uppercase letters, digits, the others): e.g. 20 random lowercase letters and digits are enough,
+/// a word repeated is not.
+///
+/// # Returns
+///
+/// A `Result` which is `Ok` if the key is strong enough, or a message describing why it is not.
+pub fn check_key_strength(pre_shared_key: &str) -> Result<(), String> {
+    let length = pre_shared_key.chars().count();
+    if length < MIN_KEY_LENGTH {
+        return Err(format!("the key has {} characters, at least {} are needed", length, MIN_KEY_LENGTH));
+    }
+    let distinct: HashSet<char> = pre_shared_key.chars().collect();
+    let pool: u32 = [
+        (pre_shared_key.chars().any(|c| c.is_ascii_lowercase()), 26),
+        (pre_shared_key.chars().any(|c| c.is_ascii_uppercase()), 26),
+        (pre_shared_key.chars().any(|c| c.is_ascii_digit()), 10),
+        (pre_shared_key.chars().any(|c| !c.is_ascii_alphanumeric()), 33),
+    ]
+    .iter()
+    .filter(|(used, _)| *used)
+    .map(|(_, size)| size)
+    .sum();
+    // A key made of few characters repeated is as weak as a short one
+    let bits = length.min(distinct.len() * 2) as f64 * f64::from(pool.max(1)).log2();
+    if bits < MIN_KEY_BITS {
+        return Err(format!(
+            "the key is too easy to guess: use at least {} random characters (e.g. the output of `openssl rand -hex 32`)",
+            MIN_KEY_LENGTH
+        ));
+    }
+    Ok(())
+}
+
+/// Returns whether an address has failed too many authentications recently.
+fn is_blocked(ip: IpAddr) -> bool {
+    let mut failures = FAILURES.lock().unwrap();
+    let failures = failures.get_or_insert_with(HashMap::new);
+    failures.retain(|_, (_, first)| first.elapsed() < FAILURE_WINDOW);
+    failures.get(&ip).is_some_and(|(count, _)| *count >= MAX_FAILURES)
+}
+
+/// Records a failed authentication of an address.
+fn record_failure(ip: IpAddr) {
+    let mut failures = FAILURES.lock().unwrap();
+    let (count, _) = failures.get_or_insert_with(HashMap::new).entry(ip).or_insert((0, Instant::now()));
+    *count += 1;
+}
+
+/// Forgets the failed authentications of an address, once it has authenticated.
+fn clear_failures(ip: IpAddr) {
+    if let Some(failures) = FAILURES.lock().unwrap().as_mut() {
+        failures.remove(&ip);
+    }
+}
+
+/// Counts a companion being served, until it is dropped.
+struct ConnectionSlot;
+
+impl ConnectionSlot {
+    /// Takes a slot, unless `MAX_CONNECTIONS` companions are already being served.
+    fn acquire() -> Option<ConnectionSlot> {
+        CONNECTIONS
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| (count < MAX_CONNECTIONS).then_some(count + 1))
+            .ok()
+            .map(|_| ConnectionSlot)
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Derives the key of the MACs from the pre-shared key.
+pub fn derive_key(pre_shared_key: &str) -> [u8; 32] {
+    blake3::derive_key(KEY_CONTEXT, pre_shared_key.as_bytes())
+}
+
+/// Returns the MAC of a command answering a challenge.
+fn command_mac(key: &[u8; 32], challenge: &str, command: &str) -> blake3::Hash {
+    blake3::keyed_hash(key, format!("{} {}", challenge, command).as_bytes())
+}
+
+/// Returns a new random challenge, in hexadecimal.
+fn new_challenge() -> io::Result<String> {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).map_err(|e| io::Error::other(e.to_string()))?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Starts listening for the companions on the LAN (a phone app or another PC), which can arm and confirm
+/// the backup when the input devices of the machine do not work at all. The thread is restarted by the watchdog
+/// if it stops.
+///
+/// The protocol is made of lines of text over TCP, one command for each connection: the program sends
+/// `EMERGENCY-BACKUP 1 <challenge>`, with a random challenge in hexadecimal; the companion answers
+/// `<command> <mac>`, where the command is `status`, `arm`, `confirm` or `cancel` and the MAC is
+/// `blake3::keyed_hash(derive_key(pre-shared key), "<challenge> <command>")` in hexadecimal; the program
+/// answers `OK <text>` or `ERR <text>`. The challenge changes at every connection, so a command seen on
+/// the network cannot be sent again. As with the activation pattern, the backup starts only if `confirm`
+/// follows `arm` within `ARM_TIMEOUT`.
+///
+/// The listener is not started with a weak key (see `check_key_strength`). At most `MAX_CONNECTIONS` companions
+/// are served at the same time, and an address failing `MAX_FAILURES` authentications is turned away for
+/// `FAILURE_WINDOW`.
+///
+/// # Arguments
+///
+/// * `config` - The address to listen on and the reference to the pre-shared key.
+pub fn start(config: RemoteControlConfig) {
+    let key = match secrets::resolve_secret(&config.key).map_err(|e| e.to_string()).and_then(|key| check_key_strength(&key).map(|()| key)) {
+        Ok(key) => derive_key(&key),
+        Err(e) => {
+            events::publish(AppEvent::Error(format!("Remote control disabled: {}", e)));
+            return;
+        }
+    };
+    supervisor::supervise("remote control listener", move || {
+        let listener = match TcpListener::bind(&config.listen) {
+            Ok(listener) => listener,
+            Err(e) => {
+                println!("Unable to listen for the remote control on {}: {}", config.listen, e);
+                return;
+            }
+        };
+        println!("Remote control listening on {}", config.listen);
+        for mut stream in listener.incoming().flatten() {
+            let Ok(peer) = stream.peer_addr() else { continue };
+            if is_blocked(peer.ip()) {
+                let _ = writeln!(stream, "ERR too many failed attempts, try again later");
+                continue;
+            }
+            let Some(slot) = ConnectionSlot::acquire() else {
+                let _ = writeln!(stream, "ERR busy, try again later");
+                continue;
+            };
+            // A slow companion must not block the others
+            thread::spawn(move || {
+                let _slot = slot;
+                if let Err(e) = serve(stream, &key, peer) {
+                    println!("Remote control from {}: {}", peer, e);
+                }
+            });
+        }
+    });
+}
+
+/// Serves one command of a companion.
+fn serve(mut stream: TcpStream, key: &[u8; 32], peer: SocketAddr) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let challenge = new_challenge()?;
+    writeln!(stream, "{} {}", GREETING, challenge)?;
+
+    let mut line = String::new();
+    read_line(&mut BufReader::new(&stream), &mut line)?;
+    let authenticated = line.trim().rsplit_once(' ').and_then(|(command, mac)| {
+        // The comparison of the hashes takes the same time whatever the MAC
+        let mac = blake3::Hash::from_hex(mac).ok()?;
+        (mac == command_mac(key, &challenge, command)).then(|| command.to_string())
+    });
+    let command = match authenticated {
+        Some(command) => command,
+        None => {
+            record_failure(peer.ip());
+            thread::sleep(FAILED_AUTH_DELAY);
+            writeln!(stream, "ERR authentication failed")?;
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "authentication failed"));
+        }
+    };
+
+    clear_failures(peer.ip());
+    let answer = execute(&command, &peer.to_string());
+    println!("Remote control from {}: {} -> {:?}", peer, command, answer);
+    match answer {
+        Ok(text) => writeln!(stream, "OK {}", text),
+        Err(text) => writeln!(stream, "ERR {}", text),
+    }
+}
+
+/// Reads a line at most as long as a command, so that the other side cannot fill the memory.
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> io::Result<usize> {
+    reader.by_ref().take(256).read_line(line)
+}
+
+/// Executes an authenticated command.
+fn execute(command: &str, peer: &str) -> Result<String, String> {
+    let mut armed_at = ARMED_AT.lock().unwrap();
+    match command {
+        "status" => Ok(app_state::current().to_string()),
+        "arm" => {
+            *armed_at = Some(Instant::now());
+            Ok(format!("armed: send confirm within {} seconds to start the backup", ARM_TIMEOUT.as_secs()))
+        }
+        "confirm" => match armed_at.take() {
+            Some(at) if at.elapsed() <= ARM_TIMEOUT => {
+                trigger::confirm_backup(format!("remote control from {}", peer));
+                Ok("backup started".to_string())
+            }
+            _ => Err("not armed: send arm first".to_string()),
+        },
+        "cancel" => {
+            *armed_at = None;
+            Ok("canceled".to_string())
+        }
+        _ => Err(format!("unknown command: {}", command)),
+    }
+}
+
+/// Sends a command to the program running on another machine, as a companion does.
+///
+/// # Arguments
+///
+/// * `address` - The address of the machine, e.g. `192.168.1.10:47020`.
+/// * `pre_shared_key` - The pre-shared key, the same of the other machine.
+/// * `command` - `status`, `arm`, `confirm` or `cancel`.
+///
+/// # Returns
+///
+/// The answer of the program, or a message describing the error.
+pub fn send_command(address: &str, pre_shared_key: &str, command: &str) -> Result<String, String> {
+    let addr: SocketAddr = address.to_socket_addrs()
+        .map_err(|e| format!("invalid address {}: {}", address, e))?
+        .next()
+        .ok_or_else(|| format!("invalid address {}", address))?;
+    let mut stream = TcpStream::connect_timeout(&addr, READ_TIMEOUT).map_err(|e| format!("unable to connect to {}: {}", address, e))?;
+    stream.set_read_timeout(Some(READ_TIMEOUT)).map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+
+    let mut greeting = String::new();
+    read_line(&mut reader, &mut greeting).map_err(|e| e.to_string())?;
+    let challenge = greeting.trim().strip_prefix(GREETING)
+        .map(str::trim)
+        .ok_or_else(|| format!("{} is not an emergency backup", address))?;
+    let mac = command_mac(&derive_key(pre_shared_key), challenge, command);
+    writeln!(stream, "{} {}", command, mac.to_hex()).map_err(|e| e.to_string())?;
+
+    let mut answer = String::new();
+    read_line(&mut reader, &mut answer).map_err(|e| e.to_string())?;
+    match answer.trim().split_once(' ') {
+        Some(("OK", text)) => Ok(text.to_string()),
+        Some(("ERR", text)) => Err(text.to_string()),
+        _ => Err(format!("unexpected answer: {}", answer.trim())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_short_or_repetitive_keys() {
+        assert!(check_key_strength("secret").is_err());
+        assert!(check_key_strength("passwordpasswordpassword").is_err());
+        assert!(check_key_strength(&"ab".repeat(20)).is_err());
+    }
+
+    #[test]
+    fn accepts_random_keys() {
+        assert!(check_key_strength("3f9c1e7a52d8b0c46e1f9a7d2b5c8e03").is_ok());
+        assert!(check_key_strength("Tq7#vL2p!xR9mZ4k&Wc8").is_ok());
+    }
+}
@@ -0,0 +1,570 @@
+use crate::backup::matches_pattern;
+use crate::encryption::{self, KEY_FILE_NAME};
+use crate::hash_cache::HASH_CACHE_FILE_NAME;
+use crate::manifest::{Manifest, MANIFEST_FILE_NAME};
+use crate::metadata_crypto;
+use crate::resume::PROGRESS_FILE_NAME;
+use crate::snapshot::{SnapshotSummary, SNAPSHOT_INFO_FILE_NAME};
+use chrono::{Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+/// Files written by the application in the root of a destination, which are not part of the backup.
+const METADATA_FILES: [&str; 5] = [MANIFEST_FILE_NAME, HASH_CACHE_FILE_NAME, PROGRESS_FILE_NAME, SNAPSHOT_INFO_FILE_NAME, KEY_FILE_NAME];
+
+/// How deep inside the given folders the backups are looked for (e.g. `E:\backup\work`).
+const SEARCH_DEPTH: usize = 3;
+
+/// A backup found on a drive, which can be restored.
+#[derive(Debug, Clone)]
+pub struct RestorePoint {
+    /// The root folder of the backup.
+    pub root: PathBuf,
+    /// Where, when and how the backup was made.
+    pub info: SnapshotSummary,
+    /// Number of files in the backup.
+    pub files: usize,
+    /// Size of the files in the backup.
+    pub size: u64,
+}
+
+/// Result of a restore.
+#[derive(Debug, Default)]
+pub struct RestoreReport {
+    pub restored: usize,
+    /// Files with no content in the backup (cloud placeholders stored as stubs), not restored.
+    pub stubs: usize,
+    /// Files that could not be restored, with the error.
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+/// Looks for the backups (the folders with a `snapshot_info.yaml`) in the given folders and their subfolders.
+///
+/// # Arguments
+///
+/// * `roots` - The folders to look in, e.g. the destinations of the configuration or the root of a drive.
+///
+/// # Returns
+///
+/// The backups found, the most recent first.
+pub fn find_restore_points(roots: &[PathBuf]) -> Vec<RestorePoint> {
+    let mut points = Vec::new();
+    for root in roots {
+        find_in(root, SEARCH_DEPTH, &mut points);
+    }
+    points.sort_by(|a, b| b.info.created.cmp(&a.info.created));
+    points.dedup_by(|a, b| a.root == b.root);
+    points
+}
+
+/// Which backups `prune` keeps (`retention` in the configuration), for each source and machine:
+/// a backup is kept if any of the rules keeps it, and the most recent one is always kept.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// Number of most recent backups kept.
+    #[serde(default)]
+    pub keep_last: Option<usize>,
+    /// Backups made in the last days kept.
+    #[serde(default)]
+    pub keep_days: Option<u64>,
+}
+
+/// What `prune` would do with the backups found: the ones deleted and the ones kept.
+#[derive(Debug, Default)]
+pub struct PrunePlan<'a> {
+    pub keep: Vec<&'a RestorePoint>,
+    pub delete: Vec<&'a RestorePoint>,
+}
+
+/// The machine and the source of a backup, whose backups are counted together by the retention policy.
+type BackupGroup = (Option<String>, Option<PathBuf>);
+
+impl PrunePlan<'_> {
+    /// Returns the space freed by deleting the backups.
+    pub fn freed(&self) -> u64 {
+        self.delete.iter().map(|point| point.size).sum()
+    }
+}
+
+/// Decides which backups the retention policy deletes, without touching them: the backups of the same source
+/// and machine are counted together, the most recent one of each is always kept, and so are the `protected`
+/// folders (e.g. the destinations in use) and the backups whose date cannot be read.
+///
+/// # Arguments
+///
+/// * `points` - The backups found, the most recent first (as returned by `find_restore_points`).
+/// * `policy` - The retention policy.
+/// * `protected` - Folders never deleted.
+pub fn prune_plan<'a>(points: &'a [RestorePoint], policy: &RetentionPolicy, protected: &[PathBuf]) -> PrunePlan<'a> {
+    let now = Local::now().naive_local();
+    let mut plan = PrunePlan::default();
+    // The same folder may be written in another way (e.g. relative, or through a link)
+    let protected: Vec<PathBuf> = protected.iter().map(|path| canonical(path)).collect();
+    // How many backups of each source and machine have been seen, from the most recent
+    let mut seen: Vec<(BackupGroup, usize)> = Vec::new();
+    for point in points {
+        let group = (point.info.hostname.clone(), point.info.source.clone());
+        let position = match seen.iter_mut().find(|(key, _)| *key == group) {
+            Some((_, count)) => {
+                *count += 1;
+                *count - 1
+            }
+            None => {
+                seen.push((group, 1));
+                0
+            }
+        };
+        let created = NaiveDateTime::parse_from_str(&point.info.created, "%Y-%m-%d %H:%M:%S").ok();
+        let recent = match (policy.keep_days, created) {
+            (Some(days), Some(created)) => (now - created).num_days() < days as i64,
+            _ => false,
+        };
+        let keep = position == 0
+            || created.is_none()
+            || policy.keep_last.is_some_and(|keep_last| position < keep_last)
+            || recent
+            || protected.contains(&canonical(&point.root))
+            // Nothing is deleted without a rule
+            || (policy.keep_last.is_none() && policy.keep_days.is_none());
+        if keep {
+            plan.keep.push(point);
+        } else {
+            plan.delete.push(point);
+        }
+    }
+    plan
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Returns whether a path recorded in a backup stays inside the folder it is joined to: not absolute,
+/// without `..` and without a drive or a prefix.
+fn is_contained(relative: &Path) -> bool {
+    relative.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// Deletes a backup: the files that its manifest lists as written by the backups, then the files of the application
+/// and the folders left empty. Any other file in the folder (e.g. put there by the user) is left where it is,
+/// together with the folders holding it.
+///
+/// # Errors
+///
+/// Returns an error, without deleting anything, if the manifest cannot be read or does not list the files of the
+/// backup (it was written by an older version: the backup has to be deleted by hand).
+pub fn delete_restore_point(point: &RestorePoint) -> io::Result<()> {
+    let manifest = Manifest::load(&point.root)?;
+    if manifest.stored.is_empty() {
+        return Err(io::Error::other(format!("the manifest of {} does not list the files of the backup", point.root.display())));
+    }
+    let mut dirs: BTreeSet<PathBuf> = BTreeSet::new();
+    for stored in &manifest.stored {
+        let relative = Path::new(stored);
+        if !is_contained(relative) {
+            println!("{:?} is outside of the backup {:?}: not deleted", stored, point.root);
+            continue;
+        }
+        match fs::remove_file(point.root.join(relative)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        dirs.extend(relative.ancestors().skip(1).filter(|dir| !dir.as_os_str().is_empty()).map(Path::to_path_buf));
+    }
+    // The files of the application last: a deletion stopped halfway can be done again
+    for entry in fs::read_dir(&point.root)?.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if METADATA_FILES.iter().any(|metadata| name == *metadata || metadata_crypto::is_sealed_copy(&name, metadata)) {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    // The deepest folders first; a folder that still holds other files is not empty and stays
+    for dir in dirs.iter().rev() {
+        let _ = fs::remove_dir(point.root.join(dir));
+    }
+    let _ = fs::remove_dir(&point.root);
+    Ok(())
+}
+
+/// Adds the backups found in a folder and in its subfolders (up to `depth` levels) to `points`.
+fn find_in(dir: &Path, depth: usize, points: &mut Vec<RestorePoint>) {
+    if let Some(info) = SnapshotSummary::load(dir) {
+        let files = backup_files(dir);
+        let size = files.iter().filter_map(|file| fs::metadata(dir.join(file)).ok()).map(|metadata| metadata.len()).sum();
+        points.push(RestorePoint { root: dir.to_path_buf(), info, files: files.len(), size });
+        // The folders of a backup are part of it, not other backups
+        return;
+    }
+    if depth == 0 {
+        return;
+    }
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                find_in(&entry.path(), depth - 1, points);
+            }
+        }
+    }
+}
+
+/// Returns the files of a backup, as paths relative to its root, leaving out the files of the application.
+pub fn backup_files(root: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    list_files(root, Path::new(""), &mut files);
+    files.retain(|file| {
+        let file = file.to_string_lossy();
+        !METADATA_FILES.iter().any(|name| file == *name || metadata_crypto::is_sealed_copy(&file, name))
+    });
+    files.sort();
+    files
+}
+
+/// Adds the files of a folder and of its subfolders to `files`, relative to the root of the backup.
+fn list_files(root: &Path, relative: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(root.join(relative)) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = relative.join(entry.file_name());
+        if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+            list_files(root, &path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+/// A file found in a backup by `search`.
+#[derive(Debug, Clone)]
+pub struct SearchMatch {
+    /// The root folder of the backup containing the file.
+    pub root: PathBuf,
+    /// When the backup was made.
+    pub created: String,
+    /// The path of the file in the backed up folder (its original name).
+    pub path: String,
+    /// The version of the file: its size and modification time.
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// Looks for the files of the backups whose name matches a pattern (e.g. `budget.xlsx` or `*.xlsx`,
+/// ignoring the case), or whose path matches it if the pattern contains folders (see `matches_path`).
+///
+/// # Arguments
+///
+/// * `points` - The backups to search.
+/// * `pattern` - The name, or path, of the files to look for.
+///
+/// # Returns
+///
+/// The matching files, grouped by backup in the order of `points`.
+pub fn search(points: &[RestorePoint], pattern: &str) -> Vec<SearchMatch> {
+    let pattern = pattern.to_lowercase();
+    let by_path = pattern.contains(['/', '\\']);
+    let mut matches = Vec::new();
+    for point in points {
+        let manifest = Manifest::load(&point.root).unwrap_or_default();
+        for file in backup_files(&point.root) {
+            let stored = file.to_string_lossy().into_owned();
+            let original = manifest.original_path(&stored).to_lowercase();
+            let name = original.rsplit(['/', '\\']).next().unwrap_or("");
+            let found = if by_path { matches_path(&original, &pattern) } else { matches_pattern(name, &pattern) };
+            if !found {
+                continue;
+            }
+            if let Ok(metadata) = fs::metadata(point.root.join(&file)) {
+                matches.push(SearchMatch {
+                    root: point.root.clone(),
+                    created: point.info.created.clone(),
+                    path: manifest.original_path(&stored).to_string(),
+                    size: metadata.len(),
+                    modified: metadata.modified().ok(),
+                });
+            }
+        }
+    }
+    matches
+}
+
+/// Copies the files of a backup to a folder, with their original names (see the manifest of the backup),
+/// decrypting them if the backup is encrypted.
+///
+/// # Arguments
+///
+/// * `point` - The backup to restore.
+/// * `target` - The folder where the files are restored.
+/// * `only` - Paths or patterns of the files to restore, relative to the backed up folder
+///   (e.g. `Documents/Thesis` or `Documents/**/*.docx`); if empty, every file is restored.
+///
+/// # Returns
+///
+/// An `io::Result` containing the `RestoreReport`, or an error if the manifest of the backup cannot be read.
+pub fn restore(point: &RestorePoint, target: &Path, only: &[String]) -> io::Result<RestoreReport> {
+    let manifest = Manifest::load(&point.root)?;
+    encryption::unlock_backup(&point.root)?;
+    let mut report = RestoreReport::default();
+    for file in backup_files(&point.root) {
+        let stored = file.to_string_lossy().into_owned();
+        let original = manifest.original_path(&stored);
+        if !only.is_empty() && !only.iter().any(|pattern| matches_path(original, pattern)) {
+            continue;
+        }
+        if manifest.placeholders.contains(&stored) {
+            report.stubs += 1;
+            continue;
+        }
+        // A manifest pointing outside of the target (absolute, or with `..`) would overwrite any file
+        if !is_contained(Path::new(original)) {
+            report.failed.push((PathBuf::from(original), "the original path is outside of the restored folder".to_string()));
+            continue;
+        }
+        let restored = target.join(original);
+        let result = match restored.parent() {
+            Some(parent) => fs::create_dir_all(parent),
+            None => Ok(()),
+        }
+        .and_then(|_| encryption::restore_file(&point.root.join(&file), &restored));
+        match result {
+            Ok(_) => report.restored += 1,
+            Err(e) => report.failed.push((restored, e.to_string())),
+        }
+    }
+    Ok(report)
+}
+
+/// Matches a relative path against a pattern of `--only`: `**` matches any number of folders, `*` and `?`
+/// match inside a single name, and a pattern also matches everything inside the folder it names
+/// (`Documents/Thesis` is the same as `Documents/Thesis/**`). Both `/` and `\` separate the folders.
+pub fn matches_path(path: &str, pattern: &str) -> bool {
+    let split = |text: &str| -> Vec<String> {
+        text.split(['/', '\\']).filter(|part| !part.is_empty()).map(String::from).collect()
+    };
+    let path = split(path);
+    let mut pattern = split(pattern);
+    if matches_segments(&path, &pattern) {
+        return true;
+    }
+    pattern.push("**".to_string());
+    matches_segments(&path, &pattern)
+}
+
+/// Matches the names of a path against the parts of a pattern, where `**` matches any number of names.
+fn matches_segments(path: &[String], pattern: &[String]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => (0..=path.len()).any(|skip| matches_segments(&path[skip..], rest)),
+        Some((first, rest)) => match path.split_first() {
+            Some((name, path_rest)) => matches_pattern(name, first) && matches_segments(path_rest, rest),
+            None => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty folder on the disk for a test.
+    fn folder(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("group_39_test_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn restore_point(root: &Path) -> RestorePoint {
+        RestorePoint { root: root.to_path_buf(), info: SnapshotSummary::default(), files: 0, size: 0 }
+    }
+
+    fn dated_point(root: &str, hostname: &str, source: &str, created: &str) -> RestorePoint {
+        let info = SnapshotSummary {
+            created: created.to_string(),
+            hostname: Some(hostname.to_string()),
+            source: Some(PathBuf::from(source)),
+            ..Default::default()
+        };
+        RestorePoint { root: PathBuf::from(root), info, files: 1, size: 10 }
+    }
+
+    fn roots(points: &[&RestorePoint]) -> Vec<PathBuf> {
+        points.iter().map(|point| point.root.clone()).collect()
+    }
+
+    #[test]
+    fn only_the_relative_paths_are_contained() {
+        assert!(is_contained(Path::new("Documents/report.pdf")));
+        assert!(is_contained(Path::new("./report.pdf")));
+        assert!(!is_contained(Path::new("../report.pdf")));
+        assert!(!is_contained(Path::new("Documents/../../report.pdf")));
+        assert!(!is_contained(Path::new("/etc/passwd")));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn the_paths_with_a_drive_or_a_prefix_are_not_contained() {
+        assert!(!is_contained(Path::new(r"C:\Windows\win.ini")));
+        assert!(!is_contained(Path::new(r"C:report.pdf")));
+        assert!(!is_contained(Path::new(r"\\server\share\report.pdf")));
+        assert!(!is_contained(Path::new(r"..\report.pdf")));
+    }
+
+    #[test]
+    fn restore_refuses_the_original_paths_outside_of_the_target() {
+        let root = folder("restore_root");
+        let target = folder("restore_target");
+        fs::write(root.join("kept.txt"), b"kept").unwrap();
+        fs::write(root.join("escaping.txt"), b"escaping").unwrap();
+        fs::write(root.join("absolute.txt"), b"absolute").unwrap();
+        let outside = target.parent().unwrap().join(format!("group_39_test_escaped_{}.txt", std::process::id()));
+        let mut manifest = Manifest::default();
+        manifest.renamed.insert("escaping.txt".to_string(), format!("../{}", outside.file_name().unwrap().to_string_lossy()));
+        manifest.renamed.insert("absolute.txt".to_string(), outside.to_string_lossy().into_owned());
+        manifest.save(&root).unwrap();
+
+        let report = restore(&restore_point(&root), &target, &[]).unwrap();
+
+        assert_eq!(report.restored, 1);
+        assert_eq!(report.failed.len(), 2);
+        assert_eq!(fs::read(target.join("kept.txt")).unwrap(), b"kept");
+        assert!(!outside.exists());
+        fs::remove_dir_all(&root).unwrap();
+        fs::remove_dir_all(&target).unwrap();
+    }
+
+    #[test]
+    fn a_folder_matches_the_files_inside_it() {
+        assert!(matches_path("Documents/Thesis/chapter1.docx", "Documents/Thesis"));
+        assert!(matches_path("Documents/Thesis/images/plot.png", "Documents/Thesis"));
+        assert!(matches_path("Documents/Thesis", "Documents/Thesis"));
+        assert!(!matches_path("Documents/Thesis2/chapter1.docx", "Documents/Thesis"));
+        assert!(!matches_path("Documents/chapter1.docx", "Documents/Thesis"));
+    }
+
+    #[test]
+    fn a_double_star_matches_any_number_of_folders() {
+        assert!(matches_path("budget.xlsx", "**/*.xlsx"));
+        assert!(matches_path("Work/2024/budget.xlsx", "**/*.xlsx"));
+        assert!(!matches_path("Work/2024/budget.xls", "**/*.xlsx"));
+        assert!(matches_path("Documents/a/b/notes.docx", "Documents/**/*.docx"));
+        assert!(!matches_path("Pictures/notes.docx", "Documents/**/*.docx"));
+    }
+
+    #[test]
+    fn both_separators_split_the_folders() {
+        assert!(matches_path(r"Documents\Thesis\chapter1.docx", "Documents/Thesis"));
+        assert!(matches_path("Documents/Thesis/chapter1.docx", r"Documents\Thesis"));
+        assert!(matches_path(r"Documents\Thesis/images\plot.png", r"Documents/**\*.png"));
+    }
+
+    #[test]
+    fn prune_keeps_the_most_recent_backup_of_each_machine_and_source() {
+        let points = vec![
+            dated_point("/backups/laptop_docs_3", "laptop", "/home/docs", "2024-03-03 10:00:00"),
+            dated_point("/backups/desktop_docs_1", "desktop", "/home/docs", "2024-03-02 10:00:00"),
+            dated_point("/backups/laptop_docs_2", "laptop", "/home/docs", "2024-02-02 10:00:00"),
+            dated_point("/backups/laptop_photos_1", "laptop", "/home/photos", "2024-01-02 10:00:00"),
+            dated_point("/backups/laptop_docs_1", "laptop", "/home/docs", "2024-01-01 10:00:00"),
+        ];
+        let policy = RetentionPolicy { keep_last: Some(1), keep_days: None };
+
+        let plan = prune_plan(&points, &policy, &[]);
+
+        assert_eq!(roots(&plan.keep), vec![
+            PathBuf::from("/backups/laptop_docs_3"),
+            PathBuf::from("/backups/desktop_docs_1"),
+            PathBuf::from("/backups/laptop_photos_1"),
+        ]);
+        assert_eq!(roots(&plan.delete), vec![PathBuf::from("/backups/laptop_docs_2"), PathBuf::from("/backups/laptop_docs_1")]);
+        assert_eq!(plan.freed(), 20);
+    }
+
+    #[test]
+    fn prune_keeps_the_recent_backups() {
+        let yesterday = (Local::now() - chrono::Duration::days(1)).format("%Y-%m-%d %H:%M:%S").to_string();
+        let points = vec![
+            dated_point("/backups/new", "laptop", "/home/docs", &yesterday),
+            dated_point("/backups/recent", "laptop", "/home/docs", &yesterday),
+            dated_point("/backups/old", "laptop", "/home/docs", "2020-01-01 10:00:00"),
+        ];
+        let policy = RetentionPolicy { keep_last: None, keep_days: Some(7) };
+
+        let plan = prune_plan(&points, &policy, &[]);
+
+        assert_eq!(roots(&plan.keep), vec![PathBuf::from("/backups/new"), PathBuf::from("/backups/recent")]);
+        assert_eq!(roots(&plan.delete), vec![PathBuf::from("/backups/old")]);
+    }
+
+    #[test]
+    fn prune_keeps_the_protected_and_the_undated_backups() {
+        let points = vec![
+            dated_point("/backups/latest", "laptop", "/home/docs", "2024-03-03 10:00:00"),
+            dated_point("/backups/undated", "laptop", "/home/docs", ""),
+            dated_point("/backups/in_use", "laptop", "/home/docs", "2024-02-02 10:00:00"),
+            dated_point("/backups/old", "laptop", "/home/docs", "2024-01-01 10:00:00"),
+        ];
+        let policy = RetentionPolicy { keep_last: Some(1), keep_days: None };
+
+        let plan = prune_plan(&points, &policy, &[PathBuf::from("/backups/in_use")]);
+
+        assert_eq!(roots(&plan.keep), vec![
+            PathBuf::from("/backups/latest"),
+            PathBuf::from("/backups/undated"),
+            PathBuf::from("/backups/in_use"),
+        ]);
+        assert_eq!(roots(&plan.delete), vec![PathBuf::from("/backups/old")]);
+    }
+
+    #[test]
+    fn prune_deletes_nothing_without_a_rule() {
+        let points = vec![
+            dated_point("/backups/new", "laptop", "/home/docs", "2024-03-03 10:00:00"),
+            dated_point("/backups/old", "laptop", "/home/docs", "2020-01-01 10:00:00"),
+        ];
+
+        let plan = prune_plan(&points, &RetentionPolicy::default(), &[]);
+
+        assert_eq!(plan.keep.len(), 2);
+        assert!(plan.delete.is_empty());
+    }
+
+    #[test]
+    fn delete_restore_point_leaves_the_files_not_written_by_the_backups() {
+        let root = folder("delete");
+        fs::create_dir_all(root.join("Documents/notes")).unwrap();
+        fs::create_dir_all(root.join("Pictures/2024")).unwrap();
+        fs::write(root.join("top.txt"), b"backed up").unwrap();
+        fs::write(root.join("Documents/report.pdf"), b"backed up").unwrap();
+        fs::write(root.join("Documents/notes/user.txt"), b"put there by the user").unwrap();
+        fs::write(root.join("Pictures/2024/photo.jpg"), b"backed up").unwrap();
+        let stored = ["top.txt", "Documents/report.pdf", "Pictures/2024/photo.jpg"].iter().map(|file| file.to_string()).collect();
+        let manifest = Manifest { stored, ..Default::default() };
+        manifest.save(&root).unwrap();
+
+        delete_restore_point(&restore_point(&root)).unwrap();
+
+        assert!(!root.join("top.txt").exists());
+        assert!(!root.join("Documents/report.pdf").exists());
+        assert!(!root.join("Pictures").exists());
+        assert!(!root.join(MANIFEST_FILE_NAME).exists());
+        assert_eq!(fs::read(root.join("Documents/notes/user.txt")).unwrap(), b"put there by the user");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn delete_restore_point_refuses_a_manifest_without_the_stored_files() {
+        let root = folder("delete_old_manifest");
+        fs::write(root.join("report.pdf"), b"backed up").unwrap();
+        Manifest::default().save(&root).unwrap();
+
+        assert!(delete_restore_point(&restore_point(&root)).is_err());
+        assert!(root.join("report.pdf").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Name of the journal, written in the root of the destination while a backup is running.
+pub const PROGRESS_FILE_NAME: &str = ".emergency_backup_progress.json";
+
+/// Minimum time between two writes of the journal, so that backups of many small files are not slowed down.
+const SAVE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The task list of a running backup and which of its files have already been copied.
+/// It is removed when the backup ends, so finding it means that the process was killed during the backup
+/// (e.g. the machine was switched off), and the next backup can skip the files already copied.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProgressJournal {
+    /// When the backup started.
+    pub started: String,
+    /// The source folder of the backup.
+    pub source: PathBuf,
+    /// The source files to copy, in the order of the task list.
+    pub tasks: Vec<PathBuf>,
+    /// One bit per task, set once its file has been copied.
+    completed: Vec<u8>,
+    /// Where the journal is saved.
+    #[serde(skip)]
+    path: PathBuf,
+    /// When the journal was last saved.
+    #[serde(skip)]
+    last_save: Option<Instant>,
+}
+
+impl ProgressJournal {
+    /// Creates the journal of a new backup, with no file copied yet. It is not saved until a file is copied.
+    ///
+    /// # Arguments
+    ///
+    /// * `destination` - The root folder of the destination, where the journal is saved.
+    /// * `source` - The source folder of the backup.
+    /// * `tasks` - The source files to copy.
+    pub fn new(destination: &Path, source: &Path, tasks: Vec<PathBuf>) -> ProgressJournal {
+        ProgressJournal {
+            started: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            source: source.to_path_buf(),
+            completed: vec![0; tasks.len().div_ceil(8)],
+            tasks,
+            path: destination.join(PROGRESS_FILE_NAME),
+            last_save: None,
+        }
+    }
+
+    /// Reads the journal left in a destination by an interrupted backup, if any.
+    /// An unreadable journal is ignored, since it only spares copying some files again.
+    ///
+    /// # Arguments
+    ///
+    /// * `destination` - The root folder of the destination.
+    pub fn load(destination: &Path) -> Option<ProgressJournal> {
+        let path = destination.join(PROGRESS_FILE_NAME);
+        let mut journal: ProgressJournal = serde_json::from_str(&fs::read_to_string(&path).ok()?).ok()?;
+        journal.completed.resize(journal.tasks.len().div_ceil(8), 0);
+        journal.path = path;
+        Some(journal)
+    }
+
+    /// Returns the number of files already copied.
+    pub fn completed_count(&self) -> usize {
+        self.completed.iter().map(|byte| byte.count_ones() as usize).sum()
+    }
+
+    /// Returns the source files already copied.
+    pub fn completed_files(&self) -> HashSet<PathBuf> {
+        self.tasks
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.completed[i / 8] & (1 << (i % 8)) != 0)
+            .map(|(_, path)| path.clone())
+            .collect()
+    }
+
+    /// Marks a task as completed, saving the journal if it has not been saved for a while.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The position of the task in the task list.
+    pub fn mark_completed(&mut self, index: usize) {
+        self.completed[index / 8] |= 1 << (index % 8);
+        if self.last_save.is_none_or(|last_save| last_save.elapsed() >= SAVE_INTERVAL) {
+            if let Err(e) = self.save() {
+                eprintln!("Unable to save the progress of the backup: {}", e);
+            }
+        }
+    }
+
+    /// Saves the journal. It is first written to a temporary file and then renamed,
+    /// so a process killed while saving never leaves a partial journal.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` indicating whether the journal has been saved.
+    pub fn save(&mut self) -> io::Result<()> {
+        self.last_save = Some(Instant::now());
+        let contents = serde_json::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, contents)?;
+        fs::rename(&tmp_path, &self.path)
+    }
+
+    /// Removes the journal, once the backup has ended.
+    pub fn remove(&self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                eprintln!("Unable to remove the progress of the backup {:?}: {}", self.path, e);
+            }
+        }
+    }
+
+    /// Describes the interrupted backup, to offer the user to resume it.
+    pub fn description(&self) -> String {
+        format!(
+            "A backup started on {} was interrupted before the end: {} of {} files had been copied.\nStart the backup again to resume it from where it stopped.",
+            self.started, self.completed_count(), self.tasks.len()
+        )
+    }
+}
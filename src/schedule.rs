@@ -0,0 +1,284 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Name of the scheduled task (Windows) and marker of the crontab line (elsewhere).
+const TASK_NAME: &str = "EmergencyBackup";
+
+/// When the scheduled backup runs.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Schedule {
+    /// Every hour, at the start of the hour
+    Hourly,
+    /// Every day at the given hour and minute
+    Daily(u8, u8),
+    /// Every week, on the given day (0 = Sunday) at the given hour and minute
+    Weekly(u8, u8, u8),
+}
+
+/// Names of the days of the week, starting from Sunday as in crontab.
+const DAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+impl Schedule {
+    /// Parses a schedule written as `hourly`, `daily HH:MM` or `weekly <day> HH:MM` (e.g. `weekly mon 21:00`).
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The schedule to parse.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the schedule, or a message describing the mistake.
+    pub fn parse(text: &str) -> Result<Schedule, String> {
+        let words: Vec<String> = text.split_whitespace().map(|w| w.to_lowercase()).collect();
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+        match words.as_slice() {
+            ["hourly"] => Ok(Schedule::Hourly),
+            ["daily", time] => {
+                let (hour, minute) = parse_time(time)?;
+                Ok(Schedule::Daily(hour, minute))
+            }
+            ["weekly", day, time] => {
+                let day = DAYS
+                    .iter()
+                    .position(|d| day.starts_with(d))
+                    .ok_or_else(|| format!("Invalid day of the week: {}", day))?;
+                let (hour, minute) = parse_time(time)?;
+                Ok(Schedule::Weekly(day as u8, hour, minute))
+            }
+            _ => Err(format!(
+                "Invalid schedule \"{}\": use \"hourly\", \"daily HH:MM\" or \"weekly <day> HH:MM\"",
+                text
+            )),
+        }
+    }
+
+    /// Returns the schedule in crontab syntax (minute, hour, day of month, month, day of week).
+    fn to_cron(&self) -> String {
+        match self {
+            Schedule::Hourly => "0 * * * *".to_string(),
+            Schedule::Daily(hour, minute) => format!("{} {} * * *", minute, hour),
+            Schedule::Weekly(day, hour, minute) => format!("{} {} * * {}", minute, hour, day),
+        }
+    }
+
+    /// Returns the schedule as arguments of `schtasks /Create`.
+    #[cfg(target_os = "windows")]
+    fn to_schtasks(&self) -> Vec<String> {
+        match self {
+            Schedule::Hourly => vec!["/SC".into(), "HOURLY".into()],
+            Schedule::Daily(hour, minute) => {
+                vec!["/SC".into(), "DAILY".into(), "/ST".into(), format!("{:02}:{:02}", hour, minute)]
+            }
+            Schedule::Weekly(day, hour, minute) => vec![
+                "/SC".into(),
+                "WEEKLY".into(),
+                "/D".into(),
+                DAYS[*day as usize].to_uppercase(),
+                "/ST".into(),
+                format!("{:02}:{:02}", hour, minute),
+            ],
+        }
+    }
+}
+
+/// Parses a time written as `HH:MM`.
+fn parse_time(time: &str) -> Result<(u8, u8), String> {
+    let invalid = || format!("Invalid time \"{}\": use HH:MM, e.g. 21:00", time);
+    let (hour, minute) = time.split_once(':').ok_or_else(invalid)?;
+    let hour: u8 = hour.parse().map_err(|_| invalid())?;
+    let minute: u8 = minute.parse().map_err(|_| invalid())?;
+    if hour > 23 || minute > 59 {
+        return Err(invalid());
+    }
+    Ok((hour, minute))
+}
+
+/// Returns the command line of the scheduled backup: this executable with `backup now --scheduled`
+/// (so that it respects `trigger_hours`) and, if given, the configuration file (as an absolute path, since the scheduler runs it from another folder).
+fn backup_command(config_path: Option<&Path>) -> Result<Vec<String>, String> {
+    let exe = env::current_exe().map_err(|e| format!("Unable to find the executable: {}", e))?;
+    let mut command = vec![exe.display().to_string(), "backup".to_string(), "now".to_string(), "--scheduled".to_string()];
+    if let Some(config_path) = config_path {
+        let config_path: PathBuf = env::current_dir().map(|dir| dir.join(config_path)).unwrap_or_else(|_| config_path.to_path_buf());
+        command.push("--config".to_string());
+        command.push(config_path.display().to_string());
+    }
+    Ok(command)
+}
+
+/// Creates (or replaces) the scheduled task that runs the backup: a Task Scheduler task on Windows,
+/// a crontab entry on the other systems.
+///
+/// # Arguments
+///
+/// * `schedule` - When the backup runs.
+/// * `config_path` - The configuration file to use, if not the default one.
+///
+/// # Returns
+///
+/// A `Result` which is `Ok` if the task has been created, or a message describing the problem.
+#[cfg(target_os = "windows")]
+pub fn install_schedule(schedule: &Schedule, config_path: Option<&Path>) -> Result<(), String> {
+    let command = backup_command(config_path)?
+        .iter()
+        .map(|arg| format!("\"{}\"", arg))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut args = vec!["/Create".to_string(), "/F".into(), "/TN".into(), TASK_NAME.into(), "/TR".into(), command];
+    args.extend(schedule.to_schtasks());
+    run_schtasks(&args)
+}
+
+/// Removes the scheduled task that runs the backup.
+///
+/// # Returns
+///
+/// A `Result` which is `Ok` if the task has been removed, or a message describing the problem.
+#[cfg(target_os = "windows")]
+pub fn remove_schedule() -> Result<(), String> {
+    run_schtasks(&["/Delete".to_string(), "/F".into(), "/TN".into(), TASK_NAME.into()])
+}
+
+/// Runs `schtasks` with the given arguments.
+#[cfg(target_os = "windows")]
+fn run_schtasks(args: &[String]) -> Result<(), String> {
+    let output = Command::new("schtasks")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("Unable to run schtasks: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+/// Creates (or replaces) the scheduled task that runs the backup: a Task Scheduler task on Windows,
+/// a crontab entry on the other systems.
+///
+/// # Arguments
+///
+/// * `schedule` - When the backup runs.
+/// * `config_path` - The configuration file to use, if not the default one.
+///
+/// # Returns
+///
+/// A `Result` which is `Ok` if the task has been created, or a message describing the problem.
+#[cfg(not(target_os = "windows"))]
+pub fn install_schedule(schedule: &Schedule, config_path: Option<&Path>) -> Result<(), String> {
+    let command = backup_command(config_path)?
+        .iter()
+        // cron turns an unescaped `%` into a new line, even inside quotes
+        .map(|arg| format!("'{}'", arg.replace('\'', r"'\''").replace('%', r"\%")))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut lines = read_crontab()?;
+    lines.retain(|line| !line.ends_with(&marker()));
+    lines.push(format!("{} {} {}", schedule.to_cron(), command, marker()));
+    write_crontab(&lines)
+}
+
+/// Removes the scheduled task that runs the backup.
+///
+/// # Returns
+///
+/// A `Result` which is `Ok` if the task has been removed, or a message describing the problem.
+#[cfg(not(target_os = "windows"))]
+pub fn remove_schedule() -> Result<(), String> {
+    let mut lines = read_crontab()?;
+    let before = lines.len();
+    lines.retain(|line| !line.ends_with(&marker()));
+    if lines.len() == before {
+        return Err("No scheduled backup found in the crontab".to_string());
+    }
+    write_crontab(&lines)
+}
+
+/// Comment appended to the crontab line, to find it again.
+#[cfg(not(target_os = "windows"))]
+fn marker() -> String {
+    format!("# {}", TASK_NAME)
+}
+
+/// Reads the lines of the crontab of the current user (none if the user has no crontab yet).
+#[cfg(not(target_os = "windows"))]
+fn read_crontab() -> Result<Vec<String>, String> {
+    let output = Command::new("crontab")
+        .arg("-l")
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("Unable to run crontab: {}", e))?;
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        // crontab -l also fails when the user has no crontab yet ("no crontab for <user>"): any other failure
+        // (e.g. cron not allowed for the user) must not be taken for an empty crontab, which would be written over
+        if error.to_lowercase().contains("no crontab for") {
+            return Ok(Vec::new());
+        }
+        return Err(format!("Unable to read the crontab: {}", error));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect())
+}
+
+/// Replaces the crontab of the current user.
+#[cfg(not(target_os = "windows"))]
+fn write_crontab(lines: &[String]) -> Result<(), String> {
+    use std::io::Write;
+
+    let mut child = Command::new("crontab")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Unable to run crontab: {}", e))?;
+    let mut content = lines.join("\n");
+    content.push('\n');
+    child
+        .stdin
+        .take()
+        .expect("stdin of crontab is piped")
+        .write_all(content.as_bytes())
+        .map_err(|e| format!("Unable to write the crontab: {}", e))?;
+    let output = child.wait_with_output().map_err(|e| format!("Unable to write the crontab: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_schedules() {
+        assert_eq!(Schedule::parse("hourly"), Ok(Schedule::Hourly));
+        assert_eq!(Schedule::parse("daily 21:00"), Ok(Schedule::Daily(21, 0)));
+        assert_eq!(Schedule::parse("  Daily   7:05 "), Ok(Schedule::Daily(7, 5)));
+        assert_eq!(Schedule::parse("weekly mon 21:00"), Ok(Schedule::Weekly(1, 21, 0)));
+        assert_eq!(Schedule::parse("Weekly Sunday 00:30"), Ok(Schedule::Weekly(0, 0, 30)));
+    }
+
+    #[test]
+    fn rejects_the_wrong_schedules() {
+        assert!(Schedule::parse("").is_err());
+        assert!(Schedule::parse("monthly").is_err());
+        assert!(Schedule::parse("daily").is_err());
+        assert!(Schedule::parse("daily 21").is_err());
+        assert!(Schedule::parse("daily 24:00").is_err());
+        assert!(Schedule::parse("daily 21:60").is_err());
+        assert!(Schedule::parse("weekly 21:00").is_err());
+        assert!(Schedule::parse("weekly someday 21:00").is_err());
+    }
+
+    #[test]
+    fn writes_the_schedules_in_crontab_syntax() {
+        assert_eq!(Schedule::Hourly.to_cron(), "0 * * * *");
+        assert_eq!(Schedule::Daily(21, 5).to_cron(), "5 21 * * *");
+        assert_eq!(Schedule::Weekly(1, 7, 30).to_cron(), "30 7 * * 1");
+    }
+}
@@ -0,0 +1,79 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Prefix of the secrets stored in the OS keyring (Windows Credential Manager, Secret Service, macOS Keychain).
+const KEYRING_PREFIX: &str = "keyring:";
+/// Prefix of the secrets stored in a separate file, readable only by the current user.
+const FILE_PREFIX: &str = "file:";
+
+/// Error raised when a secret cannot be resolved.
+#[derive(Debug)]
+pub struct SecretError(pub String);
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for SecretError {}
+
+/// Resolves a reference to a secret (e.g. the password of a remote destination) written in the configuration.
+/// Plaintext secrets are never accepted: the configuration can only contain a reference to where the secret is stored.
+///
+/// # Arguments
+///
+/// * `reference` - Either `keyring:<service>/<account>` or `file:<path>`.
+///
+/// # Returns
+///
+/// A `Result` containing the secret, or a `SecretError` if the reference is invalid or the secret cannot be read.
+pub fn resolve_secret(reference: &str) -> Result<String, SecretError> {
+    if let Some(entry) = reference.strip_prefix(KEYRING_PREFIX) {
+        let (service, account) = entry
+            .split_once('/')
+            .ok_or_else(|| SecretError(format!("Invalid keyring reference '{}', expected keyring:<service>/<account>", reference)))?;
+        read_from_keyring(service, account)
+    } else if let Some(path) = reference.strip_prefix(FILE_PREFIX) {
+        read_from_file(Path::new(path))
+    } else {
+        Err(SecretError(
+            "Secrets cannot be written in plaintext in the configuration: use keyring:<service>/<account> or file:<path>".to_string(),
+        ))
+    }
+}
+
+/// Reads a secret from the OS keyring.
+fn read_from_keyring(service: &str, account: &str) -> Result<String, SecretError> {
+    keyring::Entry::new(service, account)
+        .and_then(|entry| entry.get_password())
+        .map_err(|e| SecretError(format!("Unable to read {}/{} from the keyring: {}", service, account, e)))
+}
+
+/// Reads a secret from a file, refusing it if other users can access the file.
+fn read_from_file(path: &Path) -> Result<String, SecretError> {
+    check_permissions(path)?;
+    fs::read_to_string(path)
+        .map(|secret| secret.trim_end_matches(['\r', '\n']).to_string())
+        .map_err(|e| SecretError(format!("Unable to read the secret file {:?}: {}", path, e)))
+}
+
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> Result<(), SecretError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let metadata = fs::metadata(path).map_err(|e| SecretError(format!("Unable to access the secret file {:?}: {}", path, e)))?;
+    // Group and others must not have any permission (e.g. chmod 600)
+    if metadata.permissions().mode() & 0o077 != 0 {
+        return Err(SecretError(format!("The secret file {:?} must be readable only by its owner (chmod 600)", path)));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path) -> Result<(), SecretError> {
+    // On Windows the file is expected to be in the user profile, protected by its ACL
+    Ok(())
+}
@@ -0,0 +1,295 @@
+use crate::cli::CliArgs;
+use crate::config::Config;
+use std::env;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+/// How often the service checks which user is at the console.
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Folder in the home of each user with their own configuration file, used instead of the shared one.
+const USER_CONFIG_DIR: &str = ".emergency_backup";
+
+/// The session of the user at the console (the physical screen, keyboard and mouse).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsoleSession {
+    /// Identifier of the session in the system.
+    id: String,
+    user: String,
+    home: PathBuf,
+    /// Environment variables needed to reach the desktop of the session (e.g. `DISPLAY` on Linux).
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    environment: Vec<(String, String)>,
+}
+
+/// Runs the program as a system service (`--service`): the gestures can be detected and the notifications shown
+/// only inside the session of a user, so the service starts the program (the agent) in the session of the user
+/// at the console, with their configuration, and starts it again when another user logs in (fast user switching)
+/// or when it ends (e.g. after a backup). One service installed on the machine works for all the accounts.
+///
+/// # Arguments
+///
+/// * `cli` - The command line arguments, passed to the agents (`--config` is the configuration of the users
+///   without their own).
+///
+/// # Returns
+///
+/// The exit code of the process, only if the service cannot run.
+pub fn run_service(cli: &CliArgs) -> i32 {
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => {
+            println!("Unable to find the executable: {}", e);
+            return 1;
+        }
+    };
+    // The shared configuration, as an absolute path, since the agents may run from another folder
+    let shared_config = cli.config_path.as_ref().map(|path| env::current_dir().map(|dir| dir.join(path)).unwrap_or_else(|_| path.clone()));
+
+    println!("Service started: waiting for a user at the console");
+    let mut current: Option<(ConsoleSession, platform::Agent)> = None;
+    loop {
+        let active = platform::active_console_session();
+        // The user at the console has changed (or logged out): their agent is stopped
+        if let Some((session, mut agent)) = current.take() {
+            if active.as_ref() == Some(&session) && agent.is_running() {
+                current = Some((session, agent));
+            } else {
+                agent.stop();
+            }
+        }
+
+        if let (None, Some(session)) = (&current, active) {
+            let mut agent_cli = cli.clone();
+            agent_cli.service = false;
+            agent_cli.config_path = Config::config_file_in(&session.home.join(USER_CONFIG_DIR)).or_else(|| shared_config.clone());
+            let mut command = vec![exe.display().to_string()];
+            command.extend(agent_cli.to_args());
+
+            match platform::launch_agent(&session, &command) {
+                Ok(agent) => {
+                    println!("Agent started in the session {} of {}", session.id, session.user);
+                    current = Some((session, agent));
+                }
+                Err(e) => println!("Unable to start the agent in the session {} of {}: {}", session.id, session.user, e),
+            }
+        }
+
+        thread::sleep(SESSION_CHECK_INTERVAL);
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use super::ConsoleSession;
+    use std::io;
+    use std::mem;
+    use std::path::PathBuf;
+    use std::ptr::null_mut;
+    use winapi::shared::minwindef::{DWORD, FALSE};
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{CreateProcessAsUserW, TerminateProcess, PROCESS_INFORMATION, STARTUPINFOW};
+    use winapi::um::synchapi::WaitForSingleObject;
+    use winapi::um::userenv::{CreateEnvironmentBlock, DestroyEnvironmentBlock, GetUserProfileDirectoryW};
+    use winapi::um::winbase::{WTSGetActiveConsoleSessionId, CREATE_UNICODE_ENVIRONMENT, WAIT_OBJECT_0};
+    use winapi::um::winnt::HANDLE;
+    use winapi::um::wtsapi32::WTSQueryUserToken;
+
+    /// Returned by `WTSGetActiveConsoleSessionId` when no session is attached to the console.
+    const NO_SESSION: DWORD = 0xFFFF_FFFF;
+
+    /// The program started in the session of a user.
+    pub struct Agent {
+        process: HANDLE,
+    }
+
+    impl Agent {
+        pub fn is_running(&mut self) -> bool {
+            unsafe { WaitForSingleObject(self.process, 0) != WAIT_OBJECT_0 }
+        }
+
+        pub fn stop(&mut self) {
+            unsafe {
+                TerminateProcess(self.process, 1);
+            }
+        }
+    }
+
+    impl Drop for Agent {
+        fn drop(&mut self) {
+            unsafe {
+                CloseHandle(self.process);
+            }
+        }
+    }
+
+    /// Returns the token of the user logged in a session (only a service running as SYSTEM can get it).
+    fn user_token(session: DWORD) -> Option<HANDLE> {
+        let mut token = null_mut();
+        (unsafe { WTSQueryUserToken(session, &mut token) } != 0).then_some(token)
+    }
+
+    /// Returns the profile folder (the home) of the user of a token.
+    fn profile_dir(token: HANDLE) -> Option<PathBuf> {
+        let mut buffer = [0u16; 260];
+        let mut len = buffer.len() as DWORD;
+        if unsafe { GetUserProfileDirectoryW(token, buffer.as_mut_ptr(), &mut len) } == 0 {
+            return None;
+        }
+        // The length includes the terminating null character
+        Some(PathBuf::from(String::from_utf16_lossy(&buffer[..len as usize - 1])))
+    }
+
+    /// Returns the session attached to the console, if a user is logged in it.
+    pub fn active_console_session() -> Option<ConsoleSession> {
+        let id = unsafe { WTSGetActiveConsoleSessionId() };
+        if id == NO_SESSION {
+            return None;
+        }
+        let token = user_token(id)?;
+        let home = profile_dir(token);
+        unsafe {
+            CloseHandle(token);
+        }
+        let home = home?;
+        let user = home.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        Some(ConsoleSession { id: id.to_string(), user, home, environment: Vec::new() })
+    }
+
+    /// Starts the agent as the user of the session, on their desktop and with their environment.
+    pub fn launch_agent(session: &ConsoleSession, command: &[String]) -> io::Result<Agent> {
+        let id: DWORD = session.id.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid session"))?;
+        let token = user_token(id).ok_or_else(io::Error::last_os_error)?;
+        let command_line = command.iter().map(|arg| format!("\"{}\"", arg)).collect::<Vec<_>>().join(" ");
+        let mut command_line: Vec<u16> = command_line.encode_utf16().chain(Some(0)).collect();
+        let mut desktop: Vec<u16> = "winsta0\\default".encode_utf16().chain(Some(0)).collect();
+
+        unsafe {
+            let mut environment = null_mut();
+            CreateEnvironmentBlock(&mut environment, token, FALSE);
+            let mut startup: STARTUPINFOW = mem::zeroed();
+            startup.cb = mem::size_of::<STARTUPINFOW>() as DWORD;
+            startup.lpDesktop = desktop.as_mut_ptr();
+            let mut info: PROCESS_INFORMATION = mem::zeroed();
+            let created = CreateProcessAsUserW(
+                token, null_mut(), command_line.as_mut_ptr(), null_mut(), null_mut(), FALSE,
+                CREATE_UNICODE_ENVIRONMENT, environment, null_mut(), &mut startup, &mut info,
+            );
+            // Read before the cleanup, which could overwrite it
+            let error = io::Error::last_os_error();
+            if !environment.is_null() {
+                DestroyEnvironmentBlock(environment);
+            }
+            CloseHandle(token);
+            if created == 0 {
+                return Err(error);
+            }
+            CloseHandle(info.hThread);
+            Ok(Agent { process: info.hProcess })
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::ConsoleSession;
+    use std::collections::HashMap;
+    use std::io;
+    use std::path::PathBuf;
+    use std::process::{Child, Command};
+
+    /// The program started in the session of a user.
+    pub struct Agent {
+        child: Child,
+    }
+
+    impl Agent {
+        pub fn is_running(&mut self) -> bool {
+            matches!(self.child.try_wait(), Ok(None))
+        }
+
+        pub fn stop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+
+    /// Returns the properties of a session, as reported by systemd-logind.
+    fn session_properties(id: &str) -> Option<HashMap<String, String>> {
+        let output = Command::new("loginctl").args(["show-session", id]).output().ok()?;
+        Some(
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter_map(|line| line.split_once('='))
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        )
+    }
+
+    /// Returns the home of a user, from the user database.
+    fn home_of(user: &str) -> Option<PathBuf> {
+        let output = Command::new("getent").args(["passwd", user]).output().ok()?;
+        String::from_utf8_lossy(&output.stdout).trim().split(':').nth(5).map(PathBuf::from)
+    }
+
+    /// Returns the active graphical session on the first seat (the console), if a user is logged in it.
+    pub fn active_console_session() -> Option<ConsoleSession> {
+        let output = Command::new("loginctl").args(["list-sessions", "--no-legend"]).output().ok()?;
+        String::from_utf8_lossy(&output.stdout).lines().find_map(|line| {
+            let id = line.split_whitespace().next()?;
+            let properties = session_properties(id)?;
+            let property = |key: &str| properties.get(key).map(String::as_str).unwrap_or("");
+            if property("Active") != "yes" || property("Seat") != "seat0" || property("Class") != "user" {
+                return None;
+            }
+            let user = property("Name").to_string();
+            let runtime_dir = format!("/run/user/{}", property("User"));
+            let mut environment = vec![
+                ("DBUS_SESSION_BUS_ADDRESS".to_string(), format!("unix:path={}/bus", runtime_dir)),
+                ("XDG_RUNTIME_DIR".to_string(), runtime_dir),
+                ("XDG_SESSION_ID".to_string(), id.to_string()),
+            ];
+            if !property("Display").is_empty() {
+                environment.push(("DISPLAY".to_string(), property("Display").to_string()));
+            }
+            Some(ConsoleSession { id: id.to_string(), home: home_of(&user)?, user, environment })
+        })
+    }
+
+    /// Starts the agent as the user of the session, with the environment of their desktop.
+    pub fn launch_agent(session: &ConsoleSession, command: &[String]) -> io::Result<Agent> {
+        let child = Command::new("runuser")
+            .args(["-u", &session.user, "--"])
+            .args(command)
+            .envs(session.environment.iter().map(|(key, value)| (key, value)))
+            .spawn()?;
+        Ok(Agent { child })
+    }
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+mod platform {
+    use super::ConsoleSession;
+    use std::io;
+
+    /// The program started in the session of a user: never, on this system.
+    pub struct Agent;
+
+    impl Agent {
+        pub fn is_running(&mut self) -> bool {
+            false
+        }
+
+        pub fn stop(&mut self) {}
+    }
+
+    /// The sessions of the users cannot be followed on this system: the program has to be started by each user.
+    pub fn active_console_session() -> Option<ConsoleSession> {
+        None
+    }
+
+    pub fn launch_agent(_session: &ConsoleSession, _command: &[String]) -> io::Result<Agent> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "the service mode is supported on Windows and Linux"))
+    }
+}
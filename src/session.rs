@@ -0,0 +1,180 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long the state of the session is reused before being queried again,
+/// since the recognizers ask for it at every mouse movement.
+const CACHE_DURATION: Duration = Duration::from_secs(1);
+
+/// Last state of the session: when it was read, whether it is locked (or remote) and whether a full-screen app is running.
+static CACHE: Mutex<Option<(Instant, bool, bool)>> = Mutex::new(None);
+
+/// Returns whether the activation pattern must be ignored: while the workstation is locked or the session
+/// is remote (`when_locked`), or while a full-screen app such as a game or a presentation is running (`in_fullscreen`).
+///
+/// # Arguments
+///
+/// * `when_locked` - `true` to ignore the pattern while the session is locked or remote.
+/// * `in_fullscreen` - `true` to ignore the pattern while a full-screen app is running.
+pub fn arming_blocked(when_locked: bool, in_fullscreen: bool) -> bool {
+    if !when_locked && !in_fullscreen {
+        return false;
+    }
+
+    let mut cache = CACHE.lock().unwrap();
+    let (locked, fullscreen) = match *cache {
+        Some((read_at, locked, fullscreen)) if read_at.elapsed() < CACHE_DURATION => (locked, fullscreen),
+        _ => {
+            let state = (is_session_locked() || is_remote_session(), is_fullscreen_app_running());
+            *cache = Some((Instant::now(), state.0, state.1));
+            state
+        }
+    };
+
+    (when_locked && locked) || (in_fullscreen && fullscreen)
+}
+
+/// Returns why the activation pattern cannot be used at all, if the program runs without a display or input devices
+/// (a server, a container, an SSH session, a service): the global input hooks and the screen size would not be available,
+/// so only the automatic triggers can start the backup.
+pub fn headless_reason() -> Option<String> {
+    platform_headless_reason()
+}
+
+/// Returns whether the process runs in a window station without a display (e.g. as a service or in an SSH session).
+#[cfg(target_os = "windows")]
+fn platform_headless_reason() -> Option<String> {
+    use std::mem;
+    use std::ptr;
+    use winapi::um::winuser::{GetProcessWindowStation, GetUserObjectInformationW, UOI_FLAGS, USEROBJECTFLAGS, WSF_VISIBLE};
+
+    unsafe {
+        let station = GetProcessWindowStation();
+        if station.is_null() {
+            return Some("no window station".to_string());
+        }
+        let mut flags: USEROBJECTFLAGS = mem::zeroed();
+        let read = GetUserObjectInformationW(
+            station as _, UOI_FLAGS as i32, &mut flags as *mut _ as *mut _, mem::size_of::<USEROBJECTFLAGS>() as u32, ptr::null_mut(),
+        );
+        (read != 0 && flags.dwFlags & WSF_VISIBLE == 0).then(|| "the window station has no display (service or SSH session)".to_string())
+    }
+}
+
+/// Returns whether there is no X11 or Wayland display to connect to.
+#[cfg(target_os = "linux")]
+fn platform_headless_reason() -> Option<String> {
+    let has_display = |name: &str| std::env::var_os(name).is_some_and(|value| !value.is_empty());
+    if has_display("DISPLAY") || has_display("WAYLAND_DISPLAY") {
+        return None;
+    }
+    Some(if std::env::var_os("SSH_CONNECTION").is_some() {
+        "SSH session without X forwarding".to_string()
+    } else {
+        "no X11 or Wayland display".to_string()
+    })
+}
+
+/// Returns whether the program has been started from an SSH session, outside the graphical session.
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+fn platform_headless_reason() -> Option<String> {
+    std::env::var_os("SSH_CONNECTION").map(|_| "SSH session".to_string())
+}
+
+/// Returns whether the workstation is locked: the input desktop cannot be switched to while the lock screen is shown.
+#[cfg(target_os = "windows")]
+fn is_session_locked() -> bool {
+    use winapi::um::winuser::{CloseDesktop, OpenInputDesktop, SwitchDesktop, DESKTOP_SWITCHDESKTOP};
+
+    unsafe {
+        let desktop = OpenInputDesktop(0, 0, DESKTOP_SWITCHDESKTOP);
+        if desktop.is_null() {
+            return true;
+        }
+        let locked = SwitchDesktop(desktop) == 0;
+        CloseDesktop(desktop);
+        locked
+    }
+}
+
+/// Returns whether the program runs in a remote desktop session.
+#[cfg(target_os = "windows")]
+fn is_remote_session() -> bool {
+    use winapi::um::winuser::{GetSystemMetrics, SM_REMOTESESSION};
+
+    unsafe { GetSystemMetrics(SM_REMOTESESSION) != 0 }
+}
+
+/// Returns whether a full-screen app (a game or a presentation) is in the foreground.
+#[cfg(target_os = "windows")]
+fn is_fullscreen_app_running() -> bool {
+    use winapi::um::shellapi::{
+        SHQueryUserNotificationState, QUERY_USER_NOTIFICATION_STATE, QUNS_BUSY, QUNS_PRESENTATION_MODE,
+        QUNS_RUNNING_D3D_FULL_SCREEN,
+    };
+
+    let mut state: QUERY_USER_NOTIFICATION_STATE = 0;
+    let result = unsafe { SHQueryUserNotificationState(&mut state) };
+    result >= 0 && matches!(state, QUNS_BUSY | QUNS_PRESENTATION_MODE | QUNS_RUNNING_D3D_FULL_SCREEN)
+}
+
+/// Returns whether the session is locked, as reported by systemd-logind.
+#[cfg(target_os = "linux")]
+fn is_session_locked() -> bool {
+    use std::process::Command;
+
+    let session = std::env::var("XDG_SESSION_ID").unwrap_or_else(|_| "self".to_string());
+    Command::new("loginctl")
+        .args(["show-session", &session, "-p", "LockedHint"])
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).trim() == "LockedHint=yes")
+}
+
+/// Returns whether the program runs in a remote session (SSH with X forwarding, or a session marked as remote by logind).
+#[cfg(target_os = "linux")]
+fn is_remote_session() -> bool {
+    use std::process::Command;
+
+    if std::env::var_os("SSH_CONNECTION").is_some() {
+        return true;
+    }
+    let session = std::env::var("XDG_SESSION_ID").unwrap_or_else(|_| "self".to_string());
+    Command::new("loginctl")
+        .args(["show-session", &session, "-p", "Remote"])
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).trim() == "Remote=yes")
+}
+
+/// Returns whether the active window is full screen, according to its X11 window state.
+#[cfg(target_os = "linux")]
+fn is_fullscreen_app_running() -> bool {
+    use std::process::Command;
+
+    let xprop = |args: &[&str]| -> Option<String> {
+        let output = Command::new("xprop").args(args).output().ok()?;
+        Some(String::from_utf8_lossy(&output.stdout).to_string())
+    };
+    // e.g. "_NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007"
+    let active = match xprop(&["-root", "_NET_ACTIVE_WINDOW"]).and_then(|out| out.split_whitespace().last().map(str::to_string)) {
+        Some(id) if id.starts_with("0x") && id != "0x0" => id,
+        _ => return false,
+    };
+    xprop(&["-id", &active, "_NET_WM_STATE"]).is_some_and(|state| state.contains("_NET_WM_STATE_FULLSCREEN"))
+}
+
+/// The state of the session is not detected on the other systems.
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+fn is_session_locked() -> bool {
+    false
+}
+
+/// The state of the session is not detected on the other systems.
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+fn is_remote_session() -> bool {
+    false
+}
+
+/// The state of the session is not detected on the other systems.
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+fn is_fullscreen_app_running() -> bool {
+    false
+}
@@ -0,0 +1,214 @@
+use crate::config::Config;
+use eframe::egui;
+use group_39::accessibility;
+use native_dialog::FileDialog;
+use serde_yaml::{Mapping, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The settings window (`--settings`): it edits the main fields of the configuration file, leaving the others
+/// as they are. The file is validated before being saved, and a running instance applies it as soon as it is saved.
+struct SettingsWindow {
+    config_path: PathBuf,
+    /// The content of the file, updated with the edited fields at every save.
+    document: Mapping,
+    source: String,
+    destination: String,
+    secondary_dest: String,
+    /// One extension per line.
+    type_files: String,
+    /// One pattern per line.
+    exclude: String,
+    btn_rec: bool,
+    gesture_tolerance: f32,
+    gesture_sampling: f32,
+    pause_when_locked: bool,
+    pause_in_fullscreen: bool,
+    quick_status_hotkey: bool,
+    large_notifications: bool,
+    /// Result of the last save: the message and whether it is an error.
+    message: Option<(String, bool)>,
+}
+
+impl SettingsWindow {
+    /// Reads the configuration file to edit; a missing file is edited from scratch.
+    fn load(config_path: &Path) -> Result<SettingsWindow, String> {
+        let document = match fs::read_to_string(config_path) {
+            Ok(text) => match serde_yaml::from_str(&text).map_err(|e| e.to_string())? {
+                Value::Mapping(document) => document,
+                Value::Null => Mapping::new(),
+                _ => return Err(format!("{} does not contain a configuration", config_path.display())),
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Mapping::new(),
+            Err(e) => return Err(e.to_string()),
+        };
+        // The values as written in the file (e.g. `~/Documents`), with the defaults of the missing fields
+        let config: Config = serde_yaml::from_value(Value::Mapping(document.clone())).map_err(|e| e.to_string())?;
+        Ok(SettingsWindow {
+            config_path: config_path.to_path_buf(),
+            document,
+            source: config.path_orig_backup.display().to_string(),
+            destination: config.path_dest_backup.display().to_string(),
+            secondary_dest: config.secondary_dest.map(|path| path.display().to_string()).unwrap_or_default(),
+            type_files: config.type_files.join("\n"),
+            exclude: config.exclude.join("\n"),
+            btn_rec: config.btn_rec,
+            gesture_tolerance: config.gesture_tolerance,
+            gesture_sampling: config.gesture_sampling,
+            pause_when_locked: config.pause_when_locked,
+            pause_in_fullscreen: config.pause_in_fullscreen,
+            quick_status_hotkey: config.quick_status_hotkey,
+            large_notifications: config.large_notifications,
+            message: None,
+        })
+    }
+
+    /// Writes the edited fields in the configuration file, if the resulting configuration is valid.
+    /// The file is first written to a temporary file, validated as the application would read it, and then renamed.
+    fn save(&mut self) -> Result<(), String> {
+        let lines = |text: &str| -> Value {
+            Value::Sequence(text.lines().map(str::trim).filter(|line| !line.is_empty()).map(Value::from).collect())
+        };
+        // One decimal, so that e.g. 4.1 is not written as 4.099999904632568
+        let pixels = |value: f32| Value::from((value as f64 * 10.0).round() / 10.0);
+        let mut document = self.document.clone();
+        let mut set = |key: &str, value: Value| {
+            document.insert(Value::from(key), value);
+        };
+        set("path_orig_backup", Value::from(self.source.trim()));
+        set("path_dest_backup", Value::from(self.destination.trim()));
+        set("type_files", lines(&self.type_files));
+        set("exclude", lines(&self.exclude));
+        set("btn_rec", Value::from(self.btn_rec));
+        set("gesture_tolerance", pixels(self.gesture_tolerance));
+        set("gesture_sampling", pixels(self.gesture_sampling));
+        set("pause_when_locked", Value::from(self.pause_when_locked));
+        set("pause_in_fullscreen", Value::from(self.pause_in_fullscreen));
+        set("quick_status_hotkey", Value::from(self.quick_status_hotkey));
+        set("large_notifications", Value::from(self.large_notifications));
+        if self.secondary_dest.trim().is_empty() {
+            document.remove("secondary_dest");
+        } else {
+            document.insert(Value::from("secondary_dest"), Value::from(self.secondary_dest.trim()));
+        }
+
+        let text = serde_yaml::to_string(&Value::Mapping(document.clone())).map_err(|e| e.to_string())?;
+        let tmp_path = self.config_path.with_extension("yaml.tmp");
+        fs::write(&tmp_path, text).map_err(|e| e.to_string())?;
+        if let Err(e) = Config::read_from_file(&tmp_path) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e.to_string().replace(&tmp_path.display().to_string(), &self.config_path.display().to_string()));
+        }
+        fs::rename(&tmp_path, &self.config_path).map_err(|e| e.to_string())?;
+        self.document = document;
+        Ok(())
+    }
+
+    /// Shows a text field for a folder, with a button to choose it.
+    fn folder_field(ui: &mut egui::Ui, label: &str, value: &mut String) {
+        ui.label(label);
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(value).desired_width(360.0));
+            if ui.button("Browse...").clicked() {
+                if let Ok(Some(folder)) = FileDialog::new().show_open_single_dir() {
+                    *value = folder.display().to_string();
+                }
+            }
+        });
+        ui.end_row();
+    }
+}
+
+impl eframe::App for SettingsWindow {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Emergency backup settings");
+            ui.label(format!("Configuration file: {}", self.config_path.display()));
+            ui.separator();
+
+            egui::Grid::new("folders").num_columns(2).spacing([12.0, 8.0]).show(ui, |ui| {
+                Self::folder_field(ui, "Folder to back up", &mut self.source);
+                Self::folder_field(ui, "Destination", &mut self.destination);
+                Self::folder_field(ui, "Second destination (optional)", &mut self.secondary_dest);
+            });
+            ui.separator();
+
+            ui.columns(2, |columns| {
+                columns[0].label("Extensions to back up (one per line, e.g. .docx; none for every file)");
+                columns[0].add(egui::TextEdit::multiline(&mut self.type_files).desired_rows(5));
+                columns[1].label("Files and folders to leave out (one per line, e.g. node_modules or *.tmp)");
+                columns[1].add(egui::TextEdit::multiline(&mut self.exclude).desired_rows(5));
+            });
+            ui.separator();
+
+            ui.label("Activation");
+            ui.radio_value(&mut self.btn_rec, false, "Draw a clockwise rectangle along the edges of the screen");
+            ui.radio_value(&mut self.btn_rec, true, "Hold ctrl+alt+b for 5 seconds, then confirm with 3 left clicks");
+            ui.add_enabled_ui(!self.btn_rec, |ui| {
+                ui.add(egui::Slider::new(&mut self.gesture_tolerance, 10.0..=500.0).text("gesture tolerance (pixels)"));
+                ui.add(egui::Slider::new(&mut self.gesture_sampling, 1.0..=100.0).text("gesture sampling (pixels)"));
+            });
+            ui.checkbox(&mut self.pause_when_locked, "Ignore the activation while the session is locked or remote");
+            ui.checkbox(&mut self.pause_in_fullscreen, "Ignore the activation while a full-screen application is running");
+            ui.checkbox(&mut self.quick_status_hotkey, "Show the status of the backups with ctrl+alt+s");
+            ui.checkbox(&mut self.large_notifications, "Extra-large text in the notifications and in the windows");
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                if ui.button("Save").clicked() {
+                    self.message = Some(match self.save() {
+                        Ok(()) => ("Saved: the running application uses the new settings".to_string(), false),
+                        Err(e) => (e, true),
+                    });
+                }
+                if ui.button("Close").clicked() {
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                }
+            });
+            if let Some((message, is_error)) = &self.message {
+                let color = if *is_error { ui.visuals().error_fg_color } else { ui.visuals().text_color() };
+                ui.colored_label(color, message);
+            }
+        });
+    }
+}
+
+/// Opens the settings window (`--settings`) on the configuration file, until it is closed.
+///
+/// # Arguments
+///
+/// * `config_path` - The configuration file to edit.
+///
+/// # Returns
+///
+/// The exit code: 0 if the window has been shown, 2 if the file cannot be edited.
+pub fn open_settings(config_path: &Path) -> i32 {
+    // The window rewrites the file as YAML
+    if matches!(config_path.extension().and_then(|ext| ext.to_str()), Some("toml" | "json")) {
+        println!("Only YAML configuration files can be edited in the settings window: edit {} by hand", config_path.display());
+        return 2;
+    }
+    let window = match SettingsWindow::load(config_path) {
+        Ok(window) => window,
+        Err(e) => {
+            println!("Unable to read the configuration {}: {}", config_path.display(), e);
+            return 2;
+        }
+    };
+    accessibility::set_large_text(window.large_notifications);
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_inner_size([640.0, 620.0]),
+        ..Default::default()
+    };
+    let create = Box::new(|creation: &eframe::CreationContext| {
+        accessibility::apply_to_egui(&creation.egui_ctx);
+        Ok(Box::new(window) as Box<dyn eframe::App>)
+    });
+    match eframe::run_native("Emergency backup settings", options, create) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("Unable to open the settings window: {}", e);
+            2
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use crate::config::Config;
+use crate::encryption;
+use crate::metadata_crypto;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use sysinfo::System;
+
+/// Name of the description of the backup, written in the root of the destination.
+pub const SNAPSHOT_INFO_FILE_NAME: &str = "snapshot_info.yaml";
+
+/// Where, when and how a backup was made, so that a recovered drive describes itself.
+/// The configuration is not written (it may hold the references to the keys, the addresses and the rules of
+/// the user): only the folder that was backed up and its filters.
+#[derive(Debug, Serialize)]
+pub struct SnapshotInfo {
+    pub created: String,
+    pub tool_version: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub os_version: Option<String>,
+    /// The folder that was backed up.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<PathBuf>,
+    /// The file types backed up (all if empty).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub type_files: Vec<String>,
+}
+
+impl SnapshotInfo {
+    /// Collects the information about the current machine and user. When the copies are encrypted and the
+    /// metadata is not (no `metadata_recipient`), only the date and the version are kept: the drive must not
+    /// tell whose machine it comes from.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration of the backup.
+    pub fn collect(config: &Config) -> SnapshotInfo {
+        let mut info = SnapshotInfo {
+            created: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            tool_version: env!("CARGO_PKG_VERSION"),
+            hostname: System::host_name(),
+            username: std::env::var("USERNAME").or_else(|_| std::env::var("USER")).ok(),
+            os_version: System::long_os_version(),
+            source: Some(config.path_orig_backup.clone()),
+            type_files: config.type_files.clone(),
+        };
+        if encryption::is_enabled() && !metadata_crypto::is_enabled() {
+            info.hostname = None;
+            info.username = None;
+            info.os_version = None;
+            info.source = None;
+            info.type_files.clear();
+        }
+        info
+    }
+
+    /// Writes the information in the root of a destination, replacing the one of the previous backup.
+    /// It is encrypted for `metadata_recipient`, if configured, like the manifest.
+    ///
+    /// # Arguments
+    ///
+    /// * `destination` - The root folder of the destination.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` indicating whether the file has been written.
+    pub fn save(&self, destination: &Path) -> io::Result<()> {
+        let contents = serde_yaml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        // The secondary destination may not have been created yet
+        fs::create_dir_all(destination)?;
+        metadata_crypto::write(&destination.join(SNAPSHOT_INFO_FILE_NAME), contents.as_bytes())
+    }
+}
+
+/// The description of a backup read back from a destination.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SnapshotSummary {
+    #[serde(default)]
+    pub created: String,
+    #[serde(default)]
+    pub hostname: Option<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    /// The folder that was backed up.
+    #[serde(default)]
+    pub source: Option<PathBuf>,
+}
+
+/// The part of the configuration saved by the older versions needed to describe a backup.
+#[derive(Debug, Deserialize)]
+struct SavedConfig {
+    #[serde(default)]
+    path_orig_backup: Option<PathBuf>,
+}
+
+/// The fields of `snapshot_info.yaml` read back.
+#[derive(Debug, Deserialize)]
+struct SavedInfo {
+    #[serde(flatten)]
+    summary: SnapshotSummary,
+    #[serde(default)]
+    config: Option<SavedConfig>,
+}
+
+impl SnapshotSummary {
+    /// Reads the description of the backup in the root of a destination, if any (and readable, when encrypted).
+    ///
+    /// # Arguments
+    ///
+    /// * `destination` - The root folder of the destination.
+    pub fn load(destination: &Path) -> Option<SnapshotSummary> {
+        let contents = metadata_crypto::read(&destination.join(SNAPSHOT_INFO_FILE_NAME)).ok()?;
+        let saved: SavedInfo = serde_yaml::from_slice(&contents).ok()?;
+        let mut summary = saved.summary;
+        if summary.source.is_none() {
+            summary.source = saved.config.and_then(|config| config.path_orig_backup);
+        }
+        Some(summary)
+    }
+}
@@ -0,0 +1,83 @@
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+
+/// Whether the events are also spoken aloud.
+static SPEECH_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Channel towards the speech thread, started at the first announcement.
+static SPEECH_THREAD: OnceLock<Mutex<Sender<String>>> = OnceLock::new();
+
+/// Sets whether the events are spoken aloud (text-to-speech).
+///
+/// # Arguments
+///
+/// * `enabled` - `true` to speak the announcements.
+pub fn set_speech_enabled(enabled: bool) {
+    SPEECH_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Speaks a sentence without blocking the caller, if text-to-speech is enabled.
+/// The sentences are spoken one at a time, in the order they are announced.
+///
+/// # Arguments
+///
+/// * `text` - The sentence to speak (e.g. "Backup armed").
+pub fn announce(text: &str) {
+    if !SPEECH_ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let sender = SPEECH_THREAD.get_or_init(|| Mutex::new(start_speech_thread()));
+    if sender.lock().unwrap().send(text.to_string()).is_err() {
+        println!("Failed to speak \"{}\": the speech thread is not running", text);
+    }
+}
+
+/// Starts the thread that speaks the sentences, returning the channel to send them to it.
+fn start_speech_thread() -> Sender<String> {
+    let (tx, rx) = channel::<String>();
+
+    thread::spawn(move || {
+        while let Ok(text) = rx.recv() {
+            if let Err(e) = speak(&text) {
+                println!("Failed to speak \"{}\": {}", text, e);
+            }
+        }
+    });
+
+    tx
+}
+
+/// Speaks a sentence with the Windows speech API (SAPI), waiting for the end of it.
+#[cfg(target_os = "windows")]
+fn speak(text: &str) -> std::io::Result<()> {
+    let script = format!(
+        "Add-Type -AssemblyName System.Speech; (New-Object System.Speech.Synthesis.SpeechSynthesizer).Speak('{}')",
+        text.replace('\'', "''")
+    );
+    run(Command::new("powershell").args(["-NoProfile", "-NonInteractive", "-Command", &script]))
+}
+
+/// Speaks a sentence with the macOS speech synthesizer, waiting for the end of it.
+#[cfg(target_os = "macos")]
+fn speak(text: &str) -> std::io::Result<()> {
+    run(Command::new("say").arg(text))
+}
+
+/// Speaks a sentence with speech-dispatcher, waiting for the end of it.
+#[cfg(target_os = "linux")]
+fn speak(text: &str) -> std::io::Result<()> {
+    run(Command::new("spd-say").args(["--wait", "--", text]))
+}
+
+/// Runs the speech command, reporting a failure if it does not exit successfully.
+fn run(command: &mut Command) -> std::io::Result<()> {
+    let status = command.stdout(Stdio::null()).stderr(Stdio::null()).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!("the speech command exited with {}", status)))
+    }
+}
@@ -0,0 +1,87 @@
+use crate::crash_handler;
+use group_39::app_state;
+use chrono::Local;
+use serde::Serialize;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// Content of the status file, read by external watchdogs to verify that the application is alive.
+#[derive(Serialize, Clone)]
+struct Status {
+    pid: u32,
+    state: String,
+    /// The state of the application (e.g. `armed`, `backing up (42%)`).
+    app_state: String,
+    last_gesture: Option<String>,
+    last_backup_result: Option<String>,
+    timestamp: String,
+}
+
+/// Path of the status file, set when the heartbeat is started.
+static STATUS_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+/// Time of the last recognized gesture.
+static LAST_GESTURE: Mutex<Option<String>> = Mutex::new(None);
+/// Result of the last backup.
+static LAST_BACKUP_RESULT: Mutex<Option<String>> = Mutex::new(None);
+
+/// Starts a thread that periodically writes the status file.
+///
+/// # Arguments
+///
+/// * `path` - The path of the status file (e.g. `log/status.json`).
+/// * `interval` - How often the file is rewritten.
+pub fn start_heartbeat(path: PathBuf, interval: Duration) {
+    *STATUS_PATH.lock().unwrap() = Some(path);
+
+    thread::spawn(move || loop {
+        write_status();
+        thread::sleep(interval);
+    });
+}
+
+/// Records that an activation gesture has just been recognized.
+pub fn record_gesture() {
+    *LAST_GESTURE.lock().unwrap() = Some(now());
+    write_status();
+}
+
+/// Records the result of the last backup (e.g. `COMPLETED` or `FAILED`).
+pub fn record_backup_result(result: &str) {
+    *LAST_BACKUP_RESULT.lock().unwrap() = Some(format!("{} ({})", result, now()));
+    write_status();
+}
+
+/// Writes the current status to the status file, if the heartbeat has been started.
+/// The file is first written to a temporary file and then renamed, so readers never see a partial content.
+fn write_status() {
+    let path = match STATUS_PATH.lock().unwrap().clone() {
+        Some(path) => path,
+        None => return,
+    };
+
+    let status = Status {
+        pid: std::process::id(),
+        state: crash_handler::current_state(),
+        app_state: app_state::current().to_string(),
+        last_gesture: LAST_GESTURE.lock().unwrap().clone(),
+        last_backup_result: LAST_BACKUP_RESULT.lock().unwrap().clone(),
+        timestamp: now(),
+    };
+
+    let tmp_path = path.with_extension("json.tmp");
+    let result = serde_json::to_string_pretty(&status)
+        .map_err(|e| e.to_string())
+        .and_then(|json| fs::write(&tmp_path, json).map_err(|e| e.to_string()))
+        .and_then(|_| fs::rename(&tmp_path, &path).map_err(|e| e.to_string()));
+
+    if let Err(e) = result {
+        eprintln!("Unable to write status file {:?}: {}", path, e);
+    }
+}
+
+fn now() -> String {
+    Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+}
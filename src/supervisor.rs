@@ -0,0 +1,132 @@
+use crate::events::{self, AppEvent};
+use std::any::Any;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How often the watchdog checks the supervised threads.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Wait before the first restart of a thread; it doubles at every consecutive failure, up to `MAX_BACKOFF`.
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A thread running at least this long before stopping is considered healthy: its backoff starts again.
+const STABLE_AFTER: Duration = Duration::from_secs(120);
+
+/// Consecutive failures after which the user is notified; the thread is still restarted.
+const FAILURES_BEFORE_ALERT: u32 = 3;
+
+/// Set when the application exits: the threads that stop are not restarted anymore.
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+/// A critical thread, restarted by the watchdog whenever it stops.
+struct Supervised {
+    name: &'static str,
+    task: Arc<dyn Fn() + Send + Sync>,
+    handle: Option<JoinHandle<()>>,
+    started: Instant,
+    /// Consecutive failures, each one shortly after the previous restart.
+    failures: u32,
+    /// When the thread is restarted, if it has stopped.
+    restart_at: Option<Instant>,
+}
+
+impl Supervised {
+    fn spawn(&mut self) {
+        let task = self.task.clone();
+        self.handle = thread::Builder::new().name(self.name.to_string()).spawn(move || task()).ok();
+        self.started = Instant::now();
+        self.restart_at = None;
+    }
+
+    /// Checks the thread and restarts it if its backoff has passed.
+    fn check(&mut self) {
+        if STOPPING.load(Ordering::SeqCst) {
+            return;
+        }
+        if let Some(restart_at) = self.restart_at {
+            if Instant::now() >= restart_at {
+                println!("Watchdog: restarting the thread {}", self.name);
+                self.spawn();
+            }
+            return;
+        }
+        let stopped = match &self.handle {
+            Some(handle) => handle.is_finished(),
+            // The thread could not even be created
+            None => true,
+        };
+        if !stopped {
+            return;
+        }
+
+        let reason = match self.handle.take().map(JoinHandle::join) {
+            Some(Err(panic)) => format!("panicked: {}", panic_message(&panic)),
+            Some(Ok(())) => "stopped".to_string(),
+            None => "could not be started".to_string(),
+        };
+        self.failures = if self.started.elapsed() >= STABLE_AFTER { 1 } else { self.failures + 1 };
+        let backoff = FIRST_BACKOFF.saturating_mul(1 << (self.failures - 1).min(6)).min(MAX_BACKOFF);
+        println!("Watchdog: the thread {} {}, restarted in {:?}", self.name, reason, backoff);
+        if self.failures == FAILURES_BEFORE_ALERT {
+            events::publish(AppEvent::Error(format!(
+                "The {} keeps stopping ({}): the emergency backup may not work until the application is restarted",
+                self.name, reason
+            )));
+        }
+        self.restart_at = Some(Instant::now() + backoff);
+    }
+}
+
+/// Returns the message of a panic, if it is a string.
+fn panic_message(panic: &Box<dyn Any + Send>) -> &str {
+    panic.downcast_ref::<&str>().copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown error")
+}
+
+/// The supervised threads, checked by the watchdog started with the first one.
+static SUPERVISED: OnceLock<Mutex<Vec<Supervised>>> = OnceLock::new();
+
+fn supervised() -> &'static Mutex<Vec<Supervised>> {
+    SUPERVISED.get_or_init(|| {
+        thread::spawn(|| loop {
+            thread::sleep(CHECK_INTERVAL);
+            for task in supervised().lock().unwrap().iter_mut() {
+                task.check();
+            }
+        });
+        Mutex::new(Vec::new())
+    })
+}
+
+/// Starts a critical thread (e.g. the listener of the activation pattern) under the watchdog: whenever it panics
+/// or returns, it is started again, waiting longer after every consecutive failure, and the user is notified
+/// if it keeps stopping. A thread that is expected to end must not be supervised.
+///
+/// # Arguments
+///
+/// * `name` - The name of the thread, in the logs and in the notification (e.g. `gesture listener`).
+/// * `task` - The body of the thread, called again at every restart.
+pub fn supervise<F>(name: &'static str, task: F)
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    let mut supervised_task = Supervised {
+        name,
+        task: Arc::new(task),
+        handle: None,
+        started: Instant::now(),
+        failures: 0,
+        restart_at: None,
+    };
+    supervised_task.spawn();
+    supervised().lock().unwrap().push(supervised_task);
+}
+
+/// Stops restarting the supervised threads, when the application exits (e.g. before stopping the input listeners).
+pub fn shutdown() {
+    STOPPING.store(true, Ordering::SeqCst);
+}
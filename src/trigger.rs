@@ -0,0 +1,158 @@
+use chrono::{Local, NaiveTime};
+use std::sync::{Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+/// Reason of the backup requested without the activation pattern (e.g. a power event), if any.
+static TRIGGER: Mutex<Option<String>> = Mutex::new(None);
+
+/// Times of the day when the automatic triggers may start the backup (`trigger_hours`); always if empty.
+static ALLOWED_WINDOWS: RwLock<Vec<TimeWindow>> = RwLock::new(Vec::new());
+
+/// A time of the day, e.g. from 19:00 to 07:00 (the end excluded), which can span midnight.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl TimeWindow {
+    /// Parses a window written as `HH:MM-HH:MM`, e.g. `19:00-07:00`.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The window to parse.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the window, or a message describing the mistake.
+    pub fn parse(text: &str) -> Result<TimeWindow, String> {
+        let invalid = || format!("invalid time window \"{}\": use HH:MM-HH:MM, e.g. 19:00-07:00", text);
+        let (start, end) = text.split_once('-').ok_or_else(invalid)?;
+        let time = |value: &str| NaiveTime::parse_from_str(value.trim(), "%H:%M").map_err(|_| invalid());
+        Ok(TimeWindow { start: time(start)?, end: time(end)? })
+    }
+
+    /// Returns whether a time of the day is inside the window.
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            // Across midnight
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+/// Sets when the automatic triggers (the power events and the scheduled backups) may start the backup;
+/// the activation pattern always works.
+///
+/// # Arguments
+///
+/// * `windows` - The allowed times of the day; the triggers always work if empty.
+pub fn set_allowed_windows(windows: Vec<TimeWindow>) {
+    *ALLOWED_WINDOWS.write().unwrap() = windows;
+}
+
+/// Returns whether an automatic trigger may start the backup now.
+pub fn trigger_allowed_now() -> bool {
+    let windows = ALLOWED_WINDOWS.read().unwrap();
+    let now = Local::now().time();
+    windows.is_empty() || windows.iter().any(|window| window.contains(now))
+}
+
+/// Requests the backup to start immediately, without waiting for the activation pattern.
+/// Only the first request is kept, and only inside the times of the day allowed by `trigger_hours`.
+///
+/// # Arguments
+///
+/// * `reason` - Why the backup is started, written in the log and shown to the user.
+///
+/// # Returns
+///
+/// `false` if the request has been ignored because of the time of the day.
+pub fn trigger_backup(reason: String) -> bool {
+    if !trigger_allowed_now() {
+        println!("Backup not triggered by {}: outside trigger_hours", reason);
+        return false;
+    }
+    confirm_backup(reason);
+    true
+}
+
+/// Requests the backup to start immediately, confirmed by the user in another way than the activation pattern
+/// (e.g. from the remote control): unlike the automatic triggers, at any time of the day.
+/// Only the first request is kept.
+///
+/// # Arguments
+///
+/// * `reason` - How the backup has been confirmed, written in the log and shown to the user.
+pub fn confirm_backup(reason: String) {
+    let mut trigger = TRIGGER.lock().unwrap();
+    if trigger.is_none() {
+        println!("Backup triggered: {}", reason);
+        *trigger = Some(reason);
+    }
+}
+
+/// Returns whether the backup has been requested without the activation pattern.
+pub fn is_triggered() -> bool {
+    TRIGGER.lock().unwrap().is_some()
+}
+
+/// Waits until the backup is requested without the activation pattern, when the pattern cannot be used.
+pub fn wait_for_trigger() {
+    while !is_triggered() {
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+/// Returns the reason of the backup requested without the activation pattern, if any.
+pub fn trigger_reason() -> Option<String> {
+    TRIGGER.lock().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(text: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(text, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn parses_the_time_windows() {
+        let window = TimeWindow::parse("19:00-07:00").unwrap();
+        assert_eq!(window, TimeWindow { start: at("19:00"), end: at("07:00") });
+        assert_eq!(TimeWindow::parse(" 9:30 - 17:45 ").unwrap(), TimeWindow { start: at("09:30"), end: at("17:45") });
+    }
+
+    #[test]
+    fn rejects_the_wrong_time_windows() {
+        assert!(TimeWindow::parse("19:00").is_err());
+        assert!(TimeWindow::parse("19-07").is_err());
+        assert!(TimeWindow::parse("25:00-07:00").is_err());
+        assert!(TimeWindow::parse("19:00-07:60").is_err());
+        assert!(TimeWindow::parse("evening-morning").is_err());
+    }
+
+    #[test]
+    fn a_window_contains_its_start_but_not_its_end() {
+        let window = TimeWindow::parse("09:00-17:00").unwrap();
+        assert!(window.contains(at("09:00")));
+        assert!(window.contains(at("12:00")));
+        assert!(!window.contains(at("17:00")));
+        assert!(!window.contains(at("08:59")));
+    }
+
+    #[test]
+    fn a_window_can_span_midnight() {
+        let window = TimeWindow::parse("19:00-07:00").unwrap();
+        assert!(window.contains(at("19:00")));
+        assert!(window.contains(at("23:59")));
+        assert!(window.contains(at("00:00")));
+        assert!(window.contains(at("06:59")));
+        assert!(!window.contains(at("07:00")));
+        assert!(!window.contains(at("12:00")));
+    }
+}
@@ -0,0 +1,219 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+/// Size of the read and write buffers of a copy when the backup is not tuned (the default of `BufReader`).
+pub const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Limits of the buffers tried by the tuning.
+const MIN_BUFFER_SIZE: usize = 8 * 1024;
+const MAX_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+
+/// Parallel copies a tuned backup starts with, if the limit of the open files allows it.
+pub const INITIAL_CONCURRENCY: usize = 8;
+
+/// Time during which the throughput of a setting is measured before it is compared with the best one.
+const TUNING_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Minimum relative gain of throughput to keep a change: smaller differences are noise of the drives.
+const MIN_GAIN: f64 = 0.05;
+
+/// How much slower each copy can become with a change that improves the throughput: beyond this the queue of the
+/// destination is growing (e.g. the cache of an SMR drive filling up), and the backup would slow down later.
+const MAX_LATENCY_GROWTH: f64 = 4.0;
+
+/// Size of the buffers of the copies, changed by the tuning.
+static BUFFER_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_BUFFER_SIZE);
+
+/// Returns the size of the read and write buffers of the copies.
+pub fn buffer_size() -> usize {
+    BUFFER_SIZE.load(Ordering::SeqCst)
+}
+
+/// The settings found by the tuning, reported at the end of the backup.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct TuningResult {
+    /// Parallel copies.
+    pub concurrency: usize,
+    /// Size of the buffers of each copy, in bytes.
+    pub buffer_size: usize,
+    /// The best throughput measured, in bytes per second.
+    pub throughput: u64,
+}
+
+/// The setting changed by the next step of the tuning.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Knob {
+    Concurrency,
+    BufferSize,
+}
+
+/// Throughput and average duration of a copy (in seconds) measured with some settings.
+#[derive(Debug, Clone, Copy)]
+struct Measure {
+    throughput: f64,
+    latency: f64,
+}
+
+struct TunerState {
+    concurrency: usize,
+    /// Bytes and files copied since the start of the current interval, and the time spent copying them.
+    bytes: u64,
+    files: usize,
+    copy_time: Duration,
+    since: Instant,
+    /// The best settings so far and their measure.
+    best: Option<(usize, usize, Measure)>,
+    knob: Knob,
+    grow: bool,
+    /// Whether the current settings are a change being tried, to be kept only if faster than the best ones.
+    trying: bool,
+}
+
+/// Adjusts the parallel copies and the size of their buffers during the backup, by trial and error: each interval
+/// one of them is doubled or halved, and the change is kept only if the throughput improves, so that the backup
+/// converges on the fastest settings for the machine and the destination drive.
+pub struct AutoTuner {
+    /// The semaphore limiting the parallel copies; its permits are added or withheld to change their number.
+    semaphore: Arc<Semaphore>,
+    max_concurrency: usize,
+    state: Mutex<TunerState>,
+}
+
+impl AutoTuner {
+    /// Creates the tuner of a backup, starting from the default buffers.
+    ///
+    /// # Arguments
+    ///
+    /// * `semaphore` - The semaphore limiting the parallel copies.
+    /// * `concurrency` - The permits of the semaphore, i.e. the parallel copies at the start.
+    /// * `max_concurrency` - The maximum of parallel copies (the limit of the open files).
+    pub fn new(semaphore: Arc<Semaphore>, concurrency: usize, max_concurrency: usize) -> AutoTuner {
+        BUFFER_SIZE.store(DEFAULT_BUFFER_SIZE, Ordering::SeqCst);
+        AutoTuner {
+            semaphore,
+            max_concurrency: max_concurrency.max(1),
+            state: Mutex::new(TunerState {
+                concurrency,
+                bytes: 0,
+                files: 0,
+                copy_time: Duration::ZERO,
+                since: Instant::now(),
+                best: None,
+                knob: Knob::Concurrency,
+                grow: true,
+                trying: false,
+            }),
+        }
+    }
+
+    /// Starts adjusting the settings periodically, until the returned task is aborted.
+    pub fn start(self: &Arc<Self>) -> JoinHandle<()> {
+        let tuner = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(TUNING_INTERVAL).await;
+                tuner.adjust();
+            }
+        })
+    }
+
+    /// Records a completed copy.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The size of the file.
+    /// * `duration` - How long the copy took.
+    pub fn record(&self, bytes: u64, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.bytes += bytes;
+        state.files += 1;
+        state.copy_time += duration;
+    }
+
+    /// Returns the best settings found, and restores the default buffers for the copies made after the backup.
+    pub fn finish(&self) -> TuningResult {
+        BUFFER_SIZE.store(DEFAULT_BUFFER_SIZE, Ordering::SeqCst);
+        let state = self.state.lock().unwrap();
+        match state.best {
+            Some((concurrency, buffer_size, measure)) => TuningResult { concurrency, buffer_size, throughput: measure.throughput as u64 },
+            None => TuningResult { concurrency: state.concurrency, buffer_size: DEFAULT_BUFFER_SIZE, throughput: 0 },
+        }
+    }
+
+    /// Compares the throughput of the last interval with the best one, keeps or reverts the change being tried
+    /// and tries the next one.
+    fn adjust(&self) {
+        let mut state = self.state.lock().unwrap();
+        // Nothing completed yet (e.g. a large file being copied): the measure continues
+        if state.files == 0 {
+            return;
+        }
+        let elapsed = state.since.elapsed().as_secs_f64();
+        let measure = Measure {
+            throughput: state.bytes as f64 / elapsed,
+            latency: state.copy_time.as_secs_f64() / state.files as f64,
+        };
+        state.bytes = 0;
+        state.files = 0;
+        state.copy_time = Duration::ZERO;
+        state.since = Instant::now();
+
+        let current = (state.concurrency, buffer_size());
+        match state.best {
+            Some((concurrency, buffer_size, best)) if state.trying => {
+                let faster = measure.throughput >= best.throughput * (1.0 + MIN_GAIN);
+                if faster && measure.latency <= best.latency * MAX_LATENCY_GROWTH {
+                    state.best = Some((current.0, current.1, measure));
+                } else {
+                    // Back to the best settings, and the next time the other direction (or the other setting) is tried
+                    self.apply(&mut state, concurrency, buffer_size);
+                    state.grow = !state.grow;
+                    if state.grow {
+                        state.knob = if state.knob == Knob::Concurrency { Knob::BufferSize } else { Knob::Concurrency };
+                    }
+                    state.trying = false;
+                    return;
+                }
+            }
+            // The best settings measured once more, since the source files change along the backup (e.g. from photos to videos)
+            _ => state.best = Some((current.0, current.1, measure)),
+        }
+
+        let (concurrency, buffer_size) = match (state.knob, state.grow) {
+            (Knob::Concurrency, true) => ((current.0 * 2).min(self.max_concurrency), current.1),
+            (Knob::Concurrency, false) => ((current.0 / 2).max(1), current.1),
+            (Knob::BufferSize, true) => (current.0, (current.1 * 2).min(MAX_BUFFER_SIZE)),
+            (Knob::BufferSize, false) => (current.0, (current.1 / 2).max(MIN_BUFFER_SIZE)),
+        };
+        if (concurrency, buffer_size) == current {
+            // At the limit: the other direction is tried the next time
+            state.grow = !state.grow;
+            state.trying = false;
+        } else {
+            self.apply(&mut state, concurrency, buffer_size);
+            state.trying = true;
+        }
+    }
+
+    /// Changes the parallel copies and the size of the buffers. Fewer parallel copies are reached
+    /// as the copies in progress end, by withholding their permits.
+    fn apply(&self, state: &mut TunerState, concurrency: usize, buffer_size: usize) {
+        if concurrency > state.concurrency {
+            self.semaphore.add_permits(concurrency - state.concurrency);
+        } else if concurrency < state.concurrency {
+            let semaphore = self.semaphore.clone();
+            let withheld = (state.concurrency - concurrency) as u32;
+            tokio::spawn(async move {
+                if let Ok(permits) = semaphore.acquire_many_owned(withheld).await {
+                    permits.forget();
+                }
+            });
+        }
+        state.concurrency = concurrency;
+        BUFFER_SIZE.store(buffer_size, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,222 @@
+use eframe::egui::{self, Align2, Color32, FontId, Pos2, Rect, Stroke, Vec2};
+use group_39::{accessibility, input_hook};
+use rdev::{EventType, Key};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Names of the corners of the screen, in the order of `corners`.
+const CORNER_NAMES: [&str; 4] = ["top-left", "top-right", "bottom-right", "bottom-left"];
+
+/// The rectangles to practice, as the corners to reach in order: first the confirmation (clockwise),
+/// then the cancellation (counter-clockwise), both starting from the top-left corner.
+const ROUNDS: [(&str, [usize; 5]); 2] = [
+    ("confirm the backup: clockwise", [0, 1, 2, 3, 0]),
+    ("cancel the backup: counter-clockwise", [0, 3, 2, 1, 0]),
+];
+
+/// How long the end of the tutorial is shown before the overlay closes.
+const END_DELAY: Duration = Duration::from_secs(4);
+
+/// The tutorial overlay (`--tutorial`): arrows along the edges of the screen show the rectangle to draw,
+/// and each side turns green as soon as it is completed, so that the first real gesture does not fail.
+/// It follows the same rules as the recognizer: start near the top-left corner and stay within the tolerance of the edges.
+struct Tutorial {
+    /// The last position of the mouse, in physical pixels.
+    mouse: Arc<Mutex<Option<Pos2>>>,
+    /// Set when Esc is pressed.
+    quit: Arc<AtomicBool>,
+    /// The configured `gesture_tolerance`, in physical pixels.
+    tolerance: f32,
+    /// The rectangle being practiced (an index of `ROUNDS`), `ROUNDS.len()` once both are done.
+    round: usize,
+    /// Sides completed in the current rectangle; `None` until the starting corner is reached.
+    sides_done: Option<usize>,
+    hint: String,
+    finished_at: Option<Instant>,
+    /// Whether the high-contrast mode of the system is on, to draw with pure colors.
+    high_contrast: bool,
+}
+
+impl Tutorial {
+    fn new(tolerance: f32) -> Tutorial {
+        let mouse = Arc::new(Mutex::new(None));
+        let quit = Arc::new(AtomicBool::new(false));
+        let (mouse_clone, quit_clone) = (mouse.clone(), quit.clone());
+        // The overlay lets the mouse through, so the events are read from the global listener
+        thread::spawn(move || {
+            input_hook::listen(move |event| match event.event_type {
+                EventType::MouseMove { x, y } => *mouse_clone.lock().unwrap() = Some(egui::pos2(x as f32, y as f32)),
+                EventType::KeyPress(Key::Escape) => quit_clone.store(true, Ordering::SeqCst),
+                _ => {}
+            })
+            .unwrap();
+        });
+        Tutorial {
+            mouse,
+            quit,
+            tolerance,
+            round: 0,
+            sides_done: None,
+            hint: start_hint(0),
+            finished_at: None,
+            high_contrast: accessibility::high_contrast(),
+        }
+    }
+
+    /// Advances the tutorial with the current position of the mouse.
+    fn follow(&mut self, mouse: Pos2, corners: &[Pos2; 4], tolerance: f32) {
+        let route = match ROUNDS.get(self.round) {
+            Some((_, route)) => route,
+            None => return,
+        };
+        match self.sides_done {
+            None => {
+                if mouse.distance(corners[route[0]]) <= tolerance {
+                    self.sides_done = Some(0);
+                    self.hint = side_hint(route, 0);
+                }
+            }
+            Some(done) => {
+                let (from, to) = (corners[route[done]], corners[route[done + 1]]);
+                if distance_to_segment(mouse, from, to) > tolerance {
+                    self.sides_done = None;
+                    self.hint = format!("You left the edge: stay close to it.\n{}", start_hint(self.round));
+                } else if mouse.distance(to) <= tolerance {
+                    if done + 1 < 4 {
+                        self.sides_done = Some(done + 1);
+                        self.hint = side_hint(route, done + 1);
+                    } else {
+                        self.round += 1;
+                        self.sides_done = None;
+                        self.hint = match self.round {
+                            round if round < ROUNDS.len() => format!("Well done!\n{}", start_hint(round)),
+                            _ => "Well done! You are ready to use the gestures.".to_string(),
+                        };
+                        if self.round == ROUNDS.len() {
+                            self.finished_at = Some(Instant::now());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draws the sides of the rectangle being practiced: completed (green), current (yellow) and next (gray).
+    fn paint(&self, painter: &egui::Painter, corners: &[Pos2; 4], tolerance: f32) {
+        if let Some((_, route)) = ROUNDS.get(self.round) {
+            let center = Rect::from_two_pos(corners[0], corners[2]).center();
+            for side in 0..4 {
+                // The arrows are drawn inside the area where the gesture is accepted
+                let inset = |corner: Pos2| corner + (center - corner).normalized() * tolerance / 2.0;
+                let (from, to) = (inset(corners[route[side]]), inset(corners[route[side + 1]]));
+                let color = match (self.sides_done, self.high_contrast) {
+                    (Some(done), false) if side < done => Color32::from_rgb(40, 200, 80),
+                    (Some(done), true) if side < done => Color32::GREEN,
+                    (Some(done), false) if side == done => Color32::from_rgb(250, 200, 0),
+                    (Some(done), true) if side == done => Color32::YELLOW,
+                    (_, false) => Color32::from_gray(150),
+                    (_, true) => Color32::WHITE,
+                };
+                let width = if self.high_contrast { 10.0 } else { 6.0 };
+                painter.arrow(from, to - from, Stroke::new(width, color));
+            }
+            if self.sides_done.is_none() {
+                painter.circle_stroke(corners[route[0]], tolerance, Stroke::new(4.0, Color32::from_rgb(250, 200, 0)));
+            }
+        }
+
+        let screen = Rect::from_two_pos(corners[0], corners[2]);
+        let title = match ROUNDS.get(self.round) {
+            Some((title, _)) => format!("Gesture tutorial: {} (Esc to quit)\n\n{}", title, self.hint),
+            None => self.hint.clone(),
+        };
+        let galley = painter.layout(title, FontId::proportional(28.0), Color32::WHITE, screen.width() / 2.0);
+        let text_rect = Align2::CENTER_CENTER.anchor_size(screen.center(), galley.size());
+        let background = if self.high_contrast { Color32::BLACK } else { Color32::from_black_alpha(200) };
+        painter.rect_filled(text_rect.expand(24.0), 12.0, background);
+        if self.high_contrast {
+            painter.rect_stroke(text_rect.expand(24.0), 12.0, Stroke::new(3.0, Color32::WHITE));
+        }
+        painter.galley(text_rect.min, galley, Color32::WHITE);
+    }
+}
+
+impl eframe::App for Tutorial {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let finished = self.finished_at.is_some_and(|finished_at| finished_at.elapsed() >= END_DELAY);
+        if finished || self.quit.load(Ordering::SeqCst) {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            return;
+        }
+        let screen = ctx.screen_rect();
+        let corners = [screen.left_top(), screen.right_top(), screen.right_bottom(), screen.left_bottom()];
+        // The listener and the configuration use physical pixels, the overlay logical points
+        let pixels_per_point = ctx.pixels_per_point();
+        let tolerance = self.tolerance / pixels_per_point;
+        let mouse = *self.mouse.lock().unwrap();
+        if let Some(mouse) = mouse {
+            self.follow(egui::pos2(mouse.x / pixels_per_point, mouse.y / pixels_per_point), &corners, tolerance);
+        }
+        self.paint(&ctx.layer_painter(egui::LayerId::background()), &corners, tolerance);
+        ctx.request_repaint_after(Duration::from_millis(30));
+    }
+
+    fn clear_color(&self, _visuals: &egui::Visuals) -> [f32; 4] {
+        // Transparent, so that only the arrows and the hints cover the screen
+        [0.0, 0.0, 0.0, 0.0]
+    }
+}
+
+/// Returns the hint to reach the starting corner of a rectangle.
+fn start_hint(round: usize) -> String {
+    format!("Move the mouse to the {} corner to start the rectangle.", CORNER_NAMES[ROUNDS[round].1[0]])
+}
+
+/// Returns the hint to draw a side of a rectangle.
+fn side_hint(route: &[usize; 5], side: usize) -> String {
+    format!("Side {} of 4: move along the edge to the {} corner.", side + 1, CORNER_NAMES[route[side + 1]])
+}
+
+/// Returns the distance of a point from a segment.
+fn distance_to_segment(point: Pos2, from: Pos2, to: Pos2) -> f32 {
+    let side: Vec2 = to - from;
+    let t = if side.length_sq() > 0.0 { ((point - from).dot(side) / side.length_sq()).clamp(0.0, 1.0) } else { 0.0 };
+    point.distance(from + side * t)
+}
+
+/// Shows the gesture tutorial over the whole screen, until both rectangles are drawn or Esc is pressed.
+///
+/// # Arguments
+///
+/// * `tolerance` - The configured `gesture_tolerance`, in pixels.
+/// * `large_text` - Whether the hints are shown with extra-large text (`large_notifications`).
+///
+/// # Returns
+///
+/// The exit code: 0 if the tutorial has been shown, 1 if the overlay cannot be opened.
+pub fn run_tutorial(tolerance: f32, large_text: bool) -> i32 {
+    accessibility::set_large_text(large_text);
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default()
+            .with_title("Gesture tutorial")
+            .with_fullscreen(true)
+            .with_decorations(false)
+            .with_transparent(true)
+            .with_always_on_top()
+            .with_mouse_passthrough(true),
+        ..Default::default()
+    };
+    let create = Box::new(move |creation: &eframe::CreationContext| {
+        accessibility::apply_to_egui(&creation.egui_ctx);
+        Ok(Box::new(Tutorial::new(tolerance)) as Box<dyn eframe::App>)
+    });
+    match eframe::run_native("Gesture tutorial", options, create) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("Unable to open the tutorial: {}", e);
+            1
+        }
+    }
+}
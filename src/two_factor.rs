@@ -0,0 +1,100 @@
+use rdev::{EventType, Key};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long Ctrl+Alt+B must be held down to count as the second factor, as in the buttons and clicks pattern.
+const HOTKEY_HOLD: Duration = Duration::from_secs(5);
+
+/// Whether the backup also needs the hotkey after the gesture (`two_factor_arming`), and how far apart
+/// the two may be (`two_factor_window_secs`).
+static SETTINGS: Mutex<(bool, Duration)> = Mutex::new((false, Duration::from_secs(30)));
+
+/// The state of Ctrl+Alt+B, read from the global listener.
+#[derive(Default)]
+struct Hotkey {
+    ctrl: bool,
+    alt: bool,
+    b: bool,
+    /// When the three keys have been all held down, if they still are.
+    held_since: Option<Instant>,
+    /// When the hold has lasted `HOTKEY_HOLD` the last time.
+    completed_at: Option<Instant>,
+}
+
+static HOTKEY: Mutex<Hotkey> = Mutex::new(Hotkey { ctrl: false, alt: false, b: false, held_since: None, completed_at: None });
+
+/// Sets whether the gesture has to be followed (or preceded) by the Ctrl+Alt+B hold to start the backup.
+///
+/// # Arguments
+///
+/// * `enabled` - Whether the two factors are required.
+/// * `window` - The longest time between the gesture and the hold.
+pub fn set_two_factor(enabled: bool, window: Duration) {
+    *SETTINGS.lock().unwrap() = (enabled, window);
+}
+
+/// Returns whether the backup needs both the gesture and the Ctrl+Alt+B hold.
+pub fn is_enabled() -> bool {
+    SETTINGS.lock().unwrap().0
+}
+
+/// Handles a keyboard event, recording when Ctrl+Alt+B has been held down long enough.
+/// The keys are read by the listener of the activation pattern.
+///
+/// # Arguments
+///
+/// * `event` - The event received by the global listener.
+pub fn handle_event(event: &EventType) {
+    let mut hotkey = HOTKEY.lock().unwrap();
+    match *event {
+        EventType::KeyPress(Key::ControlLeft | Key::ControlRight) => hotkey.ctrl = true,
+        EventType::KeyRelease(Key::ControlLeft | Key::ControlRight) => hotkey.ctrl = false,
+        EventType::KeyPress(Key::Alt | Key::AltGr) => hotkey.alt = true,
+        EventType::KeyRelease(Key::Alt | Key::AltGr) => hotkey.alt = false,
+        EventType::KeyPress(Key::KeyB) => hotkey.b = true,
+        EventType::KeyRelease(Key::KeyB) => hotkey.b = false,
+        _ => {}
+    }
+    if !(hotkey.ctrl && hotkey.alt && hotkey.b) {
+        hotkey.held_since = None;
+        return;
+    }
+    // The keys held down are repeated by the system, so the hold is checked again while it lasts
+    let held_since = *hotkey.held_since.get_or_insert_with(Instant::now);
+    if held_since.elapsed() >= HOTKEY_HOLD {
+        hotkey.completed_at = Some(Instant::now());
+    }
+}
+
+/// Waits for the second factor once the gesture has been confirmed: the Ctrl+Alt+B hold, completed at most
+/// `two_factor_window_secs` before the gesture or within the same time after it.
+///
+/// # Returns
+///
+/// `true` if the hold has been completed in time (or the second factor is not required),
+/// `false` if the backup must not start.
+pub fn wait_for_hotkey() -> bool {
+    let (enabled, window) = *SETTINGS.lock().unwrap();
+    if !enabled {
+        return true;
+    }
+    let gesture_at = Instant::now();
+    loop {
+        {
+            let mut hotkey = HOTKEY.lock().unwrap();
+            if let Some(completed_at) = hotkey.completed_at {
+                let apart = if completed_at > gesture_at { completed_at - gesture_at } else { gesture_at - completed_at };
+                if apart <= window {
+                    // Used once: the next backup needs a new hold
+                    hotkey.completed_at = None;
+                    return true;
+                }
+            }
+        }
+        if gesture_at.elapsed() > window {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
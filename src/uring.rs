@@ -0,0 +1,110 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Whether this build can copy with io_uring: only on Linux, built with the `io_uring` feature.
+pub const AVAILABLE: bool = cfg!(all(target_os = "linux", feature = "io_uring"));
+
+/// Whether the copies of the backup read and write with io_uring (`io_uring` in the configuration).
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Called whenever some data has been read, so that the watchdog of the copies sees their progress.
+pub type OnProgress = Arc<dyn Fn() + Send + Sync>;
+
+/// Sets whether the copies use io_uring; ignored if this build cannot (see `AVAILABLE`).
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled && AVAILABLE, Ordering::SeqCst);
+}
+
+/// Returns whether the copies use io_uring.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+pub use worker::copy_file;
+
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+mod worker {
+    use super::OnProgress;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::sync::OnceLock;
+    use std::thread;
+    use tokio::sync::{mpsc, oneshot};
+    use tokio_uring::buf::BoundedBuf;
+
+    /// Bytes read and written at a time.
+    const CHUNK: usize = 256 * 1024;
+
+    /// A copy requested to the io_uring thread.
+    struct Job {
+        src: PathBuf,
+        dest: PathBuf,
+        on_progress: OnProgress,
+        reply: oneshot::Sender<io::Result<()>>,
+    }
+
+    /// The queue of the thread running the io_uring runtime, started by the first copy.
+    static JOBS: OnceLock<mpsc::UnboundedSender<Job>> = OnceLock::new();
+
+    /// Returns the queue of the io_uring thread, starting it if needed. The copies run concurrently on its
+    /// runtime, which cannot be mixed with the Tokio runtime of the backup.
+    fn jobs() -> &'static mpsc::UnboundedSender<Job> {
+        JOBS.get_or_init(|| {
+            let (tx, mut rx) = mpsc::unbounded_channel::<Job>();
+            thread::spawn(move || {
+                tokio_uring::start(async move {
+                    while let Some(job) = rx.recv().await {
+                        tokio_uring::spawn(async move {
+                            let result = copy(&job.src, &job.dest, &job.on_progress).await;
+                            let _ = job.reply.send(result);
+                        });
+                    }
+                })
+            });
+            tx
+        })
+    }
+
+    fn worker_stopped() -> io::Error {
+        io::Error::new(io::ErrorKind::Other, "the io_uring thread has stopped")
+    }
+
+    /// Copies a file with io_uring, on the io_uring thread. A copy canceled by the watchdog is not stopped
+    /// on that thread, but its result is discarded.
+    ///
+    /// # Arguments
+    ///
+    /// * `src` - The source file.
+    /// * `dest` - The destination file, created or truncated.
+    /// * `on_progress` - Called whenever some data has been read.
+    pub async fn copy_file(src: &Path, dest: &Path, on_progress: OnProgress) -> io::Result<()> {
+        let (reply, result) = oneshot::channel();
+        let job = Job { src: src.to_path_buf(), dest: dest.to_path_buf(), on_progress, reply };
+        jobs().send(job).map_err(|_| worker_stopped())?;
+        result.await.unwrap_or_else(|_| Err(worker_stopped()))
+    }
+
+    async fn copy(src: &Path, dest: &Path, on_progress: &OnProgress) -> io::Result<()> {
+        let source = tokio_uring::fs::File::open(src).await?;
+        let destination = tokio_uring::fs::File::create(dest).await?;
+        let mut buffer = vec![0u8; CHUNK];
+        let mut position = 0u64;
+        loop {
+            // The buffers are owned by the kernel during the operations, and given back with their result
+            let (read, returned) = source.read_at(buffer, position).await;
+            buffer = returned;
+            let read = read?;
+            if read == 0 {
+                break;
+            }
+            on_progress();
+            let (written, slice) = destination.write_all_at(buffer.slice(..read), position).await;
+            buffer = slice.into_inner();
+            written?;
+            position += read as u64;
+        }
+        destination.close().await?;
+        source.close().await
+    }
+}
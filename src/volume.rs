@@ -0,0 +1,190 @@
+use crate::config::SharedConfig;
+use crate::notification_popup::{show_popup, NotificationType};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// Checks that the destination exists and that files can be written in it, by creating and removing a probe file.
+///
+/// # Arguments
+///
+/// * `path` - The destination folder.
+///
+/// # Returns
+///
+/// * `Ok(())` if the destination is writable, otherwise a message describing the problem.
+pub fn check_writable(path: &Path) -> Result<(), String> {
+    if !path.is_dir() {
+        return Err(format!("The destination {:?} does not exist or is not reachable", path));
+    }
+
+    let probe = path.join(format!(".backup_probe_{}", std::process::id()));
+    fs::write(&probe, b"probe")
+        .and_then(|_| fs::remove_file(&probe))
+        .map_err(|e| format!("The destination {:?} is not writable: {}", path, e))
+}
+
+/// Checks the destination now and then keeps checking it in a separate thread, notifying the user
+/// when it becomes unavailable (e.g. the USB drive is unplugged) and when it is available again,
+/// so that problems are discovered before the emergency and not during it.
+///
+/// # Arguments
+///
+/// * `shared_config` - The configuration, read at every check to follow its reloads.
+/// * `interval` - Time between two checks.
+pub fn start_destination_monitor(shared_config: SharedConfig, interval: Duration) {
+    thread::spawn(move || {
+        let mut last_problem: Option<String> = None;
+        loop {
+            let mut config = shared_config.read().unwrap().clone();
+            let problem = config.resolve_destination_volume()
+                .and_then(|_| check_writable(&config.path_dest_backup))
+                .err();
+
+            match (&last_problem, &problem) {
+                // Notify only the changes, not every check
+                (_, Some(msg)) if last_problem.as_ref() != Some(msg) => {
+                    show_popup(NotificationType::GenericError, Some(msg.clone()));
+                }
+                (Some(_), None) => {
+                    show_popup(NotificationType::Info, Some(format!(
+                        "The destination {:?} is available again", config.path_dest_backup
+                    )));
+                }
+                _ => {}
+            }
+            last_problem = problem;
+            thread::sleep(interval);
+        }
+    });
+}
+
+/// Finds where the volume with the given label is mounted (e.g. `E:\` on Windows, `/media/user/LABEL` on Linux).
+/// On Linux the filesystem UUID is accepted as well.
+///
+/// # Arguments
+///
+/// * `label` - The label (or UUID) of the volume.
+///
+/// # Returns
+///
+/// * The mount point of the volume, or `None` if it is not connected.
+#[cfg(target_os = "linux")]
+pub fn find_mount_point(label: &str) -> Option<PathBuf> {
+    use std::fs;
+
+    // udev publishes a link to the device for every label and UUID
+    let device = ["/dev/disk/by-label", "/dev/disk/by-uuid"]
+        .iter()
+        .find_map(|dir| fs::canonicalize(PathBuf::from(dir).join(label)).ok())?;
+
+    // Look for the device in the mounted filesystems
+    let mounts = fs::read_to_string("/proc/mounts").ok()?;
+    mounts.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let mounted_device = fields.next()?;
+        let mount_point = fields.next()?;
+        if fs::canonicalize(mounted_device).ok()? == device {
+            // Spaces in the mount point are escaped as \040
+            Some(PathBuf::from(mount_point.replace("\\040", " ")))
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(target_os = "macos")]
+pub fn find_mount_point(label: &str) -> Option<PathBuf> {
+    let path = PathBuf::from("/Volumes").join(label);
+    if path.exists() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+#[cfg(target_os = "windows")]
+pub fn find_mount_point(label: &str) -> Option<PathBuf> {
+    use sysinfo::Disks;
+
+    // On Windows the name of a disk is the label of its volume
+    let disks = Disks::new_with_refreshed_list();
+    disks
+        .iter()
+        .find(|disk| disk.name().to_str().map_or(false, |name| name.eq_ignore_ascii_case(label)))
+        .map(|disk| disk.mount_point().to_path_buf())
+}
+
+/// File systems of the network shares.
+const NETWORK_FILE_SYSTEMS: &[&str] = &["nfs", "nfs4", "cifs", "smb3", "smbfs", "afpfs", "webdav", "fuse.sshfs", "9p"];
+
+/// Returns whether a path is on a network share (e.g. an SMB or NFS share, or a UNC path on Windows),
+/// looking at the file system of the volume it belongs to.
+///
+/// # Arguments
+///
+/// * `path` - The path to check, e.g. the destination of the backup.
+pub fn is_remote(path: &Path) -> bool {
+    if path.to_string_lossy().starts_with(r"\\") {
+        return true;
+    }
+    file_system_of(path).is_some_and(|file_system| NETWORK_FILE_SYSTEMS.contains(&file_system.as_str()))
+}
+
+/// File systems that restrict the characters and the names of the files (FAT and exFAT, as used on most USB drives).
+const RESTRICTED_FILE_SYSTEMS: &[&str] = &["vfat", "fat", "fat12", "fat16", "fat32", "msdos", "exfat"];
+
+/// Returns whether a path is on a FAT or exFAT volume, where names with characters such as `:` or `?`,
+/// reserved names such as `CON` and names ending with a dot cannot be stored.
+///
+/// # Arguments
+///
+/// * `path` - The path to check, e.g. the destination of the backup.
+pub fn has_restricted_names(path: &Path) -> bool {
+    file_system_of(path).is_some_and(|file_system| RESTRICTED_FILE_SYSTEMS.contains(&file_system.as_str()))
+}
+
+/// Returns the file system (in lowercase) of the volume a path belongs to: the one with the longest mount point containing it.
+fn file_system_of(path: &Path) -> Option<String> {
+    use sysinfo::Disks;
+
+    let disks = Disks::new_with_refreshed_list();
+    disks
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| disk.file_system().to_string_lossy().to_lowercase())
+}
+
+/// Returns whether a path is on a rotational disk (HDD), where many files read at the same time make the heads seek.
+///
+/// # Arguments
+///
+/// * `path` - The path to check, e.g. the source of the backup.
+pub fn is_rotational(path: &Path) -> bool {
+    use sysinfo::{DiskKind, Disks};
+
+    let disks = Disks::new_with_refreshed_list();
+    disks
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .is_some_and(|disk| disk.kind() == DiskKind::HDD)
+}
+
+/// Returns the space available in the volume a path belongs to, if it is known.
+///
+/// # Arguments
+///
+/// * `path` - The path to check, e.g. the destination of the backup.
+pub fn available_space(path: &Path) -> Option<u64> {
+    use sysinfo::Disks;
+
+    let disks = Disks::new_with_refreshed_list();
+    disks
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| disk.available_space())
+}
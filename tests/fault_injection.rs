@@ -0,0 +1,149 @@
+use group_39::backup::{backup, BackupOptions, BackupReport};
+use group_39::filesystem::{FaultyFilesystem, Filesystem, MemoryFilesystem};
+use group_39::resume::{ProgressJournal, PROGRESS_FILE_NAME};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+const SOURCE: &str = "/src";
+
+/// A source of three small files, each copied with a single write.
+fn source() -> MemoryFilesystem {
+    let filesystem = MemoryFilesystem::new();
+    filesystem.add_file("/src/a.txt", b"aaaa");
+    filesystem.add_file("/src/b.txt", b"bbbb");
+    filesystem.add_file("/src/c.txt", b"cccc");
+    filesystem
+}
+
+/// A folder on the disk for what the backup keeps there (the progress journal and the manifest),
+/// while the copies are written in memory.
+fn destination(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("group_39_faults_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    path
+}
+
+/// The files are copied one at a time, so that the faults always hit the same file.
+fn options() -> BackupOptions {
+    BackupOptions { total_files: 3, max_file_opened: 1, ..Default::default() }
+}
+
+async fn run(filesystem: Arc<dyn Filesystem>, destination: &Path, options: &BackupOptions) -> (io::Result<()>, BackupReport) {
+    let report = Arc::new(Mutex::new(BackupReport::default()));
+    let result = backup(filesystem, Path::new(SOURCE), destination, options, report.clone(), None).await;
+    let report = report.lock().unwrap().clone();
+    (result, report)
+}
+
+fn assert_all_copied(filesystem: &MemoryFilesystem, destination: &Path) {
+    assert_eq!(filesystem.file(destination.join("a.txt")).as_deref(), Some(&b"aaaa"[..]));
+    assert_eq!(filesystem.file(destination.join("b.txt")).as_deref(), Some(&b"bbbb"[..]));
+    assert_eq!(filesystem.file(destination.join("c.txt")).as_deref(), Some(&b"cccc"[..]));
+}
+
+#[tokio::test]
+async fn a_failed_write_fails_only_its_file_and_the_next_backup_copies_it() {
+    let memory = source();
+    let dest = destination("nth_write");
+    let faulty = FaultyFilesystem::new(Arc::new(memory.clone())).fail_nth_write(2);
+
+    let (result, report) = run(Arc::new(faulty), &dest, &options()).await;
+
+    result.unwrap();
+    assert_eq!(report.failed, 1);
+    assert_eq!(report.errors.get("Other"), Some(&1));
+    assert_eq!(memory.file(dest.join("a.txt")).as_deref(), Some(&b"aaaa"[..]));
+    assert_eq!(memory.file(dest.join("c.txt")).as_deref(), Some(&b"cccc"[..]));
+    // Every file has been tried: nothing is left to resume
+    assert!(!dest.join(PROGRESS_FILE_NAME).exists());
+
+    let (result, report) = run(Arc::new(memory.clone()), &dest, &options()).await;
+
+    result.unwrap();
+    assert_eq!(report.failed, 0);
+    assert_all_copied(&memory, &dest);
+    std::fs::remove_dir_all(&dest).unwrap();
+}
+
+#[tokio::test]
+async fn a_full_disk_fails_the_files_that_do_not_fit() {
+    let memory = source();
+    let dest = destination("disk_full");
+    // The third file fits only in part
+    let faulty = FaultyFilesystem::new(Arc::new(memory.clone())).disk_full_after(10);
+
+    let (result, report) = run(Arc::new(faulty), &dest, &options()).await;
+
+    result.unwrap();
+    assert_eq!(report.failed, 1);
+    assert_eq!(report.errors.get("StorageFull"), Some(&1));
+    assert_eq!(memory.file(dest.join("a.txt")).as_deref(), Some(&b"aaaa"[..]));
+    assert_eq!(memory.file(dest.join("b.txt")).as_deref(), Some(&b"bbbb"[..]));
+    assert_ne!(memory.file(dest.join("c.txt")).as_deref(), Some(&b"cccc"[..]));
+    std::fs::remove_dir_all(&dest).unwrap();
+}
+
+#[tokio::test]
+async fn a_removed_device_fails_the_rest_of_the_backup() {
+    let memory = source();
+    let dest = destination("removed");
+    // The first file is written and flushed; the device disappears during the second one
+    let faulty = FaultyFilesystem::new(Arc::new(memory.clone())).remove_device_after(&dest, 2);
+
+    let (result, report) = run(Arc::new(faulty), &dest, &options()).await;
+
+    result.unwrap();
+    assert_eq!(report.failed, 2);
+    assert_eq!(report.errors.get("NotFound"), Some(&2));
+    assert_eq!(memory.file(dest.join("a.txt")).as_deref(), Some(&b"aaaa"[..]));
+    std::fs::remove_dir_all(&dest).unwrap();
+}
+
+#[tokio::test]
+async fn a_resumed_backup_skips_the_files_already_copied() {
+    let memory = source();
+    let dest = destination("resume");
+    // An interrupted backup copied the first file and saved its journal
+    memory.add_file(dest.join("a.txt"), b"aaaa");
+    let tasks = ["a.txt", "b.txt", "c.txt"].iter().map(|name| Path::new(SOURCE).join(name)).collect();
+    ProgressJournal::new(&dest, Path::new(SOURCE), tasks).mark_completed(0);
+    assert!(dest.join(PROGRESS_FILE_NAME).exists());
+    let faulty = FaultyFilesystem::new(Arc::new(memory.clone()));
+
+    let (result, report) = run(Arc::new(faulty.clone()), &dest, &options()).await;
+
+    result.unwrap();
+    assert_eq!(report.resumed, 1);
+    assert_eq!(report.failed, 0);
+    assert_eq!(faulty.writes(), 2);
+    assert_all_copied(&memory, &dest);
+    // The backup has ended: the next one starts from scratch
+    assert!(!dest.join(PROGRESS_FILE_NAME).exists());
+    std::fs::remove_dir_all(&dest).unwrap();
+}
+
+#[tokio::test]
+async fn a_backup_out_of_time_keeps_its_journal_for_the_next_one() {
+    let memory = source();
+    let dest = destination("deadline");
+    let out_of_time = BackupOptions { deadline: Some(Instant::now()), ..options() };
+
+    let (result, report) = run(Arc::new(memory.clone()), &dest, &out_of_time).await;
+
+    result.unwrap();
+    assert_eq!(report.unfinished, 3);
+    let journal = ProgressJournal::load(&dest).expect("the journal is kept");
+    assert_eq!(journal.completed_count(), 0);
+
+    let (result, report) = run(Arc::new(memory.clone()), &dest, &options()).await;
+
+    result.unwrap();
+    assert_eq!(report.unfinished, 0);
+    assert_eq!(report.failed, 0);
+    assert_all_copied(&memory, &dest);
+    assert!(!dest.join(PROGRESS_FILE_NAME).exists());
+    std::fs::remove_dir_all(&dest).unwrap();
+}